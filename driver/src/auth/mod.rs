@@ -0,0 +1,177 @@
+// src/auth/mod.rs
+// Std lib imports
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::RwLock;
+
+// Keys are presented in the headers of the HTTP and gRPC requests
+use hyper::header::{HeaderMap, AUTHORIZATION};
+
+// Custom modules
+use crate::dal::{ApiKey, DAL, UsageLimits};
+use crate::meal::transfer::sha256_hex;
+
+// Prefix of the generated keys, followed by the key id and the secret
+const KEY_PREFIX: &str = "mer";
+// Random bytes of the key id and of the secret
+const KEY_ID_BYTES: usize = 6;
+const SECRET_BYTES: usize = 24;
+
+
+// API keys of the network APIs by the hash of the key, loaded from the DB on startup and kept in sync by the key commands
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    // Requests without a key are rejected instead of being served anonymously
+    required: bool,
+    keys: RwLock<HashMap<String, ApiKey>>,
+}
+
+impl KeyStore {
+    pub fn new(required: bool) -> Self {
+        Self { required, keys: RwLock::new(HashMap::new()) }
+    }
+
+    // Replace the keys, e.g. with the ones stored in the DB
+    pub fn load(&self, keys: Vec<ApiKey>) {
+        *self.keys.write().unwrap() = keys.into_iter().map(|key| (key.key_hash.clone(), key)).collect();
+    }
+
+    // List the keys, the oldest first
+    pub fn keys(&self) -> Vec<ApiKey> {
+        let mut keys: Vec<ApiKey> = self.keys.read().unwrap().values().cloned().collect();
+        keys.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.key_id.cmp(&b.key_id)));
+        keys
+    }
+
+    // Identify the caller of a request by the key in its headers, None for anonymous callers when keys are optional
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<ApiKey>, String> {
+        let key = match presented_key(headers) {
+            Some(key) => key,
+            None if self.required => return Err("Unauthorized: An API key is required".to_string()),
+            None => return Ok(None),
+        };
+        match self.keys.read().unwrap().get(&sha256_hex(key.as_bytes())) {
+            Some(api_key) => Ok(Some(api_key.clone())),
+            None => Err("Unauthorized: Invalid API key".to_string()),
+        }
+    }
+
+    // Create a key for the owner, the key itself is only returned here and only its hash is stored
+    // Keys created without models may execute every model
+    pub async fn create(&self, dal: &tokio::sync::Mutex<DAL>, name: &str, mut models: Vec<String>, admin: bool, limits: UsageLimits) -> Result<(String, ApiKey), String> {
+        if name.is_empty() {
+            return Err("Invalid request: The name of the key owner is required".to_string());
+        }
+        if models.is_empty() {
+            models.push("*".to_string());
+        }
+        let key_id = random_hex(KEY_ID_BYTES)?;
+        let key = format!("{}_{}_{}", KEY_PREFIX, key_id, random_hex(SECRET_BYTES)?);
+        let api_key = ApiKey {
+            key_id,
+            name: name.to_string(),
+            key_hash: sha256_hex(key.as_bytes()),
+            models,
+            admin,
+            limits,
+            created_at: chrono::Utc::now(),
+        };
+        dal.lock().await.save_api_key(&api_key).await?;
+        self.keys.write().unwrap().insert(api_key.key_hash.clone(), api_key.clone());
+        log::info!("Created the API key {} of {}", api_key.key_id, api_key.name);
+        Ok((key, api_key))
+    }
+
+    // Delete a key, requests with it are rejected right away
+    pub async fn delete(&self, dal: &tokio::sync::Mutex<DAL>, key_id: &str) -> Result<(), String> {
+        dal.lock().await.delete_api_key(key_id).await?;
+        self.keys.write().unwrap().retain(|_, key| key.key_id != key_id);
+        log::info!("Deleted the API key {}", key_id);
+        Ok(())
+    }
+}
+
+
+// Check that the caller may execute the model, anonymous callers may execute every model
+pub fn authorize_model(caller: Option<&ApiKey>, model: &str) -> Result<(), String> {
+    match caller {
+        Some(key) if !key.models.iter().any(|scope| scope == "*" || scope == model) => {
+            Err(format!("Forbidden: The API key {} may not execute model {}", key.key_id, model))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Check that the caller may change the model entries, anonymous callers only depend on the runtime changes flag
+pub fn authorize_admin(caller: Option<&ApiKey>) -> Result<(), String> {
+    match caller {
+        Some(key) if !key.admin => Err(format!("Forbidden: The API key {} may not change model entries", key.key_id)),
+        _ => Ok(()),
+    }
+}
+
+// Get the key of the Authorization: Bearer or X-API-Key header
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(authorization) = headers.get(AUTHORIZATION).and_then(|value| value.to_str().ok()) {
+        if let Some(key) = authorization.strip_prefix("Bearer ").or_else(|| authorization.strip_prefix("bearer ")) {
+            return Some(key.trim());
+        }
+    }
+    headers.get("x-api-key").and_then(|value| value.to_str().ok()).map(str::trim)
+}
+
+// Hex encoded random bytes of the OS
+fn random_hex(bytes: usize) -> Result<String, String> {
+    let mut data = vec![0u8; bytes];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut data))
+        .map_err(|err| format!("Failed to generate an API key: {}", err))?;
+    Ok(data.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_key_store() {
+        let key = format!("{}_{}_{}", KEY_PREFIX, "0a1b2c", "secret");
+        let api_key = ApiKey {
+            key_id: "0a1b2c".to_string(),
+            name: "team".to_string(),
+            key_hash: sha256_hex(key.as_bytes()),
+            models: vec!["echo".to_string()],
+            admin: false,
+            limits: UsageLimits::default(),
+            created_at: chrono::Utc::now(),
+        };
+        let optional = KeyStore::new(false);
+        optional.load(vec![api_key.clone()]);
+        let required = KeyStore::new(true);
+        required.load(vec![api_key.clone()]);
+
+        // Keys are presented as bearer tokens or in X-API-Key, requests without one are anonymous unless keys are required
+        let mut headers = HeaderMap::new();
+        assert_eq!(optional.authenticate(&headers), Ok(None));
+        assert!(required.authenticate(&headers).unwrap_err().starts_with("Unauthorized"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", key)).unwrap());
+        assert_eq!(required.authenticate(&headers), Ok(Some(api_key.clone())));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_str(&key).unwrap());
+        assert_eq!(optional.authenticate(&headers), Ok(Some(api_key.clone())));
+        headers.insert("x-api-key", HeaderValue::from_static("mer_0a1b2c_other"));
+        assert!(optional.authenticate(&headers).unwrap_err().starts_with("Unauthorized"));
+
+        // The scopes limit the models and the admin operations
+        assert!(authorize_model(Some(&api_key), "echo").is_ok());
+        assert!(authorize_model(Some(&api_key), "other").unwrap_err().starts_with("Forbidden"));
+        assert!(authorize_model(None, "other").is_ok());
+        assert!(authorize_admin(Some(&api_key)).is_err());
+        let all = ApiKey { models: vec!["*".to_string()], admin: true, ..api_key };
+        assert!(authorize_model(Some(&all), "other").is_ok());
+        assert!(authorize_admin(Some(&all)).is_ok());
+        assert_eq!(random_hex(4).unwrap().len(), 8);
+    }
+}
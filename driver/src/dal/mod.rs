@@ -2,6 +2,12 @@
 use std::result::Result;
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::metrics;
 
 //////////////////////////////////////////////////////////////////////////////////////////
 // Define DALArgs struct
@@ -11,9 +17,259 @@ pub struct DALArgs {
     pub password: String,
 }
 
+// Single answered request of a model, stored when the model has transcripts enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptTurn {
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub request_id: String,
+    pub model: String,
+    #[serde(default)]
+    pub weights_version: Option<String>,
+    pub input: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    // Error code and message reported by the model, if any
+    #[serde(default)]
+    pub error: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub responded_at: DateTime<Utc>,
+}
+
+// Filter of the transcript queries, unset fields match every turn
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranscriptQuery {
+    pub model: Option<String>,
+    pub session_id: Option<String>,
+    // Inclusive start and exclusive end of the request time range
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+// Weights produced for a model, e.g. by a feedback training run, appended to its ModelWeights record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelWeightsVersion {
+    pub model_uid: String,
+    pub model: String,
+    pub version: String,
+    // Weights the training started from, None for the default weights
+    #[serde(default)]
+    pub parent_version: Option<String>,
+    #[serde(default)]
+    pub dataset: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // Whether the model serves these weights from now on, the model params are updated accordingly
+    pub serving: bool,
+}
+
+// State of a training or inference job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    // Check if the job ended, one way or another
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Queued => write!(f, "queued"),
+            JobState::Running => write!(f, "running"),
+            JobState::Succeeded => write!(f, "succeeded"),
+            JobState::Failed => write!(f, "failed"),
+            JobState::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+// Run of the train command of a model, stored on every state change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainingJob {
+    pub id: String,
+    pub model_uid: String,
+    pub model: String,
+    // File name of the dataset in the datasets folder of the model path
+    pub dataset: String,
+    #[serde(default)]
+    pub old_weights: Option<String>,
+    pub new_weights: String,
+    // Extra arguments of the train command
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub state: JobState,
+    #[serde(default)]
+    pub error: Option<String>,
+    // Last line the train command printed
+    #[serde(default)]
+    pub progress: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Input of an inference job with the id the caller gave it, its position in the job by default
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceJobInput {
+    pub id: String,
+    pub input: String,
+}
+
+// Answer of the model to an input of an inference job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceJobResult {
+    pub id: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    // Error code and message reported by the model or the driver, if any
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// Inputs run against a model in the background, stored on every state change and while it runs so a
+// restarted driver continues with the inputs that have no result yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceJob {
+    // Stored as jobId, the id of the record
+    #[serde(alias = "jobId")]
+    pub id: String,
+    pub model: String,
+    // Id of the API key that submitted the job, None for anonymous callers
+    #[serde(default)]
+    pub caller: Option<String>,
+    // Params sent to the model with every input
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    pub inputs: Vec<InferenceJobInput>,
+    // Results in the order the model answered them
+    #[serde(default)]
+    pub results: Vec<InferenceJobResult>,
+    // Number of inputs sent to the model at a time
+    pub concurrency: usize,
+    // URL the finished job is posted to and the outcome of the delivery
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default)]
+    pub webhook_status: Option<String>,
+    pub state: JobState,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// API key of the network APIs, only the SHA-256 hash of the secret key is stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    // Public id of the key, recorded as the caller of its requests
+    pub key_id: String,
+    // Owner of the key
+    pub name: String,
+    pub key_hash: String,
+    // Model names the key may execute, "*" for every model
+    #[serde(default)]
+    pub models: Vec<String>,
+    // Whether the key may create, modify and delete model entries
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub limits: UsageLimits,
+    pub created_at: DateTime<Utc>,
+}
+
+// Rate limit and quotas of an API key or a model, unset limits are unlimited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageLimits {
+    // Requests per minute refilled into the token bucket
+    #[serde(default)]
+    pub rate_per_minute: Option<u64>,
+    // Requests the token bucket holds, the rate per minute by default
+    #[serde(default)]
+    pub burst: Option<u64>,
+    #[serde(default)]
+    pub daily_requests: Option<u64>,
+    #[serde(default)]
+    pub monthly_requests: Option<u64>,
+    // Bytes of the inputs and outputs
+    #[serde(default)]
+    pub daily_bytes: Option<u64>,
+    #[serde(default)]
+    pub monthly_bytes: Option<u64>,
+}
+
+// Requests and bytes used by an API key or a model in a day or a month, stored after every request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageCounter {
+    // "key:<key id>" or "model:<model name>"
+    pub subject: String,
+    // UTC day ("2026-10-19") or month ("2026-10")
+    pub period: String,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+// Request of a model, stored in the ModelAccess table with the caller that sent it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelAccess {
+    pub request_id: String,
+    pub model: String,
+    // Id of the API key of the caller, None for the REPL and requests without a key
+    pub caller: Option<String>,
+    pub start_access: DateTime<Utc>,
+    pub stop_access: DateTime<Utc>,
+}
+
+// Model entry written by the admin commands, the values are stored as JSON when they parse as such
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelEntry {
+    pub name: String,
+    pub conn_type: String,
+    pub conn_params: HashMap<String, String>,
+    pub model_params: HashMap<String, String>,
+}
+
+// Fields of the param records maintained by the DAL itself
+const RESERVED_PARAMS: [&str; 3] = ["uid", "createdAt", "lastUpdated"];
+
+// Check that the param names of a model entry are plain identifiers and not maintained by the DAL
+pub fn check_param_names(params: &HashMap<String, String>) -> Result<(), String> {
+    for name in params.keys() {
+        let identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier || RESERVED_PARAMS.contains(&name.as_str()) {
+            return Err(format!("Invalid request: the param name {:#?} can not be set", name));
+        }
+    }
+    Ok(())
+}
+
 // Create the DatabaseDriver trait, should be implemented by all DAL drivers
 #[async_trait]
-pub trait DatabaseDriver {
+pub trait DatabaseDriver: Send {
 
     // Create the DatabaseDriver constructor
     fn new(dal_args: DALArgs) -> Self where Self: Sized;
@@ -24,6 +280,22 @@ pub trait DatabaseDriver {
     
     // DatabaseDriver querry methods
     async fn get_available_models(&mut self) -> Result<Vec<Vec<HashMap<String, String>>>, String>;
+    async fn append_transcript_turn(&mut self, turn: &TranscriptTurn) -> Result<(), String>;
+    async fn get_transcript_turns(&mut self, query: &TranscriptQuery) -> Result<Vec<TranscriptTurn>, String>;
+    async fn register_model_weights(&mut self, weights: &ModelWeightsVersion) -> Result<(), String>;
+    async fn save_training_job(&mut self, job: &TrainingJob) -> Result<(), String>;
+    async fn abort_training_jobs(&mut self, error: &str) -> Result<(), String>;
+    async fn create_model(&mut self, entry: &ModelEntry) -> Result<String, String>;
+    async fn modify_model(&mut self, uid: &str, changes: &ModelEntry) -> Result<(), String>;
+    async fn delete_model(&mut self, uid: &str) -> Result<(), String>;
+    async fn get_api_keys(&mut self) -> Result<Vec<ApiKey>, String>;
+    async fn save_api_key(&mut self, key: &ApiKey) -> Result<(), String>;
+    async fn delete_api_key(&mut self, key_id: &str) -> Result<(), String>;
+    async fn append_model_access(&mut self, access: &ModelAccess) -> Result<(), String>;
+    async fn get_usage(&mut self, periods: &[String]) -> Result<Vec<UsageCounter>, String>;
+    async fn save_usage(&mut self, usage: &UsageCounter) -> Result<(), String>;
+    async fn get_inference_jobs(&mut self, finished_limit: usize) -> Result<Vec<InferenceJob>, String>;
+    async fn save_inference_job(&mut self, job: &InferenceJob) -> Result<(), String>;
 }
 
 // Re-export driver modules
@@ -49,19 +321,127 @@ impl DAL {
     }
 
     pub async fn connect(&mut self) -> Result<(), String> {
-        self.driver.connect().await
+        let result = timed("connect", self.driver.connect()).await;
+        metrics::set("mer_dal_connected", &[], if result.is_ok() { 1.0 } else { 0.0 });
+        result
     }
 
     pub async fn disconnect(&mut self) -> Result<(), String> {
-        self.driver.disconnect().await
+        let result = timed("disconnect", self.driver.disconnect()).await;
+        metrics::set("mer_dal_connected", &[], 0.0);
+        result
     }
 
     pub async fn get_available_models(&mut self) -> Result<Vec<Vec<HashMap<String, String>>>, String> {
-        self.driver.get_available_models().await
+        timed("get_available_models", self.driver.get_available_models()).await
+    }
+
+    pub async fn append_transcript_turn(&mut self, turn: &TranscriptTurn) -> Result<(), String> {
+        timed("append_transcript_turn", self.driver.append_transcript_turn(turn)).await
+    }
+
+    pub async fn get_transcript_turns(&mut self, query: &TranscriptQuery) -> Result<Vec<TranscriptTurn>, String> {
+        timed("get_transcript_turns", self.driver.get_transcript_turns(query)).await
+    }
+
+    pub async fn register_model_weights(&mut self, weights: &ModelWeightsVersion) -> Result<(), String> {
+        timed("register_model_weights", self.driver.register_model_weights(weights)).await
+    }
+
+    pub async fn save_training_job(&mut self, job: &TrainingJob) -> Result<(), String> {
+        timed("save_training_job", self.driver.save_training_job(job)).await
+    }
+
+    // Fail the jobs a previous run of the driver left queued or running
+    pub async fn abort_training_jobs(&mut self, error: &str) -> Result<(), String> {
+        timed("abort_training_jobs", self.driver.abort_training_jobs(error)).await
+    }
+
+    // Create a model entry and return its uid
+    pub async fn create_model(&mut self, entry: &ModelEntry) -> Result<String, String> {
+        check_param_names(&entry.conn_params)?;
+        check_param_names(&entry.model_params)?;
+        timed("create_model", self.driver.create_model(entry)).await
+    }
+
+    // Modify a model entry, an empty name or connection type is kept and params with empty values are removed
+    pub async fn modify_model(&mut self, uid: &str, changes: &ModelEntry) -> Result<(), String> {
+        check_param_names(&changes.conn_params)?;
+        check_param_names(&changes.model_params)?;
+        timed("modify_model", self.driver.modify_model(uid, changes)).await
+    }
+
+    // Delete a model entry, its weights, transcripts and training jobs are kept
+    pub async fn delete_model(&mut self, uid: &str) -> Result<(), String> {
+        timed("delete_model", self.driver.delete_model(uid)).await
+    }
+
+    pub async fn get_api_keys(&mut self) -> Result<Vec<ApiKey>, String> {
+        timed("get_api_keys", self.driver.get_api_keys()).await
+    }
+
+    pub async fn save_api_key(&mut self, key: &ApiKey) -> Result<(), String> {
+        timed("save_api_key", self.driver.save_api_key(key)).await
+    }
+
+    // Delete an API key, the accesses recorded with it are kept
+    pub async fn delete_api_key(&mut self, key_id: &str) -> Result<(), String> {
+        timed("delete_api_key", self.driver.delete_api_key(key_id)).await
+    }
+
+    pub async fn append_model_access(&mut self, access: &ModelAccess) -> Result<(), String> {
+        timed("append_model_access", self.driver.append_model_access(access)).await
+    }
+
+    // Get the usage counters of the periods, e.g. of the current day and month
+    pub async fn get_usage(&mut self, periods: &[String]) -> Result<Vec<UsageCounter>, String> {
+        timed("get_usage", self.driver.get_usage(periods)).await
+    }
+
+    pub async fn save_usage(&mut self, usage: &UsageCounter) -> Result<(), String> {
+        timed("save_usage", self.driver.save_usage(usage)).await
+    }
+
+    // Get the unfinished inference jobs and the most recent finished ones
+    pub async fn get_inference_jobs(&mut self, finished_limit: usize) -> Result<Vec<InferenceJob>, String> {
+        timed("get_inference_jobs", self.driver.get_inference_jobs(finished_limit)).await
+    }
+
+    pub async fn save_inference_job(&mut self, job: &InferenceJob) -> Result<(), String> {
+        timed("save_inference_job", self.driver.save_inference_job(job)).await
+    }
+
+    // Get the static fields, connection params and model params of a single model
+    pub async fn get_model(&mut self, uid: &str) -> Result<Vec<HashMap<String, String>>, String> {
+        self.get_available_models().await?.into_iter()
+            .find(|model| model[0].get("uid").map(|model_uid| model_uid == uid).unwrap_or(false))
+            .ok_or_else(|| format!("Model {} not found", uid))
+    }
+
+    // Export the matching turns as JSON lines, oldest first
+    pub async fn export_transcript(&mut self, query: &TranscriptQuery) -> Result<String, String> {
+        let turns = self.get_transcript_turns(query).await?;
+        let mut export = String::new();
+        for turn in turns {
+            export += &serde_json::to_string(&turn).map_err(|err| err.to_string())?;
+            export += "\n";
+        }
+        Ok(export)
     }
 
     // Add other DAL methods here
 }
+
+// Record the latency and the failure of an operation of the driver
+async fn timed<T>(operation: &'static str, query: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    let started = Instant::now();
+    let result = query.await;
+    metrics::observe("mer_dal_query_duration_seconds", &[("operation", operation)], started.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics::increment("mer_dal_query_errors_total", &[("operation", operation)]);
+    }
+    result
+}
 //////////////////////////////////////////////////////////////////////////////////////////
 
 
@@ -124,7 +504,7 @@ mod tests {
         let mut dal = DAL::create("surreal", dal_args).unwrap();
 
         // Connect to the DAL
-        let _ = dal.connect().await.expect("Failed to connect to the DAL");
+        dal.connect().await.expect("Failed to connect to the DAL");
 
         // Get the available models
         let available_models = dal.get_available_models().await.expect("Failed to get available models");
@@ -142,11 +522,11 @@ mod tests {
         }
 
         // Check if not empty
-        assert!(available_models.len() > 0);
-        assert!(available_models[0].len() > 0);
+        assert!(!available_models.is_empty());
+        assert!(!available_models[0].is_empty());
 
         // Check if static fields are present
-        assert!(available_models[0][0].len() > 0);
+        assert!(!available_models[0][0].is_empty());
         assert!(available_models[0][0].contains_key("uid"));
         assert!(available_models[0][0].contains_key("name"));
         assert!(available_models[0][0].contains_key("connType"));
@@ -154,19 +534,88 @@ mod tests {
         assert!(available_models[0][0].contains_key("lastUpdated"));
 
         // Check if connection_params are present
-        assert!(available_models[0][1].len() > 0);
+        assert!(!available_models[0][1].is_empty());
         assert!(available_models[0][1].contains_key("uid"));
         assert!(available_models[0][1].contains_key("createdAt"));
         assert!(available_models[0][1].contains_key("lastUpdated"));
 
         // Check if model_params are present
-        assert!(available_models[0][2].len() > 0);
+        assert!(!available_models[0][2].is_empty());
         assert!(available_models[0][2].contains_key("uid"));
         assert!(available_models[0][2].contains_key("createdAt"));
         assert!(available_models[0][2].contains_key("lastUpdated"));
 
         // Disconnect from the DAL
-        let _ = dal.disconnect().await.expect("Failed to disconnect from the DAL");
+        dal.disconnect().await.expect("Failed to disconnect from the DAL");
+    }
+
+    // Test the transcript query building and the export format
+    #[test]
+    fn test_transcript_query() {
+        // Only the set fields are filtered on
+        let query = TranscriptQuery::default();
+        assert_eq!(surreal::transcript_query(&query), "SELECT * FROM Transcripts ORDER BY requestedAt ASC;");
+        let query = TranscriptQuery {
+            session_id: Some("chat-1".to_string()),
+            from: Some(Utc::now()),
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            surreal::transcript_query(&query),
+            "SELECT * FROM Transcripts WHERE sessionId = $sessionId AND requestedAt >= <datetime> $from ORDER BY requestedAt ASC LIMIT $limit;"
+        );
+
+        // Turns are exported with the column names of the table
+        let turn: TranscriptTurn = serde_json::from_str(
+            r#"{"id":"Transcripts:abc","uid":"abc","requestId":"1","model":"DialoGPT-small","input":"Hello","output":"Hi","requestedAt":"2024-01-01T10:00:00Z","respondedAt":"2024-01-01T10:00:01.5Z"}"#
+        ).unwrap();
+        assert_eq!(turn.session_id, None);
+        assert_eq!((turn.responded_at - turn.requested_at).num_milliseconds(), 1500);
+        let exported: serde_json::Value = serde_json::to_value(&turn).unwrap();
+        assert_eq!(exported["requestId"], "1");
+        assert_eq!(exported["requestedAt"], "2024-01-01T10:00:00Z");
+    }
+
+    // Test the DAL transcript append and query
+    #[tokio::test]
+    async fn test_dal_transcripts() {
+        // Create the DALArgs instance
+        let dal_args = DALArgs {
+            connection_url: "localhost:4321".to_string(),
+            username: "driver".to_string(),
+            password: "M0d3lDr1v3r".to_string(),
+        };
+
+        // Create the DAL instance and connect to it
+        let mut dal = DAL::create("surreal", dal_args).unwrap();
+        dal.connect().await.expect("Failed to connect to the DAL");
+
+        // Append a turn to a new session
+        let session_id = format!("test-session-{}", Utc::now().timestamp_micros());
+        let turn = TranscriptTurn {
+            session_id: Some(session_id.clone()),
+            request_id: "1".to_string(),
+            model: "DialoGPT-small".to_string(),
+            weights_version: Some("pretrained".to_string()),
+            input: "Hello, how are you?".to_string(),
+            output: Some("I am fine.".to_string()),
+            error: None,
+            requested_at: Utc::now(),
+            responded_at: Utc::now(),
+        };
+        dal.append_transcript_turn(&turn).await.expect("Failed to append the transcript turn");
+
+        // Query the session
+        let query = TranscriptQuery { session_id: Some(session_id), ..Default::default() };
+        let turns = dal.get_transcript_turns(&query).await.expect("Failed to get the transcript turns");
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].input, turn.input);
+        assert_eq!(turns[0].output, turn.output);
+        assert_eq!(dal.export_transcript(&query).await.unwrap().lines().count(), 1);
+
+        // Disconnect from the DAL
+        dal.disconnect().await.expect("Failed to disconnect from the DAL");
     }
 }
 //////////////////////////////////////////////////////////////////////////////////////////
\ No newline at end of file
@@ -1,5 +1,5 @@
 // /src/dal/surreal.rs
-use super::{DatabaseDriver, DALArgs};
+use super::{ApiKey, DatabaseDriver, DALArgs, InferenceJob, ModelAccess, ModelEntry, ModelWeightsVersion, TrainingJob, TranscriptQuery, TranscriptTurn, UsageCounter};
 use async_trait::async_trait;
 use std::collections::HashMap;
 
@@ -125,13 +125,13 @@ impl DatabaseDriver for SurrealDriver {
             // Parse the available model static fields into a HashMap
             let result_json = result.unwrap().into_json();
             let mut static_fields = HashMap::new();
-            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.get(0)) {
-                // Convert JsonValues to Strings or other appropriate types if needed
+            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.first()) {
+                // Convert JsonValues to Strings, non-string values are kept as their JSON text
                 static_fields = first_element
                     .as_object()
                     .expect("Expected object in the array")
                     .iter()
-                    .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+                    .map(|(key, value)| (key.clone(), json_to_string(value)))
                     .collect();
                 log::debug!("    - Static fields processed succesfully");
             }
@@ -154,13 +154,13 @@ impl DatabaseDriver for SurrealDriver {
             // Parse the available model connection params into a HashMap
             let result_json = result.unwrap().into_json();
             let mut connection_params = HashMap::new();
-            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.get(0)) {
-                // Convert JsonValues to Strings or other appropriate types if needed
+            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.first()) {
+                // Convert JsonValues to Strings, non-string values are kept as their JSON text
                 connection_params = first_element
                     .as_object()
                     .expect("Expected object in the array")
                     .iter()
-                    .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+                    .map(|(key, value)| (key.clone(), json_to_string(value)))
                     .collect();
                 log::debug!("    - Connection params processed succesfully");
             }
@@ -181,13 +181,13 @@ impl DatabaseDriver for SurrealDriver {
             // Parse the available model model params into a HashMap
             let result_json = result.unwrap().into_json();
             let mut model_params = HashMap::new();
-            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.get(0)) {
-                // Convert JsonValues to Strings or other appropriate types if needed
+            if let Some(first_element) = result_json.as_array().and_then(|arr| arr.first()) {
+                // Convert JsonValues to Strings, non-string values are kept as their JSON text
                 model_params = first_element
                     .as_object()
                     .expect("Expected object in the array")
                     .iter()
-                    .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+                    .map(|(key, value)| (key.clone(), json_to_string(value)))
                     .collect();
                 log::debug!("    - Model params for uid processed succesfully");
             }
@@ -204,6 +204,640 @@ impl DatabaseDriver for SurrealDriver {
         Ok(available_models)
     }
 
+    async fn append_transcript_turn(&mut self, turn: &TranscriptTurn) -> Result<(), String> {
+        log::debug!("Appending the transcript turn {:#?} of model {:#?} to the DB...", turn.request_id, turn.model);
+
+        // The timestamps are bound as RFC 3339 strings and cast to datetimes by the query
+        let response = self.db_conn
+            .query(
+                "CREATE Transcripts CONTENT {
+                    uid: <string> rand::uuid::v4(),
+                    sessionId: $sessionId,
+                    requestId: $requestId,
+                    model: $model,
+                    weightsVersion: $weightsVersion,
+                    input: $input,
+                    output: $output,
+                    error: $error,
+                    requestedAt: <datetime> $requestedAt,
+                    respondedAt: <datetime> $respondedAt,
+                } RETURN NONE;"
+            )
+            .bind(("sessionId", turn.session_id.clone()))
+            .bind(("requestId", turn.request_id.clone()))
+            .bind(("model", turn.model.clone()))
+            .bind(("weightsVersion", turn.weights_version.clone()))
+            .bind(("input", turn.input.clone()))
+            .bind(("output", turn.output.clone()))
+            .bind(("error", turn.error.clone()))
+            .bind(("requestedAt", turn.requested_at.to_rfc3339()))
+            .bind(("respondedAt", turn.responded_at.to_rfc3339()))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to append the transcript turn to the DB: {}", err);
+            return Err("Failed to append the transcript turn to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn get_transcript_turns(&mut self, query: &TranscriptQuery) -> Result<Vec<TranscriptTurn>, String> {
+        log::debug!("Getting the transcript turns matching {:#?} from the DB...", query);
+
+        let response = self.db_conn
+            .query(transcript_query(query))
+            .bind(("model", query.model.clone()))
+            .bind(("sessionId", query.session_id.clone()))
+            .bind(("from", query.from.map(|from| from.to_rfc3339())))
+            .bind(("to", query.to.map(|to| to.to_rfc3339())))
+            .bind(("limit", query.limit))
+            .await;
+
+        let result: Result<Value, _> = match response {
+            Ok(mut response) => response.take(0),
+            Err(err) => Err(err),
+        };
+        let result_json = match result {
+            Ok(result) => result.into_json(),
+            Err(err) => {
+                log::error!("Failed to get the transcript turns from the DB: {}", err);
+                return Err("Failed to get the transcript turns from the DB: ".to_string() + &err.to_string());
+            }
+        };
+        serde_json::from_value(result_json).map_err(|err| {
+            log::error!("Failed to parse the transcript turns from the DB: {}", err);
+            "Failed to parse the transcript turns from the DB: ".to_string() + &err.to_string()
+        })
+    }
+
+    async fn register_model_weights(&mut self, weights: &ModelWeightsVersion) -> Result<(), String> {
+        log::debug!("Registering the weights {:#?} of model {:#?} in the DB...", weights.version, weights.model);
+
+        // The record of a model is created with its first weights, the served version is kept in the model params
+        let response = self.db_conn
+            .query(
+                "UPDATE type::thing(\"ModelWeights\", $uid) SET
+                    uid = $uid,
+                    name = $model,
+                    weights = array::append(weights OR [], {
+                        version: $version,
+                        parentVersion: $parentVersion,
+                        dataset: $dataset,
+                        createdAt: <datetime> $createdAt,
+                    }),
+                    lastUpdated = time::now()
+                RETURN NONE;
+                IF $serving THEN
+                    (UPDATE ModelParams SET weightsVersion = $version, lastUpdated = time::now() WHERE uid = $uid RETURN NONE)
+                END;"
+            )
+            .bind(("uid", weights.model_uid.clone()))
+            .bind(("model", weights.model.clone()))
+            .bind(("version", weights.version.clone()))
+            .bind(("parentVersion", weights.parent_version.clone()))
+            .bind(("dataset", weights.dataset.clone()))
+            .bind(("createdAt", weights.created_at.to_rfc3339()))
+            .bind(("serving", weights.serving))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to register the model weights in the DB: {}", err);
+            return Err("Failed to register the model weights in the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn save_training_job(&mut self, job: &TrainingJob) -> Result<(), String> {
+        log::debug!("Saving the training job {:#?} of model {:#?} in state {} to the DB...", job.id, job.model, job.state);
+
+        // The record is created by the first save of the job and replaced by the following ones
+        let response = self.db_conn
+            .query(
+                "UPDATE type::thing(\"TrainingJobs\", $jobId) CONTENT {
+                    jobId: $jobId,
+                    modelUid: $modelUid,
+                    model: $model,
+                    dataset: $dataset,
+                    oldWeights: $oldWeights,
+                    newWeights: $newWeights,
+                    args: $args,
+                    state: $state,
+                    error: $error,
+                    progress: $progress,
+                    createdAt: <datetime> $createdAt,
+                    startedAt: IF $startedAt THEN <datetime> $startedAt END,
+                    finishedAt: IF $finishedAt THEN <datetime> $finishedAt END,
+                } RETURN NONE;"
+            )
+            .bind(("jobId", job.id.clone()))
+            .bind(("modelUid", job.model_uid.clone()))
+            .bind(("model", job.model.clone()))
+            .bind(("dataset", job.dataset.clone()))
+            .bind(("oldWeights", job.old_weights.clone()))
+            .bind(("newWeights", job.new_weights.clone()))
+            .bind(("args", job.args.clone()))
+            .bind(("state", job.state.to_string()))
+            .bind(("error", job.error.clone()))
+            .bind(("progress", job.progress.clone()))
+            .bind(("createdAt", job.created_at.to_rfc3339()))
+            .bind(("startedAt", job.started_at.map(|started_at| started_at.to_rfc3339())))
+            .bind(("finishedAt", job.finished_at.map(|finished_at| finished_at.to_rfc3339())))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to save the training job to the DB: {}", err);
+            return Err("Failed to save the training job to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn abort_training_jobs(&mut self, error: &str) -> Result<(), String> {
+        log::debug!("Failing the unfinished training jobs in the DB...");
+
+        let response = self.db_conn
+            .query(
+                "UPDATE TrainingJobs SET state = \"failed\", error = $error, finishedAt = time::now()
+                WHERE state = \"queued\" OR state = \"running\" RETURN NONE;"
+            )
+            .bind(("error", error.to_string()))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to abort the unfinished training jobs in the DB: {}", err);
+            return Err("Failed to abort the unfinished training jobs in the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn create_model(&mut self, entry: &ModelEntry) -> Result<String, String> {
+        log::info!("Creating the model entry {:#?} in the DB...", entry.name);
+
+        // The uid is generated first so the records of the entry can be created in one transaction
+        let uid: Result<Option<String>, _> = match self.db_conn.query("RETURN <string> rand::uuid::v4();").await {
+            Ok(mut response) => response.take(0),
+            Err(err) => Err(err),
+        };
+        let uid = match uid {
+            Ok(Some(uid)) => uid,
+            Ok(None) => return Err("Failed to generate the model uid in the DB".to_string()),
+            Err(err) => {
+                log::error!("Failed to generate the model uid in the DB: {}", err);
+                return Err("Failed to generate the model uid in the DB: ".to_string() + &err.to_string());
+            }
+        };
+
+        let response = self.db_conn
+            .query(
+                "BEGIN TRANSACTION;
+                CREATE type::thing(\"ConnTypeParams\", $uid) SET uid = $uid, createdAt = time::now(), lastUpdated = time::now() RETURN NONE;
+                UPDATE type::thing(\"ConnTypeParams\", $uid) MERGE $connParams RETURN NONE;
+                CREATE type::thing(\"ModelParams\", $uid) SET uid = $uid, createdAt = time::now(), lastUpdated = time::now() RETURN NONE;
+                UPDATE type::thing(\"ModelParams\", $uid) MERGE $modelParams RETURN NONE;
+                CREATE type::thing(\"AvailableModels\", $uid) CONTENT {
+                    uid: $uid,
+                    createdAt: time::now(),
+                    lastUpdated: time::now(),
+                    name: $name,
+                    connType: $connType,
+                    connTypeParams: [type::thing(\"ConnTypeParams\", $uid)],
+                    modelParams: [type::thing(\"ModelParams\", $uid)],
+                } RETURN NONE;
+                COMMIT TRANSACTION;"
+            )
+            .bind(("uid", uid.clone()))
+            .bind(("name", entry.name.clone()))
+            .bind(("connType", entry.conn_type.clone()))
+            .bind(("connParams", params_to_json(&entry.conn_params)))
+            .bind(("modelParams", params_to_json(&entry.model_params)))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to create the model entry in the DB: {}", err);
+            return Err("Failed to create the model entry in the DB: ".to_string() + &err.to_string());
+        }
+        Ok(uid)
+    }
+
+    async fn modify_model(&mut self, uid: &str, changes: &ModelEntry) -> Result<(), String> {
+        log::info!("Modifying the model entry {:#?} in the DB...", uid);
+        self.check_model_exists(uid).await?;
+
+        // Params with empty values are removed, their names were checked to be identifiers by the DAL
+        let (conn_set, conn_unset) = split_params(&changes.conn_params);
+        let (model_set, model_unset) = split_params(&changes.model_params);
+        let response = self.db_conn
+            .query(format!(
+                "BEGIN TRANSACTION;
+                UPDATE AvailableModels SET name = $name OR name, connType = $connType OR connType, lastUpdated = time::now() WHERE uid = $uid RETURN NONE;
+                UPDATE ConnTypeParams MERGE $connParams WHERE uid = $uid RETURN NONE;
+                UPDATE ConnTypeParams SET {} WHERE uid = $uid RETURN NONE;
+                UPDATE ModelParams MERGE $modelParams WHERE uid = $uid RETURN NONE;
+                UPDATE ModelParams SET {} WHERE uid = $uid RETURN NONE;
+                COMMIT TRANSACTION;",
+                unset_fields(&conn_unset), unset_fields(&model_unset),
+            ))
+            .bind(("uid", uid.to_string()))
+            .bind(("name", changes.name.clone()))
+            .bind(("connType", changes.conn_type.clone()))
+            .bind(("connParams", conn_set))
+            .bind(("modelParams", model_set))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to modify the model entry in the DB: {}", err);
+            return Err("Failed to modify the model entry in the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn delete_model(&mut self, uid: &str) -> Result<(), String> {
+        log::info!("Deleting the model entry {:#?} from the DB...", uid);
+        self.check_model_exists(uid).await?;
+
+        let response = self.db_conn
+            .query(
+                "BEGIN TRANSACTION;
+                DELETE AvailableModels WHERE uid = $uid RETURN NONE;
+                DELETE ConnTypeParams WHERE uid = $uid RETURN NONE;
+                DELETE ModelParams WHERE uid = $uid RETURN NONE;
+                COMMIT TRANSACTION;"
+            )
+            .bind(("uid", uid.to_string()))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to delete the model entry from the DB: {}", err);
+            return Err("Failed to delete the model entry from the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn get_api_keys(&mut self) -> Result<Vec<ApiKey>, String> {
+        log::debug!("Getting the API keys from the DB...");
+
+        let response = self.db_conn
+            .query("SELECT keyId, name, keyHash, models, admin, limits, createdAt FROM ApiKeys ORDER BY createdAt")
+            .await;
+
+        let result: Result<Value, _> = match response {
+            Ok(mut response) => response.take(0),
+            Err(err) => Err(err),
+        };
+        let result_json = match result {
+            Ok(result) => result.into_json(),
+            Err(err) => {
+                log::error!("Failed to get the API keys from the DB: {}", err);
+                return Err("Failed to get the API keys from the DB: ".to_string() + &err.to_string());
+            }
+        };
+        serde_json::from_value(result_json).map_err(|err| {
+            log::error!("Failed to parse the API keys from the DB: {}", err);
+            "Failed to parse the API keys from the DB: ".to_string() + &err.to_string()
+        })
+    }
+
+    async fn save_api_key(&mut self, key: &ApiKey) -> Result<(), String> {
+        log::info!("Saving the API key {:#?} of {:#?} to the DB...", key.key_id, key.name);
+
+        // The record is keyed by the key id so saving a key again replaces its scopes
+        let response = self.db_conn
+            .query(
+                "UPDATE type::thing(\"ApiKeys\", $keyId) CONTENT {
+                    keyId: $keyId,
+                    name: $name,
+                    keyHash: $keyHash,
+                    models: $models,
+                    admin: $admin,
+                    limits: $limits,
+                    createdAt: <datetime> $createdAt,
+                } RETURN NONE;"
+            )
+            .bind(("keyId", key.key_id.clone()))
+            .bind(("name", key.name.clone()))
+            .bind(("keyHash", key.key_hash.clone()))
+            .bind(("models", key.models.clone()))
+            .bind(("admin", key.admin))
+            .bind(("limits", key.limits.clone()))
+            .bind(("createdAt", key.created_at.to_rfc3339()))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to save the API key to the DB: {}", err);
+            return Err("Failed to save the API key to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn delete_api_key(&mut self, key_id: &str) -> Result<(), String> {
+        log::info!("Deleting the API key {:#?} from the DB...", key_id);
+
+        let response = self.db_conn
+            .query("DELETE ApiKeys WHERE keyId = $keyId RETURN BEFORE")
+            .bind(("keyId", key_id.to_string()))
+            .await;
+
+        let result: Result<Value, _> = match response {
+            Ok(mut response) => response.take(0),
+            Err(err) => Err(err),
+        };
+        match result.map(|deleted| deleted.into_json()) {
+            Ok(serde_json::Value::Array(deleted)) if deleted.is_empty() => Err(format!("API key {} not found", key_id)),
+            Ok(_) => Ok(()),
+            Err(err) => {
+                log::error!("Failed to delete the API key from the DB: {}", err);
+                Err("Failed to delete the API key from the DB: ".to_string() + &err.to_string())
+            }
+        }
+    }
+
+    async fn append_model_access(&mut self, access: &ModelAccess) -> Result<(), String> {
+        log::debug!("Appending the access {:#?} of model {:#?} to the DB...", access.request_id, access.model);
+
+        let response = self.db_conn
+            .query(
+                "CREATE ModelAccess CONTENT {
+                    uid: <string> rand::uuid::v4(),
+                    requestUid: $requestUid,
+                    model: $model,
+                    caller: $caller,
+                    startAccess: <datetime> $startAccess,
+                    stopAccess: <datetime> $stopAccess,
+                } RETURN NONE;"
+            )
+            .bind(("requestUid", access.request_id.clone()))
+            .bind(("model", access.model.clone()))
+            .bind(("caller", access.caller.clone()))
+            .bind(("startAccess", access.start_access.to_rfc3339()))
+            .bind(("stopAccess", access.stop_access.to_rfc3339()))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to append the model access to the DB: {}", err);
+            return Err("Failed to append the model access to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn get_usage(&mut self, periods: &[String]) -> Result<Vec<UsageCounter>, String> {
+        log::debug!("Getting the usage of the periods {:#?} from the DB...", periods);
+
+        let response = self.db_conn
+            .query("SELECT subject, period, requests, bytes FROM Usage WHERE period INSIDE $periods")
+            .bind(("periods", periods.to_vec()))
+            .await;
+
+        let result: Result<Value, _> = match response {
+            Ok(mut response) => response.take(0),
+            Err(err) => Err(err),
+        };
+        let result_json = match result {
+            Ok(result) => result.into_json(),
+            Err(err) => {
+                log::error!("Failed to get the usage from the DB: {}", err);
+                return Err("Failed to get the usage from the DB: ".to_string() + &err.to_string());
+            }
+        };
+        serde_json::from_value(result_json).map_err(|err| {
+            log::error!("Failed to parse the usage from the DB: {}", err);
+            "Failed to parse the usage from the DB: ".to_string() + &err.to_string()
+        })
+    }
+
+    async fn save_usage(&mut self, usage: &UsageCounter) -> Result<(), String> {
+        log::debug!("Saving the usage of {:#?} in {:#?} to the DB...", usage.subject, usage.period);
+
+        // The record is keyed by the subject and the period so every save replaces the counters
+        let response = self.db_conn
+            .query(
+                "UPDATE type::thing(\"Usage\", [$subject, $period]) CONTENT {
+                    subject: $subject,
+                    period: $period,
+                    requests: $requests,
+                    bytes: $bytes,
+                    updatedAt: time::now(),
+                } RETURN NONE;"
+            )
+            .bind(("subject", usage.subject.clone()))
+            .bind(("period", usage.period.clone()))
+            .bind(("requests", usage.requests))
+            .bind(("bytes", usage.bytes))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to save the usage to the DB: {}", err);
+            return Err("Failed to save the usage to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+    async fn get_inference_jobs(&mut self, finished_limit: usize) -> Result<Vec<InferenceJob>, String> {
+        log::debug!("Getting the unfinished and the last {} finished inference jobs from the DB...", finished_limit);
+
+        let response = self.db_conn
+            .query(
+                "SELECT * OMIT id FROM InferenceJobs WHERE state INSIDE [\"queued\", \"running\"] ORDER BY createdAt;
+                SELECT * OMIT id FROM InferenceJobs WHERE state NOTINSIDE [\"queued\", \"running\"] ORDER BY createdAt DESC LIMIT $limit;"
+            )
+            .bind(("limit", finished_limit))
+            .await;
+
+        let result: Result<(Value, Value), _> = match response {
+            Ok(mut response) => match response.take(0) {
+                Ok(unfinished) => response.take(1).map(|finished| (unfinished, finished)),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        let (unfinished, finished) = match result {
+            Ok((unfinished, finished)) => (unfinished.into_json(), finished.into_json()),
+            Err(err) => {
+                log::error!("Failed to get the inference jobs from the DB: {}", err);
+                return Err("Failed to get the inference jobs from the DB: ".to_string() + &err.to_string());
+            }
+        };
+        let parse = |jobs: serde_json::Value| serde_json::from_value::<Vec<InferenceJob>>(jobs).map_err(|err| {
+            log::error!("Failed to parse the inference jobs from the DB: {}", err);
+            "Failed to parse the inference jobs from the DB: ".to_string() + &err.to_string()
+        });
+        let mut jobs = parse(unfinished)?;
+        jobs.extend(parse(finished)?);
+        Ok(jobs)
+    }
+
+    async fn save_inference_job(&mut self, job: &InferenceJob) -> Result<(), String> {
+        log::debug!("Saving the inference job {:#?} of model {:#?} in state {} to the DB...", job.id, job.model, job.state);
+
+        // The record is created by the first save of the job and replaced by the following ones
+        let response = self.db_conn
+            .query(
+                "UPDATE type::thing(\"InferenceJobs\", $jobId) CONTENT {
+                    jobId: $jobId,
+                    model: $model,
+                    caller: $caller,
+                    params: $params,
+                    inputs: $inputs,
+                    results: $results,
+                    concurrency: $concurrency,
+                    webhook: $webhook,
+                    webhookStatus: $webhookStatus,
+                    state: $state,
+                    error: $error,
+                    createdAt: <datetime> $createdAt,
+                    startedAt: IF $startedAt THEN <datetime> $startedAt END,
+                    finishedAt: IF $finishedAt THEN <datetime> $finishedAt END,
+                } RETURN NONE;"
+            )
+            .bind(("jobId", job.id.clone()))
+            .bind(("model", job.model.clone()))
+            .bind(("caller", job.caller.clone()))
+            .bind(("params", job.params.clone()))
+            .bind(("inputs", job.inputs.clone()))
+            .bind(("results", job.results.clone()))
+            .bind(("concurrency", job.concurrency))
+            .bind(("webhook", job.webhook.clone()))
+            .bind(("webhookStatus", job.webhook_status.clone()))
+            .bind(("state", job.state.to_string()))
+            .bind(("error", job.error.clone()))
+            .bind(("createdAt", job.created_at.to_rfc3339()))
+            .bind(("startedAt", job.started_at.map(|started_at| started_at.to_rfc3339())))
+            .bind(("finishedAt", job.finished_at.map(|finished_at| finished_at.to_rfc3339())))
+            .await;
+
+        let result = match response {
+            Ok(response) => response.check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Failed to save the inference job to the DB: {}", err);
+            return Err("Failed to save the inference job to the DB: ".to_string() + &err.to_string());
+        }
+        Ok(())
+    }
+
+}
+
+impl SurrealDriver {
+    // Check that an available model with the uid exists
+    async fn check_model_exists(&self, uid: &str) -> Result<(), String> {
+        let response = self.db_conn
+            .query("SELECT uid FROM AvailableModels WHERE uid = $uid")
+            .bind(("uid", uid.to_string()))
+            .await;
+        let result: Result<Vec<String>, _> = match response {
+            Ok(mut response) => response.take("uid"),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(uids) if uids.is_empty() => Err(format!("Model {} not found", uid)),
+            Ok(_) => Ok(()),
+            Err(err) => {
+                log::error!("Failed to get the model entry from the DB: {}", err);
+                Err("Failed to get the model entry from the DB: ".to_string() + &err.to_string())
+            }
+        }
+    }
+}
+
+// Build the transcript select for the set fields of the query, the values are bound as parameters
+pub fn transcript_query(query: &TranscriptQuery) -> String {
+    let mut conditions = Vec::new();
+    if query.model.is_some() {
+        conditions.push("model = $model");
+    }
+    if query.session_id.is_some() {
+        conditions.push("sessionId = $sessionId");
+    }
+    if query.from.is_some() {
+        conditions.push("requestedAt >= <datetime> $from");
+    }
+    if query.to.is_some() {
+        conditions.push("requestedAt < <datetime> $to");
+    }
+
+    let mut select = "SELECT * FROM Transcripts".to_string();
+    if !conditions.is_empty() {
+        select += &(" WHERE ".to_string() + &conditions.join(" AND "));
+    }
+    select += " ORDER BY requestedAt ASC";
+    if query.limit.is_some() {
+        select += " LIMIT $limit";
+    }
+    select + ";"
+}
+
+// Convert a JSON value into the string stored in the model config HashMaps
+fn json_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(string) => string.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
 
+// Convert the strings of a model config HashMap into JSON values, the inverse of json_to_string
+fn string_to_json(value: &str) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(value) if !value.is_string() && !value.is_null() => value,
+        _ => serde_json::Value::String(value.to_string()),
+    }
+}
 
-}
\ No newline at end of file
+// Convert model config params into a JSON object
+fn params_to_json(params: &HashMap<String, String>) -> serde_json::Value {
+    serde_json::Value::Object(params.iter().map(|(name, value)| (name.clone(), string_to_json(value))).collect())
+}
+
+// Split modified params into the JSON object of the set ones and the names of the removed ones
+fn split_params(params: &HashMap<String, String>) -> (serde_json::Value, Vec<String>) {
+    let set: HashMap<String, String> = params.iter().filter(|(_, value)| !value.is_empty()).map(|(name, value)| (name.clone(), value.clone())).collect();
+    let mut unset: Vec<String> = params.iter().filter(|(_, value)| value.is_empty()).map(|(name, _)| name.clone()).collect();
+    unset.sort();
+    (params_to_json(&set), unset)
+}
+
+// Build the SET clause removing the fields, the modification time is always updated
+fn unset_fields(names: &[String]) -> String {
+    let mut fields = vec!["lastUpdated = time::now()".to_string()];
+    fields.extend(names.iter().map(|name| format!("{} = NONE", name)));
+    fields.join(", ")
+}
@@ -0,0 +1,576 @@
+// src/grpc/mod.rs
+// Std lib imports
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+
+// gRPC over the HTTP/2 server of hyper
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+// Custom modules
+use crate::auth::{self, KeyStore};
+use crate::dal::{self, ApiKey, DAL};
+use crate::http::ApiError;
+use crate::meal::pool::{InstanceInfo, ModelPool};
+use crate::meal::protocol::MEALRequest;
+use crate::meal::quota;
+
+// gRPC submodules
+mod proto;
+use proto::Message;
+
+// Full name of the service in proto/mer.proto
+const SERVICE: &str = "mer.v1.ModelExecutor";
+// Largest accepted request message, the default of the gRPC implementations
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+
+// Status codes of gRPC used by the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    Unknown = 2,
+    InvalidArgument = 3,
+    NotFound = 5,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+// Status sent in the trailers, the code of the HTTP API errors is kept in the mer-error-code trailer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub code: Code,
+    pub message: String,
+    pub error_code: Option<String>,
+}
+
+impl Status {
+    pub fn new(code: Code, message: &str) -> Self {
+        Self { code, message: message.to_string(), error_code: None }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(Code::Ok, "")
+    }
+
+    pub fn invalid_argument(message: &str) -> Self {
+        Self::new(Code::InvalidArgument, message)
+    }
+
+    // Build the grpc-status, grpc-message, mer-error-code and retry-after headers
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from(self.code as u16));
+        if !self.message.is_empty() {
+            if let Ok(message) = HeaderValue::from_str(&percent_encode(&self.message)) {
+                headers.insert("grpc-message", message);
+            }
+        }
+        if let Some(Ok(error_code)) = self.error_code.as_deref().map(HeaderValue::from_str) {
+            headers.insert("mer-error-code", error_code);
+        }
+        if let Some(seconds) = quota::retry_after(&self.message) {
+            headers.insert("retry-after", HeaderValue::from(seconds));
+        }
+        headers
+    }
+}
+
+// Errors are shared with the HTTP API, errors reported by the model are UNKNOWN to gRPC
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Self {
+        let code = match err.status {
+            StatusCode::BAD_REQUEST => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::UNPROCESSABLE_ENTITY => Code::Unknown,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Self { code, message: err.message, error_code: Some(err.code) }
+    }
+}
+
+
+// Serves the models of the pool over gRPC, the admin RPCs change the model entries in the DB and the pool
+pub struct GrpcApi {
+    model_pool: Arc<ModelPool>,
+    dal: Arc<tokio::sync::Mutex<DAL>>,
+    keys: Arc<KeyStore>,
+    allow_model_server_runtime_changes: bool,
+}
+
+impl GrpcApi {
+    pub fn new(model_pool: Arc<ModelPool>, dal: Arc<tokio::sync::Mutex<DAL>>, keys: Arc<KeyStore>, allow_model_server_runtime_changes: bool) -> Self {
+        Self { model_pool, dal, keys, allow_model_server_runtime_changes }
+    }
+
+    // Serve the API on the listener until the shutdown future completes, the open calls are finished first
+    pub async fn serve(self: Arc<Self>, listener: std::net::TcpListener, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+        listener.set_nonblocking(true).map_err(|err| "Failed to configure the gRPC listener: ".to_string() + &err.to_string())?;
+        let server = Server::from_tcp(listener).map_err(|err| {
+            log::error!("Failed to create the gRPC server: {}", err);
+            "Failed to create the gRPC server: ".to_string() + &err.to_string()
+        })?.http2_only(true);
+        log::info!("Serving the gRPC API on {}", server.local_addr());
+
+        let make_service = make_service_fn(move |_connection| {
+            let api = Arc::clone(&self);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let api = Arc::clone(&api);
+                    async move { Ok::<_, Infallible>(api.handle(request).await) }
+                }))
+            }
+        });
+        server.serve(make_service).with_graceful_shutdown(shutdown).await.map_err(|err| {
+            log::error!("The gRPC server failed: {}", err);
+            "The gRPC server failed: ".to_string() + &err.to_string()
+        })
+    }
+
+    // Answer a call, errors are sent as a response with only the status headers
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let path = request.uri().path().to_string();
+        let is_grpc = request.headers().get("content-type")
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| content_type.starts_with("application/grpc"))
+            .unwrap_or(false);
+        if !is_grpc {
+            return Response::builder().status(StatusCode::UNSUPPORTED_MEDIA_TYPE).body(Body::empty()).unwrap();
+        }
+
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(status) => {
+                log::debug!("gRPC {} -> {:?}: {}", path, status.code, status.message);
+                let mut response = grpc_response(Body::empty());
+                response.headers_mut().extend(status.headers());
+                response
+            }
+        }
+    }
+
+    // Dispatch a call to the handler of its method
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let path = request.uri().path().to_string();
+        let method = match (request.method(), path.strip_prefix('/').and_then(|path| path.split_once('/'))) {
+            (&Method::POST, Some((SERVICE, method))) => method.to_string(),
+            _ => return Err(Status::new(Code::Unimplemented, &format!("No method {}", path))),
+        };
+        // The key is sent in the authorization metadata like a bearer token
+        let caller = self.keys.authenticate(request.headers()).map_err(ApiError::from_pool)?;
+        let message = read_message(request.into_body()).await?;
+        log::debug!("gRPC {}", path);
+
+        match method.as_str() {
+            "ListModels" => {
+                decode::<proto::ListModelsRequest>(&message)?;
+                Ok(unary_response(&self.list_models(caller.as_ref())))
+            }
+            "GetModel" => Ok(unary_response(&self.get_model(decode(&message)?, caller.as_ref()).await?)),
+            "Infer" => Ok(unary_response(&self.infer(decode(&message)?, caller).await?)),
+            "InferStream" => self.infer_stream(decode(&message)?, caller),
+            "CreateModel" => Ok(unary_response(&self.create_model(decode(&message)?, caller.as_ref()).await?)),
+            "ModifyModel" => Ok(unary_response(&self.modify_model(decode(&message)?, caller.as_ref()).await?)),
+            "DeleteModel" => Ok(unary_response(&self.delete_model(decode(&message)?, caller.as_ref()).await?)),
+            _ => Err(Status::new(Code::Unimplemented, &format!("No method {}", path))),
+        }
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////////////// Models ////////////////////////
+    //////////////////////////////////////////////////////
+
+    // List the models the caller may execute, instances that are locked for a (re)start are only reported as busy
+    fn list_models(&self, caller: Option<&ApiKey>) -> proto::ListModelsResponse {
+        let models = self.model_pool.list_models().into_iter()
+            .filter(|model| auth::authorize_model(caller, &model.name).is_ok())
+            .map(|model| proto::Model { name: model.name, instances: model.instances.into_iter().map(instance_message).collect() })
+            .collect();
+        proto::ListModelsResponse { models }
+    }
+
+    // Describe the instances of a model
+    async fn get_model(&self, request: proto::GetModelRequest, caller: Option<&ApiKey>) -> Result<proto::Model, Status> {
+        auth::authorize_model(caller, &request.name).map_err(ApiError::from_pool)?;
+        let infos = self.model_pool.model_info(&request.name).await.ok_or_else(|| ApiError::not_found(&format!("Model {} not found", request.name)))?;
+        Ok(proto::Model { name: request.name, instances: infos.into_iter().map(instance_message).collect() })
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////////////// Inference //////////////////////
+    //////////////////////////////////////////////////////
+
+    // Run an inference on the model
+    async fn infer(&self, request: proto::InferRequest, caller: Option<ApiKey>) -> Result<proto::InferResponse, Status> {
+        let (model, request) = self.meal_request(request, caller.as_ref())?;
+        let response = self.model_pool.infer(&model, request, caller.as_ref()).await.map_err(ApiError::from_pool)?;
+        match (response.output, response.error) {
+            (_, Some(error)) => Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, &error.code, &error.message).into()),
+            (output, None) => Ok(proto::InferResponse { id: response.id, output: output.unwrap_or_default() }),
+        }
+    }
+
+    // Run an inference and send the chunks of the output as they arrive, the status follows the last chunk
+    fn infer_stream(&self, request: proto::InferRequest, caller: Option<ApiKey>) -> Result<Response<Body>, Status> {
+        let (model, request) = self.meal_request(request, caller.as_ref())?;
+        let (mut body_tx, body) = Body::channel();
+        let model_pool = Arc::clone(&self.model_pool);
+        tokio::spawn(async move {
+            let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel::<String>();
+            let id = request.id.clone();
+            let infer = model_pool.infer_stream(&model, request, caller.as_ref(), chunk_tx);
+            tokio::pin!(infer);
+
+            // Chunks are forwarded while the model runs, a client that went away does not cancel the request
+            let mut connected = true;
+            let result = loop {
+                tokio::select! {
+                    result = &mut infer => break result,
+                    Some(chunk) = chunk_rx.recv() => {
+                        let message = proto::InferChunk { id: id.clone(), chunk };
+                        connected = connected && body_tx.send_data(frame(&message).into()).await.is_ok();
+                    }
+                }
+            };
+            while let Ok(chunk) = chunk_rx.try_recv() {
+                let message = proto::InferChunk { id: id.clone(), chunk };
+                connected = connected && body_tx.send_data(frame(&message).into()).await.is_ok();
+            }
+
+            let status = match result {
+                Ok(response) => match response.error {
+                    Some(error) => ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, &error.code, &error.message).into(),
+                    None => Status::ok(),
+                },
+                Err(err) => ApiError::from_pool(err).into(),
+            };
+            if connected {
+                let _ = body_tx.send_trailers(status.headers()).await;
+            }
+        });
+        Ok(grpc_response(body))
+    }
+
+    // Build the request of the model pool, the param values are parsed as JSON and kept as strings otherwise
+    fn meal_request(&self, request: proto::InferRequest, caller: Option<&ApiKey>) -> Result<(String, MEALRequest), Status> {
+        auth::authorize_model(caller, &request.model).map_err(ApiError::from_pool)?;
+        if self.model_pool.instances(&request.model).is_none() {
+            return Err(ApiError::not_found(&format!("Model {} not found", request.model)).into());
+        }
+        let mut meal_request = MEALRequest::new(&request.input);
+        meal_request.id = request.id;
+        meal_request.session = Some(request.session).filter(|session| !session.is_empty());
+        meal_request.params = request.params.into_iter()
+            .map(|(name, value)| {
+                let value = serde_json::from_str::<Value>(&value).unwrap_or(Value::String(value));
+                (name, value)
+            })
+            .collect();
+        Ok((request.model, meal_request))
+    }
+
+
+    //////////////////////////////////////////////////////
+    /////////////////// Model entries ////////////////////
+    //////////////////////////////////////////////////////
+
+    // Create a model entry in the DB and add its instance to the pool
+    async fn create_model(&self, request: proto::CreateModelRequest, caller: Option<&ApiKey>) -> Result<proto::ModelEntry, Status> {
+        self.check_runtime_changes(caller)?;
+        let entry = dal::ModelEntry {
+            name: request.name,
+            conn_type: request.conn_type,
+            conn_params: request.conn_params,
+            model_params: request.model_params,
+        };
+        let config = self.model_pool.create_entry(&self.dal, &entry).await.map_err(ApiError::from_pool)?;
+        Ok(entry_message(config))
+    }
+
+    // Modify a model entry in the DB and replace its instance in the pool
+    async fn modify_model(&self, request: proto::ModifyModelRequest, caller: Option<&ApiKey>) -> Result<proto::ModelEntry, Status> {
+        self.check_runtime_changes(caller)?;
+        let changes = dal::ModelEntry {
+            name: request.name,
+            conn_type: request.conn_type,
+            conn_params: request.conn_params,
+            model_params: request.model_params,
+        };
+        let config = self.model_pool.modify_entry(&self.dal, &request.uid, &changes).await.map_err(ApiError::from_pool)?;
+        Ok(entry_message(config))
+    }
+
+    // Delete a model entry from the DB and shut down its instance
+    async fn delete_model(&self, request: proto::DeleteModelRequest, caller: Option<&ApiKey>) -> Result<proto::DeleteModelResponse, Status> {
+        self.check_runtime_changes(caller)?;
+        self.model_pool.delete_entry(&self.dal, &request.uid).await.map_err(ApiError::from_pool)?;
+        Ok(proto::DeleteModelResponse {})
+    }
+
+    // The admin RPCs are allowed under the same flag as the model-create/modify/delete REPL commands and only to admin keys
+    fn check_runtime_changes(&self, caller: Option<&ApiKey>) -> Result<(), Status> {
+        if !self.allow_model_server_runtime_changes {
+            return Err(Status::new(Code::PermissionDenied, "Runtime changes to the model server are not allowed"));
+        }
+        auth::authorize_admin(caller).map_err(|err| ApiError::from_pool(err).into())
+    }
+}
+
+
+// Decode a request message
+fn decode<T: Message>(message: &[u8]) -> Result<T, Status> {
+    T::decode(message).map_err(|err| Status::invalid_argument(&format!("Invalid request message: {}", err)))
+}
+
+// Read the single length-prefixed message of a request, compressed messages are not supported
+async fn read_message(mut body: Body) -> Result<Vec<u8>, Status> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| Status::new(Code::Internal, &format!("Failed to read the request: {}", err)))?;
+        if data.len() + chunk.len() > MAX_MESSAGE_BYTES + 5 {
+            return Err(Status::new(Code::ResourceExhausted, &format!("The request message is larger than {} bytes", MAX_MESSAGE_BYTES)));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.len() < 5 {
+        return Err(Status::invalid_argument("Missing the request message"));
+    }
+    if data[0] != 0 {
+        return Err(Status::new(Code::Unimplemented, "Compressed messages are not supported"));
+    }
+    let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    if data.len() != length + 5 {
+        return Err(Status::invalid_argument("The request must contain exactly one message"));
+    }
+    Ok(data.split_off(5))
+}
+
+// Frame a message with the uncompressed flag and its length
+fn frame(message: &impl Message) -> Vec<u8> {
+    let encoded = message.encode_to_vec();
+    let mut frame = Vec::with_capacity(encoded.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&encoded);
+    frame
+}
+
+fn grpc_response(body: Body) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/grpc")
+        .body(body)
+        .unwrap()
+}
+
+// Send a single message followed by the OK status
+fn unary_response(message: &impl Message) -> Response<Body> {
+    let (mut body_tx, body) = Body::channel();
+    let frame = frame(message);
+    tokio::spawn(async move {
+        if body_tx.send_data(frame.into()).await.is_ok() {
+            let _ = body_tx.send_trailers(Status::ok().headers()).await;
+        }
+    });
+    grpc_response(body)
+}
+
+// Describe an instance like the GET /models/{name} route of the HTTP API
+fn instance_message(info: InstanceInfo) -> proto::Instance {
+    proto::Instance {
+        uid: info.uid,
+        conn_type: info.conn_type,
+        protocol: info.protocol,
+        weights: info.weights.unwrap_or_default(),
+        state: info.state,
+        capabilities_json: info.capabilities.map(|capabilities| serde_json::to_string(&capabilities).unwrap_or_default()).unwrap_or_default(),
+    }
+}
+
+// Describe a stored model entry, the fields maintained by the DAL are left out of the params
+fn entry_message(config: Vec<HashMap<String, String>>) -> proto::ModelEntry {
+    let params = |index: usize| -> HashMap<String, String> {
+        config.get(index).cloned().unwrap_or_default().into_iter()
+            .filter(|(name, _)| !["id", "uid", "createdAt", "lastUpdated"].contains(&name.as_str()))
+            .collect()
+    };
+    let static_fields = config.first().cloned().unwrap_or_default();
+    proto::ModelEntry {
+        uid: static_fields.get("uid").cloned().unwrap_or_default(),
+        name: static_fields.get("name").cloned().unwrap_or_default(),
+        conn_type: static_fields.get("connType").cloned().unwrap_or_default(),
+        conn_params: params(1),
+        model_params: params(2),
+    }
+}
+
+// Percent-encode a status message as required for grpc-message
+fn percent_encode(message: &str) -> String {
+    message.bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dal::DALArgs;
+    use crate::meal::{MEAL, MEALArgs};
+    use std::time::Duration;
+
+    // Echoes the inputs with the jsonl protocol word by word when streaming, the input "fail" is answered with a model error
+    const ECHO_MODEL: &str = r#"
+import json, sys
+print('@!#READY#!@ {"streaming": true}', flush=True)
+for line in sys.stdin:
+    if line.strip() == "@!#EXIT#!@":
+        break
+    request = json.loads(line)
+    if request["input"] == "fail":
+        print(json.dumps({"id": request["id"], "error": {"code": "bad_input", "message": "Failed on purpose"}}), flush=True)
+        continue
+    output = "echo: {}".format(request["input"])
+    if "temperature" in request.get("params", {}):
+        output += " (temperature {})".format(request["params"]["temperature"])
+    if request.get("stream"):
+        for word in output.split(" "):
+            print(json.dumps({"id": request["id"], "chunk": word + " "}), flush=True)
+    print(json.dumps({"id": request["id"], "output": output}), flush=True)
+"#;
+
+    // Call a method over a new HTTP/2 connection, returns the status code and message with the response messages
+    async fn call<T: Message>(addr: std::net::SocketAddr, method: &str, request: &impl Message) -> (u16, String, Vec<T>) {
+        let client = hyper::Client::builder().http2_only(true).build_http::<Body>();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/{}/{}", addr, SERVICE, method))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(Body::from(frame(request)))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers().clone();
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+
+        // Trailers-only responses carry the status in the headers
+        let status = body.trailers().await.unwrap().unwrap_or(headers);
+        let code = status.get("grpc-status").unwrap().to_str().unwrap().parse().unwrap();
+        let message = status.get("grpc-message").map(|message| message.to_str().unwrap().to_string()).unwrap_or_default();
+        let mut messages = Vec::new();
+        while !data.is_empty() {
+            let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+            messages.push(T::decode(&data[5..5 + length]).unwrap());
+            data.drain(..5 + length);
+        }
+        (code, message, messages)
+    }
+
+    #[tokio::test]
+    async fn test_grpc_api() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-grpc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("uid".to_string(), "1".to_string());
+        static_fields.insert("name".to_string(), "echo".to_string());
+        static_fields.insert("connType".to_string(), "local".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        model_params.insert("inferenceArgv".to_string(), serde_json::json!(["python3", "-c", ECHO_MODEL]).to_string());
+        let meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+        let model_pool = Arc::new(ModelPool::new());
+        model_pool.insert("echo", meal);
+
+        // The DAL is only used by the admin RPCs, which are not allowed here
+        let dal_args = DALArgs { connection_url: "localhost:4321".to_string(), username: String::new(), password: String::new() };
+        let dal = Arc::new(tokio::sync::Mutex::new(DAL::create("surreal", dal_args).unwrap()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Arc::new(GrpcApi::new(Arc::clone(&model_pool), dal, Arc::new(KeyStore::default()), false)).serve(listener, async { let _ = stop_rx.await; }));
+
+        // The lazy model is listed as stopped until its first request
+        let (code, _, models) = call::<proto::ListModelsResponse>(addr, "ListModels", &proto::ListModelsRequest {}).await;
+        assert_eq!(code, Code::Ok as u16);
+        assert_eq!(models[0].models[0].name, "echo");
+        assert_eq!(models[0].models[0].instances[0].uid, "1");
+        assert_eq!(models[0].models[0].instances[0].state, "stopped");
+
+        // Unary inference with params parsed as JSON
+        let mut request = proto::InferRequest { model: "echo".to_string(), input: "Hi".to_string(), id: "1".to_string(), ..Default::default() };
+        request.params.insert("temperature".to_string(), "0.5".to_string());
+        let (code, _, responses) = call::<proto::InferResponse>(addr, "Infer", &request).await;
+        assert_eq!(code, Code::Ok as u16);
+        assert_eq!(responses, vec![proto::InferResponse { id: "1".to_string(), output: "echo: Hi (temperature 0.5)".to_string() }]);
+        let (code, _, models) = call::<proto::Model>(addr, "GetModel", &proto::GetModelRequest { name: "echo".to_string() }).await;
+        assert_eq!(code, Code::Ok as u16);
+        assert_eq!(models[0].instances[0].state, "ready");
+        let capabilities: Value = serde_json::from_str(&models[0].instances[0].capabilities_json).unwrap();
+        assert_eq!(capabilities["streaming"], true);
+
+        // Streamed inference sends the chunks of the model
+        let request = proto::InferRequest { model: "echo".to_string(), input: "Hi there".to_string(), ..Default::default() };
+        let (code, _, chunks) = call::<proto::InferChunk>(addr, "InferStream", &request).await;
+        assert_eq!(code, Code::Ok as u16);
+        let chunks: Vec<String> = chunks.into_iter().map(|chunk| chunk.chunk).collect();
+        assert_eq!(chunks, vec!["echo: ", "Hi ", "there "]);
+
+        // Errors of the model, unknown models and methods and the admin RPCs without runtime changes are rejected
+        let request = proto::InferRequest { model: "echo".to_string(), input: "fail".to_string(), ..Default::default() };
+        assert_eq!(call::<proto::InferResponse>(addr, "Infer", &request).await.0, Code::Unknown as u16);
+        assert_eq!(call::<proto::InferChunk>(addr, "InferStream", &request).await.0, Code::Unknown as u16);
+        let (code, message, _) = call::<proto::Model>(addr, "GetModel", &proto::GetModelRequest { name: "other".to_string() }).await;
+        assert_eq!((code, message.as_str()), (Code::NotFound as u16, "Model other not found"));
+        assert_eq!(call::<proto::Model>(addr, "Other", &proto::GetModelRequest::default()).await.0, Code::Unimplemented as u16);
+        let request = proto::CreateModelRequest { name: "other".to_string(), conn_type: "local".to_string(), ..Default::default() };
+        assert_eq!(call::<proto::ModelEntry>(addr, "CreateModel", &request).await.0, Code::PermissionDenied as u16);
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_grpc_messages() {
+        // Maps, nested messages and unknown fields survive the encoding
+        let mut entry = proto::ModelEntry { uid: "1".to_string(), name: "echo".to_string(), ..Default::default() };
+        entry.model_params.insert("protocol".to_string(), "jsonl".to_string());
+        entry.model_params.insert("removed".to_string(), String::new());
+        assert_eq!(proto::ModelEntry::decode(&entry.encode_to_vec()).unwrap(), entry);
+        let mut data = proto::GetModelRequest { name: "echo".to_string() }.encode_to_vec();
+        data.extend_from_slice(&[0x10, 0x96, 0x01, 0x1d, 0, 0, 0, 0]);
+        assert_eq!(proto::GetModelRequest::decode(&data).unwrap().name, "echo");
+        assert!(proto::GetModelRequest::decode(&[0x0a, 0x05, b'e']).is_err());
+
+        // Status messages are percent-encoded
+        assert_eq!(percent_encode("100% ü"), "100%25 %C3%BC");
+        let status: Status = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "bad_input", "Failed").into();
+        assert_eq!((status.code, status.error_code.as_deref()), (Code::Unknown, Some("bad_input")));
+    }
+}
@@ -0,0 +1,461 @@
+// src/grpc/proto.rs
+// Messages of proto/mer.proto with their protobuf encoding written by hand, the fields are only strings,
+// string maps and nested messages
+use std::collections::HashMap;
+
+// Wire types of the protobuf encoding
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+
+// Value of a decoded field, fixed size values are skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+// Message of the service, unknown fields are ignored when decoding
+pub trait Message: Default {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String>;
+
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    fn decode(mut data: &[u8]) -> Result<Self, String> {
+        let mut message = Self::default();
+        while !data.is_empty() {
+            let key = read_varint(&mut data)?;
+            let field = u32::try_from(key >> 3).map_err(|_| "Invalid protobuf field number".to_string())?;
+            let value = match key & 0x7 {
+                WIRE_VARINT => FieldValue::Varint(read_varint(&mut data)?),
+                WIRE_LENGTH_DELIMITED => {
+                    let length = read_varint(&mut data)? as usize;
+                    if length > data.len() {
+                        return Err("Truncated protobuf message".to_string());
+                    }
+                    let (bytes, rest) = data.split_at(length);
+                    data = rest;
+                    FieldValue::Bytes(bytes)
+                }
+                WIRE_FIXED64 | WIRE_FIXED32 => {
+                    let size = if key & 0x7 == WIRE_FIXED64 { 8 } else { 4 };
+                    data = data.get(size..).ok_or("Truncated protobuf message")?;
+                    continue;
+                }
+                wire_type => return Err(format!("Unsupported protobuf wire type {}", wire_type)),
+            };
+            message.merge(field, value)?;
+        }
+        Ok(message)
+    }
+}
+
+
+//////////////////////////////////////////////////////
+///////////////// Encoding helpers ///////////////////
+//////////////////////////////////////////////////////
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            *data = &data[index + 1..];
+            return Ok(value);
+        }
+    }
+    Err("Invalid protobuf varint".to_string())
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(out, (u64::from(field) << 3) | WIRE_LENGTH_DELIMITED);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+// Strings with the default (empty) value are not written
+fn put_string(out: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        put_bytes(out, field, value.as_bytes());
+    }
+}
+
+fn put_message(out: &mut Vec<u8>, field: u32, message: &impl Message) {
+    put_bytes(out, field, &message.encode_to_vec());
+}
+
+// Maps are written as repeated key (1) and value (2) entries, sorted by key
+fn put_map(out: &mut Vec<u8>, field: u32, map: &HashMap<String, String>) {
+    let mut entries: Vec<(&String, &String)> = map.iter().collect();
+    entries.sort();
+    for (key, value) in entries {
+        let mut entry = Vec::new();
+        put_string(&mut entry, 1, key);
+        put_string(&mut entry, 2, value);
+        put_bytes(out, field, &entry);
+    }
+}
+
+fn get_string(field: u32, value: FieldValue) -> Result<String, String> {
+    match value {
+        FieldValue::Bytes(bytes) => String::from_utf8(bytes.to_vec()).map_err(|_| format!("Field {} is not valid UTF-8", field)),
+        FieldValue::Varint(_) => Err(format!("Field {} must be a string", field)),
+    }
+}
+
+fn get_message<T: Message>(field: u32, value: FieldValue) -> Result<T, String> {
+    match value {
+        FieldValue::Bytes(bytes) => T::decode(bytes),
+        FieldValue::Varint(_) => Err(format!("Field {} must be a message", field)),
+    }
+}
+
+fn get_map_entry(map: &mut HashMap<String, String>, field: u32, value: FieldValue) -> Result<(), String> {
+    let entry: MapEntry = get_message(field, value)?;
+    map.insert(entry.key, entry.value);
+    Ok(())
+}
+
+// Entry of a map<string, string> field
+#[derive(Debug, Default)]
+struct MapEntry {
+    key: String,
+    value: String,
+}
+
+impl Message for MapEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.key);
+        put_string(out, 2, &self.value);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.key = get_string(field, value)?,
+            2 => self.value = get_string(field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+
+//////////////////////////////////////////////////////
+///////////////////// Messages ///////////////////////
+//////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListModelsRequest {}
+
+impl Message for ListModelsRequest {
+    fn encode(&self, _out: &mut Vec<u8>) {}
+
+    fn merge(&mut self, _field: u32, _value: FieldValue) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListModelsResponse {
+    pub models: Vec<Model>,
+}
+
+impl Message for ListModelsResponse {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for model in &self.models {
+            put_message(out, 1, model);
+        }
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        if field == 1 {
+            self.models.push(get_message(field, value)?);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GetModelRequest {
+    pub name: String,
+}
+
+impl Message for GetModelRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.name);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        if field == 1 {
+            self.name = get_string(field, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Model {
+    pub name: String,
+    pub instances: Vec<Instance>,
+}
+
+impl Message for Model {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.name);
+        for instance in &self.instances {
+            put_message(out, 2, instance);
+        }
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.name = get_string(field, value)?,
+            2 => self.instances.push(get_message(field, value)?),
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Instance {
+    pub uid: String,
+    pub conn_type: String,
+    pub protocol: String,
+    pub weights: String,
+    pub state: String,
+    pub capabilities_json: String,
+}
+
+impl Message for Instance {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.uid);
+        put_string(out, 2, &self.conn_type);
+        put_string(out, 3, &self.protocol);
+        put_string(out, 4, &self.weights);
+        put_string(out, 5, &self.state);
+        put_string(out, 6, &self.capabilities_json);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.uid = get_string(field, value)?,
+            2 => self.conn_type = get_string(field, value)?,
+            3 => self.protocol = get_string(field, value)?,
+            4 => self.weights = get_string(field, value)?,
+            5 => self.state = get_string(field, value)?,
+            6 => self.capabilities_json = get_string(field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InferRequest {
+    pub model: String,
+    pub input: String,
+    pub id: String,
+    pub session: String,
+    pub params: HashMap<String, String>,
+}
+
+impl Message for InferRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.model);
+        put_string(out, 2, &self.input);
+        put_string(out, 3, &self.id);
+        put_string(out, 4, &self.session);
+        put_map(out, 5, &self.params);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.model = get_string(field, value)?,
+            2 => self.input = get_string(field, value)?,
+            3 => self.id = get_string(field, value)?,
+            4 => self.session = get_string(field, value)?,
+            5 => get_map_entry(&mut self.params, field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InferResponse {
+    pub id: String,
+    pub output: String,
+}
+
+impl Message for InferResponse {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.id);
+        put_string(out, 2, &self.output);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.id = get_string(field, value)?,
+            2 => self.output = get_string(field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InferChunk {
+    pub id: String,
+    pub chunk: String,
+}
+
+impl Message for InferChunk {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.id);
+        put_string(out, 2, &self.chunk);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.id = get_string(field, value)?,
+            2 => self.chunk = get_string(field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelEntry {
+    pub uid: String,
+    pub name: String,
+    pub conn_type: String,
+    pub conn_params: HashMap<String, String>,
+    pub model_params: HashMap<String, String>,
+}
+
+impl Message for ModelEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.uid);
+        put_string(out, 2, &self.name);
+        put_string(out, 3, &self.conn_type);
+        put_map(out, 4, &self.conn_params);
+        put_map(out, 5, &self.model_params);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.uid = get_string(field, value)?,
+            2 => self.name = get_string(field, value)?,
+            3 => self.conn_type = get_string(field, value)?,
+            4 => get_map_entry(&mut self.conn_params, field, value)?,
+            5 => get_map_entry(&mut self.model_params, field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreateModelRequest {
+    pub name: String,
+    pub conn_type: String,
+    pub conn_params: HashMap<String, String>,
+    pub model_params: HashMap<String, String>,
+}
+
+impl Message for CreateModelRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.name);
+        put_string(out, 2, &self.conn_type);
+        put_map(out, 3, &self.conn_params);
+        put_map(out, 4, &self.model_params);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.name = get_string(field, value)?,
+            2 => self.conn_type = get_string(field, value)?,
+            3 => get_map_entry(&mut self.conn_params, field, value)?,
+            4 => get_map_entry(&mut self.model_params, field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModifyModelRequest {
+    pub uid: String,
+    pub name: String,
+    pub conn_type: String,
+    pub conn_params: HashMap<String, String>,
+    pub model_params: HashMap<String, String>,
+}
+
+impl Message for ModifyModelRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.uid);
+        put_string(out, 2, &self.name);
+        put_string(out, 3, &self.conn_type);
+        put_map(out, 4, &self.conn_params);
+        put_map(out, 5, &self.model_params);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        match field {
+            1 => self.uid = get_string(field, value)?,
+            2 => self.name = get_string(field, value)?,
+            3 => self.conn_type = get_string(field, value)?,
+            4 => get_map_entry(&mut self.conn_params, field, value)?,
+            5 => get_map_entry(&mut self.model_params, field, value)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteModelRequest {
+    pub uid: String,
+}
+
+impl Message for DeleteModelRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_string(out, 1, &self.uid);
+    }
+
+    fn merge(&mut self, field: u32, value: FieldValue) -> Result<(), String> {
+        if field == 1 {
+            self.uid = get_string(field, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteModelResponse {}
+
+impl Message for DeleteModelResponse {
+    fn encode(&self, _out: &mut Vec<u8>) {}
+
+    fn merge(&mut self, _field: u32, _value: FieldValue) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+// src/http/chat.rs
+// Std lib imports
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// WebSocket connections upgraded from the HTTP server
+use futures_util::{SinkExt, StreamExt};
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::Upgraded;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+
+// Custom modules
+use super::{ApiError, HttpApi};
+use crate::dal::ApiKey;
+use crate::meal::pool::ModelPool;
+use crate::meal::protocol::MEALRequest;
+
+// Numbers the chat connections, the ids of their turns are unique over the driver
+static CONNECTION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+// Message of the client, sent as a JSON text frame
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ClientMessage {
+    // Send a turn, only one turn runs at a time
+    Turn {
+        input: String,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        params: HashMap<String, Value>,
+    },
+    // Cancel the running turn
+    Cancel,
+    // Clear the history of the session
+    Reset,
+}
+
+// Turn the model is generating
+struct RunningTurn {
+    id: String,
+    task: tokio::task::JoinHandle<()>,
+}
+
+
+impl HttpApi {
+    // Upgrade GET /models/{name}/chat to a WebSocket connection holding a session of the model
+    pub(super) async fn chat(&self, name: &str, request: Request<Body>, caller: Option<ApiKey>) -> Result<Response<Body>, ApiError> {
+        if self.model_pool.instances(name).is_none() {
+            return Err(ApiError::not_found(&format!("Model {} not found", name)));
+        }
+        let upgrade = request.headers().get(UPGRADE).and_then(|upgrade| upgrade.to_str().ok()).unwrap_or_default();
+        let key = match (upgrade.eq_ignore_ascii_case("websocket"), request.headers().get(SEC_WEBSOCKET_KEY)) {
+            (true, Some(key)) => derive_accept_key(key.as_bytes()),
+            _ => return Err(ApiError::new(StatusCode::UPGRADE_REQUIRED, "upgrade_required", "The chat endpoint requires a WebSocket connection")),
+        };
+
+        // The connection is taken over once the switching protocols response is sent
+        let model_pool = Arc::clone(&self.model_pool);
+        let name = name.to_string();
+        tokio::spawn(async move {
+            match hyper::upgrade::on(request).await {
+                Ok(upgraded) => {
+                    let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                    run_chat(model_pool, name, caller, socket).await;
+                }
+                Err(err) => log::error!("Failed to upgrade the chat connection of model {}: {}", name, err),
+            }
+        });
+        Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(UPGRADE, HeaderValue::from_static("websocket"))
+            .header(CONNECTION, HeaderValue::from_static("Upgrade"))
+            .header(SEC_WEBSOCKET_ACCEPT, key)
+            .body(Body::empty())
+            .unwrap())
+    }
+}
+
+
+// Run a chat connection: open a session, answer its turns with streamed chunks and close the session at the end
+async fn run_chat(model_pool: Arc<ModelPool>, name: String, caller: Option<ApiKey>, socket: WebSocketStream<Upgraded>) {
+    let connection = CONNECTION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let (mut sink, mut stream) = socket.split();
+
+    // The turns send their events to the writer so they never wait for each other
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            if sink.send(Message::Text(event.to_string())).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    // The session pins the history to one instance, models without sessions answer with their default history
+    let session = match model_pool.create_session(&name).await {
+        Ok(session) => Some(session),
+        Err(_) if !model_pool.capabilities(&name).map(|capabilities| capabilities.sessions).unwrap_or(false) => None,
+        Err(err) => {
+            let _ = event_tx.send(error_event(None, &ApiError::from_pool(err)));
+            drop(event_tx);
+            let _ = writer.await;
+            return;
+        }
+    };
+    log::info!("Chat {} of model {} opened with session {:?}", connection, name, session);
+    let _ = event_tx.send(json!({ "type": "session", "model": name, "session": session }));
+
+    let (finished_tx, mut finished_rx) = mpsc::unbounded_channel::<String>();
+    let mut running: Option<RunningTurn> = None;
+    let mut turns = 0u64;
+    loop {
+        let message = tokio::select! {
+            Some(id) = finished_rx.recv() => {
+                if running.as_ref().map(|turn| turn.id == id).unwrap_or(false) {
+                    running = None;
+                }
+                continue;
+            }
+            message = stream.next() => message,
+        };
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(Message::Binary(_))) => {
+                let _ = event_tx.send(error_event(None, &ApiError::bad_request("Only text messages are accepted")));
+                continue;
+            }
+            Some(Ok(_)) => continue,
+        };
+        let message = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(message) => message,
+            Err(err) => {
+                let _ = event_tx.send(error_event(None, &ApiError::bad_request(&format!("Invalid message: {}", err))));
+                continue;
+            }
+        };
+
+        match message {
+            ClientMessage::Turn { input, id, params } => {
+                if let Some(turn) = &running {
+                    let _ = event_tx.send(error_event(Some(&turn.id), &ApiError::new(StatusCode::CONFLICT, "busy", "A turn is already running")));
+                    continue;
+                }
+                turns += 1;
+                let mut request = MEALRequest::new(&input);
+                request.id = id.unwrap_or_else(|| format!("chat-{}-{}", connection, turns));
+                request.session = session.clone();
+                request.params = params;
+                let id = request.id.clone();
+                let task = tokio::spawn(run_turn(Arc::clone(&model_pool), name.clone(), request, caller.clone(), event_tx.clone(), finished_tx.clone()));
+                running = Some(RunningTurn { id, task });
+            }
+            ClientMessage::Cancel => match &running {
+                // The turn answers with a cancelled event once its request failed
+                Some(turn) => {
+                    if let Err(err) = model_pool.cancel(&name, &turn.id).await {
+                        log::debug!("Failed to cancel the turn {} of chat {}: {}", turn.id, connection, err);
+                    }
+                }
+                None => {
+                    let _ = event_tx.send(error_event(None, &ApiError::bad_request("No turn is running")));
+                }
+            },
+            ClientMessage::Reset => {
+                let result = match (&running, &session) {
+                    (Some(_), _) => Err(ApiError::new(StatusCode::CONFLICT, "busy", "A turn is already running")),
+                    (None, Some(session)) => model_pool.reset_session(session).await.map_err(ApiError::from_pool),
+                    (None, None) => Err(ApiError::bad_request(&format!("Model {} does not support sessions", name))),
+                };
+                let _ = match result {
+                    Ok(()) => event_tx.send(json!({ "type": "reset" })),
+                    Err(err) => event_tx.send(error_event(None, &err)),
+                };
+            }
+        }
+    }
+
+    // A client that went away cancels its turn, the session is closed once the turn released the instance
+    if let Some(turn) = running {
+        let _ = model_pool.cancel(&name, &turn.id).await;
+        let _ = turn.task.await;
+    }
+    if let Some(session) = &session {
+        if let Err(err) = model_pool.close_session(session).await {
+            log::warn!("Failed to close the session {} of chat {}: {}", session, connection, err);
+        }
+    }
+    drop(event_tx);
+    let _ = writer.await;
+    log::info!("Chat {} of model {} closed", connection, name);
+}
+
+// Run a turn of the caller through the request routing of the pool, forwarding the chunks and the final output as events
+async fn run_turn(model_pool: Arc<ModelPool>, name: String, request: MEALRequest, caller: Option<ApiKey>, event_tx: mpsc::UnboundedSender<Value>, finished_tx: mpsc::UnboundedSender<String>) {
+    let id = request.id.clone();
+    let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel::<String>();
+    let chunk_events = event_tx.clone();
+    let chunk_id = id.clone();
+    let forwarder = tokio::spawn(async move {
+        while let Some(chunk) = chunk_rx.recv().await {
+            let _ = chunk_events.send(json!({ "type": "chunk", "id": chunk_id, "chunk": chunk }));
+        }
+    });
+
+    let result = model_pool.infer_stream(&name, request, caller.as_ref(), chunk_tx).await;
+    let _ = forwarder.await;
+    let event = match result {
+        Ok(response) => match (response.output, response.error) {
+            (_, Some(error)) if error.code == "cancelled" => json!({ "type": "cancelled", "id": id }),
+            (_, Some(error)) => error_event(Some(&id), &ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, &error.code, &error.message)),
+            (output, None) => json!({ "type": "done", "id": id, "output": output }),
+        },
+        Err(err) => error_event(Some(&id), &ApiError::from_pool(err)),
+    };
+    let _ = event_tx.send(event);
+    let _ = finished_tx.send(id);
+}
+
+// Build the error event of a turn or of the connection
+fn error_event(id: Option<&str>, err: &ApiError) -> Value {
+    json!({ "type": "error", "id": id, "error": { "code": err.code, "message": err.message } })
+}
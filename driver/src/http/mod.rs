@@ -0,0 +1,733 @@
+// src/http/mod.rs
+// Std lib imports
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+
+// HTTP server with hyper
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// Custom modules
+use crate::auth::{self, KeyStore};
+use crate::dal::{ApiKey, InferenceJob};
+use crate::jobs::{self, InferenceJobs, JobSubmission};
+use crate::meal::pool::{ModelListing, ModelPool};
+use crate::meal::protocol::MEALRequest;
+use crate::meal::quota;
+use crate::meal::settings::Lifecycle;
+use crate::metrics;
+
+// HTTP submodules
+mod chat;
+mod openai;
+
+// Largest accepted request body, jobs carry a whole batch of inputs
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const MAX_JOB_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+
+// Error returned to the client as {"error": {"code": ..., "message": ...}}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, message: &str) -> Self {
+        Self { status, code: code.to_string(), message: message.to_string() }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    // Map an error of the model pool, the pool reports its errors as strings
+    pub fn from_pool(message: String) -> Self {
+        if message.starts_with("The driver is shutting down") {
+            Self::new(StatusCode::SERVICE_UNAVAILABLE, "shutting_down", &message)
+        } else if message.starts_with("Unauthorized") {
+            Self::new(StatusCode::UNAUTHORIZED, "unauthorized", &message)
+        } else if message.starts_with("Forbidden") {
+            Self::new(StatusCode::FORBIDDEN, "forbidden", &message)
+        } else if message.starts_with("Too many requests") {
+            Self::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", &message)
+        } else if message.starts_with("Quota exceeded") {
+            Self::new(StatusCode::TOO_MANY_REQUESTS, "quota_exceeded", &message)
+        } else if message.starts_with("Invalid request") {
+            Self::bad_request(&message)
+        } else if message.starts_with("Model ") && message.ends_with(" not found") {
+            Self::not_found(&message)
+        } else {
+            Self::new(StatusCode::BAD_GATEWAY, "model_unavailable", &message)
+        }
+    }
+
+    // Seconds a rate limited or over quota caller has to wait before retrying
+    pub fn retry_after(&self) -> Option<u64> {
+        quota::retry_after(&self.message)
+    }
+
+    // Build the JSON response of the error
+    pub fn response(&self) -> Response<Body> {
+        self.with_retry_after(json_response(self.status, &json!({ "error": { "code": self.code, "message": self.message } })))
+    }
+
+    // Add the Retry-After header of rate limited and over quota requests to the response
+    pub fn with_retry_after(&self, mut response: Response<Body>) -> Response<Body> {
+        if let Some(seconds) = self.retry_after() {
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+// Body of POST /models/{name}/infer
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InferBody {
+    input: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+
+// Serves the models of the pool over HTTP with JSON bodies, the driver runs it in place of the REPL
+pub struct HttpApi {
+    model_pool: Arc<ModelPool>,
+    keys: Arc<KeyStore>,
+    jobs: Arc<InferenceJobs>,
+}
+
+impl HttpApi {
+    pub fn new(model_pool: Arc<ModelPool>, keys: Arc<KeyStore>, jobs: Arc<InferenceJobs>) -> Self {
+        Self { model_pool, keys, jobs }
+    }
+
+    // Serve the API on the listener until the shutdown future completes, the open requests are finished first
+    pub async fn serve(self: Arc<Self>, listener: std::net::TcpListener, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+        listener.set_nonblocking(true).map_err(|err| "Failed to configure the HTTP listener: ".to_string() + &err.to_string())?;
+        let server = Server::from_tcp(listener).map_err(|err| {
+            log::error!("Failed to create the HTTP server: {}", err);
+            "Failed to create the HTTP server: ".to_string() + &err.to_string()
+        })?;
+        log::info!("Serving the HTTP API on {}", server.local_addr());
+
+        let make_service = make_service_fn(move |_connection| {
+            let api = Arc::clone(&self);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let api = Arc::clone(&api);
+                    async move { Ok::<_, Infallible>(api.handle(request).await) }
+                }))
+            }
+        });
+        server.serve(make_service).with_graceful_shutdown(shutdown).await.map_err(|err| {
+            log::error!("The HTTP server failed: {}", err);
+            "The HTTP server failed: ".to_string() + &err.to_string()
+        })
+    }
+
+    // Answer a request, errors are turned into JSON error responses
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let (method, path) = (request.method().clone(), request.uri().path().to_string());
+        let response = match self.route(request).await {
+            Ok(response) => response,
+            Err(err) => err.response(),
+        };
+        log::debug!("HTTP {} {} -> {}", method, path, response.status());
+        response
+    }
+
+    // Dispatch a request to the handler of its path
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, ApiError> {
+        let path = request.uri().path().to_string();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (request.method(), segments.as_slice()) {
+            (_, ["v1", ..]) => return Ok(self.openai(request).await),
+            (&Method::GET, ["health"]) => return Ok(json_response(StatusCode::OK, &json!({ "status": "alive" }))),
+            (&Method::GET, ["ready"]) => return Ok(self.ready()),
+            (&Method::GET, ["metrics"]) => return Ok(metrics::response(&self.model_pool)),
+            _ => {}
+        }
+
+        // The probes and the metrics are open, the models are only served to the callers their key allows
+        let caller = self.keys.authenticate(request.headers()).map_err(ApiError::from_pool)?;
+        match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["models"]) => Ok(self.list_models(caller.as_ref())),
+            (&Method::GET, ["models", name]) => {
+                auth::authorize_model(caller.as_ref(), name).map_err(ApiError::from_pool)?;
+                self.model_info(name).await
+            }
+            (&Method::POST, ["models", name, "infer"]) => {
+                auth::authorize_model(caller.as_ref(), name).map_err(ApiError::from_pool)?;
+                let name = name.to_string();
+                let body = read_json::<InferBody>(request.into_body()).await?;
+                self.infer(&name, body, caller).await
+            }
+            (&Method::GET, ["models", name, "chat"]) => {
+                auth::authorize_model(caller.as_ref(), name).map_err(ApiError::from_pool)?;
+                let name = name.to_string();
+                self.chat(&name, request, caller).await
+            }
+            (&Method::POST, ["jobs"]) => {
+                let submission = read_json_limited::<JobSubmission>(request.into_body(), MAX_JOB_BODY_BYTES).await?;
+                auth::authorize_model(caller.as_ref(), &submission.model).map_err(ApiError::from_pool)?;
+                let job = self.jobs.submit(caller, submission).map_err(ApiError::from_pool)?;
+                Ok(json_response(StatusCode::ACCEPTED, &jobs::job_json(&job, false)))
+            }
+            (&Method::GET, ["jobs"]) => {
+                let listed: Vec<Value> = self.jobs.jobs().iter()
+                    .filter(|job| jobs::authorize_job(caller.as_ref(), job).is_ok())
+                    .map(|job| jobs::job_json(job, false))
+                    .collect();
+                Ok(json_response(StatusCode::OK, &json!({ "jobs": listed })))
+            }
+            (&Method::GET, ["jobs", id]) => Ok(json_response(StatusCode::OK, &jobs::job_json(&self.job(id, caller.as_ref())?, false))),
+            (&Method::GET, ["jobs", id, "results"]) => Ok(json_response(StatusCode::OK, &jobs::job_json(&self.job(id, caller.as_ref())?, true))),
+            (&Method::DELETE, ["jobs", id]) => {
+                self.job(id, caller.as_ref())?;
+                self.jobs.cancel(id).map_err(|err| match err.ends_with(" not found") {
+                    true => ApiError::not_found(&err),
+                    false => ApiError::new(StatusCode::CONFLICT, "job_finished", &err),
+                })?;
+                Ok(json_response(StatusCode::OK, &jobs::job_json(&self.job(id, caller.as_ref())?, false)))
+            }
+            (_, ["health"] | ["ready"] | ["metrics"] | ["models"] | ["models", _] | ["models", _, "infer"] | ["models", _, "chat"] | ["jobs"] | ["jobs", _] | ["jobs", _, "results"]) => {
+                Err(ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", &format!("Method {} is not allowed on {}", request.method(), path)))
+            }
+            _ => Err(ApiError::not_found(&format!("No route for {}", path))),
+        }
+    }
+
+
+    //////////////////////////////////////////////////////
+    /////////////////// Route handlers ///////////////////
+    //////////////////////////////////////////////////////
+
+    // Ready once every eager model has a ready instance, lazy models are started on their first request
+    fn ready(&self) -> Response<Body> {
+        if self.model_pool.is_shutting_down() {
+            return json_response(StatusCode::SERVICE_UNAVAILABLE, &json!({ "status": "shutting down" }));
+        }
+        let mut waiting = Vec::new();
+        for name in self.model_pool.model_names() {
+            let instances = self.model_pool.instances(&name).unwrap_or_default();
+            // Instances that are being (re)started are locked and unhealthy ones do not count as ready
+            let states: Vec<Option<(Lifecycle, bool)>> = instances.iter()
+                .map(|instance| instance.try_read().ok().map(|meal| (meal.settings().lifecycle, meal.is_serving())))
+                .collect();
+            let eager = states.iter().any(|state| matches!(state, Some((Lifecycle::Eager, _))));
+            let ready = states.iter().any(|state| matches!(state, Some((_, true))));
+            if eager && !ready {
+                waiting.push(name);
+            }
+        }
+        match waiting.is_empty() {
+            true => json_response(StatusCode::OK, &json!({ "status": "ready" })),
+            false => json_response(StatusCode::SERVICE_UNAVAILABLE, &json!({ "status": "starting", "waiting": waiting })),
+        }
+    }
+
+    // List the models the caller may execute with the states of their instances
+    fn list_models(&self, caller: Option<&ApiKey>) -> Response<Body> {
+        let models: Vec<Value> = self.model_pool.list_models().iter()
+            .filter(|model| auth::authorize_model(caller, &model.name).is_ok())
+            .map(ModelListing::summary)
+            .collect();
+        json_response(StatusCode::OK, &json!({ "models": models }))
+    }
+
+    // Describe the instances of a model like the model-info REPL command, without the connection details
+    async fn model_info(&self, name: &str) -> Result<Response<Body>, ApiError> {
+        let infos: Vec<Value> = self.model_pool.model_info(name).await
+            .ok_or_else(|| ApiError::not_found(&format!("Model {} not found", name)))?
+            .into_iter()
+            .map(|info| json!({
+                "connType": info.conn_type,
+                "protocol": info.protocol,
+                "weights": info.weights,
+                "state": info.state,
+                "capabilities": info.capabilities,
+            }))
+            .collect();
+        Ok(json_response(StatusCode::OK, &json!({ "name": name, "instances": infos })))
+    }
+
+    // Run an inference on the model, errors reported by the model are returned with their code
+    async fn infer(&self, name: &str, body: InferBody, caller: Option<ApiKey>) -> Result<Response<Body>, ApiError> {
+        if self.model_pool.instances(name).is_none() {
+            return Err(ApiError::not_found(&format!("Model {} not found", name)));
+        }
+        let mut request = MEALRequest::new(&body.input);
+        request.id = body.id.unwrap_or_default();
+        request.session = body.session;
+        request.params = body.params;
+
+        let response = self.model_pool.infer(name, request, caller.as_ref()).await.map_err(ApiError::from_pool)?;
+        match (response.output, response.error) {
+            (_, Some(error)) => Ok(json_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &json!({ "id": response.id, "error": { "code": error.code, "message": error.message } }),
+            )),
+            (output, None) => Ok(json_response(StatusCode::OK, &json!({ "id": response.id, "output": output }))),
+        }
+    }
+
+    // Get an inference job of the caller, the jobs of other callers are not found
+    fn job(&self, id: &str, caller: Option<&ApiKey>) -> Result<InferenceJob, ApiError> {
+        let job = self.jobs.job(id).ok_or_else(|| ApiError::not_found(&format!("Inference job {} not found", id)))?;
+        jobs::authorize_job(caller, &job).map_err(|err| ApiError::not_found(&err))?;
+        Ok(job)
+    }
+}
+
+
+// Build a JSON response
+pub fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// Read a JSON body of at most MAX_BODY_BYTES
+pub async fn read_json<T: DeserializeOwned>(body: Body) -> Result<T, ApiError> {
+    read_json_limited(body, MAX_BODY_BYTES).await
+}
+
+// Read a JSON body of at most max_bytes
+async fn read_json_limited<T: DeserializeOwned>(mut body: Body, max_bytes: usize) -> Result<T, ApiError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| ApiError::bad_request(&format!("Failed to read the body: {}", err)))?;
+        if data.len() + chunk.len() > max_bytes {
+            return Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "body_too_large", &format!("The body is larger than {} bytes", max_bytes)));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&data).map_err(|err| ApiError::bad_request(&format!("Invalid JSON body: {}", err)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meal::{MEAL, MEALArgs};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Echoes the inputs with the jsonl protocol word by word when streaming, the input "fail" is answered with a model error,
+    // the input "slow" after a second and the number of the turn in the session is prepended to the output
+    const ECHO_MODEL: &str = r#"
+import json, sys, time
+print('@!#READY#!@ {"streaming": true, "sessions": true, "parameters": ["temperature", "do_sample"]}', flush=True)
+sessions = {None: 0}
+for line in sys.stdin:
+    if line.strip() == "@!#EXIT#!@":
+        break
+    request = json.loads(line)
+    session = request.get("session")
+    if "sessionOp" in request:
+        if request["sessionOp"] == "close":
+            sessions.pop(session, None)
+        else:
+            sessions[session] = 0
+        print(json.dumps({"id": request["id"]}), flush=True)
+        continue
+    if request["input"] == "fail":
+        print(json.dumps({"id": request["id"], "error": {"code": "bad_input", "message": "Failed on purpose"}}), flush=True)
+        continue
+    if request["input"] == "slow":
+        time.sleep(1)
+    sessions[session] += 1
+    output = "echo {}: {}".format(sessions[session], request["input"])
+    if "temperature" in request.get("params", {}):
+        output += " (temperature {})".format(request["params"]["temperature"])
+    if request.get("stream"):
+        for word in output.split(" "):
+            print(json.dumps({"id": request["id"], "chunk": word + " "}), flush=True)
+    print(json.dumps({"id": request["id"], "output": output}), flush=True)
+"#;
+
+    // Start the echo model in a pool and serve it on a loopback port
+    async fn serve_echo(dir: &std::path::Path, keys: KeyStore) -> (Arc<ModelPool>, std::net::SocketAddr, tokio::sync::oneshot::Sender<()>, tokio::task::JoinHandle<Result<(), String>>) {
+        std::fs::create_dir_all(dir).unwrap();
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "echo".to_string());
+        static_fields.insert("connType".to_string(), "local".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        model_params.insert("inferenceArgv".to_string(), json!(["python3", "-c", ECHO_MODEL]).to_string());
+        let meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+        let model_pool = Arc::new(ModelPool::new());
+        model_pool.insert("echo", meal);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let jobs = Arc::new(InferenceJobs::new(Arc::clone(&model_pool), Some("secret".to_string()), vec!["127.0.0.1".to_string()]));
+        let server = tokio::spawn(Arc::new(HttpApi::new(Arc::clone(&model_pool), Arc::new(keys), jobs)).serve(listener, async { let _ = stop_rx.await; }));
+        (model_pool, addr, stop_tx, server)
+    }
+
+    // Send a request over a new connection and return the status and the raw body of the response
+    async fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        request_as(addr, None, method, path, body).await
+    }
+
+    // Send a request with the API key as a bearer token
+    async fn request_as(addr: std::net::SocketAddr, key: Option<&str>, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let authorization = key.map(|key| format!("Authorization: Bearer {}\r\n", key)).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, authorization, body.len(), body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.split(' ').nth(1).unwrap().parse().unwrap(), body.to_string())
+    }
+
+    // Send a request and parse the JSON body of the response
+    async fn call(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let (status, body) = request(addr, method, path, body).await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_http_api() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-http-{}", std::process::id()));
+        let (model_pool, addr, stop_tx, server) = serve_echo(&dir, KeyStore::default()).await;
+
+        // The lazy model is ready to be started and is listed as stopped
+        assert_eq!(call(addr, "GET", "/health", "").await, (200, json!({ "status": "alive" })));
+        assert_eq!(call(addr, "GET", "/ready", "").await, (200, json!({ "status": "ready" })));
+        assert_eq!(
+            call(addr, "GET", "/models", "").await,
+            (200, json!({ "models": [{ "name": "echo", "instances": 1, "readyInstances": 0, "states": ["stopped"] }] })),
+        );
+
+        // Inference starts the model, model errors keep their code
+        let (status, body) = call(addr, "POST", "/models/echo/infer", r#"{"input": "Hi", "id": "1"}"#).await;
+        assert_eq!((status, body), (200, json!({ "id": "1", "output": "echo 1: Hi" })));
+        let (status, body) = call(addr, "POST", "/models/echo/infer", r#"{"input": "fail"}"#).await;
+        assert_eq!(status, 422);
+        assert_eq!(body["error"]["code"], "bad_input");
+        let (status, body) = call(addr, "GET", "/models/echo", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["instances"][0]["state"], "ready");
+        assert_eq!(body["instances"][0]["protocol"], "jsonl");
+
+        // Unknown models and routes, wrong methods and invalid bodies are rejected
+        assert_eq!(call(addr, "POST", "/models/other/infer", r#"{"input": "Hi"}"#).await.0, 404);
+        assert_eq!(call(addr, "GET", "/other", "").await.0, 404);
+        assert_eq!(call(addr, "GET", "/models/echo/infer", "").await.0, 405);
+        let (status, body) = call(addr, "POST", "/models/echo/infer", r#"{"prompt": "Hi"}"#).await;
+        assert_eq!((status, body["error"]["code"].clone()), (400, json!("invalid_request")));
+
+        // The metrics are shared with the other tests, so only their presence is checked
+        let (status, metrics) = request(addr, "GET", "/metrics", "").await;
+        assert_eq!(status, 200);
+        assert!(metrics.contains("mer_instance_state{model=\"echo\",instance=\"\",state=\"ready\"} 1\n"));
+        assert!(metrics.contains("mer_instance_queue_depth{model=\"echo\",instance=\"\"} 0\n"));
+        assert!(metrics.contains("mer_request_errors_total{model=\"echo\",kind=\"bad_input\"}"));
+        assert!(metrics.contains("mer_inference_duration_seconds_count{model=\"echo\",instance=\"\"}"));
+        assert!(metrics.contains("mer_instance_time_to_ready_seconds_count{model=\"echo\",instance=\"\"}"));
+        assert!(!metrics.contains("model=\"other\""));
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-keys-{}", std::process::id()));
+        let api_key = |key_id: &str, models: &[&str]| ApiKey {
+            key_id: key_id.to_string(),
+            name: "team".to_string(),
+            key_hash: crate::meal::transfer::sha256_hex(format!("mer_{}_secret", key_id).as_bytes()),
+            models: models.iter().map(|model| model.to_string()).collect(),
+            admin: false,
+            limits: Default::default(),
+            created_at: chrono::Utc::now(),
+        };
+        let keys = KeyStore::new(true);
+        let limited = ApiKey { limits: crate::dal::UsageLimits { rate_per_minute: Some(1), ..Default::default() }, ..api_key("c3", &["*"]) };
+        keys.load(vec![api_key("a1", &["echo"]), api_key("b2", &["other"]), limited]);
+        let (model_pool, addr, stop_tx, server) = serve_echo(&dir, keys).await;
+        let call_as = |key: Option<&'static str>, method: &'static str, path: &'static str, body: &'static str| async move {
+            let (status, body) = request_as(addr, key, method, path, body).await;
+            (status, serde_json::from_str::<Value>(&body).unwrap())
+        };
+
+        // The probes are open, the other routes require a valid key
+        assert_eq!(call_as(None, "GET", "/health", "").await.0, 200);
+        let (status, body) = call_as(None, "GET", "/models", "").await;
+        assert_eq!((status, body["error"]["code"].clone()), (401, json!("unauthorized")));
+        assert_eq!(call_as(Some("mer_a1_wrong"), "GET", "/models", "").await.0, 401);
+        let (status, body) = call_as(None, "GET", "/v1/models", "").await;
+        assert_eq!((status, body["error"]["type"].clone()), (401, json!("invalid_request_error")));
+
+        // Keys only list and execute the models in their scope
+        let (status, body) = call_as(Some("mer_a1_secret"), "GET", "/models", "").await;
+        assert_eq!((status, body["models"].as_array().unwrap().len()), (200, 1));
+        let (status, body) = call_as(Some("mer_a1_secret"), "POST", "/models/echo/infer", r#"{"input": "Hi"}"#).await;
+        assert_eq!((status, body["output"].clone()), (200, json!("echo 1: Hi")));
+        let (status, body) = call_as(Some("mer_b2_secret"), "GET", "/models", "").await;
+        assert_eq!((status, body["models"].clone()), (200, json!([])));
+        let (status, body) = call_as(Some("mer_b2_secret"), "POST", "/models/echo/infer", r#"{"input": "Hi"}"#).await;
+        assert_eq!((status, body["error"]["code"].clone()), (403, json!("forbidden")));
+        let (status, body) = call_as(Some("mer_b2_secret"), "POST", "/v1/completions", r#"{"model": "echo", "prompt": "Hi"}"#).await;
+        assert_eq!((status, body["error"]["type"].clone()), (403, json!("invalid_request_error")));
+        assert_eq!(call_as(Some("mer_b2_secret"), "GET", "/v1/models", "").await.1["data"], json!([]));
+
+        // Requests that reach no instance do not count, requests over the rate limit of a key are answered with 429
+        // until a token is refilled
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "broken".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("inferenceArgv".to_string(), json!(["sh", "-c", "exit 1"]).to_string());
+        model_pool.insert("broken", MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap());
+        assert_eq!(call_as(Some("mer_c3_secret"), "POST", "/models/broken/infer", r#"{"input": "Hi"}"#).await.0, 502);
+        assert_eq!(call_as(Some("mer_c3_secret"), "POST", "/models/echo/infer", r#"{"input": "Hi"}"#).await.0, 200);
+        let (status, body) = call_as(Some("mer_c3_secret"), "POST", "/models/echo/infer", r#"{"input": "Hi"}"#).await;
+        assert_eq!((status, body["error"]["code"].clone()), (429, json!("rate_limited")));
+        assert_eq!(ApiError::from_pool(body["error"]["message"].as_str().unwrap().to_string()).retry_after(), Some(60));
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_openai_api() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-openai-{}", std::process::id()));
+        let (model_pool, addr, stop_tx, server) = serve_echo(&dir, KeyStore::default()).await;
+
+        let (status, body) = call(addr, "GET", "/v1/models", "").await;
+        assert_eq!((status, body["data"][0]["id"].clone()), (200, json!("echo")));
+        let (status, body) = call(addr, "GET", "/v1/models/other", "").await;
+        assert_eq!((status, body["error"]["code"].clone(), body["error"]["type"].clone()), (404, json!("model_not_found"), json!("invalid_request_error")));
+
+        // Completions answer every prompt, the options are forwarded once the model announced them
+        let (status, body) = call(addr, "POST", "/v1/completions", r#"{"model": "echo", "prompt": ["Hi", "Bye"], "max_tokens": 5}"#).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["object"], "text_completion");
+        assert_eq!(body["choices"][0]["text"], "echo 1: Hi");
+        assert_eq!(body["choices"][1]["text"], "echo 2: Bye");
+        let (_, body) = call(addr, "POST", "/v1/completions", r#"{"model": "echo", "prompt": "Hi", "temperature": 0.5}"#).await;
+        assert_eq!(body["choices"][0]["text"], "echo 3: Hi (temperature 0.5)");
+
+        // Every chat runs in a session of its own and answers the last user message
+        let chat = r#"{"model": "echo", "messages": [{"role": "system", "content": "Be nice"}, {"role": "user", "content": "Hi"}], "n": 2}"#;
+        let (status, body) = call(addr, "POST", "/v1/chat/completions", chat).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"], json!({ "role": "assistant", "content": "echo 1: Hi" }));
+        assert_eq!(body["choices"][1]["message"]["content"], "echo 2: Hi");
+        let (_, body) = call(addr, "POST", "/v1/chat/completions", chat).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "echo 1: Hi");
+
+        // Streamed chats send the chunks of the model as server-sent events
+        let chat = r#"{"model": "echo", "messages": [{"role": "user", "content": [{"type": "text", "text": "Hi"}]}], "stream": true}"#;
+        let (status, body) = request(addr, "POST", "/v1/chat/completions", chat).await;
+        assert_eq!(status, 200);
+        let events: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+        assert_eq!(events.last(), Some(&"[DONE]"));
+        let chunks: Vec<Value> = events[..events.len() - 1].iter().map(|event| serde_json::from_str(event).unwrap()).collect();
+        assert_eq!(chunks[0]["choices"][0]["delta"], json!({ "role": "assistant", "content": "echo " }));
+        let content: String = chunks.iter().filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str()).collect();
+        assert_eq!(content, "echo 1: Hi ");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+
+        // Model errors keep their code
+        let (status, body) = call(addr, "POST", "/v1/completions", r#"{"model": "echo", "prompt": "fail"}"#).await;
+        assert_eq!((status, body["error"]["code"].clone()), (422, json!("bad_input")));
+        assert_eq!(call(addr, "POST", "/v1/chat/completions", r#"{"model": "echo", "messages": []}"#).await.0, 400);
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_inference_jobs() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-jobs-{}", std::process::id()));
+        let (model_pool, addr, stop_tx, server) = serve_echo(&dir, KeyStore::default()).await;
+
+        // Receive the webhook deliveries with their signature
+        let (hook_tx, mut hook_rx) = tokio::sync::mpsc::unbounded_channel::<(String, String)>();
+        let hook_service = hyper::service::make_service_fn(move |_connection| {
+            let hook_tx = hook_tx.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let hook_tx = hook_tx.clone();
+                    async move {
+                        let signature = request.headers().get("x-mer-signature").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let _ = hook_tx.send((signature, String::from_utf8_lossy(&body).to_string()));
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let hook_server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(hook_service);
+        let hook_addr = hook_server.local_addr();
+        tokio::spawn(hook_server);
+
+        // The job is accepted right away and answers the inputs in the background
+        let submission = json!({
+            "model": "echo",
+            "inputs": ["a", { "id": "x", "input": "fail" }, { "input": "c" }],
+            "concurrency": 2,
+            "webhook": format!("http://{}/hook", hook_addr),
+        });
+        let (status, body) = call(addr, "POST", "/jobs", &submission.to_string()).await;
+        assert_eq!((status, body["state"].clone(), body["inputs"].clone()), (202, json!("queued"), json!(3)));
+        let id = body["jobId"].as_str().unwrap().to_string();
+        let mut job = Value::Null;
+        for _ in 0..100 {
+            job = call(addr, "GET", &format!("/jobs/{}", id), "").await.1;
+            if job["state"] == "succeeded" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!((job["state"].clone(), job["completed"].clone(), job["failed"].clone()), (json!("succeeded"), json!(3), json!(1)));
+
+        // The results keep the ids and the order of the inputs, model errors are kept per input
+        let (status, body) = call(addr, "GET", &format!("/jobs/{}/results", id), "").await;
+        assert_eq!(status, 200);
+        let results = body["results"].as_array().unwrap();
+        let ids: Vec<&str> = results.iter().map(|result| result["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["0", "x", "2"]);
+        assert!(results[0]["output"].as_str().unwrap().ends_with(": a"));
+        assert_eq!(results[1]["error"], "bad_input: Failed on purpose");
+
+        // The finished job is posted to the webhook signed with the secret
+        let (signature, hook_body) = tokio::time::timeout(Duration::from_secs(10), hook_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(signature, format!("sha256={}", jobs::sign("secret", &hook_body)));
+        let hook_body: Value = serde_json::from_str(&hook_body).unwrap();
+        assert_eq!((hook_body["jobId"].as_str(), hook_body["results"].as_array().map(Vec::len)), (Some(id.as_str()), Some(3)));
+
+        // Invalid jobs are rejected, finished jobs can not be cancelled
+        assert_eq!(call(addr, "POST", "/jobs", r#"{"model": "echo"}"#).await.0, 400);
+        assert_eq!(call(addr, "POST", "/jobs", r#"{"model": "echo", "inputs": ["a", {"id": "0", "input": "b"}]}"#).await.0, 400);
+        assert_eq!(call(addr, "POST", "/jobs", r#"{"model": "other", "input": "a"}"#).await.0, 404);
+        for webhook in ["ftp://example.com/hook", "http://169.254.169.254/latest", "http://10.0.0.1/hook", "http://[::1]/hook", "http://[::ffff:127.0.0.2]/hook"] {
+            assert_eq!(call(addr, "POST", "/jobs", &json!({ "model": "echo", "input": "a", "webhook": webhook }).to_string()).await.0, 400, "{}", webhook);
+        }
+        assert_eq!(call(addr, "GET", "/jobs/other", "").await.0, 404);
+        assert_eq!(call(addr, "DELETE", &format!("/jobs/{}", id), "").await.0, 409);
+        assert_eq!(call(addr, "GET", "/jobs", "").await.1["jobs"].as_array().unwrap().len(), 1);
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Read the next JSON event of a chat connection
+    async fn next_event<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(socket: &mut tokio_tungstenite::WebSocketStream<S>) -> Value {
+        use futures_util::StreamExt;
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                tokio_tungstenite::tungstenite::Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_api() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-chat-{}", std::process::id()));
+        let (model_pool, addr, stop_tx, server) = serve_echo(&dir, KeyStore::default()).await;
+        assert_eq!(call(addr, "GET", "/models/echo/chat", "").await.0, 426);
+
+        // The connection opens a session of its own
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::client_async(format!("ws://{}/models/echo/chat", addr), stream).await.unwrap();
+        let event = next_event(&mut socket).await;
+        assert_eq!((event["type"].as_str(), event["model"].as_str()), (Some("session"), Some("echo")));
+        assert!(event["session"].is_string());
+
+        // Turns stream their chunks before the whole output
+        socket.send(Message::Text(r#"{"type": "turn", "input": "Hi", "id": "t1"}"#.to_string())).await.unwrap();
+        let mut chunks = String::new();
+        let done = loop {
+            let event = next_event(&mut socket).await;
+            match event["type"].as_str() {
+                Some("chunk") => chunks += event["chunk"].as_str().unwrap(),
+                _ => break event,
+            }
+        };
+        assert_eq!(done, json!({ "type": "done", "id": "t1", "output": "echo 1: Hi" }));
+        assert_eq!(chunks, "echo 1: Hi ");
+
+        // Only one turn runs at a time and the running one can be cancelled
+        socket.send(Message::Text(r#"{"type": "turn", "input": "slow", "id": "t2"}"#.to_string())).await.unwrap();
+        socket.send(Message::Text(r#"{"type": "turn", "input": "Hi"}"#.to_string())).await.unwrap();
+        let event = next_event(&mut socket).await;
+        assert_eq!((event["type"].as_str(), event["error"]["code"].as_str()), (Some("error"), Some("busy")));
+        socket.send(Message::Text(r#"{"type": "cancel"}"#.to_string())).await.unwrap();
+        assert_eq!(next_event(&mut socket).await, json!({ "type": "cancelled", "id": "t2" }));
+
+        // The model still finished the cancelled turn, its late output is dropped
+        socket.send(Message::Text(r#"{"type": "turn", "input": "Hi", "id": "t3"}"#.to_string())).await.unwrap();
+        let done = loop {
+            let event = next_event(&mut socket).await;
+            if event["type"] != "chunk" {
+                break event;
+            }
+        };
+        assert_eq!(done["output"], "echo 3: Hi");
+
+        // Resetting clears the history, invalid messages are answered with an error
+        socket.send(Message::Text(r#"{"type": "reset"}"#.to_string())).await.unwrap();
+        assert_eq!(next_event(&mut socket).await, json!({ "type": "reset" }));
+        socket.send(Message::Text(r#"{"type": "turn", "input": "Hi"}"#.to_string())).await.unwrap();
+        let done = loop {
+            let event = next_event(&mut socket).await;
+            if event["type"] != "chunk" {
+                break event;
+            }
+        };
+        assert_eq!(done["output"], "echo 1: Hi");
+        socket.send(Message::Text(r#"{"type": "other"}"#.to_string())).await.unwrap();
+        assert_eq!(next_event(&mut socket).await["error"]["code"], "invalid_request");
+        socket.close(None).await.unwrap();
+
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,398 @@
+// src/http/openai.rs
+// OpenAI compatible completion endpoints, the model field is the name of the model in the AvailableModels table
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use super::{json_response, read_json, ApiError, HttpApi};
+use crate::auth;
+use crate::dal::ApiKey;
+use crate::meal::pool::ModelPool;
+use crate::meal::protocol::{MEALRequest, ProtocolKind};
+
+
+// Prompt of a completion, a single one or one per choice
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Prompt {
+    One(String),
+    Many(Vec<String>),
+}
+
+// Content of a chat message, a string or a list of parts of which only the text parts are used
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<MessagePart>),
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagePart {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<MessageContent>,
+}
+
+// Generation options shared by both endpoints, other OpenAI fields are accepted and ignored
+#[derive(Debug, Default, Deserialize)]
+struct GenerationOptions {
+    #[serde(default)]
+    max_tokens: Option<u64>,
+    #[serde(default)]
+    max_completion_tokens: Option<u64>,
+    #[serde(default)]
+    temperature: Option<f64>,
+    #[serde(default)]
+    top_p: Option<f64>,
+    #[serde(default)]
+    n: Option<usize>,
+    #[serde(default)]
+    stream: bool,
+    // Not part of the OpenAI API, continues a session created with the REPL or another front end
+    #[serde(default)]
+    session: Option<String>,
+}
+
+// Body of POST /v1/completions
+#[derive(Debug, Deserialize)]
+struct CompletionBody {
+    model: String,
+    prompt: Prompt,
+    #[serde(flatten)]
+    options: GenerationOptions,
+}
+
+// Body of POST /v1/chat/completions
+#[derive(Debug, Deserialize)]
+struct ChatCompletionBody {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(flatten)]
+    options: GenerationOptions,
+}
+
+// Completion that is answered once or streamed as server-sent events
+struct Completion {
+    id: String,
+    chat: bool,
+    model: String,
+    created: i64,
+    // Input of every choice, in the order of their index
+    inputs: Vec<String>,
+    params: HashMap<String, Value>,
+    session: Option<String>,
+    // Session opened for this completion only, closed once it is answered
+    temporary_session: bool,
+    // API key of the caller
+    caller: Option<ApiKey>,
+}
+
+
+impl HttpApi {
+    // Answer the requests below /v1, errors are formatted like the OpenAI errors
+    pub(super) async fn openai(&self, request: Request<Body>) -> Response<Body> {
+        let path = request.uri().path().trim_end_matches('/').to_string();
+        let caller = match self.keys.authenticate(request.headers()) {
+            Ok(caller) => caller,
+            Err(err) => return openai_error(&ApiError::from_pool(err)),
+        };
+        let result = match (request.method(), path.as_str()) {
+            (&Method::GET, "/v1/models") => Ok(self.openai_models(caller.as_ref())),
+            (&Method::GET, model) if model.starts_with("/v1/models/") => self.openai_model(&model["/v1/models/".len()..], caller.as_ref()),
+            (&Method::POST, "/v1/completions") => match read_json::<CompletionBody>(request.into_body()).await {
+                Ok(body) => self.completions(body, caller).await,
+                Err(err) => Err(err),
+            },
+            (&Method::POST, "/v1/chat/completions") => match read_json::<ChatCompletionBody>(request.into_body()).await {
+                Ok(body) => self.chat_completions(body, caller).await,
+                Err(err) => Err(err),
+            },
+            _ => Err(ApiError::not_found(&format!("No route for {}", path))),
+        };
+        result.unwrap_or_else(|err| openai_error(&err))
+    }
+
+    // List the models the caller may execute like GET /v1/models
+    fn openai_models(&self, caller: Option<&ApiKey>) -> Response<Body> {
+        let models: Vec<Value> = self.model_pool.model_names().iter()
+            .filter(|name| auth::authorize_model(caller, name).is_ok())
+            .map(|name| model_object(name))
+            .collect();
+        json_response(StatusCode::OK, &json!({ "object": "list", "data": models }))
+    }
+
+    // Describe a model like GET /v1/models/{model}
+    fn openai_model(&self, name: &str, caller: Option<&ApiKey>) -> Result<Response<Body>, ApiError> {
+        auth::authorize_model(caller, name).map_err(ApiError::from_pool)?;
+        match self.model_pool.instances(name) {
+            Some(_) => Ok(json_response(StatusCode::OK, &model_object(name))),
+            None => Err(model_not_found(name)),
+        }
+    }
+
+    // Complete the prompts, every prompt is answered n times
+    async fn completions(&self, body: CompletionBody, caller: Option<ApiKey>) -> Result<Response<Body>, ApiError> {
+        let prompts = match body.prompt {
+            Prompt::One(prompt) => vec![prompt],
+            Prompt::Many(prompts) => prompts,
+        };
+        if prompts.is_empty() {
+            return Err(ApiError::bad_request("The prompt is empty"));
+        }
+        let n = choices(&body.options)?;
+        let inputs = prompts.iter().flat_map(|prompt| std::iter::repeat_n(prompt.clone(), n)).collect();
+        auth::authorize_model(caller.as_ref(), &body.model).map_err(ApiError::from_pool)?;
+        let mut completion = self.completion(false, &body.model, inputs, &body.options, body.options.session.clone(), false)?;
+        completion.caller = caller;
+        self.answer(completion, body.options.stream).await
+    }
+
+    // Answer the last user message of a chat, the model keeps the history of its sessions itself
+    async fn chat_completions(&self, body: ChatCompletionBody, caller: Option<ApiKey>) -> Result<Response<Body>, ApiError> {
+        let input = body.messages.iter().rev()
+            .find(|message| message.role == "user")
+            .and_then(|message| message.content.as_ref())
+            .map(|content| match content {
+                MessageContent::Text(text) => text.clone(),
+                MessageContent::Parts(parts) => parts.iter()
+                    .filter(|part| part.kind == "text")
+                    .filter_map(|part| part.text.clone())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })
+            .ok_or_else(|| ApiError::bad_request("The messages have no user message"))?;
+        let n = choices(&body.options)?;
+        auth::authorize_model(caller.as_ref(), &body.model).map_err(ApiError::from_pool)?;
+        if self.model_pool.instances(&body.model).is_none() {
+            return Err(model_not_found(&body.model));
+        }
+
+        // Without a session the chat runs in a session of its own, so it does not continue the history of other clients
+        let (session, temporary_session) = match body.options.session.clone() {
+            Some(session) => (Some(session), false),
+            None => match self.model_pool.create_session(&body.model).await {
+                Ok(session) => (Some(session), true),
+                // Models without sessions answer with their default history
+                Err(_) if !self.model_pool.capabilities(&body.model).map(|capabilities| capabilities.sessions).unwrap_or(false) => (None, false),
+                Err(err) => return Err(ApiError::from_pool(err)),
+            },
+        };
+        let mut completion = self.completion(true, &body.model, vec![input; n], &body.options, session, temporary_session)?;
+        completion.caller = caller;
+        self.answer(completion, body.options.stream).await
+    }
+
+    // Build a completion with the generation options the model accepts
+    fn completion(&self, chat: bool, model: &str, inputs: Vec<String>, options: &GenerationOptions, session: Option<String>, temporary_session: bool) -> Result<Completion, ApiError> {
+        if self.model_pool.instances(model).is_none() {
+            return Err(model_not_found(model));
+        }
+        let created = chrono::Utc::now();
+        let mut params = HashMap::new();
+        if let Some(max_tokens) = options.max_completion_tokens.or(options.max_tokens) {
+            params.insert("max_new_tokens".to_string(), json!(max_tokens));
+        }
+        if let Some(temperature) = options.temperature {
+            params.insert("temperature".to_string(), json!(temperature));
+            params.insert("do_sample".to_string(), json!(temperature > 0.0));
+        }
+        if let Some(top_p) = options.top_p {
+            params.insert("top_p".to_string(), json!(top_p));
+        }
+        // Options are only forwarded to jsonl models that announced to accept them
+        let accepted = self.model_pool.instances(model).unwrap_or_default().iter()
+            .find_map(|instance| instance.try_read().ok().and_then(|meal| {
+                let kind = meal.protocol().kind;
+                meal.capabilities().map(|capabilities| (kind, capabilities.parameters))
+            }));
+        params.retain(|name, _| match &accepted {
+            Some((ProtocolKind::Jsonl, Some(parameters))) => parameters.contains(name),
+            Some((ProtocolKind::Jsonl, None)) => true,
+            _ => false,
+        });
+
+        Ok(Completion {
+            id: format!("{}-{:x}", if chat { "chatcmpl" } else { "cmpl" }, created.timestamp_micros()),
+            chat,
+            model: model.to_string(),
+            created: created.timestamp(),
+            inputs,
+            params,
+            session,
+            temporary_session,
+            caller: None,
+        })
+    }
+
+    // Answer a completion at once or stream it as server-sent events
+    async fn answer(&self, completion: Completion, stream: bool) -> Result<Response<Body>, ApiError> {
+        if stream {
+            let (sender, body) = Body::channel();
+            tokio::spawn(stream_completion(Arc::clone(&self.model_pool), completion, sender));
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .body(body)
+                .unwrap());
+        }
+
+        let mut results = Vec::new();
+        for input in &completion.inputs {
+            results.push(infer(&self.model_pool, &completion, input, None).await);
+        }
+        close_session(&self.model_pool, &completion).await;
+        let mut choices = Vec::new();
+        for (index, result) in results.into_iter().enumerate() {
+            let output = result?;
+            choices.push(match completion.chat {
+                true => json!({ "index": index, "message": { "role": "assistant", "content": output }, "finish_reason": "stop" }),
+                false => json!({ "index": index, "text": output, "logprobs": null, "finish_reason": "stop" }),
+            });
+        }
+        Ok(json_response(StatusCode::OK, &json!({
+            "id": completion.id,
+            "object": if completion.chat { "chat.completion" } else { "text_completion" },
+            "created": completion.created,
+            "model": completion.model,
+            "choices": choices,
+        })))
+    }
+}
+
+
+// Run the request of a choice, forwarding the output chunks when streaming
+async fn infer(model_pool: &ModelPool, completion: &Completion, input: &str, chunk_tx: Option<mpsc::UnboundedSender<String>>) -> Result<String, ApiError> {
+    let mut request = MEALRequest::new(input);
+    request.params = completion.params.clone();
+    request.session = completion.session.clone();
+    let response = match chunk_tx {
+        Some(chunk_tx) => model_pool.infer_stream(&completion.model, request, completion.caller.as_ref(), chunk_tx).await,
+        None => model_pool.infer(&completion.model, request, completion.caller.as_ref()).await,
+    }.map_err(ApiError::from_pool)?;
+    match (response.output, response.error) {
+        (_, Some(error)) => Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, &error.code, &error.message)),
+        (output, None) => Ok(output.unwrap_or_default()),
+    }
+}
+
+// Stream the choices one after another as chunks, the stream ends with a finish chunk per choice and [DONE]
+async fn stream_completion(model_pool: Arc<ModelPool>, completion: Completion, mut sender: hyper::body::Sender) {
+    let object = if completion.chat { "chat.completion.chunk" } else { "text_completion" };
+    let event = |choice: Value| json!({
+        "id": completion.id,
+        "object": object,
+        "created": completion.created,
+        "model": completion.model,
+        "choices": [choice],
+    });
+    let delta = |index: usize, chunk: &str, first: bool| match (completion.chat, first) {
+        (true, true) => json!({ "index": index, "delta": { "role": "assistant", "content": chunk }, "finish_reason": null }),
+        (true, false) => json!({ "index": index, "delta": { "content": chunk }, "finish_reason": null }),
+        (false, _) => json!({ "index": index, "text": chunk, "logprobs": null, "finish_reason": null }),
+    };
+
+    let mut failed = None;
+    'choices: for (index, input) in completion.inputs.iter().enumerate() {
+        let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel();
+        let response = infer(&model_pool, &completion, input, Some(chunk_tx));
+        tokio::pin!(response);
+        let mut first = true;
+        let result = loop {
+            tokio::select! {
+                result = &mut response => break result,
+                Some(chunk) = chunk_rx.recv() => {
+                    if send_event(&mut sender, &event(delta(index, &chunk, first))).await.is_err() {
+                        // The client is gone, the request still finishes so the model stays in sync
+                        let _ = response.await;
+                        break 'choices;
+                    }
+                    first = false;
+                }
+            }
+        };
+        while let Ok(chunk) = chunk_rx.try_recv() {
+            let _ = send_event(&mut sender, &event(delta(index, &chunk, first))).await;
+            first = false;
+        }
+        if let Err(err) = result {
+            failed = Some(err);
+            break;
+        }
+    }
+
+    match failed {
+        Some(err) => {
+            let _ = send_event(&mut sender, &openai_error_body(&err)).await;
+        }
+        None => {
+            for index in 0..completion.inputs.len() {
+                let finish = match completion.chat {
+                    true => json!({ "index": index, "delta": {}, "finish_reason": "stop" }),
+                    false => json!({ "index": index, "text": "", "logprobs": null, "finish_reason": "stop" }),
+                };
+                let _ = send_event(&mut sender, &event(finish)).await;
+            }
+        }
+    }
+    let _ = sender.send_data("data: [DONE]\n\n".into()).await;
+    close_session(&model_pool, &completion).await;
+}
+
+// Send a server-sent event with a JSON payload
+async fn send_event(sender: &mut hyper::body::Sender, data: &Value) -> Result<(), hyper::Error> {
+    sender.send_data(format!("data: {}\n\n", data).into()).await
+}
+
+// Close the session opened for a completion
+async fn close_session(model_pool: &ModelPool, completion: &Completion) {
+    if let (true, Some(session)) = (completion.temporary_session, &completion.session) {
+        if let Err(err) = model_pool.close_session(session).await {
+            log::warn!("Failed to close the completion session {}: {}", session, err);
+        }
+    }
+}
+
+// Get the number of choices per prompt
+fn choices(options: &GenerationOptions) -> Result<usize, ApiError> {
+    match options.n.unwrap_or(1) {
+        0 => Err(ApiError::bad_request("n must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+fn model_object(name: &str) -> Value {
+    json!({ "id": name, "object": "model", "created": 0, "owned_by": "mer-driver" })
+}
+
+fn model_not_found(name: &str) -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "model_not_found", &format!("The model {} does not exist", name))
+}
+
+// Format an error like the OpenAI API
+fn openai_error_body(err: &ApiError) -> Value {
+    let kind = match (err.status, err.code.as_str()) {
+        (status, _) if status.is_server_error() => "server_error",
+        (_, "rate_limited") => "requests",
+        (_, "quota_exceeded") => "insufficient_quota",
+        _ => "invalid_request_error",
+    };
+    json!({ "error": { "message": err.message, "type": kind, "param": null, "code": err.code } })
+}
+
+fn openai_error(err: &ApiError) -> Response<Body> {
+    err.with_retry_after(json_response(err.status, &openai_error_body(err)))
+}
@@ -0,0 +1,613 @@
+// src/jobs/mod.rs
+// Std lib imports
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Webhooks are posted with reqwest and signed with HMAC-SHA256
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+
+// Custom modules
+use crate::auth::KeyStore;
+use crate::dal::{ApiKey, InferenceJob, InferenceJobInput, InferenceJobResult, JobState};
+use crate::meal::pool::ModelPool;
+use crate::meal::protocol::MEALRequest;
+use crate::meal::quota;
+
+// Number of inputs of a job sent to the model at a time, unless the job asks for another one
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const MAX_CONCURRENCY: usize = 64;
+// Largest number of inputs of a job
+pub const MAX_INPUTS: usize = 100_000;
+// Number of finished jobs kept in memory and loaded on startup, they stay in the DB
+pub const MAX_FINISHED_JOBS: usize = 100;
+// Interval at which the results of a running job are stored
+const PROGRESS_RECORD_INTERVAL: Duration = Duration::from_secs(5);
+// Delays of the retries of a failed webhook delivery and the time a delivery may take
+const WEBHOOK_RETRY_DELAYS: [Duration; 3] = [Duration::from_secs(1), Duration::from_secs(5), Duration::from_secs(25)];
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+// Job as submitted to the HTTP and JSON-RPC APIs, either a single input or a list of inputs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSubmission {
+    pub model: String,
+    #[serde(default)]
+    pub input: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<SubmittedInput>,
+    #[serde(default)]
+    pub params: HashMap<String, Value>,
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+// Input of a submitted job, inputs without an id are named after their position
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SubmittedInput {
+    Text(String),
+    Input {
+        #[serde(default)]
+        id: Option<String>,
+        input: String,
+    },
+}
+
+#[derive(Debug)]
+struct JobEntry {
+    job: InferenceJob,
+    cancel_tx: watch::Sender<bool>,
+}
+
+
+// Runs batches of inputs against the models in the background, the jobs are stored while they run and the
+// unfinished ones are continued after a restart of the driver
+#[derive(Debug)]
+pub struct InferenceJobs {
+    model_pool: Arc<ModelPool>,
+    jobs: Mutex<HashMap<String, JobEntry>>,
+    // Receives the jobs on every state change and the results every few seconds
+    record_tx: Mutex<Option<mpsc::UnboundedSender<InferenceJob>>>,
+    // Set on shutdown, the jobs stop sending inputs and stay running for the next start
+    stop_tx: watch::Sender<bool>,
+    // Runners of the jobs and webhook deliveries, awaited on shutdown
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    client: reqwest::Client,
+    // Secret the webhook bodies are signed with
+    webhook_secret: Option<String>,
+    // Hosts the webhooks may be posted to even if they are private
+    webhook_hosts: Vec<String>,
+    counter: AtomicU64,
+}
+
+impl InferenceJobs {
+    pub fn new(model_pool: Arc<ModelPool>, webhook_secret: Option<String>, webhook_hosts: Vec<String>) -> Self {
+        // Redirects are not followed, they could lead a webhook to a private address
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(WebhookResolver { allowed_hosts: webhook_hosts.clone() }))
+            .build()
+            .unwrap_or_default();
+        Self {
+            model_pool,
+            jobs: Mutex::new(HashMap::new()),
+            record_tx: Mutex::new(None),
+            stop_tx: watch::channel(false).0,
+            tasks: Mutex::new(Vec::new()),
+            client,
+            webhook_secret,
+            webhook_hosts,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    // Send the jobs to the channel on every state change and while they run
+    pub fn record(&self, record_tx: mpsc::UnboundedSender<InferenceJob>) {
+        *self.record_tx.lock().unwrap() = Some(record_tx);
+    }
+
+    // Take over the jobs stored by a previous run, unfinished jobs continue with the inputs without a result
+    // and finished jobs deliver their webhook if it was not delivered yet
+    pub fn resume(self: &Arc<Self>, jobs: Vec<InferenceJob>, keys: &KeyStore) {
+        let api_keys = keys.keys();
+        for job in jobs {
+            let id = job.id.clone();
+            let (state, webhook_pending) = (job.state, job.webhook.is_some() && job.webhook_status.is_none());
+            let caller = job.caller.as_ref().map(|key_id| api_keys.iter().find(|key| key.key_id == *key_id).cloned());
+            let (cancel_tx, cancel_rx) = watch::channel(false);
+            self.jobs.lock().unwrap().insert(id.clone(), JobEntry { job, cancel_tx });
+
+            if state.is_finished() {
+                if webhook_pending {
+                    self.spawn(Arc::clone(self).deliver(id));
+                }
+                continue;
+            }
+            match caller {
+                Some(None) => {
+                    log::warn!("Failing the inference job {}, its API key was deleted", id);
+                    self.finish(&id, JobState::Failed, Some("Unauthorized: The API key of the job was deleted".to_string()));
+                }
+                caller => {
+                    log::info!("Resuming the inference job {}", id);
+                    self.spawn(Arc::clone(self).run(id, caller.flatten(), cancel_rx));
+                }
+            }
+        }
+    }
+
+    // Queue a job of the caller and return it, the caller must be allowed to execute the model
+    pub fn submit(self: &Arc<Self>, caller: Option<ApiKey>, submission: JobSubmission) -> Result<InferenceJob, String> {
+        if *self.stop_tx.borrow() || self.model_pool.is_shutting_down() {
+            return Err("The driver is shutting down".to_string());
+        }
+        if self.model_pool.instances(&submission.model).is_none() {
+            return Err(format!("Model {} not found", submission.model));
+        }
+        let inputs = job_inputs(submission.input, submission.inputs)?;
+        let concurrency = submission.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        if concurrency == 0 || concurrency > MAX_CONCURRENCY {
+            return Err(format!("Invalid request: The concurrency must be between 1 and {}", MAX_CONCURRENCY));
+        }
+        if let Some(webhook) = &submission.webhook {
+            self.check_webhook(webhook)?;
+        }
+
+        let id = format!(
+            "{}-job-{:x}{:04x}",
+            submission.model, chrono::Utc::now().timestamp_micros(), self.counter.fetch_add(1, Ordering::Relaxed) & 0xffff
+        );
+        let job = InferenceJob {
+            id: id.clone(),
+            model: submission.model,
+            caller: caller.as_ref().map(|key| key.key_id.clone()),
+            params: submission.params,
+            inputs,
+            results: Vec::new(),
+            concurrency,
+            webhook: submission.webhook,
+            webhook_status: None,
+            state: JobState::Queued,
+            error: None,
+            created_at: chrono::Utc::now(),
+            started_at: None,
+            finished_at: None,
+        };
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        self.send_record(&job);
+        self.prune();
+        self.jobs.lock().unwrap().insert(id.clone(), JobEntry { job: job.clone(), cancel_tx });
+        log::info!("Queued the inference job {} of model {} with {} inputs", id, job.model, job.inputs.len());
+
+        self.spawn(Arc::clone(self).run(id, caller, cancel_rx));
+        Ok(job)
+    }
+
+    // Get a job
+    pub fn job(&self, id: &str) -> Option<InferenceJob> {
+        self.jobs.lock().unwrap().get(id).map(|entry| entry.job.clone())
+    }
+
+    // Get the jobs, the newest first
+    pub fn jobs(&self) -> Vec<InferenceJob> {
+        let mut jobs: Vec<InferenceJob> = self.jobs.lock().unwrap().values().map(|entry| entry.job.clone()).collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    // Cancel a queued or running job, the inputs the model is answering are cancelled as well
+    pub fn cancel(&self, id: &str) -> Result<(), String> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get(id).ok_or_else(|| format!("Inference job {} not found", id))?;
+        if entry.job.state.is_finished() {
+            return Err(format!("Inference job {} already {}", id, entry.job.state));
+        }
+        let _ = entry.cancel_tx.send(true);
+        Ok(())
+    }
+
+    // Stop sending inputs to the models before the pool drains, the running jobs are continued on the next start
+    pub fn stop(&self) {
+        let _ = self.stop_tx.send(true);
+    }
+
+    // Wait up to the timeout for the jobs to store their results and for the webhook deliveries to end
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.stop();
+        let tasks: Vec<JoinHandle<()>> = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let wait_all = async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        };
+        if tokio::time::timeout(timeout, wait_all).await.is_err() {
+            log::error!("The inference jobs did not stop within {:?}", timeout);
+        }
+
+        // Close the record channel so the writer can store the last states and stop
+        self.record_tx.lock().unwrap().take();
+    }
+
+    // Send the inputs without a result to the model, up to the concurrency of the job at a time, until every
+    // input is answered, the job is cancelled or the driver stops
+    async fn run(self: Arc<Self>, id: String, caller: Option<ApiKey>, mut cancel_rx: watch::Receiver<bool>) {
+        let job = match self.job(&id) {
+            Some(job) => job,
+            None => return,
+        };
+        let answered: HashSet<&str> = job.results.iter().map(|result| result.id.as_str()).collect();
+        let mut pending: VecDeque<InferenceJobInput> = job.inputs.iter()
+            .filter(|input| !answered.contains(input.id.as_str()))
+            .cloned()
+            .collect();
+        self.update(&id, |job| {
+            job.state = JobState::Running;
+            job.started_at.get_or_insert_with(chrono::Utc::now);
+        });
+
+        let mut stop_rx = self.stop_tx.subscribe();
+        let context = JobContext {
+            model_pool: Arc::clone(&self.model_pool),
+            model: job.model.clone(),
+            params: job.params.clone(),
+            caller,
+            stop_rx: stop_rx.clone(),
+            cancel_rx: cancel_rx.clone(),
+        };
+        let mut running = JoinSet::new();
+        let mut in_flight: HashSet<String> = HashSet::new();
+        let mut cancelled = false;
+        let mut last_record = Instant::now();
+        loop {
+            while running.len() < job.concurrency && !cancelled && !*stop_rx.borrow() {
+                let input = match pending.pop_front() {
+                    Some(input) => input,
+                    None => break,
+                };
+                let request_id = format!("{}-{}", id, input.id);
+                in_flight.insert(request_id.clone());
+                running.spawn(context.clone().infer(request_id, input));
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            tokio::select! {
+                Some(joined) = running.join_next() => match joined {
+                    Ok((request_id, _, Some(result))) => {
+                        in_flight.remove(&request_id);
+                        self.push_result(&id, result, &mut last_record);
+                    }
+                    // Inputs the driver stopped before they were answered are sent again on the next start
+                    Ok((request_id, input, None)) => {
+                        in_flight.remove(&request_id);
+                        pending.push_front(input);
+                    }
+                    Err(err) => log::error!("An input of the inference job {} failed: {}", id, err),
+                },
+                Ok(()) = cancel_rx.changed(), if !cancelled => {
+                    cancelled = true;
+                    log::info!("Cancelling the {} inputs of the inference job {} in flight", in_flight.len(), id);
+                    for request_id in &in_flight {
+                        let _ = self.model_pool.cancel(&job.model, request_id).await;
+                    }
+                }
+                Ok(()) = stop_rx.changed() => {}
+            }
+        }
+
+        if cancelled {
+            self.finish(&id, JobState::Cancelled, None);
+        } else if !pending.is_empty() {
+            log::info!("Pausing the inference job {} with {} inputs left until the next start", id, pending.len());
+            if let Some(job) = self.job(&id) {
+                self.send_record(&job);
+            }
+        } else {
+            self.finish(&id, JobState::Succeeded, None);
+        }
+    }
+
+    // End a job with its results in the order of the inputs and post it to its webhook
+    fn finish(self: &Arc<Self>, id: &str, state: JobState, error: Option<String>) {
+        match &error {
+            Some(error) => log::error!("Inference job {} {}: {}", id, state, error),
+            None => log::info!("Inference job {} {}", id, state),
+        }
+        let mut webhook = false;
+        self.update(id, |job| {
+            job.results = ordered_results(job).into_iter().cloned().collect();
+            job.state = state;
+            job.error = error;
+            job.finished_at = Some(chrono::Utc::now());
+            webhook = job.webhook.is_some();
+        });
+        if webhook {
+            self.spawn(Arc::clone(self).deliver(id.to_string()));
+        }
+    }
+
+    // Check that a webhook is an HTTP URL the driver may post to, hosts given by name are checked when they are
+    // resolved on delivery
+    fn check_webhook(&self, webhook: &str) -> Result<(), String> {
+        let url = match reqwest::Url::parse(webhook) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => url,
+            _ => return Err(format!("Invalid request: The webhook {:#?} is not an HTTP URL", webhook)),
+        };
+        let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+        if self.webhook_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+            return Ok(());
+        }
+        match host.parse::<IpAddr>() {
+            Ok(ip) if is_private_address(ip) => Err(format!("Invalid request: The webhook {:#?} points to a private address", webhook)),
+            _ => Ok(()),
+        }
+    }
+
+    // Post the finished job with its results to its webhook, failed deliveries are retried a few times and
+    // deliveries the driver stops are made again on the next start
+    async fn deliver(self: Arc<Self>, id: String) {
+        let job = match self.job(&id) {
+            Some(job) => job,
+            None => return,
+        };
+        let url = match &job.webhook {
+            Some(url) => url.clone(),
+            None => return,
+        };
+        let body = job_json(&job, true).to_string();
+        let mut stop_rx = self.stop_tx.subscribe();
+        let mut status = String::new();
+        for delay in std::iter::once(Duration::ZERO).chain(WEBHOOK_RETRY_DELAYS) {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop_rx.wait_for(|stopped| *stopped) => return,
+            }
+            let mut request = self.client.post(&url).header("Content-Type", "application/json").body(body.clone());
+            if let Some(secret) = &self.webhook_secret {
+                request = request.header("X-MER-Signature", format!("sha256={}", sign(secret, &body)));
+            }
+            status = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    log::info!("Delivered the inference job {} to its webhook", id);
+                    "delivered".to_string()
+                }
+                Ok(response) => format!("failed: the webhook answered with {}", response.status()),
+                Err(err) => format!("failed: {}", err),
+            };
+            if status == "delivered" {
+                break;
+            }
+            log::warn!("Failed to deliver the inference job {} to its webhook: {}", id, status);
+        }
+        self.update(&id, |job| job.webhook_status = Some(status));
+    }
+
+    // Keep the result of an input, the results are stored every few seconds
+    fn push_result(&self, id: &str, result: InferenceJobResult, last_record: &mut Instant) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = match jobs.get_mut(id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.job.results.push(result);
+        if last_record.elapsed() >= PROGRESS_RECORD_INTERVAL {
+            *last_record = Instant::now();
+            let job = entry.job.clone();
+            drop(jobs);
+            self.send_record(&job);
+        }
+    }
+
+    // Change a job and store it
+    fn update(&self, id: &str, change: impl FnOnce(&mut InferenceJob)) {
+        let job = match self.jobs.lock().unwrap().get_mut(id) {
+            Some(entry) => {
+                change(&mut entry.job);
+                entry.job.clone()
+            }
+            None => return,
+        };
+        self.send_record(&job);
+    }
+
+    fn send_record(&self, job: &InferenceJob) {
+        if let Some(record_tx) = self.record_tx.lock().unwrap().as_ref() {
+            if record_tx.send(job.clone()).is_err() {
+                log::warn!("Dropping the state of inference job {}, the inference job writer stopped", job.id);
+            }
+        }
+    }
+
+    // Run a task of the jobs, the finished ones are forgotten
+    fn spawn(&self, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
+
+    // Forget the oldest finished jobs beyond the number kept in memory
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(chrono::DateTime<chrono::Utc>, String)> = jobs.values()
+            .filter(|entry| entry.job.state.is_finished())
+            .map(|entry| (entry.job.created_at, entry.job.id.clone()))
+            .collect();
+        if finished.len() < MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() + 1 - MAX_FINISHED_JOBS) {
+            jobs.remove(id);
+        }
+    }
+}
+
+
+// What the inputs of a running job are sent with
+#[derive(Clone)]
+struct JobContext {
+    model_pool: Arc<ModelPool>,
+    model: String,
+    params: HashMap<String, Value>,
+    caller: Option<ApiKey>,
+    stop_rx: watch::Receiver<bool>,
+    cancel_rx: watch::Receiver<bool>,
+}
+
+impl JobContext {
+    // Send an input to the model until it is answered, rate limited and over quota inputs wait and are sent again,
+    // None if the driver stopped or the job was cancelled before the input was answered
+    async fn infer(mut self, request_id: String, input: InferenceJobInput) -> (String, InferenceJobInput, Option<InferenceJobResult>) {
+        loop {
+            let mut request = MEALRequest::new(&input.input);
+            request.id = request_id.clone();
+            request.params = self.params.clone();
+            let result = match self.model_pool.infer(&self.model, request, self.caller.as_ref()).await {
+                Ok(response) => InferenceJobResult {
+                    id: input.id.clone(),
+                    output: response.output,
+                    error: response.error.map(|error| format!("{}: {}", error.code, error.message)),
+                },
+                Err(err) if err.starts_with("The driver is shutting down") => return (request_id, input, None),
+                Err(err) => match quota::retry_after(&err) {
+                    Some(seconds) => {
+                        log::debug!("Sending the input {} of model {} again in {}s: {}", request_id, self.model, seconds, err);
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_secs(seconds)) => continue,
+                            _ = self.stop_rx.wait_for(|stopped| *stopped) => return (request_id, input, None),
+                            _ = self.cancel_rx.wait_for(|cancelled| *cancelled) => return (request_id, input, None),
+                        }
+                    }
+                    None => InferenceJobResult { id: input.id.clone(), output: None, error: Some(err) },
+                },
+            };
+            return (request_id, input, Some(result));
+        }
+    }
+}
+
+// Name the inputs of a submitted job, every input needs a distinct id
+fn job_inputs(input: Option<String>, inputs: Vec<SubmittedInput>) -> Result<Vec<InferenceJobInput>, String> {
+    let inputs: Vec<InferenceJobInput> = match (input, inputs.is_empty()) {
+        (Some(input), true) => vec![InferenceJobInput { id: "0".to_string(), input }],
+        (None, false) => inputs.into_iter().enumerate()
+            .map(|(position, input)| match input {
+                SubmittedInput::Text(input) => InferenceJobInput { id: position.to_string(), input },
+                SubmittedInput::Input { id, input } => InferenceJobInput { id: id.unwrap_or_else(|| position.to_string()), input },
+            })
+            .collect(),
+        (Some(_), false) => return Err("Invalid request: A job has either an input or a list of inputs".to_string()),
+        (None, true) => return Err("Invalid request: A job needs an input or a list of inputs".to_string()),
+    };
+    if inputs.len() > MAX_INPUTS {
+        return Err(format!("Invalid request: A job has at most {} inputs", MAX_INPUTS));
+    }
+    let mut ids = HashSet::new();
+    if let Some(input) = inputs.iter().find(|input| !ids.insert(input.id.as_str())) {
+        return Err(format!("Invalid request: The input id {:#?} is used more than once", input.id));
+    }
+    Ok(inputs)
+}
+
+// Resolves the hosts of the webhooks without their private addresses, so a webhook can not reach the services
+// of the driver host or its network unless the host is allowed
+#[derive(Debug)]
+struct WebhookResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for WebhookResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let allowed = self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host));
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?
+                .filter(|addr| allowed || !is_private_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("The webhook host {} only resolves to private addresses", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Check if an address is not publicly routable: loopback, private, link-local (including the cloud metadata
+// services), shared, unspecified, broadcast or multicast
+fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+                || ip.is_multicast() || first == 0 || (first == 100 && (64..128).contains(&second))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_address(IpAddr::V4(ip)),
+            None => ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || ip.is_unique_local() || ip.is_unicast_link_local(),
+        },
+    }
+}
+
+// Hex encoded HMAC-SHA256 of the webhook body, the receiver verifies it with the shared secret
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Check that the caller may see and cancel the job, callers see their own jobs and admin keys every job
+pub fn authorize_job(caller: Option<&ApiKey>, job: &InferenceJob) -> Result<(), String> {
+    match caller {
+        Some(key) if key.admin => Ok(()),
+        caller if caller.map(|key| &key.key_id) == job.caller.as_ref() => Ok(()),
+        _ => Err(format!("Inference job {} not found", job.id)),
+    }
+}
+
+// Describe a job with the counts of its results, and the results in the order of the inputs if asked for
+pub fn job_json(job: &InferenceJob, results: bool) -> Value {
+    let failed = job.results.iter().filter(|result| result.error.is_some()).count();
+    let mut description = json!({
+        "jobId": job.id,
+        "model": job.model,
+        "caller": job.caller,
+        "state": job.state,
+        "inputs": job.inputs.len(),
+        "completed": job.results.len(),
+        "failed": failed,
+        "concurrency": job.concurrency,
+        "webhook": job.webhook,
+        "webhookStatus": job.webhook_status,
+        "error": job.error,
+        "createdAt": job.created_at.to_rfc3339(),
+        "startedAt": job.started_at.map(|started_at| started_at.to_rfc3339()),
+        "finishedAt": job.finished_at.map(|finished_at| finished_at.to_rfc3339()),
+    });
+    if results {
+        description["results"] = json!(ordered_results(job));
+    }
+    description
+}
+
+// Get the results of a job in the order of its inputs
+pub fn ordered_results(job: &InferenceJob) -> Vec<&InferenceJobResult> {
+    let positions: HashMap<&str, usize> = job.inputs.iter().enumerate().map(|(position, input)| (input.id.as_str(), position)).collect();
+    let mut results: Vec<&InferenceJobResult> = job.results.iter().collect();
+    results.sort_by_key(|result| positions.get(result.id.as_str()).copied().unwrap_or(usize::MAX));
+    results
+}
@@ -0,0 +1,977 @@
+// src/jsonrpc/mod.rs
+// Std lib imports
+use std::collections::HashMap;
+use std::future::Future;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// JSON-RPC 2.0 over newline delimited JSON on a Unix socket or stdin/stdout
+use futures_util::future::join_all;
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+
+// Custom modules
+use crate::auth::KeyStore;
+use crate::dal::{self, ApiKey, DAL, TranscriptQuery, UsageLimits};
+use crate::http::ApiError;
+use crate::jobs::{self, InferenceJobs, JobSubmission};
+use crate::meal::feedback::Rating;
+use crate::meal::pool::{instance_state, ModelListing, ModelPool};
+use crate::meal::protocol::MEALRequest;
+use crate::meal::training::TrainingSpec;
+
+// Number of buffered log and training output lines returned by default, like in the REPL
+const DEFAULT_LOG_LINES: usize = 50;
+
+// Error codes defined by the JSON-RPC 2.0 specification
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+// Error codes of the driver, the error code of the HTTP API or the model is kept in error.data.code
+pub const NOT_FOUND: i64 = -32001;
+pub const PERMISSION_DENIED: i64 = -32002;
+pub const MODEL_ERROR: i64 = -32003;
+pub const MODEL_UNAVAILABLE: i64 = -32004;
+pub const SHUTTING_DOWN: i64 = -32005;
+pub const RATE_LIMITED: i64 = -32006;
+
+
+// Error object of a response
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Value,
+}
+
+impl RpcError {
+    pub fn new(code: i64, error_code: &str, message: &str) -> Self {
+        Self { code, message: message.to_string(), data: json!({ "code": error_code }) }
+    }
+
+    pub fn invalid_params(message: &str) -> Self {
+        Self::new(INVALID_PARAMS, "invalid_request", message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(NOT_FOUND, "not_found", message)
+    }
+
+    // Errors of the pool like in the HTTP API, sessions and training jobs are not found the same way as models
+    pub fn from_pool(message: String) -> Self {
+        match message.ends_with(" not found") {
+            true => Self::not_found(&message),
+            false => ApiError::from_pool(message).into(),
+        }
+    }
+}
+
+// Errors are shared with the HTTP API
+impl From<ApiError> for RpcError {
+    fn from(err: ApiError) -> Self {
+        let code = match err.status {
+            StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE => INVALID_PARAMS,
+            StatusCode::NOT_FOUND => NOT_FOUND,
+            StatusCode::UNPROCESSABLE_ENTITY => MODEL_ERROR,
+            StatusCode::BAD_GATEWAY => MODEL_UNAVAILABLE,
+            StatusCode::SERVICE_UNAVAILABLE => SHUTTING_DOWN,
+            StatusCode::TOO_MANY_REQUESTS => RATE_LIMITED,
+            _ => INTERNAL_ERROR,
+        };
+        let mut rpc_error = Self::new(code, &err.code, &err.message);
+        if let Some(seconds) = err.retry_after() {
+            rpc_error.data["retryAfter"] = json!(seconds);
+        }
+        rpc_error
+    }
+}
+
+
+//////////////////////////////////////////////////////
+////////////////////// Params ////////////////////////
+//////////////////////////////////////////////////////
+
+// Params of the methods taking a model name
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameParams {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogsParams {
+    name: String,
+    #[serde(default)]
+    lines: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExecuteParams {
+    name: String,
+    input: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionParams {
+    session: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TranscriptParams {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FeedbackState {
+    On,
+    Off,
+    Status,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedbackParams {
+    name: String,
+    state: FeedbackState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateParams {
+    name: String,
+    rating: u8,
+    #[serde(default)]
+    request: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UploadParams {
+    name: String,
+    file: String,
+    #[serde(default)]
+    dataset: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DownloadParams {
+    name: String,
+    weights: String,
+    #[serde(default)]
+    output: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct TrainStartParams {
+    name: String,
+    dataset: String,
+    #[serde(default)]
+    upload: bool,
+    #[serde(default)]
+    old_weights: Option<String>,
+    #[serde(default)]
+    new_weights: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrainStatusParams {
+    #[serde(default)]
+    job: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    lines: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobParams {
+    job: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobStatusParams {
+    #[serde(default)]
+    job: Option<String>,
+    #[serde(default)]
+    results: bool,
+}
+
+// Params of model-create and model-modify, empty fields are kept on modify and params with empty values removed
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct EntryParams {
+    #[serde(default)]
+    uid: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    conn_type: String,
+    #[serde(default)]
+    conn_params: HashMap<String, String>,
+    #[serde(default)]
+    model_params: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UidParams {
+    uid: String,
+}
+
+// Params of apikey-create, keys without models may execute every model
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyCreateParams {
+    name: String,
+    #[serde(default)]
+    models: Vec<String>,
+    #[serde(default)]
+    admin: bool,
+    #[serde(default)]
+    limits: UsageLimits,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct KeyIdParams {
+    key_id: String,
+}
+
+
+// Serves the REPL commands as JSON-RPC methods for external schedulers, one request or batch per line
+pub struct JsonRpcApi {
+    model_pool: Arc<ModelPool>,
+    dal: Arc<tokio::sync::Mutex<DAL>>,
+    keys: Arc<KeyStore>,
+    jobs: Arc<InferenceJobs>,
+    allow_model_server_runtime_changes: bool,
+}
+
+impl JsonRpcApi {
+    pub fn new(model_pool: Arc<ModelPool>, dal: Arc<tokio::sync::Mutex<DAL>>, keys: Arc<KeyStore>, jobs: Arc<InferenceJobs>, allow_model_server_runtime_changes: bool) -> Self {
+        Self { model_pool, dal, keys, jobs, allow_model_server_runtime_changes }
+    }
+
+    // Serve the API on the Unix socket until the shutdown future completes, the open requests are answered first
+    pub async fn serve(self: Arc<Self>, socket: ApiSocket, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+        let ApiSocket { listener, path } = socket;
+        listener.set_nonblocking(true).map_err(|err| "Failed to configure the JSON-RPC socket: ".to_string() + &err.to_string())?;
+        let listener = tokio::net::UnixListener::from_std(listener).map_err(|err| {
+            log::error!("Failed to create the JSON-RPC server: {}", err);
+            "Failed to create the JSON-RPC server: ".to_string() + &err.to_string()
+        })?;
+        log::info!("Serving the JSON-RPC API on {:?}", path);
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let (reader, writer) = stream.into_split();
+                        connections.spawn(Arc::clone(&self).serve_connection(reader, writer, stop_rx.clone()));
+                    }
+                    Err(err) => log::warn!("Failed to accept a JSON-RPC connection: {}", err),
+                },
+            }
+        }
+
+        let _ = stop_tx.send(true);
+        while connections.join_next().await.is_some() {}
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    // Serve the API on stdin and stdout until stdin is closed or the shutdown future completes
+    pub async fn serve_stdio(self: Arc<Self>, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+        log::info!("Serving the JSON-RPC API on stdin and stdout");
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let connection = tokio::spawn(Arc::clone(&self).serve_connection(tokio::io::stdin(), tokio::io::stdout(), stop_rx));
+        tokio::pin!(connection);
+        tokio::select! {
+            _ = &mut connection => return Ok(()),
+            _ = shutdown => {}
+        }
+        let _ = stop_tx.send(true);
+        connection.await.map_err(|err| "The JSON-RPC connection failed: ".to_string() + &err.to_string())
+    }
+
+    // Answer the lines of a connection, requests run concurrently and their responses are written as they finish
+    async fn serve_connection<R, W>(self: Arc<Self>, reader: R, mut writer: W, mut stop_rx: watch::Receiver<bool>)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel::<String>();
+        let responder = tokio::spawn(async move {
+            while let Some(mut response) = response_rx.recv().await {
+                response.push('\n');
+                if writer.write_all(response.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut requests = JoinSet::new();
+        loop {
+            let line = tokio::select! {
+                _ = stop_rx.changed() => break,
+                Some(_) = requests.join_next(), if !requests.is_empty() => continue,
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break,
+                    Err(err) => {
+                        log::warn!("Failed to read a JSON-RPC request: {}", err);
+                        break;
+                    }
+                },
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let api = Arc::clone(&self);
+            let response_tx = response_tx.clone();
+            requests.spawn(async move {
+                if let Some(response) = api.handle(&line).await {
+                    let _ = response_tx.send(response.to_string());
+                }
+            });
+        }
+
+        // The requests that were read are still answered
+        while requests.join_next().await.is_some() {}
+        drop(response_tx);
+        let _ = responder.await;
+    }
+
+    // Answer a line holding a request or a batch, notifications are not answered
+    pub async fn handle(&self, line: &str) -> Option<Value> {
+        let message = match serde_json::from_str::<Value>(line) {
+            Ok(message) => message,
+            Err(err) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, "parse_error", &format!("Invalid JSON: {}", err)))),
+        };
+        match message {
+            Value::Array(batch) if batch.is_empty() => Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "invalid_request", "Empty batch"))),
+            Value::Array(batch) => {
+                let responses: Vec<Value> = join_all(batch.into_iter().map(|request| self.handle_request(request))).await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                // A batch of notifications is not answered at all
+                match responses.is_empty() {
+                    true => None,
+                    false => Some(Value::Array(responses)),
+                }
+            }
+            request => self.handle_request(request).await,
+        }
+    }
+
+    // Answer a single request, requests without an id are notifications
+    async fn handle_request(&self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        if !matches!(id, None | Some(Value::Null | Value::String(_) | Value::Number(_))) {
+            return Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "invalid_request", "The id must be a string, a number or null")));
+        }
+        let method = match (request.get("jsonrpc").and_then(Value::as_str), request.get("method").and_then(Value::as_str)) {
+            (Some("2.0"), Some(method)) => method.to_string(),
+            _ => return Some(error_response(id.unwrap_or_default(), RpcError::new(INVALID_REQUEST, "invalid_request", "Expected a JSON-RPC 2.0 request with a method"))),
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = self.call(&method, params).await;
+        log::debug!("JSON-RPC {} -> {}", method, match &result { Ok(_) => "ok".to_string(), Err(err) => err.code.to_string() });
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    // Dispatch a method, the methods are named after the REPL commands
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "version" => Ok(json!({ "version": env!("CARGO_PKG_VERSION") })),
+            "model-list" => Ok(self.model_list()),
+            "model-info" => self.model_info(parse(params)?).await,
+            "model-ping" => self.model_ping(parse(params)?).await,
+            "model-stats" => self.model_stats(parse(params)?).await,
+            "model-logs" => self.model_logs(parse(params)?),
+            "model-execute" => self.model_execute(parse(params)?).await,
+            "session-create" => {
+                let params: NameParams = parse(params)?;
+                let session = self.model_pool.create_session(&params.name).await.map_err(RpcError::from_pool)?;
+                Ok(json!({ "session": session }))
+            }
+            "session-reset" => {
+                let params: SessionParams = parse(params)?;
+                self.model_pool.reset_session(&params.session).await.map_err(RpcError::from_pool)?;
+                Ok(json!({ "session": params.session }))
+            }
+            "session-close" => {
+                let params: SessionParams = parse(params)?;
+                self.model_pool.close_session(&params.session).await.map_err(RpcError::from_pool)?;
+                Ok(json!({ "session": params.session }))
+            }
+            "transcript-export" => self.transcript_export(parse(params)?).await,
+            "model-continuous-feedback" => self.model_feedback(parse(params)?),
+            "model-feedback-rate" => self.model_feedback_rate(parse(params)?),
+            "dataset-upload" => self.dataset_upload(parse(params)?).await,
+            "weights-list" => self.weights_list(parse(params)?).await,
+            "weights-download" => self.weights_download(parse(params)?).await,
+            "train-start" => self.train_start(parse(params)?).await,
+            "train-status" => self.train_status(parse(params)?),
+            "train-cancel" => {
+                let params: JobParams = parse(params)?;
+                self.model_pool.training().cancel(&params.job).map_err(RpcError::from_pool)?;
+                Ok(json!({ "job": params.job }))
+            }
+            // Jobs submitted here run without an API key, every job can be seen and cancelled
+            "job-submit" => {
+                let submission: JobSubmission = parse(params)?;
+                let job = self.jobs.submit(None, submission).map_err(RpcError::from_pool)?;
+                Ok(jobs::job_json(&job, false))
+            }
+            "job-status" => {
+                let params: JobStatusParams = parse(params)?;
+                match params.job {
+                    Some(id) => {
+                        let job = self.jobs.job(&id).ok_or_else(|| RpcError::not_found(&format!("Inference job {} not found", id)))?;
+                        Ok(jobs::job_json(&job, params.results))
+                    }
+                    None => Ok(json!({ "jobs": self.jobs.jobs().iter().map(|job| jobs::job_json(job, params.results)).collect::<Vec<Value>>() })),
+                }
+            }
+            "job-cancel" => {
+                let params: JobParams = parse(params)?;
+                self.jobs.cancel(&params.job).map_err(RpcError::from_pool)?;
+                Ok(json!({ "job": params.job }))
+            }
+            "model-create" => {
+                self.check_runtime_changes()?;
+                let params: EntryParams = parse(params)?;
+                let entry = dal::ModelEntry { name: params.name, conn_type: params.conn_type, conn_params: params.conn_params, model_params: params.model_params };
+                let config = self.model_pool.create_entry(&self.dal, &entry).await.map_err(RpcError::from_pool)?;
+                Ok(entry_json(config))
+            }
+            "model-modify" => {
+                self.check_runtime_changes()?;
+                let params: EntryParams = parse(params)?;
+                if params.uid.is_empty() {
+                    return Err(RpcError::invalid_params("The uid of the model entry is required"));
+                }
+                let changes = dal::ModelEntry { name: params.name, conn_type: params.conn_type, conn_params: params.conn_params, model_params: params.model_params };
+                let config = self.model_pool.modify_entry(&self.dal, &params.uid, &changes).await.map_err(RpcError::from_pool)?;
+                Ok(entry_json(config))
+            }
+            "model-delete" => {
+                self.check_runtime_changes()?;
+                let params: UidParams = parse(params)?;
+                self.model_pool.delete_entry(&self.dal, &params.uid).await.map_err(RpcError::from_pool)?;
+                Ok(json!({ "uid": params.uid }))
+            }
+            // The socket is only reachable by the owner, so the keys of the network APIs are managed here
+            "apikey-create" => {
+                let params: KeyCreateParams = parse(params)?;
+                let (key, api_key) = self.keys.create(&self.dal, &params.name, params.models, params.admin, params.limits).await.map_err(RpcError::from_pool)?;
+                let mut result = key_json(&api_key);
+                result["key"] = json!(key);
+                Ok(result)
+            }
+            "apikey-list" => {
+                parse::<HashMap<String, Value>>(params)?;
+                Ok(json!({ "keys": self.keys.keys().iter().map(key_json).collect::<Vec<Value>>() }))
+            }
+            "apikey-delete" => {
+                let params: KeyIdParams = parse(params)?;
+                self.keys.delete(&self.dal, &params.key_id).await.map_err(RpcError::from_pool)?;
+                Ok(json!({ "keyId": params.key_id }))
+            }
+            "usage-status" => {
+                parse::<HashMap<String, Value>>(params)?;
+                let counter = |counter: &dal::UsageCounter| json!({ "period": counter.period, "requests": counter.requests, "bytes": counter.bytes });
+                let usage: Vec<Value> = self.model_pool.limiter().usage().iter()
+                    .map(|(day, month)| json!({ "subject": day.subject, "day": counter(day), "month": counter(month) }))
+                    .collect();
+                Ok(json!({ "usage": usage }))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "method_not_found", &format!("No method {}", method))),
+        }
+    }
+
+    // The admin methods are allowed under the same flag as the model-create/modify/delete REPL commands
+    fn check_runtime_changes(&self) -> Result<(), RpcError> {
+        match self.allow_model_server_runtime_changes {
+            true => Ok(()),
+            false => Err(RpcError::new(PERMISSION_DENIED, "permission_denied", "Runtime changes to the model server are not allowed")),
+        }
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////////////// Models ////////////////////////
+    //////////////////////////////////////////////////////
+
+    // List the models with the states of their instances like GET /models of the HTTP API
+    fn model_list(&self) -> Value {
+        let models: Vec<Value> = self.model_pool.list_models().iter().map(ModelListing::summary).collect();
+        json!({ "models": models })
+    }
+
+    // Describe the configuration, state and capabilities of all instances of a model
+    async fn model_info(&self, params: NameParams) -> Result<Value, RpcError> {
+        let infos = self.model_pool.model_info(&params.name).await.ok_or_else(|| RpcError::not_found(&format!("Model {} not found", params.name)))?;
+        Ok(json!({ "name": params.name, "instances": infos }))
+    }
+
+    // Probe all instances of a model, instances that are not running are not probed
+    async fn model_ping(&self, params: NameParams) -> Result<Value, RpcError> {
+        let instances = self.model_pool.instances(&params.name).ok_or_else(|| RpcError::not_found(&format!("Model {} not found", params.name)))?;
+
+        let mut pings = Vec::new();
+        for instance in instances {
+            let meal = instance.read().await;
+            let state = instance_state(Some(meal.state()));
+            pings.push(match meal.is_ready() {
+                false => json!({ "state": state, "alive": false, "latencyMs": null, "error": null }),
+                true => match meal.ping().await {
+                    Ok(latency) => json!({ "state": state, "alive": true, "latencyMs": latency.as_secs_f64() * 1000.0, "error": null }),
+                    Err(err) => json!({ "state": state, "alive": false, "latencyMs": null, "error": err }),
+                },
+            });
+        }
+        Ok(json!({ "name": params.name, "instances": pings }))
+    }
+
+    // Sample the resource usage of all instances of a model
+    async fn model_stats(&self, params: NameParams) -> Result<Value, RpcError> {
+        let instances = self.model_pool.instances(&params.name).ok_or_else(|| RpcError::not_found(&format!("Model {} not found", params.name)))?;
+
+        let mut samples = Vec::new();
+        for instance in instances {
+            let meal = instance.read().await;
+            let state = instance_state(Some(meal.state()));
+            samples.push(match meal.is_ready() {
+                false => json!({ "state": state, "stats": null, "error": null }),
+                true => match meal.stats().await {
+                    Ok(stats) => json!({
+                        "state": state,
+                        "stats": {
+                            "pid": stats.pid,
+                            "processes": stats.processes,
+                            "rssBytes": stats.rss_bytes,
+                            "cpuPercent": stats.cpu_percent,
+                            "threads": stats.threads,
+                            "uptimeSecs": stats.uptime.as_secs(),
+                        },
+                        "error": null,
+                    }),
+                    Err(err) => json!({ "state": state, "stats": null, "error": err }),
+                },
+            });
+        }
+        Ok(json!({ "name": params.name, "instances": samples }))
+    }
+
+    // Return the buffered output of all instances of a model, following is left to repeated calls
+    fn model_logs(&self, params: LogsParams) -> Result<Value, RpcError> {
+        let logs = self.model_pool.logs(&params.name).ok_or_else(|| RpcError::not_found(&format!("Model {} not found", params.name)))?;
+        let lines = params.lines.unwrap_or(DEFAULT_LOG_LINES);
+        let instances: Vec<Value> = logs.iter()
+            .map(|log| json!({ "path": log.path().map(|path| path.display().to_string()), "lines": log.tail(lines) }))
+            .collect();
+        Ok(json!({ "name": params.name, "instances": instances }))
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////////////// Inference //////////////////////
+    //////////////////////////////////////////////////////
+
+    // Run an inference on the model, errors reported by the model are returned with their code
+    async fn model_execute(&self, params: ExecuteParams) -> Result<Value, RpcError> {
+        let mut request = MEALRequest::new(&params.input);
+        request.id = params.id.unwrap_or_default();
+        request.session = params.session;
+        request.params = params.params;
+
+        let response = self.model_pool.infer(&params.name, request, None).await.map_err(RpcError::from_pool)?;
+        match (response.output, response.error) {
+            (_, Some(error)) => Err(RpcError {
+                code: MODEL_ERROR,
+                message: error.message,
+                data: json!({ "code": error.code, "id": response.id }),
+            }),
+            (output, None) => Ok(json!({ "id": response.id, "output": output })),
+        }
+    }
+
+    // Export the stored transcript turns, the filters are the ones of the REPL command
+    async fn transcript_export(&self, params: TranscriptParams) -> Result<Value, RpcError> {
+        let time = |key: &str, time: Option<String>| -> Result<Option<chrono::DateTime<chrono::Utc>>, RpcError> {
+            match time {
+                Some(time) => chrono::DateTime::parse_from_rfc3339(&time)
+                    .map(|time| Some(time.with_timezone(&chrono::Utc)))
+                    .map_err(|err| RpcError::invalid_params(&format!("Invalid {} time {:#?}: {}", key, time, err))),
+                None => Ok(None),
+            }
+        };
+        let query = TranscriptQuery {
+            model: params.model,
+            session_id: params.session,
+            from: time("from", params.from)?,
+            to: time("to", params.to)?,
+            limit: params.limit,
+        };
+        let export = self.dal.lock().await.export_transcript(&query).await.map_err(|err| RpcError::new(INTERNAL_ERROR, "internal", &err))?;
+        let turns: Vec<Value> = export.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+        Ok(json!({ "turns": turns }))
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////////////// Feedback ///////////////////////
+    //////////////////////////////////////////////////////
+
+    // Turn the continuous feedback learning of a model on or off, the status is returned in every case
+    fn model_feedback(&self, params: FeedbackParams) -> Result<Value, RpcError> {
+        match params.state {
+            FeedbackState::On => self.model_pool.set_feedback(&params.name, true),
+            FeedbackState::Off => self.model_pool.set_feedback(&params.name, false),
+            FeedbackState::Status => Ok(()),
+        }.map_err(RpcError::from_pool)?;
+
+        let status = self.model_pool.feedback_status(&params.name).map_err(RpcError::from_pool)?;
+        let last_result = match status.last_result {
+            Some(Ok(weights)) => json!({ "weights": weights }),
+            Some(Err(err)) => json!({ "error": err }),
+            None => Value::Null,
+        };
+        Ok(json!({
+            "name": params.name,
+            "enabled": status.enabled,
+            "collected": status.collected,
+            "threshold": status.threshold,
+            "training": status.training,
+            "lastResult": last_result,
+        }))
+    }
+
+    // Rate an answer of a model, well rated answers are collected for the next training
+    fn model_feedback_rate(&self, params: RateParams) -> Result<Value, RpcError> {
+        if !(1..=5).contains(&params.rating) {
+            return Err(RpcError::invalid_params("The rating must be from 1 to 5"));
+        }
+        match self.model_pool.rate(&params.name, params.request.as_deref(), params.rating).map_err(RpcError::from_pool)? {
+            Rating::Rejected => Ok(json!({ "learned": false, "collected": null, "threshold": null })),
+            Rating::Collected(collected) => {
+                let threshold = self.model_pool.feedback_status(&params.name).map(|status| status.threshold).unwrap_or_default();
+                Ok(json!({ "learned": true, "collected": collected, "threshold": threshold }))
+            }
+        }
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////////// Datasets and weights ///////////////
+    //////////////////////////////////////////////////////
+
+    // Upload a file on the driver host as a dataset of a model
+    async fn dataset_upload(&self, params: UploadParams) -> Result<Value, RpcError> {
+        let dataset = match params.dataset {
+            Some(dataset) => dataset,
+            None => file_name(&params.file)?,
+        };
+        let data = std::fs::read(&params.file).map_err(|err| RpcError::invalid_params(&format!("Failed to read {:#?}: {}", params.file, err)))?;
+        self.model_pool.upload_dataset(&params.name, &dataset, &data).await.map_err(RpcError::from_pool)?;
+        Ok(json!({ "name": params.name, "dataset": dataset, "size": data.len() }))
+    }
+
+    // List the files in the weights directory of a model with the served weights
+    async fn weights_list(&self, params: NameParams) -> Result<Value, RpcError> {
+        let files = self.model_pool.list_weights(&params.name).await.map_err(RpcError::from_pool)?;
+        let served = self.model_pool.weights_version(&params.name).await.ok().flatten();
+        let files: Vec<Value> = files.into_iter()
+            .map(|file| json!({ "path": file.path, "size": file.size, "sha256": file.sha256 }))
+            .collect();
+        Ok(json!({ "name": params.name, "serving": served, "files": files }))
+    }
+
+    // Download the files of a weights version of a model below a directory of the driver host
+    async fn weights_download(&self, params: DownloadParams) -> Result<Value, RpcError> {
+        let output = params.output.unwrap_or_else(|| ".".to_string());
+        let downloads = self.model_pool.download_weights(&params.name, &params.weights).await.map_err(RpcError::from_pool)?;
+        for (file, data) in &downloads {
+            let path = Path::new(&output).join(&file.path);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| RpcError::new(INTERNAL_ERROR, "internal", &format!("Failed to create {:#?}: {}", dir, err)))?;
+            }
+            std::fs::write(&path, data).map_err(|err| RpcError::new(INTERNAL_ERROR, "internal", &format!("Failed to write {:#?}: {}", path, err)))?;
+        }
+        Ok(json!({ "name": params.name, "weights": params.weights, "output": output, "files": downloads.len() }))
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////////////// Training ///////////////////////
+    //////////////////////////////////////////////////////
+
+    // Start a training job of a model, the defaults are the ones of the train-start REPL command
+    async fn train_start(&self, params: TrainStartParams) -> Result<Value, RpcError> {
+        // Uploaded datasets are stored under their file name
+        let (dataset, upload) = match params.upload {
+            true => {
+                let data = std::fs::read(&params.dataset).map_err(|err| RpcError::invalid_params(&format!("Failed to read {:#?}: {}", params.dataset, err)))?;
+                (file_name(&params.dataset)?, Some(data))
+            }
+            false => (params.dataset, None),
+        };
+        let old_weights = match params.old_weights {
+            Some(old_weights) => Some(old_weights),
+            None => self.model_pool.weights_version(&params.name).await.map_err(RpcError::from_pool)?,
+        };
+        let new_weights = params.new_weights.unwrap_or_else(|| format!("train-{}", chrono::Utc::now().format("%Y%m%d%H%M%S")));
+
+        let spec = TrainingSpec { dataset, upload, old_weights, new_weights, args: params.args };
+        let id = self.model_pool.start_training(&params.name, spec).await.map_err(RpcError::from_pool)?;
+        Ok(json!({ "job": id }))
+    }
+
+    // List the training jobs, the newest first, or return the state and recent output of one
+    fn train_status(&self, params: TrainStatusParams) -> Result<Value, RpcError> {
+        let training = self.model_pool.training();
+        match params.job {
+            Some(id) => {
+                let job = training.job(&id).ok_or_else(|| RpcError::not_found(&format!("Training job {} not found", id)))?;
+                let output = training.output(&id, params.lines.unwrap_or(DEFAULT_LOG_LINES));
+                Ok(json!({ "job": job, "output": output }))
+            }
+            None => Ok(json!({ "jobs": training.jobs(params.model.as_deref()) })),
+        }
+    }
+}
+
+
+// Parse the named params of a method, methods without required params also take no params
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Null => json!({}),
+        Value::Object(params) => Value::Object(params),
+        _ => return Err(RpcError::invalid_params("The params must be an object")),
+    };
+    serde_json::from_value(params).map_err(|err| RpcError::invalid_params(&format!("Invalid params: {}", err)))
+}
+
+// Build the response of a failed request
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message, "data": err.data } })
+}
+
+// Name of an uploaded dataset file
+fn file_name(file: &str) -> Result<String, RpcError> {
+    Path::new(file).file_name()
+        .and_then(|file_name| file_name.to_str())
+        .map(|file_name| file_name.to_string())
+        .ok_or_else(|| RpcError::invalid_params(&format!("Invalid dataset file {:#?}", file)))
+}
+
+// Describe a stored model entry like the gRPC API, the fields maintained by the DAL are left out of the params
+fn entry_json(config: Vec<HashMap<String, String>>) -> Value {
+    let params = |index: usize| -> HashMap<String, String> {
+        config.get(index).cloned().unwrap_or_default().into_iter()
+            .filter(|(name, _)| !["id", "uid", "createdAt", "lastUpdated"].contains(&name.as_str()))
+            .collect()
+    };
+    let static_fields = config.first().cloned().unwrap_or_default();
+    json!({
+        "uid": static_fields.get("uid").cloned().unwrap_or_default(),
+        "name": static_fields.get("name").cloned().unwrap_or_default(),
+        "connType": static_fields.get("connType").cloned().unwrap_or_default(),
+        "connParams": params(1),
+        "modelParams": params(2),
+    })
+}
+
+// Describe an API key without its hash
+fn key_json(api_key: &ApiKey) -> Value {
+    json!({
+        "keyId": api_key.key_id,
+        "name": api_key.name,
+        "models": api_key.models,
+        "admin": api_key.admin,
+        "limits": api_key.limits,
+        "createdAt": api_key.created_at.to_rfc3339(),
+    })
+}
+
+// Unix socket of the API, the address it was bound to differs from the path it was moved to
+pub struct ApiSocket {
+    listener: std::os::unix::net::UnixListener,
+    path: PathBuf,
+}
+
+// Bind the Unix socket of the API, a socket left behind by a previous run is replaced and only the owner may connect
+pub fn bind(path: &Path) -> Result<ApiSocket, String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{:#?} exists and is not a socket", path));
+        }
+        std::fs::remove_file(path).map_err(|err| format!("Failed to remove the stale socket {:#?}: {}", path, err))?;
+    }
+    let file_name = path.file_name().ok_or_else(|| format!("{:#?} is not a socket path", path))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+
+    // The socket is bound in a directory only the owner can enter, so nobody else can connect before it is
+    // restricted, and then moved into place next to it
+    let private_dir = parent.join(format!(".mer-jsonrpc-{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private_dir)
+        .map_err(|err| format!("Failed to create {:#?}: {}", private_dir, err))?;
+    let bound_path = private_dir.join(file_name);
+    let listener = std::os::unix::net::UnixListener::bind(&bound_path)
+        .map_err(|err| format!("Failed to bind {:#?}: {}", bound_path, err))
+        .and_then(|listener| {
+            std::fs::set_permissions(&bound_path, std::fs::Permissions::from_mode(0o600))
+                .map_err(|err| format!("Failed to restrict {:#?}: {}", bound_path, err))?;
+            std::fs::rename(&bound_path, path).map_err(|err| format!("Failed to move the socket to {:#?}: {}", path, err))?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_file(&bound_path);
+    let _ = std::fs::remove_dir(&private_dir);
+    Ok(ApiSocket { listener: listener?, path: path.to_path_buf() })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dal::DALArgs;
+    use crate::meal::{MEAL, MEALArgs};
+    use std::time::Duration;
+    use tokio::net::UnixStream;
+
+    // Echoes the inputs with the jsonl protocol, the input "fail" is answered with a model error
+    const ECHO_MODEL: &str = r#"
+import json, sys
+print('@!#READY#!@ {}', flush=True)
+for line in sys.stdin:
+    if line.strip() == "@!#EXIT#!@":
+        break
+    request = json.loads(line)
+    if request["input"] == "fail":
+        print(json.dumps({"id": request["id"], "error": {"code": "bad_input", "message": "Failed on purpose"}}), flush=True)
+        continue
+    print(json.dumps({"id": request["id"], "output": "echo: {}".format(request["input"])}), flush=True)
+"#;
+
+    // Send a line and read the line of the response
+    async fn call(lines: &mut tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>, writer: &mut tokio::net::unix::OwnedWriteHalf, line: &str) -> Value {
+        writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_api() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-jsonrpc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("uid".to_string(), "1".to_string());
+        static_fields.insert("name".to_string(), "echo".to_string());
+        static_fields.insert("connType".to_string(), "local".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        model_params.insert("inferenceArgv".to_string(), json!(["python3", "-c", ECHO_MODEL]).to_string());
+        let meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+        let model_pool = Arc::new(ModelPool::new());
+        model_pool.insert("echo", meal);
+
+        // The DAL is only used by the transcripts and the admin methods, which are not allowed here
+        let dal_args = DALArgs { connection_url: "localhost:4321".to_string(), username: String::new(), password: String::new() };
+        let dal = Arc::new(tokio::sync::Mutex::new(DAL::create("surreal", dal_args).unwrap()));
+        let path = dir.join("driver.sock");
+        let socket = bind(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!dir.join(format!(".mer-jsonrpc-{}", std::process::id())).exists());
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Arc::new(JsonRpcApi::new(Arc::clone(&model_pool), dal, Arc::new(KeyStore::default()), Arc::new(InferenceJobs::new(Arc::clone(&model_pool), None, Vec::new())), false)).serve(socket, async { let _ = stop_rx.await; }));
+        let (reader, mut writer) = UnixStream::connect(&path).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        // Results of the REPL commands
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 1, "method": "model-list"}"#).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["models"][0], json!({ "name": "echo", "instances": 1, "readyInstances": 0, "states": ["stopped"] }));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": "a", "method": "model-execute", "params": {"name": "echo", "input": "Hi", "id": "r1"}}"#).await;
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "id": "a", "result": { "id": "r1", "output": "echo: Hi" } }));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 2, "method": "model-info", "params": {"name": "echo"}}"#).await;
+        assert_eq!(response["result"]["instances"][0]["state"], "ready");
+        assert_eq!(response["result"]["instances"][0]["uid"], "1");
+
+        // Errors carry the code of the driver and the code of the model or the HTTP API
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 3, "method": "model-execute", "params": {"name": "echo", "input": "fail"}}"#).await;
+        assert_eq!((response["error"]["code"].as_i64(), &response["error"]["data"]["code"]), (Some(MODEL_ERROR), &json!("bad_input")));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 4, "method": "model-ping", "params": {"name": "other"}}"#).await;
+        assert_eq!((response["error"]["code"].as_i64(), response["error"]["message"].as_str()), (Some(NOT_FOUND), Some("Model other not found")));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 5, "method": "model-info", "params": {"model": "echo"}}"#).await;
+        assert_eq!(response["error"]["code"].as_i64(), Some(INVALID_PARAMS));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 6, "method": "model-create", "params": {"name": "other", "connType": "local"}}"#).await;
+        assert_eq!(response["error"]["code"].as_i64(), Some(PERMISSION_DENIED));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 7, "method": "exit"}"#).await;
+        assert_eq!(response["error"]["code"].as_i64(), Some(METHOD_NOT_FOUND));
+        let response = call(&mut lines, &mut writer, r#"{"jsonrpc": "2.0", "id": 8"#).await;
+        assert_eq!((response["id"].clone(), response["error"]["code"].as_i64()), (Value::Null, Some(PARSE_ERROR)));
+        let response = call(&mut lines, &mut writer, r#"{"id": 9, "method": "version"}"#).await;
+        assert_eq!((response["id"].clone(), response["error"]["code"].as_i64()), (json!(9), Some(INVALID_REQUEST)));
+
+        // Notifications are not answered, also not within a batch
+        let response = call(&mut lines, &mut writer, r#"[{"jsonrpc": "2.0", "method": "version"}, {"jsonrpc": "2.0", "id": 10, "method": "version"}]"#).await;
+        assert_eq!(response, json!([{ "jsonrpc": "2.0", "id": 10, "result": { "version": env!("CARGO_PKG_VERSION") } }]));
+
+        // The socket is removed once the server stopped
+        drop(writer);
+        let _ = stop_tx.send(());
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
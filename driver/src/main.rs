@@ -1,17 +1,26 @@
 // src/main.rs
+// The layer names (DAL, MEAL) are acronyms by design
+#![allow(clippy::upper_case_acronyms)]
+
 // Standard liraries
-use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
-// Logging with env_logger
-use env_logger;
-use log;
+// Signal handling
+use tokio::signal::unix::{signal, SignalKind};
 
 // CLI parsing
 use clap::Parser;
 
 // Custom modules
+mod auth;
 mod dal;
+mod grpc;
+mod http;
+mod jobs;
+mod jsonrpc;
 mod meal;
+mod metrics;
 mod repl;
 
 
@@ -34,6 +43,67 @@ struct Cli {
 
     #[arg(short, long, env = "ALLOW_MODEL_SERVER_RUNTIME_CHANGES", default_value = "false", help = "Allow runtime changes to the model server DB")]
     allow_model_server_runtime_changes: bool,
+
+    #[arg(long, env = "SERVE_HTTP", help = "Serve the HTTP API on the address (e.g. 0.0.0.0:8080) instead of running the REPL")]
+    serve_http: Option<std::net::SocketAddr>,
+
+    #[arg(long, env = "SERVE_GRPC", help = "Serve the gRPC API on the address (e.g. 0.0.0.0:50051) instead of running the REPL")]
+    serve_grpc: Option<std::net::SocketAddr>,
+
+    #[arg(long, env = "JSONRPC_SOCKET", help = "Serve the JSON-RPC API on the Unix socket path instead of running the REPL")]
+    jsonrpc_socket: Option<std::path::PathBuf>,
+
+    #[arg(long, env = "JSONRPC", default_value = "false", help = "Serve the JSON-RPC API on stdin and stdout instead of running the REPL")]
+    jsonrpc: bool,
+
+    #[arg(long, env = "SERVE_METRICS", help = "Serve the Prometheus metrics on the address (e.g. 0.0.0.0:9100) next to the REPL or the APIs")]
+    serve_metrics: Option<std::net::SocketAddr>,
+
+    #[arg(long, env = "REQUIRE_API_KEYS", default_value = "false", help = "Reject requests to the HTTP and gRPC APIs without an API key")]
+    require_api_keys: bool,
+
+    #[arg(long, env = "JOB_WEBHOOK_SECRET", help = "Sign the webhook bodies of the inference jobs with HMAC-SHA256 using the secret")]
+    job_webhook_secret: Option<String>,
+
+    #[arg(long, env = "JOB_WEBHOOK_ALLOW_HOSTS", value_delimiter = ',', help = "Hosts the webhooks of the inference jobs may be posted to even if they are private (e.g. 127.0.0.1,hooks.internal)")]
+    job_webhook_allow_hosts: Vec<String>,
+
+    #[arg(long, env = "SHUTDOWN_DRAIN_TIMEOUT_MS", default_value = "30000", help = "Time in-flight requests get to finish on shutdown")]
+    shutdown_drain_timeout_ms: u64,
+
+    #[arg(long, env = "SHUTDOWN_EXIT_TIMEOUT_MS", default_value = "10000", help = "Time models get to exit on shutdown before they are killed")]
+    shutdown_exit_timeout_ms: u64,
+
+    #[arg(trailing_var_arg = true, help = "Run the REPL command (e.g. model-batch <name> <input.jsonl> <output.jsonl>) and exit instead of running the REPL")]
+    command: Vec<String>,
+}
+
+
+// Bind the listener of an API, the driver can not run without it
+fn bind_api(api: &str, addr: std::net::SocketAddr) -> std::net::TcpListener {
+    match std::net::TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(error) => {
+            log::error!("Failed to bind the {} API to {}: {:#?}", api, addr, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Wait for SIGINT (Ctrl-C) or SIGTERM (e.g. docker stop)
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            log::error!("Failed to listen for SIGTERM: {:#?}", error);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => log::info!("Received SIGINT"),
+        _ = terminate.recv() => log::info!("Received SIGTERM"),
+    }
 }
 
 
@@ -54,6 +124,16 @@ async fn main() {
     log::info!("    - connection_url: {}", args.connection_url);
     log::info!("    - username: {}", args.username);
     log::info!("    - allow_model_server_runtime_changes: {:#?}", args.allow_model_server_runtime_changes);
+    log::info!("    - serve_http: {:#?}", args.serve_http);
+    log::info!("    - serve_grpc: {:#?}", args.serve_grpc);
+    log::info!("    - jsonrpc_socket: {:#?}", args.jsonrpc_socket);
+    log::info!("    - jsonrpc: {:#?}", args.jsonrpc);
+    log::info!("    - serve_metrics: {:#?}", args.serve_metrics);
+    log::info!("    - require_api_keys: {:#?}", args.require_api_keys);
+    log::info!("    - job_webhook_secret: {}", if args.job_webhook_secret.is_some() { "set" } else { "not set" });
+    log::info!("    - job_webhook_allow_hosts: {:?}", args.job_webhook_allow_hosts);
+    log::info!("    - shutdown_drain_timeout_ms: {}", args.shutdown_drain_timeout_ms);
+    log::info!("    - shutdown_exit_timeout_ms: {}", args.shutdown_exit_timeout_ms);
 
 
     log::info!("Initializing the DAL...");
@@ -77,13 +157,10 @@ async fn main() {
     };
 
     // Connect to the DAL
-    let _ = match dal_instance.connect().await {
-        Ok(_) => (),
-        Err(error) => {
-            log::error!("Failed to connect to the DAL: {:#?}", error);
-            std::process::exit(1);
-        }
-    };
+    if let Err(error) = dal_instance.connect().await {
+        log::error!("Failed to connect to the DAL: {:#?}", error);
+        std::process::exit(1);
+    }
 
     // Get the available models
     let available_models = match dal_instance.get_available_models().await {
@@ -95,7 +172,7 @@ async fn main() {
     };
 
     // Create the MEAL instances for every available model
-    let mut meal_instances: HashMap<String, Vec<meal::MEAL>> = HashMap::new();
+    let model_pool = Arc::new(meal::pool::ModelPool::new());
     for model in available_models {
         // Get the model name
         let model_name = model[0].get("name").cloned().unwrap_or_default();
@@ -116,117 +193,294 @@ async fn main() {
                 meal_config: model.clone(),
             };
             // Create the MEAL instance
-            let meal = match meal::MEAL::create(&connection_type, meal_args) {
+            let meal = match meal::MEAL::create(connection_type, meal_args) {
                 Ok(instance) => instance,
                 Err(error) => {
                     log::error!("Failed to create the MEAL instance: {:#?}", error);
                     std::process::exit(1);
                 }
             };
-            // Add the MEAL instance to the vector of MEAL instances for the same model, defined by the model name
-            match meal_instances.get_mut(&model_name) {
-                Some(meal_vec) => meal_vec.push(meal),
-                None => {
-                    meal_instances.insert(model_name, vec![meal]);
-                }
-            }
+            log::debug!("Created the MEAL instance with driver: {}", meal.driver_type());
+            // Add the MEAL instance to the model pool, grouped with the instances of the same model
+            model_pool.insert(&model_name, meal);
         } else {
             log::error!("Unsupported connection type: {:#?}", connection_type);
         }
     }
 
     // Print the MEAL instances
-    log::info!("MEAL instances: {:#?}", meal_instances);
-
-
-
-
-
-    ///////////////////////////////////////////////////////////////////////////////////////
-    let mut local_meal_instance = &mut meal_instances.get_mut("DialoGPT-small").unwrap()[1];
-
-    let (mut tx, mut rx, mut rx_err) = match local_meal_instance.spawn_model().await {
-        Ok((tx, rx, rx_err)) => (tx, rx, rx_err),
-        Err(error) => {
-            log::error!("Failed to spawn the model: {:#?}", error);
-            std::process::exit(1);
+    log::info!("MEAL instances: {:#?}", model_pool);
+
+    // Store the transcript turns of the models with transcripts enabled in the background
+    let dal_instance = Arc::new(tokio::sync::Mutex::new(dal_instance));
+    let (transcript_tx, mut transcript_rx) = tokio::sync::mpsc::unbounded_channel::<dal::TranscriptTurn>();
+    model_pool.record_transcripts(transcript_tx);
+    let transcript_dal = Arc::clone(&dal_instance);
+    let transcript_writer = tokio::spawn(async move {
+        while let Some(turn) = transcript_rx.recv().await {
+            if let Err(error) = transcript_dal.lock().await.append_transcript_turn(&turn).await {
+                log::error!("Failed to store the transcript turn {:#?}: {:#?}", turn.request_id, error);
+            }
         }
-    };
-
-    let mut ready = false;
-    while !ready {
-        
-        let stdout = match rx.try_recv() {
-            Ok(stdout) => stdout,
-            Err(_) => "".to_string(),
-        };
-
-        let stderr = match rx_err.try_recv() {
-            Ok(stderr) => stderr,
-            Err(_) => "".to_string(),
-        };
-
-        if stdout.contains("@!#READY#!@") {
-            ready = true;
+    });
+
+    // Register the weights trained from the feedback of the models in the background
+    let (weights_tx, mut weights_rx) = tokio::sync::mpsc::unbounded_channel::<dal::ModelWeightsVersion>();
+    model_pool.record_weights(weights_tx);
+    let weights_dal = Arc::clone(&dal_instance);
+    let weights_writer = tokio::spawn(async move {
+        while let Some(weights) = weights_rx.recv().await {
+            if let Err(error) = weights_dal.lock().await.register_model_weights(&weights).await {
+                log::error!("Failed to register the weights {:#?} of model {:#?}: {:#?}", weights.version, weights.model, error);
+            }
         }
+    });
 
-        if !stdout.is_empty() {
-            log::info!("Model stdout: {:#?}", stdout);
-        }
+    // Store the state changes of the training jobs in the background, jobs a previous run left unfinished failed with it
+    if let Err(error) = dal_instance.lock().await.abort_training_jobs("The driver stopped while the job was unfinished").await {
+        log::error!("Failed to abort the unfinished training jobs: {:#?}", error);
     }
+    let (training_tx, mut training_rx) = tokio::sync::mpsc::unbounded_channel::<dal::TrainingJob>();
+    model_pool.training().record(training_tx);
+    let training_dal = Arc::clone(&dal_instance);
+    let training_writer = tokio::spawn(async move {
+        while let Some(job) = training_rx.recv().await {
+            if let Err(error) = training_dal.lock().await.save_training_job(&job).await {
+                log::error!("Failed to store the training job {:#?}: {:#?}", job.id, error);
+            }
+        }
+    });
+
+    // Store the accesses of the models with the key of the caller in the background
+    let (access_tx, mut access_rx) = tokio::sync::mpsc::unbounded_channel::<dal::ModelAccess>();
+    model_pool.record_access(access_tx);
+    let access_dal = Arc::clone(&dal_instance);
+    let access_writer = tokio::spawn(async move {
+        while let Some(access) = access_rx.recv().await {
+            if let Err(error) = access_dal.lock().await.append_model_access(&access).await {
+                log::error!("Failed to store the access {:#?} of model {:#?}: {:#?}", access.request_id, access.model, error);
+            }
+        }
+    });
 
-    // Send the message to the model
-    log::info!("Sending the message to the model...");
-    let _ = tx.send("@!#START#!@\nHello, how are you?\n@!#STOP#!@\n".to_string()).await;
-
-    // Receive the message from the model
-    let message = match rx.recv().await {
-        Some(message) => message,
-        None => {
-            log::error!("Failed to receive the message from the model");
+    // Continue the usage counters of the current day and month and store their changes in the background
+    match dal_instance.lock().await.get_usage(&meal::quota::periods(chrono::Utc::now())).await {
+        Ok(usage) => model_pool.limiter().load(usage),
+        Err(error) => {
+            log::error!("Failed to get the usage of the API keys and models: {:#?}", error);
             std::process::exit(1);
         }
-    };
-    log::info!("Message from the model: {:#?}", message);
+    }
+    let (usage_tx, mut usage_rx) = tokio::sync::mpsc::unbounded_channel::<dal::UsageCounter>();
+    model_pool.limiter().record(usage_tx);
+    let usage_dal = Arc::clone(&dal_instance);
+    let usage_writer = tokio::spawn(async move {
+        while let Some(usage) = usage_rx.recv().await {
+            if let Err(error) = usage_dal.lock().await.save_usage(&usage).await {
+                log::error!("Failed to store the usage of {:#?} in {:#?}: {:#?}", usage.subject, usage.period, error);
+            }
+        }
+    });
 
-    // Receive the error from the model
-    let error = match rx_err.recv().await {
-        Some(error) => error,
-        None => {
-            log::error!("Failed to receive the error from the model");
+    // Load the API keys of the network APIs
+    let api_keys = match dal_instance.lock().await.get_api_keys().await {
+        Ok(api_keys) => api_keys,
+        Err(error) => {
+            log::error!("Failed to get the API keys: {:#?}", error);
             std::process::exit(1);
         }
     };
-    log::info!("Error from the model: {:#?}", error);
-
-
-
+    log::info!("Loaded {} API keys", api_keys.len());
+    let key_store = Arc::new(auth::KeyStore::new(args.require_api_keys));
+    key_store.load(api_keys);
+
+    // Store the inference jobs in the background and continue the ones a previous run left unfinished
+    let inference_jobs = Arc::new(jobs::InferenceJobs::new(Arc::clone(&model_pool), args.job_webhook_secret.clone(), args.job_webhook_allow_hosts.clone()));
+    let (job_tx, mut job_rx) = tokio::sync::mpsc::unbounded_channel::<dal::InferenceJob>();
+    inference_jobs.record(job_tx);
+    let job_dal = Arc::clone(&dal_instance);
+    let job_writer = tokio::spawn(async move {
+        while let Some(job) = job_rx.recv().await {
+            if let Err(error) = job_dal.lock().await.save_inference_job(&job).await {
+                log::error!("Failed to store the inference job {:#?}: {:#?}", job.id, error);
+            }
+        }
+    });
 
+    // Start the eager instances and warm pools, lazy instances are started on their first request
+    log::info!("Starting the eager MEAL instances...");
+    model_pool.start_eager().await;
 
-    
+    // Start probing, evicting and warming up the instances in the background
+    model_pool.spawn_maintenance();
 
-    
+    // Continue the unfinished inference jobs once the eager instances are up
+    match dal_instance.lock().await.get_inference_jobs(jobs::MAX_FINISHED_JOBS).await {
+        Ok(stored_jobs) => {
+            log::info!("Loaded {} inference jobs", stored_jobs.len());
+            inference_jobs.resume(stored_jobs, &key_store);
+        }
+        Err(error) => {
+            log::error!("Failed to get the inference jobs: {:#?}", error);
+            std::process::exit(1);
+        }
+    }
 
 
-    
     ///////////////////////////////////////////////////////////////////////////////////////
+    // Serve the metrics next to whichever front end runs, until the models are shut down
+    let (metrics_stop_tx, metrics_stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let metrics_server = args.serve_metrics.map(|addr| {
+        log::info!("Starting the metrics server...");
+        let listener = bind_api("metrics", addr);
+        tokio::spawn(metrics::serve(listener, Arc::clone(&model_pool), async { let _ = metrics_stop_rx.await; }))
+    });
 
 
     ///////////////////////////////////////////////////////////////////////////////////////
-    // Start the CLI loop
-    log::info!("Starting the CLI loop...");
+    // Serve the HTTP, gRPC and JSON-RPC APIs until the driver receives a shutdown signal, on the signal the servers stop
+    // accepting connections and finish the open requests while the pool drains
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    let stopped = |mut stop_rx: tokio::sync::watch::Receiver<bool>| async move { let _ = stop_rx.changed().await; };
+    let mut servers = tokio::task::JoinSet::new();
+    if let Some(addr) = args.serve_http {
+        log::info!("Starting the HTTP API...");
+        let listener = bind_api("HTTP", addr);
+        let http_api = Arc::new(http::HttpApi::new(Arc::clone(&model_pool), Arc::clone(&key_store), Arc::clone(&inference_jobs)));
+        servers.spawn(http_api.serve(listener, stopped(stop_rx.clone())));
+    }
+    if let Some(addr) = args.serve_grpc {
+        log::info!("Starting the gRPC API...");
+        let listener = bind_api("gRPC", addr);
+        let grpc_api = Arc::new(grpc::GrpcApi::new(Arc::clone(&model_pool), Arc::clone(&dal_instance), Arc::clone(&key_store), args.allow_model_server_runtime_changes));
+        servers.spawn(grpc_api.serve(listener, stopped(stop_rx.clone())));
+    }
+    if args.jsonrpc_socket.is_some() || args.jsonrpc {
+        let jsonrpc_api = Arc::new(jsonrpc::JsonRpcApi::new(Arc::clone(&model_pool), Arc::clone(&dal_instance), Arc::clone(&key_store), Arc::clone(&inference_jobs), args.allow_model_server_runtime_changes));
+        if let Some(path) = &args.jsonrpc_socket {
+            log::info!("Starting the JSON-RPC API...");
+            let socket = match jsonrpc::bind(path) {
+                Ok(socket) => socket,
+                Err(error) => {
+                    log::error!("Failed to bind the JSON-RPC API: {}", error);
+                    std::process::exit(1);
+                }
+            };
+            servers.spawn(Arc::clone(&jsonrpc_api).serve(socket, stopped(stop_rx.clone())));
+        }
+        // Closing stdin stops the driver like exiting the REPL
+        if args.jsonrpc {
+            log::info!("Starting the JSON-RPC API on stdin and stdout...");
+            servers.spawn(jsonrpc_api.serve_stdio(stopped(stop_rx.clone())));
+        }
+    }
+
+    let (signalled, failed) = if !servers.is_empty() {
+        // A server that exits stops the others
+        let signalled = tokio::select! {
+            Some(result) = servers.join_next() => {
+                if let Ok(Err(error)) = result {
+                    log::error!("An API server exited with an error: {:#?}", error);
+                }
+                false
+            }
+            _ = shutdown_signal() => true,
+        };
+        let _ = stop_tx.send(true);
+        (signalled, false)
+    } else {
+        // Start the CLI loop
+        log::info!("Starting the CLI loop...");
+
+        // Get the STD pipes
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        let stderr = std::io::stderr();
+
+        // Initialize the CliReplManager
+        let mut crm_instance = repl::CliReplManager::new(stdin, stdout, stderr,
+                                                         args.allow_model_server_runtime_changes,
+                                                         Arc::clone(&model_pool),
+                                                         Arc::clone(&dal_instance),
+                                                         Arc::clone(&key_store),
+                                                         Arc::clone(&inference_jobs))
+                                                         .expect("Failed to initialize the CliReplManager");
+
+        // Run the REPL, or the command given on the command line, until it exits or the driver receives a shutdown signal
+        let command = args.command.clone();
+        let repl_task = tokio::spawn(async move {
+            if command.is_empty() {
+                if let Err(error) = crm_instance.repl().await {
+                    log::error!("REPL exited with an error: {:#?}", error);
+                }
+                true
+            } else if let Err(error) = crm_instance.run_command(&command).await {
+                log::error!("Command {:?} failed: {}", command, error);
+                eprint!("{error}");
+                false
+            } else {
+                true
+            }
+        });
+        tokio::select! {
+            succeeded = repl_task => (false, !succeeded.unwrap_or(false)),
+            _ = shutdown_signal() => (true, false),
+        }
+    };
 
-    // Get the STD pipes
-    let stdin = std::io::stdin();
-    let stdout = std::io::stdout();
-    let stderr = std::io::stderr();
 
-    // Initialize the CliReplManager
-    let mut crm_instance = repl::CliReplManager::new(stdin, stdout, stderr,
-                                                     args.allow_model_server_runtime_changes)
-                                                     .expect("Failed to initialize the CliReplManager");
+    ///////////////////////////////////////////////////////////////////////////////////////
+    // Shut down: stop accepting requests, drain the in-flight ones and stop every model, the inference jobs stop
+    // sending inputs first and are continued on the next start
+    log::info!("Shutting down the MEAL instances...");
+    inference_jobs.stop();
+    model_pool.shutdown(
+        Duration::from_millis(args.shutdown_drain_timeout_ms),
+        Duration::from_millis(args.shutdown_exit_timeout_ms),
+    ).await;
+    inference_jobs.shutdown(Duration::from_millis(args.shutdown_exit_timeout_ms)).await;
+    let servers_closed = async { while servers.join_next().await.is_some() {} };
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), servers_closed).await.is_err() {
+        log::error!("Failed to close the API connections within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    let _ = metrics_stop_tx.send(());
+    if let Some(metrics_server) = metrics_server {
+        if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), metrics_server).await.is_err() {
+            log::error!("Failed to close the metrics connections within {}ms", args.shutdown_exit_timeout_ms);
+        }
+    }
 
-    // Start the REPL
-    let _ = crm_instance.repl();
+    // Store the remaining transcript turns, weights, training jobs, model accesses, usage and inference jobs and disconnect from the DAL
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), transcript_writer).await.is_err() {
+        log::error!("Failed to store the remaining transcript turns within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), weights_writer).await.is_err() {
+        log::error!("Failed to register the remaining model weights within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), training_writer).await.is_err() {
+        log::error!("Failed to store the remaining training job states within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), access_writer).await.is_err() {
+        log::error!("Failed to store the remaining model accesses within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), usage_writer).await.is_err() {
+        log::error!("Failed to store the remaining usage counters within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if tokio::time::timeout(Duration::from_millis(args.shutdown_exit_timeout_ms), job_writer).await.is_err() {
+        log::error!("Failed to store the remaining inference job states within {}ms", args.shutdown_exit_timeout_ms);
+    }
+    if let Err(error) = dal_instance.lock().await.disconnect().await {
+        log::error!("Failed to disconnect from the DAL: {:#?}", error);
+    }
 
-}
\ No newline at end of file
+    // A failed command on the command line exits with an error code, so scripts can check it
+    if failed {
+        log::info!("Shutdown complete");
+        std::process::exit(1);
+    }
+    // The REPL or the JSON-RPC API may still be blocked reading stdin, exit without waiting for it
+    if signalled || args.jsonrpc {
+        log::info!("Shutdown complete");
+        std::process::exit(0);
+    }
+}
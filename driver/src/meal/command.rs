@@ -0,0 +1,324 @@
+// src/meal/command.rs
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+
+
+// Anything a shell would interpret, legacy command strings containing these need an explicit argv
+const SHELL_OPERATORS: &[&str] = &["&&", "||", ";", "|", "&", ">", "<", "`", "$(", "\n"];
+
+
+// How the environment of the model is activated before running its argv
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activation {
+    None,
+    // Run the argv with `conda run` inside the named conda environment
+    Conda { executable: String, env: String },
+    // Put the bin directory of the virtualenv first on the PATH
+    Venv(PathBuf),
+}
+
+// Model process description parsed from the model params, executed without a shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelCommand {
+    pub argv: Vec<String>,
+    pub cwd: PathBuf,
+    pub env: HashMap<String, String>,
+    pub env_remove: Vec<String>,
+    pub inherit_env: bool,
+    pub activation: Activation,
+}
+
+impl ModelCommand {
+    // Parse the command of the given kind (e.g. "inference" or "train") from the model params:
+    //     - <kind>Argv: JSON array with the program and its arguments
+    //     - <kind>Command: legacy command string, split like a shell would but never run through one
+    //     - workingDir: working directory, relative to the modelPath (default modelPath)
+    //     - env: JSON object of variables to set, null values unset the variable
+    //     - envInherit: whether the driver environment is inherited (default true)
+    //     - condaEnv, condaExecutable, venv: optional environment activation
+    pub fn from_model_params(model_params: &HashMap<String, String>, kind: &str) -> Result<Self, String> {
+        // Get the model path
+        let model_path = model_params.get("modelPath").filter(|path| !path.is_empty()).ok_or_else(|| {
+            log::error!("Failed to get the model path");
+            "Failed to get the model path".to_string()
+        })?;
+
+        let mut activation = match model_params.get("condaEnv").filter(|env| !env.is_empty()) {
+            Some(env) => Activation::Conda {
+                executable: non_empty(model_params, "condaExecutable").unwrap_or("conda").to_string(),
+                env: env.clone(),
+            },
+            None => Activation::None,
+        };
+        if let Some(venv) = non_empty(model_params, "venv") {
+            if activation != Activation::None {
+                log::error!("Only one of condaEnv and venv can be set");
+                return Err("Only one of condaEnv and venv can be set".to_string());
+            }
+            activation = Activation::Venv(PathBuf::from(venv));
+        }
+
+        // Prefer the explicit argv over the legacy command string
+        let argv_key = format!("{}Argv", kind);
+        let command_key = format!("{}Command", kind);
+        let argv = match (non_empty(model_params, &argv_key), non_empty(model_params, &command_key)) {
+            (Some(argv), _) => parse_argv(&argv_key, argv)?,
+            (None, Some(command)) => {
+                let (argv, legacy_activation) = parse_legacy_command(&command_key, command)?;
+                if let Some(legacy_activation) = legacy_activation {
+                    if activation != Activation::None {
+                        log::error!("{} activates an environment while condaEnv or venv is set", command_key);
+                        return Err(command_key + " activates an environment while condaEnv or venv is set");
+                    }
+                    activation = legacy_activation;
+                }
+                argv
+            }
+            (None, None) => {
+                log::error!("Failed to get the model command, set {} or {}", argv_key, command_key);
+                return Err(format!("Failed to get the model command, set {} or {}", argv_key, command_key));
+            }
+        };
+
+        // Relative paths are resolved against the model path
+        let model_path = Path::new(model_path);
+        let cwd = match non_empty(model_params, "workingDir") {
+            Some(working_dir) => model_path.join(working_dir),
+            None => model_path.to_path_buf(),
+        };
+        if let Activation::Venv(venv) = &mut activation {
+            *venv = cwd.join(&*venv);
+        }
+
+        let (env, env_remove) = match non_empty(model_params, "env") {
+            Some(env) => parse_env(env)?,
+            None => (HashMap::new(), Vec::new()),
+        };
+        let inherit_env = match non_empty(model_params, "envInherit") {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(inherit_env) => {
+                log::error!("Invalid value for the model param envInherit: {:#?}", inherit_env);
+                return Err(format!("Invalid value for the model param envInherit: {:#?}", inherit_env));
+            }
+        };
+
+        Ok(Self { argv, cwd, env, env_remove, inherit_env, activation })
+    }
+
+    // Get the program and arguments that are executed, wrapped by the conda activation if any
+    pub fn program_argv(&self) -> Vec<String> {
+        match &self.activation {
+            Activation::Conda { executable, env } => {
+                let mut argv = vec![executable.clone(), "run".to_string(), "--no-capture-output".to_string(), "-n".to_string(), env.clone()];
+                argv.extend(self.argv.iter().cloned());
+                argv
+            }
+            _ => self.argv.clone(),
+        }
+    }
+
+    // Get the PATH the model runs with when a virtualenv is activated
+    fn venv_path(&self, venv: &Path) -> String {
+        let bin = venv.join("bin").to_string_lossy().to_string();
+        let inherited = if self.inherit_env { std::env::var("PATH").ok() } else { None };
+        match self.env.get("PATH").cloned().or(inherited) {
+            Some(path) if !path.is_empty() => format!("{}:{}", bin, path),
+            _ => bin,
+        }
+    }
+
+    // Build the process command, the program is looked up on the PATH of the model environment
+    pub fn to_command(&self) -> tokio::process::Command {
+        let argv = self.program_argv();
+        let mut command = tokio::process::Command::new(&argv[0]);
+        command.args(&argv[1..]).current_dir(&self.cwd);
+
+        if !self.inherit_env {
+            command.env_clear();
+        }
+        for key in &self.env_remove {
+            command.env_remove(key);
+        }
+        command.envs(&self.env);
+        if let Activation::Venv(venv) = &self.activation {
+            command.env("VIRTUAL_ENV", venv)
+                .env("PATH", self.venv_path(venv))
+                .env_remove("PYTHONHOME");
+        }
+        command
+    }
+
+    // Build the equivalent command line for a remote shell, every value is quoted
+    pub fn to_shell_command(&self) -> Result<String, String> {
+        let quote = |value: &str| -> Result<String, String> {
+            shlex::try_quote(value).map(|quoted| quoted.to_string()).map_err(|err| {
+                log::error!("Failed to quote {:#?}: {}", value, err);
+                format!("Failed to quote {:#?}: {}", value, err)
+            })
+        };
+
+        let mut parts = vec!["cd".to_string(), quote(&self.cwd.to_string_lossy())?, "&&".to_string()];
+        if let Activation::Venv(venv) = &self.activation {
+            let venv = quote(&venv.to_string_lossy())?;
+            parts.push(format!("export VIRTUAL_ENV={} PATH={}/bin:\"$PATH\" && unset PYTHONHOME &&", venv, venv));
+        }
+        parts.push("exec".to_string());
+        parts.push("env".to_string());
+        if !self.inherit_env {
+            parts.push("-i".to_string());
+        }
+        for key in &self.env_remove {
+            parts.push("-u".to_string());
+            parts.push(quote(key)?);
+        }
+        let mut env: Vec<(&String, &String)> = self.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            parts.push(quote(&format!("{}={}", key, value))?);
+        }
+        for arg in self.program_argv() {
+            parts.push(quote(&arg)?);
+        }
+        Ok(parts.join(" "))
+    }
+}
+
+// Get a model param that is set and not empty
+fn non_empty<'a>(model_params: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    model_params.get(key).map(|value| value.as_str()).filter(|value| !value.is_empty())
+}
+
+// Parse a JSON array of strings
+fn parse_argv(key: &str, argv: &str) -> Result<Vec<String>, String> {
+    let argv: Vec<String> = serde_json::from_str(argv).map_err(|err| {
+        log::error!("The model param {} must be a JSON array of strings: {}", key, err);
+        format!("The model param {} must be a JSON array of strings: {}", key, err)
+    })?;
+    if argv.first().map(|program| program.is_empty()).unwrap_or(true) {
+        log::error!("The model param {} has no program", key);
+        return Err(format!("The model param {} has no program", key));
+    }
+    Ok(argv)
+}
+
+// Split a legacy command string, a leading `conda activate <env> &&` or `source <venv>/bin/activate &&`
+// becomes the activation and any other shell syntax is rejected
+fn parse_legacy_command(key: &str, command: &str) -> Result<(Vec<String>, Option<Activation>), String> {
+    let mut command = command.trim();
+    let mut activation = None;
+    if let Some((prefix, rest)) = command.split_once("&&") {
+        let prefix: Vec<&str> = prefix.split_whitespace().collect();
+        activation = match prefix.as_slice() {
+            ["conda", "activate", env] => Some(Activation::Conda { executable: "conda".to_string(), env: env.to_string() }),
+            ["source", script] | [".", script] => script.strip_suffix("/bin/activate").map(|venv| Activation::Venv(PathBuf::from(venv))),
+            _ => None,
+        };
+        if activation.is_some() {
+            command = rest.trim();
+        }
+    }
+
+    if let Some(operator) = SHELL_OPERATORS.iter().find(|operator| command.contains(*operator)) {
+        log::error!("The model param {} uses the shell syntax {:#?}, set the argv instead", key, operator);
+        return Err(format!("The model param {} uses the shell syntax {:#?}, set the argv instead", key, operator));
+    }
+    match shlex::split(command) {
+        Some(argv) if !argv.is_empty() => Ok((argv, activation)),
+        _ => {
+            log::error!("Failed to split the model param {}: {:#?}", key, command);
+            Err(format!("Failed to split the model param {}: {:#?}", key, command))
+        }
+    }
+}
+
+// Parse the env JSON object into the variables to set and the ones to unset
+fn parse_env(env: &str) -> Result<(HashMap<String, String>, Vec<String>), String> {
+    let env: serde_json::Map<String, Value> = serde_json::from_str(env).map_err(|err| {
+        log::error!("The model param env must be a JSON object: {}", err);
+        "The model param env must be a JSON object: ".to_string() + &err.to_string()
+    })?;
+
+    let mut set = HashMap::new();
+    let mut remove = Vec::new();
+    for (key, value) in env {
+        match value {
+            Value::Null => remove.push(key),
+            Value::String(value) => {
+                set.insert(key, value);
+            }
+            value => {
+                set.insert(key, value.to_string());
+            }
+        }
+    }
+    remove.sort();
+    Ok((set, remove))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_command() {
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), "/models/DialoGPT-small".to_string());
+
+        // The argv is taken as is and runs in the model path by default
+        model_params.insert("inferenceArgv".to_string(), r#"["python3", "inference.py", "--protocol", "jsonl; rm -rf /"]"#.to_string());
+        let model_command = ModelCommand::from_model_params(&model_params, "inference").unwrap();
+        assert_eq!(model_command.argv, vec!["python3", "inference.py", "--protocol", "jsonl; rm -rf /"]);
+        assert_eq!(model_command.cwd, std::path::PathBuf::from("/models/DialoGPT-small"));
+        assert!(model_command.inherit_env);
+        assert_eq!(model_command.activation, Activation::None);
+        assert_eq!(
+            model_command.to_shell_command().unwrap(),
+            "cd /models/DialoGPT-small && exec env python3 inference.py --protocol 'jsonl; rm -rf /'"
+        );
+
+        // Conda environments are activated with conda run
+        model_params.insert("condaEnv".to_string(), "transformer-venv".to_string());
+        let model_command = ModelCommand::from_model_params(&model_params, "inference").unwrap();
+        assert_eq!(model_command.program_argv()[..5], ["conda", "run", "--no-capture-output", "-n", "transformer-venv"]);
+        model_params.remove("condaEnv");
+
+        // Env values are set, null values are unset and the working directory is relative to the model path
+        model_params.insert("env".to_string(), r#"{"CUDA_VISIBLE_DEVICES": "0", "OMP_NUM_THREADS": 4, "PYTHONPATH": null}"#.to_string());
+        model_params.insert("envInherit".to_string(), "false".to_string());
+        model_params.insert("workingDir".to_string(), "src".to_string());
+        let model_command = ModelCommand::from_model_params(&model_params, "inference").unwrap();
+        assert_eq!(model_command.env.get("CUDA_VISIBLE_DEVICES"), Some(&"0".to_string()));
+        assert_eq!(model_command.env.get("OMP_NUM_THREADS"), Some(&"4".to_string()));
+        assert_eq!(model_command.env_remove, vec!["PYTHONPATH"]);
+        assert!(!model_command.inherit_env);
+        assert_eq!(model_command.cwd, std::path::PathBuf::from("/models/DialoGPT-small/src"));
+
+        // Legacy commands are split without a shell and their activation prefix is recognised
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), "/models/DialoGPT-small".to_string());
+        model_params.insert("inferenceCommand".to_string(), "conda activate transformer-venv && python3 inference.py".to_string());
+        let model_command = ModelCommand::from_model_params(&model_params, "inference").unwrap();
+        assert_eq!(model_command.argv, vec!["python3", "inference.py"]);
+        assert_eq!(model_command.activation, Activation::Conda { executable: "conda".to_string(), env: "transformer-venv".to_string() });
+
+        model_params.insert("inferenceCommand".to_string(), "source venv/bin/activate && python3 'inference.py'".to_string());
+        let model_command = ModelCommand::from_model_params(&model_params, "inference").unwrap();
+        assert_eq!(model_command.argv, vec!["python3", "inference.py"]);
+        assert_eq!(model_command.activation, Activation::Venv(std::path::PathBuf::from("/models/DialoGPT-small/venv")));
+
+        // Any other shell syntax is rejected
+        model_params.insert("inferenceCommand".to_string(), "python3 inference.py && rm -rf /".to_string());
+        assert!(ModelCommand::from_model_params(&model_params, "inference").is_err());
+        model_params.insert("inferenceCommand".to_string(), "python3 inference.py $(whoami)".to_string());
+        assert!(ModelCommand::from_model_params(&model_params, "inference").is_err());
+
+        // The argv must be a non-empty JSON array of strings
+        model_params.insert("inferenceArgv".to_string(), "python3 inference.py".to_string());
+        assert!(ModelCommand::from_model_params(&model_params, "inference").is_err());
+        model_params.insert("inferenceArgv".to_string(), "[]".to_string());
+        assert!(ModelCommand::from_model_params(&model_params, "inference").is_err());
+    }
+}
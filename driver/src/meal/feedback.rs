@@ -0,0 +1,323 @@
+// src/meal/feedback.rs
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use super::settings::{parse_bool, parse_u64};
+
+
+// Default directory of the collected datasets, relative to the working directory of the driver
+pub const DEFAULT_FEEDBACK_DIR: &str = "feedback";
+// Default number of collected interactions that triggers a training run
+pub const DEFAULT_FEEDBACK_THRESHOLD: u64 = 100;
+// Default lowest rating of an interaction that is added to the dataset
+pub const DEFAULT_FEEDBACK_MIN_RATING: u64 = 4;
+// Default number of answered requests that can still be rated
+pub const DEFAULT_FEEDBACK_RECENT_TURNS: u64 = 1000;
+// Ratings go from 1 (bad) to MAX_RATING (good)
+pub const MAX_RATING: u8 = 5;
+// Name of the file the rated interactions are appended to until the threshold is reached
+const COLLECTING_FILE: &str = "collecting.txt";
+
+
+// Feedback learning settings parsed from the model params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedbackSettings {
+    // Whether feedback is collected from the start, the REPL toggles it at runtime
+    pub enabled: bool,
+    pub dir: PathBuf,
+    pub threshold: usize,
+    pub min_rating: u8,
+    pub recent_turns: usize,
+    // Whether the running instances are restarted with the trained weights
+    pub hot_swap: bool,
+}
+
+impl FeedbackSettings {
+    pub fn from_model_params(model_params: &HashMap<String, String>) -> Result<Self, String> {
+        let min_rating = parse_u64(model_params, "feedbackMinRating", DEFAULT_FEEDBACK_MIN_RATING)?;
+        if !(1..=MAX_RATING as u64).contains(&min_rating) {
+            log::error!("The model param feedbackMinRating must be between 1 and {}", MAX_RATING);
+            return Err(format!("The model param feedbackMinRating must be between 1 and {}", MAX_RATING));
+        }
+
+        Ok(Self {
+            enabled: parse_bool(model_params, "feedbackEnabled", false)?,
+            dir: PathBuf::from(model_params.get("feedbackDir").filter(|dir| !dir.is_empty()).map(|dir| dir.as_str()).unwrap_or(DEFAULT_FEEDBACK_DIR)),
+            threshold: parse_u64(model_params, "feedbackThreshold", DEFAULT_FEEDBACK_THRESHOLD)?.max(1) as usize,
+            min_rating: min_rating as u8,
+            recent_turns: parse_u64(model_params, "feedbackRecentTurns", DEFAULT_FEEDBACK_RECENT_TURNS)? as usize,
+            hot_swap: parse_bool(model_params, "feedbackHotSwap", false)?,
+        })
+    }
+}
+
+
+// Answered request that can still be rated
+#[derive(Debug, Clone)]
+struct RecentTurn {
+    request_id: String,
+    input: String,
+    output: String,
+}
+
+// Dataset handed to a training run once enough interactions were collected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedbackDataset {
+    // File name of the dataset, it is uploaded to the datasets directory of the model
+    pub name: String,
+    pub data: Vec<u8>,
+    pub examples: usize,
+}
+
+// Outcome of rating an interaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rating {
+    // Rated below the minimum rating, the interaction is not learned from
+    Rejected,
+    // Added to the dataset, with the number of collected interactions
+    Collected(usize),
+}
+
+// Snapshot of the feedback learning of a model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedbackStatus {
+    pub enabled: bool,
+    pub collected: usize,
+    pub threshold: usize,
+    pub training: bool,
+    pub last_result: Option<Result<String, String>>,
+}
+
+
+// Collects the rated interactions of a model into a dataset file
+#[derive(Debug)]
+pub struct ModelFeedback {
+    settings: FeedbackSettings,
+    enabled: bool,
+    dir: PathBuf,
+    recent: VecDeque<RecentTurn>,
+    collected: usize,
+    training: bool,
+    // Weights version produced by the last training run or its error
+    last_result: Option<Result<String, String>>,
+}
+
+impl ModelFeedback {
+    // Create the feedback of a model, interactions collected before a restart of the driver are kept
+    pub fn new(settings: FeedbackSettings, model_name: &str) -> Self {
+        let model_dir: String = model_name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+            .collect();
+        let dir = settings.dir.join(model_dir);
+        let collected = std::fs::read_to_string(dir.join(COLLECTING_FILE))
+            .map(|data| data.matches("\n\n").count())
+            .unwrap_or(0);
+
+        Self {
+            enabled: settings.enabled,
+            settings,
+            dir,
+            recent: VecDeque::new(),
+            collected,
+            training: false,
+            last_result: None,
+        }
+    }
+
+    // Get the feedback settings
+    pub fn settings(&self) -> &FeedbackSettings {
+        &self.settings
+    }
+
+    // Check if the answered requests are kept for rating
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Turn the feedback collection on or off, the collected interactions are kept
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.recent.clear();
+        }
+    }
+
+    // Keep an answered request so it can be rated, the oldest ones are forgotten
+    pub fn observe(&mut self, request_id: &str, input: &str, output: &str) {
+        if !self.enabled || self.settings.recent_turns == 0 {
+            return;
+        }
+        if self.recent.len() >= self.settings.recent_turns {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(RecentTurn {
+            request_id: request_id.to_string(),
+            input: input.to_string(),
+            output: output.to_string(),
+        });
+    }
+
+    // Rate an answered request, the last one if no id is given, well rated ones are appended to the dataset
+    pub fn rate(&mut self, request_id: Option<&str>, rating: u8) -> Result<Rating, String> {
+        if !self.enabled {
+            return Err("Feedback learning is disabled".to_string());
+        }
+        if !(1..=MAX_RATING).contains(&rating) {
+            return Err(format!("The rating must be between 1 and {}", MAX_RATING));
+        }
+        let index = match request_id {
+            Some(request_id) => self.recent.iter().rposition(|turn| turn.request_id == request_id)
+                .ok_or_else(|| format!("Request {} is not known or was already rated", request_id))?,
+            None => self.recent.len().checked_sub(1).ok_or("There is no request to rate")?,
+        };
+        let turn = self.recent.remove(index).unwrap();
+        if rating < self.settings.min_rating {
+            return Ok(Rating::Rejected);
+        }
+
+        // Every example is the input and the output on their own line followed by an empty line
+        let flatten = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let example = format!("{}\n{}\n\n", flatten(&turn.input), flatten(&turn.output));
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::OpenOptions::new().create(true).append(true).open(self.dir.join(COLLECTING_FILE)))
+            .and_then(|mut file| file.write_all(example.as_bytes()))
+            .map_err(|err| {
+                log::error!("Failed to append to the feedback dataset {:#?}: {}", self.dir, err);
+                format!("Failed to append to the feedback dataset {:#?}: {}", self.dir, err)
+            })?;
+        self.collected += 1;
+        Ok(Rating::Collected(self.collected))
+    }
+
+    // Take the collected dataset once the threshold is reached and no training is running,
+    // the file is kept next to the collecting one under the dataset name
+    pub fn take_dataset(&mut self) -> Result<Option<FeedbackDataset>, String> {
+        if self.training || self.collected < self.settings.threshold {
+            return Ok(None);
+        }
+        let name = format!("feedback-{}.txt", chrono::Utc::now().format("%Y%m%d%H%M%S"));
+        let path = self.dir.join(&name);
+        let data = std::fs::rename(self.dir.join(COLLECTING_FILE), &path)
+            .and_then(|_| std::fs::read(&path))
+            .map_err(|err| {
+                log::error!("Failed to take the feedback dataset {:#?}: {}", path, err);
+                format!("Failed to take the feedback dataset {:#?}: {}", path, err)
+            })?;
+
+        let examples = self.collected;
+        self.collected = 0;
+        self.training = true;
+        Ok(Some(FeedbackDataset { name, data, examples }))
+    }
+
+    // Record the outcome of the training run started with the last dataset
+    pub fn training_finished(&mut self, result: Result<String, String>) {
+        self.training = false;
+        self.last_result = Some(result);
+    }
+
+    // Get a snapshot of the feedback learning
+    pub fn status(&self) -> FeedbackStatus {
+        FeedbackStatus {
+            enabled: self.enabled,
+            collected: self.collected,
+            threshold: self.settings.threshold,
+            training: self.training,
+            last_result: self.last_result.clone(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dal::JobState;
+    use crate::meal::{logs, training};
+    use std::sync::Arc;
+
+    // Test the collection of rated interactions and a training run on the collected dataset
+    #[tokio::test]
+    async fn test_model_feedback() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-feedback-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("feedbackDir".to_string(), dir.join("feedback").to_string_lossy().to_string());
+        model_params.insert("feedbackThreshold".to_string(), "2".to_string());
+        model_params.insert("feedbackRecentTurns".to_string(), "2".to_string());
+        let settings = FeedbackSettings::from_model_params(&model_params).unwrap();
+        assert!(!settings.enabled);
+        assert_eq!(settings.min_rating, 4);
+        model_params.insert("feedbackMinRating".to_string(), "6".to_string());
+        assert!(FeedbackSettings::from_model_params(&model_params).is_err());
+
+        // Only the recent answers of an enabled model can be rated and only well rated ones are collected
+        let mut model_feedback = ModelFeedback::new(settings.clone(), "DialoGPT-small");
+        assert!(model_feedback.rate(None, 5).is_err());
+        model_feedback.set_enabled(true);
+        model_feedback.observe("1", "Hi", "Hello");
+        model_feedback.observe("2", "How are\nyou?", "Fine");
+        model_feedback.observe("3", "Bye", "Goodbye");
+        assert!(model_feedback.rate(Some("1"), 5).is_err());
+        assert_eq!(model_feedback.rate(Some("3"), 2).unwrap(), Rating::Rejected);
+        assert!(model_feedback.rate(Some("3"), 5).is_err());
+        assert_eq!(model_feedback.rate(None, 5).unwrap(), Rating::Collected(1));
+        assert_eq!(model_feedback.take_dataset().unwrap(), None);
+
+        // The collected interactions survive a restart and are taken once the threshold is reached
+        let mut model_feedback = ModelFeedback::new(settings, "DialoGPT-small");
+        model_feedback.set_enabled(true);
+        assert_eq!(model_feedback.status().collected, 1);
+        model_feedback.observe("4", "Bye", "Goodbye");
+        assert_eq!(model_feedback.rate(Some("4"), 4).unwrap(), Rating::Collected(2));
+        let dataset = model_feedback.take_dataset().unwrap().unwrap();
+        assert_eq!(dataset.examples, 2);
+        assert_eq!(String::from_utf8_lossy(&dataset.data), "How are you?\nFine\n\nBye\nGoodbye\n\n");
+        assert!(model_feedback.status().training);
+        assert_eq!(model_feedback.status().collected, 0);
+
+        // The training job gets the uploaded dataset and the weights to produce
+        let model_path = dir.join("model");
+        std::fs::create_dir_all(&model_path).unwrap();
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "DialoGPT-small".to_string());
+        static_fields.insert("connType".to_string(), "local".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), model_path.to_string_lossy().to_string());
+        model_params.insert(
+            "trainArgv".to_string(),
+            r#"["sh", "-c", "echo training on $2; mkdir -p weights/$4 && cp datasets/$2 weights/$4/", "train"]"#.to_string(),
+        );
+        let mut meal_config = vec![static_fields, HashMap::new(), model_params];
+        let model_log = Arc::new(logs::ModelLog::new(logs::LogSettings::from_model_params(&meal_config[2]).unwrap(), "DialoGPT-small", ""));
+        let training_jobs = Arc::new(training::TrainingJobs::default());
+        let spec = |old_weights: Option<&str>, new_weights: &str| training::TrainingSpec {
+            dataset: dataset.name.clone(),
+            upload: Some(dataset.data.clone()),
+            old_weights: old_weights.map(|old_weights| old_weights.to_string()),
+            new_weights: new_weights.to_string(),
+            args: Vec::new(),
+        };
+        let id = training_jobs.submit(meal_config.clone(), Arc::clone(&model_log), spec(None, "feedback-1")).unwrap();
+        let job = training_jobs.wait(&id).await.unwrap();
+        assert_eq!(job.state, JobState::Succeeded);
+        assert_eq!(std::fs::read(model_path.join("weights/feedback-1").join(&dataset.name)).unwrap(), dataset.data);
+        assert_eq!(training_jobs.output(&id, 10), vec![format!("training on {}", dataset.name)]);
+        assert!(model_log.tail(10).iter().any(|line| line.ends_with(&format!("[train] training on {}", dataset.name))));
+        assert!(training_jobs.submit(meal_config.clone(), Arc::clone(&model_log), spec(None, "../feedback-2")).is_err());
+
+        // A failing train command fails the job and a cancelled one is stopped
+        meal_config[2].insert("trainArgv".to_string(), r#"["sh", "-c", "exit 3"]"#.to_string());
+        let id = training_jobs.submit(meal_config.clone(), Arc::clone(&model_log), spec(Some("feedback-1"), "feedback-2")).unwrap();
+        let job = training_jobs.wait(&id).await.unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert!(job.error.is_some());
+        meal_config[2].insert("trainArgv".to_string(), r#"["sleep", "30"]"#.to_string());
+        let id = training_jobs.submit(meal_config, Arc::clone(&model_log), spec(Some("feedback-1"), "feedback-3")).unwrap();
+        training_jobs.cancel(&id).unwrap();
+        assert_eq!(training_jobs.wait(&id).await.unwrap().state, JobState::Cancelled);
+        assert!(training_jobs.cancel(&id).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
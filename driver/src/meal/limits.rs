@@ -0,0 +1,266 @@
+// src/meal/limits.rs
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use super::settings::parse_u64;
+
+
+// Mount point of the cgroup v2 hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// Extra CPU seconds between SIGXCPU and SIGKILL
+const CPU_HARD_LIMIT_GRACE_SECS: u64 = 5;
+// Number of the cgroups created by the driver, they are created before the pid of the model is known
+static CGROUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+// Resource limits and credentials of a local model process parsed from the model params:
+//     - maxAddressSpaceMb: virtual memory limit (RLIMIT_AS)
+//     - maxRssMb: resident memory limit, enforced by a cgroup v2 child group of cgroupParent (memory.max)
+//     - maxCpuSeconds: CPU time limit (RLIMIT_CPU)
+//     - maxOpenFiles: open file descriptor limit (RLIMIT_NOFILE)
+//     - nice: scheduling priority between -20 and 19
+//     - runAsUid, runAsGid: dedicated user and group the model runs as, the uid param holds the id of the entry
+//     - cgroupParent: cgroup v2 group delegated to the driver the child groups are created in, required by maxRssMb
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    pub max_address_space: Option<u64>,
+    pub max_rss: Option<u64>,
+    pub max_cpu_seconds: Option<u64>,
+    pub max_open_files: Option<u64>,
+    pub nice: Option<i32>,
+    pub run_as_uid: Option<u32>,
+    pub run_as_gid: Option<u32>,
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+impl ResourceLimits {
+    pub fn from_model_params(model_params: &HashMap<String, String>) -> Result<Self, String> {
+        // Get an optional limit, 0 and missing mean unlimited
+        let limit = |key: &str| -> Result<Option<u64>, String> {
+            Ok(Some(parse_u64(model_params, key, 0)?).filter(|limit| *limit > 0))
+        };
+        let id = |key: &str| -> Result<Option<u32>, String> {
+            match model_params.get(key).filter(|id| !id.is_empty()) {
+                Some(_) => u32::try_from(parse_u64(model_params, key, 0)?).map(Some).map_err(|_| {
+                    log::error!("Invalid value for the model param {}", key);
+                    format!("Invalid value for the model param {}", key)
+                }),
+                None => Ok(None),
+            }
+        };
+
+        let nice = match model_params.get("nice").filter(|nice| !nice.is_empty()) {
+            Some(nice) => match nice.trim().parse::<i32>() {
+                Ok(nice) if (-20..=19).contains(&nice) => Some(nice),
+                _ => {
+                    log::error!("Invalid value for the model param nice: {:#?}", nice);
+                    return Err(format!("Invalid value for the model param nice: {:#?}", nice));
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            max_address_space: limit("maxAddressSpaceMb")?.map(|mb| mb * 1024 * 1024),
+            max_rss: limit("maxRssMb")?.map(|mb| mb * 1024 * 1024),
+            max_cpu_seconds: limit("maxCpuSeconds")?,
+            max_open_files: limit("maxOpenFiles")?,
+            nice,
+            run_as_uid: id("runAsUid")?,
+            run_as_gid: id("runAsGid")?,
+            cgroup_parent: model_params.get("cgroupParent").filter(|path| !path.is_empty()).map(PathBuf::from),
+        })
+    }
+
+    // Configure the cgroup, rlimits, priority and credentials of the command, they are all set in the forked child
+    // before it runs the model
+    pub fn apply(&self, command: &mut tokio::process::Command, cgroup: Option<&Cgroup>) {
+        let rlimits: Vec<(libc::__rlimit_resource_t, libc::rlim_t, libc::rlim_t)> = [
+            self.max_address_space.map(|limit| (libc::RLIMIT_AS, limit, limit)),
+            self.max_cpu_seconds.map(|limit| (libc::RLIMIT_CPU, limit, limit + CPU_HARD_LIMIT_GRACE_SECS)),
+            self.max_open_files.map(|limit| (libc::RLIMIT_NOFILE, limit, limit)),
+        ].into_iter().flatten().collect();
+        let (nice, uid, gid) = (self.nice, self.run_as_uid, self.run_as_gid);
+        let procs = cgroup.map(|cgroup| cgroup.procs.clone());
+        if rlimits.is_empty() && nice.is_none() && uid.is_none() && gid.is_none() && procs.is_none() {
+            return;
+        }
+
+        // SAFETY: the closure runs in the forked child and only makes async-signal-safe system calls on memory
+        // allocated before the fork
+        unsafe {
+            command.pre_exec(move || {
+                // Join the cgroup first, so the model never runs without its memory limit
+                if let Some(procs) = &procs {
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                    let err = std::io::Error::last_os_error();
+                    libc::close(fd);
+                    if written != 1 {
+                        return Err(err);
+                    }
+                }
+                for (resource, soft, hard) in &rlimits {
+                    let rlimit = libc::rlimit { rlim_cur: *soft, rlim_max: *hard };
+                    if libc::setrlimit(*resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+
+                // The credentials are switched last, joining the cgroup and raising the priority need the
+                // privileges of the driver
+                if let Some(gid) = gid {
+                    if libc::setgid(gid) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some(uid) = uid {
+                    if libc::getuid() == 0 {
+                        libc::setgroups(0, std::ptr::null());
+                    }
+                    if libc::setuid(uid) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Get the limit a process killed by the signal exceeded, SIGXCPU is only sent for the CPU time limit
+    pub fn exceeded_by_signal(&self, signal: i32) -> Option<String> {
+        match self.max_cpu_seconds {
+            Some(limit) if signal == libc::SIGXCPU => Some(format!("CPU time limit of {}s exceeded", limit)),
+            _ => None,
+        }
+    }
+}
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Child cgroup of a single model process
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    // cgroup.procs of the group, the model process writes itself to it before it runs the model
+    procs: CString,
+    max_rss: u64,
+}
+
+impl Cgroup {
+    // Create a child group with the memory limit of a model process under the configured parent, the process joins
+    // it with ResourceLimits::apply. The parent has to be delegated to the driver and must not hold processes itself,
+    // cgroup v2 only enables controllers for the children of groups without processes
+    pub fn create(limits: &ResourceLimits, name: &str) -> Result<Option<Self>, String> {
+        let max_rss = match limits.max_rss {
+            Some(max_rss) => max_rss,
+            None => return Ok(None),
+        };
+        if !Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+            return Err("cgroup v2 is not mounted at ".to_string() + CGROUP_ROOT);
+        }
+
+        let parent = match &limits.cgroup_parent {
+            Some(parent) if parent.is_absolute() => parent.clone(),
+            Some(parent) => Path::new(CGROUP_ROOT).join(parent),
+            None => return Err("The model param maxRssMb requires the model param cgroupParent".to_string()),
+        };
+
+        // Enable the memory controller for the children of the parent
+        write_cgroup_file(&parent.join("cgroup.subtree_control"), "+memory")?;
+
+        let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+        let path = parent.join(format!("mer-{}-{}-{}", name, std::process::id(), CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let procs = CString::new(path.join("cgroup.procs").as_os_str().as_bytes())
+            .map_err(|_| format!("Invalid cgroup path {:#?}", path))?;
+        std::fs::create_dir(&path).map_err(|err| format!("Failed to create the cgroup {:#?}: {}", path, err))?;
+        let cgroup = Self { path, procs, max_rss };
+
+        // Swapping would only delay hitting the limit, the file is missing without swap accounting
+        if let Err(err) = write_cgroup_file(&cgroup.path.join("memory.max"), &max_rss.to_string()) {
+            cgroup.remove();
+            return Err(err);
+        }
+        let _ = std::fs::write(cgroup.path.join("memory.swap.max"), "0");
+        Ok(Some(cgroup))
+    }
+
+    // Get the limit the group exceeded if the OOM killer acted in it
+    pub fn exceeded(&self) -> Option<String> {
+        let events = std::fs::read_to_string(self.path.join("memory.events")).ok()?;
+        let oom_kills = events.lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        (oom_kills > 0).then(|| format!("Memory limit of {} MB exceeded", self.max_rss / 1024 / 1024))
+    }
+
+    // Remove the group once its processes exited
+    pub fn remove(&self) {
+        if let Err(err) = std::fs::remove_dir(&self.path) {
+            log::warn!("Failed to remove the cgroup {:#?}: {}", self.path, err);
+        }
+    }
+}
+
+fn write_cgroup_file(path: &Path, value: &str) -> Result<(), String> {
+    std::fs::write(path, value).map_err(|err| format!("Failed to write {:#?} to {:#?}: {}", value, path, err))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_limits() {
+        // Missing and zero limits mean unlimited
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("maxOpenFiles".to_string(), "0".to_string());
+        // Params loaded from the DB carry the id of the entry as uid, it is not the user the model runs as
+        model_params.insert("uid".to_string(), "0b7c2a1e-5f4d-4e8a-9c3b-6d2f1a8e7b90".to_string());
+        assert_eq!(ResourceLimits::from_model_params(&model_params).unwrap(), ResourceLimits::default());
+
+        // Memory limits are given in MB
+        model_params.insert("maxAddressSpaceMb".to_string(), "8192".to_string());
+        model_params.insert("maxRssMb".to_string(), "4096".to_string());
+        model_params.insert("maxCpuSeconds".to_string(), "3600".to_string());
+        model_params.insert("maxOpenFiles".to_string(), "1024".to_string());
+        model_params.insert("nice".to_string(), "10".to_string());
+        model_params.insert("runAsUid".to_string(), "1500".to_string());
+        model_params.insert("runAsGid".to_string(), "1500".to_string());
+        let resource_limits = ResourceLimits::from_model_params(&model_params).unwrap();
+        assert_eq!(resource_limits.max_address_space, Some(8192 * 1024 * 1024));
+        assert_eq!(resource_limits.max_rss, Some(4096 * 1024 * 1024));
+        assert_eq!(resource_limits.max_cpu_seconds, Some(3600));
+        assert_eq!(resource_limits.max_open_files, Some(1024));
+        assert_eq!(resource_limits.nice, Some(10));
+        assert_eq!(resource_limits.run_as_uid, Some(1500));
+        assert_eq!(resource_limits.run_as_gid, Some(1500));
+
+        // The memory limit is only enforced in a delegated parent group, without one the model is not started
+        assert!(Cgroup::create(&ResourceLimits::default(), "model").unwrap().is_none());
+        assert!(Cgroup::create(&resource_limits, "model").is_err());
+
+        // Only SIGXCPU is attributed to the CPU time limit
+        assert!(resource_limits.exceeded_by_signal(libc::SIGXCPU).is_some());
+        assert!(resource_limits.exceeded_by_signal(libc::SIGKILL).is_none());
+
+        // Out of range values are rejected
+        model_params.insert("nice".to_string(), "-21".to_string());
+        assert!(ResourceLimits::from_model_params(&model_params).is_err());
+        model_params.insert("nice".to_string(), "0".to_string());
+        model_params.insert("runAsUid".to_string(), "4294967296".to_string());
+        assert!(ResourceLimits::from_model_params(&model_params).is_err());
+    }
+}
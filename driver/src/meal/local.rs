@@ -1,19 +1,28 @@
 // src/meal/local.rs
-use super::{MEALDriver, MEALArgs};
+use super::{MEALDriver, MEALArgs, ModelExit};
+use super::command::ModelCommand;
+use super::limits::{Cgroup, ResourceLimits};
+use super::stats::{self, CpuTracker, ProcessStats};
+use super::transfer::{self, ModelFile};
 use std::fmt;
 use std::collections::HashMap;
 use async_trait::async_trait;
 
 
 // Std libraries
-use std::sync::{Arc, Mutex};
+use std::os::unix::process::ExitStatusExt;
 use std::process::Stdio;
+use std::time::Duration;
 
 // tokio libraries
-use tokio::sync::mpsc;
-use tokio::process::Command;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+
+
+// Capacity of the stdin, stdout and stderr channels
+const CHANNEL_CAPACITY: usize = 64;
+// Time the model process group has to exit on SIGTERM before it is killed
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
 
 
 // Create the LocalDriver struct
@@ -21,6 +30,10 @@ pub struct LocalDriver {
     static_fields: HashMap<String, String>,
     model_params: HashMap<String, String>,
     connection_params: HashMap<String, String>,
+    // Process group of the last spawned model, the model and everything it started
+    process_group: Option<i32>,
+    exited_rx: Option<watch::Receiver<Option<ModelExit>>>,
+    cpu_tracker: CpuTracker,
 }
 
 #[async_trait]
@@ -35,160 +48,229 @@ impl MEALDriver for LocalDriver {
             static_fields: meal_args.meal_config[0].clone(),
             connection_params: meal_args.meal_config[1].clone(),
             model_params: meal_args.meal_config[2].clone(),
+            process_group: None,
+            exited_rx: None,
+            cpu_tracker: CpuTracker::default(),
         }
     }
 
     //////////////////////////////////////////////////////
     ////// Management of the LocalDriver connection //////
     //////////////////////////////////////////////////////
-    async fn spawn_model(&mut self) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String> {
-        // Get the model path
-        let model_path = self.model_params.get("modelPath").ok_or_else(|| {
-            log::error!("Failed to get the model path");
-            "Failed to get the model path".to_string()
-        })?;
-        // Get the model command
-        let model_command = self.model_params.get("inferenceCommand").ok_or_else(|| {
-            log::error!("Failed to get the model command");
-            "Failed to get the model command".to_string()
-        })?;
-        // Get the ready token
-        let ready_token = self.model_params.get("readyToken").ok_or_else(|| {
-            log::error!("Failed to get the ready token");
-            "Failed to get the ready token".to_string()
-        })?;
-        // Get the exit token
-        let exit_token = self.model_params.get("exitToken").ok_or_else(|| {
-            log::error!("Failed to get the exit token");
-            "Failed to get the exit token".to_string()
-        })?;
-        // Get the start token
-        let start_token = self.model_params.get("startToken").ok_or_else(|| {
-            log::error!("Failed to get the start token");
-            "Failed to get the start token".to_string()
-        })?;
-        // Get the stop token
-        let stop_token = self.model_params.get("stopToken").ok_or_else(|| {
-            log::error!("Failed to get the stop token");
-            "Failed to get the stop token".to_string()
-        })?;
+    async fn spawn_command(&mut self, kind: &str, args: &[String]) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String> {
+        // Parse the model command, it is executed directly without a shell
+        let mut model_command = ModelCommand::from_model_params(&self.model_params, kind)?;
+        model_command.argv.extend(args.iter().cloned());
+        let limits = ResourceLimits::from_model_params(&self.model_params)?;
 
         // Log the model parameters
         log::info!(
-            "Model parameters:\n    - Model path: {:#?}\n    - Model command: {:#?}\n    - Ready token: {:#?}\n    - Exit token: {:#?}",
-            model_path, model_command, ready_token, exit_token
+            "Model parameters:\n    - Working directory: {:#?}\n    - Model argv: {:#?}\n    - Activation: {:#?}",
+            model_command.cwd, model_command.program_argv(), model_command.activation
         );
+        log::info!("Model resource limits: {:#?}", limits);
 
-        // Check if the model path exists
-        if !std::path::Path::new(model_path).exists() {
-            log::error!("The model path does not exist: {:#?}", model_path);
-            return Err("The model path does not exist: ".to_string() + model_path);
+        // Check if the working directory exists
+        if !model_command.cwd.is_dir() {
+            log::error!("The model working directory does not exist: {:#?}", model_command.cwd);
+            return Err(format!("The model working directory does not exist: {:#?}", model_command.cwd));
         }
 
-        // Combine the cd into model path and model command into one string
-        let model_command = format!("cd {} && {}", model_path, model_command);
+        // The memory limit is enforced by a cgroup the model process joins before it runs the model, the model
+        // is not started when the limit can not be enforced
+        let name = self.static_fields.get("name").cloned().unwrap_or_default();
+        let cgroup = Cgroup::create(&limits, &name).map_err(|err| {
+            log::error!("Failed to enforce the memory limit of model {}: {}", name, err);
+            format!("Failed to enforce the memory limit of model {}: {}", name, err)
+        })?;
 
-        // Create Tokio channels for communication
-        let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(1);
-        let (stdout_tx, mut stdout_rx) = mpsc::channel::<String>(1);
-        let (stderr_tx, stderr_rx) = mpsc::channel::<String>(1);
+        // Spawn the model process in its own process group so the whole tree can be signalled
+        let mut command = model_command.to_command();
+        limits.apply(&mut command, cgroup.as_ref());
+        let spawned = command
+            .process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(err) => {
+                if let Some(cgroup) = &cgroup {
+                    cgroup.remove();
+                }
+                log::error!("Failed to start the model command: {}", err);
+                return Err("Failed to start the model command: ".to_string() + &err.to_string());
+            }
+        };
+
+        // Get the stdin, stdout, and stderr handles
+        let mut stdin = child.stdin.take().ok_or("Failed to open stdin")?;
+        let stdout = child.stdout.take().ok_or("Failed to open stdout")?;
+        let stderr = child.stderr.take().ok_or("Failed to open stderr")?;
 
-        let cloned_stdout_rx = Arc::new(Mutex::new(stdout_rx));
+        // Create Tokio channels for communication
+        let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+        let (stdout_tx, stdout_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+        let (stderr_tx, stderr_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
 
-        // Spawn a Tokio task to handle command execution
+        // Forward the stdin channel to the model stdin, closing the channel closes the pipe
         tokio::spawn(async move {
-            // Create a new Command
-            let mut child = Command::new("sh")
-                .arg("-c")
-                .arg(&model_command)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("Failed to start command");
-
-            // Get the stdin, stdout, and stderr handles
-            let mut stdin = child.stdin.take().expect("Failed to open stdin");
-            let mut stdout = child.stdout.take().expect("Failed to open stdout");
-            let mut stderr = child.stderr.take().expect("Failed to open stderr");
-
-            // Wait for the ready token
-            let mut ready = false;
-            while !ready {
-                // Check if the ready token is in the stdout channel
-                let cloned_stdout_rx = Arc::clone(&cloned_stdout_rx);
-                while let Some(chunk) = cloned_stdout_rx.lock().unwrap().recv().await {
-                    let stdout_str = String::from_utf8_lossy(&chunk.as_bytes());
-                    if stdout_str.contains(ready_token) {
-                        ready = true;
-                    } else {
-                        log::info!("stdout: {:#?}", stdout_str);
-                    }
+            while let Some(data) = stdin_rx.recv().await {
+                if let Err(err) = stdin.write_all(data.as_bytes()).await {
+                    log::error!("Failed to write to the model stdin: {}", err);
+                    break;
+                }
+                if let Err(err) = stdin.flush().await {
+                    log::error!("Failed to flush the model stdin: {}", err);
+                    break;
                 }
             }
+        });
 
-            // Start the main loop
-            let mut exit = false;
-            while !exit {
-                // Read data from the command's standard output and send it to the channel
-                let mut buffer = String::new();
-                while stdout.read_to_string(&mut buffer).await.expect("Failed to read from stdout") > 0 {
-                    // Check if the exit token is in the buffer
-                    if buffer.contains(exit_token) {
-                        exit = true;
-                    }
-                    // Check if start  tokes is in the buffer
-                    if buffer.contains(start_token) {
-                        // Remove the start token from the buffer
-                        buffer = buffer.replace(start_token, "");
-                    }
-                    // Check if stop token is in the buffer
-                    if buffer.contains(stop_token) {
-                        // Remove the stop token from the buffer
-                        buffer = buffer.replace(stop_token, "");
-                    }
-                    // Send the buffer to the channel
-                    stdout_tx.send(buffer.clone()).await.expect("Failed to send stdout data");
-                    buffer.clear();
-                }
+        // Forward the model stdout and stderr line by line
+        tokio::spawn(forward_lines(stdout, stdout_tx));
+        tokio::spawn(forward_lines(stderr, stderr_tx));
 
-                // Read data from the command's standard error and send it to the channel
-                buffer.clear();
-                while stderr.read_to_string(&mut buffer).await.expect("Failed to read from stderr") > 0 {
-                    stderr_tx.send(buffer.clone()).await.expect("Failed to send stderr data");
-                    buffer.clear();
-                }
+        // The process group id equals the pid of the group leader
+        let pid = child.id();
+        self.process_group = pid.map(|pid| pid as i32);
 
-                // Check if there is data in the stdin channel
-                if let Ok(stdin_buf) = stdin_rx.try_recv() {
-                    // Check if the exit token is in the stdin channel
-                    if stdin_buf.contains(exit_token) {
-                        exit = true;
+        // Reap the model process once it exits and report whether it exceeded a limit
+        let (exited_tx, exited_rx) = watch::channel(None);
+        tokio::spawn(async move {
+            let model_exit = match child.wait().await {
+                Ok(status) => {
+                    let exceeded = cgroup.as_ref().and_then(|cgroup| cgroup.exceeded())
+                        .or_else(|| status.signal().and_then(|signal| limits.exceeded_by_signal(signal)));
+                    match exceeded {
+                        Some(reason) => {
+                            log::error!("Model exceeded its resource limits: {}", reason);
+                            ModelExit::LimitExceeded(reason)
+                        }
+                        None if status.success() => {
+                            log::info!("Model exited successfully");
+                            ModelExit::Succeeded
+                        }
+                        None => {
+                            log::error!("Model exited with status: {}", status);
+                            ModelExit::Exited(status.to_string())
+                        }
                     }
-                    // Write the start token to the command's standard input
-                    stdin.write_all(start_token.clone().as_bytes()).await.expect("Failed to write to stdin");
-                    // Write the data to the command's standard input
-                    stdin.write_all(stdin_buf.as_bytes()).await.expect("Failed to write to stdin");
-                    // Write the stop token to the command's standard input
-                    stdin.write_all(stop_token.clone().as_bytes()).await.expect("Failed to write to stdin");
                 }
+                Err(err) => {
+                    log::error!("Failed to wait for the model process: {}", err);
+                    ModelExit::Exited(err.to_string())
+                }
+            };
+            if let Some(cgroup) = cgroup {
+                cgroup.remove();
             }
-
-            // Wait for the child process to finish
-            let status = child.wait().await.expect("Failed to wait for child process");
-            
-            if status.success() {
-                log::info!("Model exited successfully");
-            } else {
-                log::error!("Model exited with status: {}", status);
-            }
+            let _ = exited_tx.send(Some(model_exit));
         });
+        self.exited_rx = Some(exited_rx);
 
         // Return the channels
         Ok((stdin_tx, stdout_rx, stderr_rx))
     }
 
+    async fn put_file(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        let path = transfer::model_file(&self.model_params, path)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|err| {
+                log::error!("Failed to create the directory {:#?}: {}", dir, err);
+                format!("Failed to create the directory {:#?}: {}", dir, err)
+            })?;
+        }
+        tokio::fs::write(&path, data).await.map_err(|err| {
+            log::error!("Failed to write the file {:#?}: {}", path, err);
+            format!("Failed to write the file {:#?}: {}", path, err)
+        })
+    }
+
+    async fn get_file(&mut self, path: &str) -> Result<Vec<u8>, String> {
+        let path = transfer::model_file(&self.model_params, path)?;
+        tokio::fs::read(&path).await.map_err(|err| {
+            log::error!("Failed to read the file {:#?}: {}", path, err);
+            format!("Failed to read the file {:#?}: {}", path, err)
+        })
+    }
+
+    async fn list_files(&mut self, dir: &str) -> Result<Vec<ModelFile>, String> {
+        let dir = transfer::model_file(&self.model_params, dir)?;
+        tokio::task::spawn_blocking(move || transfer::list_local(&dir)).await
+            .map_err(|err| "Failed to list the files: ".to_string() + &err.to_string())?
+            .inspect_err(|err| log::error!("{}", err))
+    }
+
+    async fn terminate(&mut self) -> Result<(), String> {
+        let process_group = match self.process_group.take() {
+            Some(process_group) => process_group,
+            None => return Ok(()),
+        };
+
+        // Ask the model and its children to terminate and give them a moment to do so
+        if let Some(mut exited_rx) = self.exited_rx.take() {
+            if exited_rx.borrow().is_none() {
+                log::warn!("Sending SIGTERM to the model process group {}", process_group);
+                kill_process_group(process_group, libc::SIGTERM)?;
+                if tokio::time::timeout(TERMINATE_TIMEOUT, exited_rx.wait_for(|exit| exit.is_some())).await.is_err() {
+                    log::warn!("The model process group {} did not exit within {:?}", process_group, TERMINATE_TIMEOUT);
+                }
+            }
+        }
+
+        // Kill whatever is left of the group, e.g. children that outlived the model
+        kill_process_group(process_group, libc::SIGKILL)
+    }
+
+    async fn stats(&self) -> Result<ProcessStats, String> {
+        let pid = match (self.process_group, &self.exited_rx) {
+            (Some(pid), Some(exited_rx)) if exited_rx.borrow().is_none() => pid as u32,
+            _ => return Err("The model process is not running".to_string()),
+        };
+        let sample = tokio::task::spawn_blocking(move || stats::sample_local_tree(pid)).await
+            .map_err(|err| "Failed to sample the model process: ".to_string() + &err.to_string())??;
+        Ok(self.cpu_tracker.stats(sample))
+    }
+
+    fn model_exit(&self) -> Option<watch::Receiver<Option<ModelExit>>> {
+        self.exited_rx.clone()
+    }
+}
 
+// Send a signal to every process of a process group, a group that is already gone is not an error
+fn kill_process_group(process_group: i32, signal: i32) -> Result<(), String> {
+    // SAFETY: kill has no memory safety requirements, a negative pid addresses the process group
+    if unsafe { libc::kill(-process_group, signal) } == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::ESRCH) {
+        return Ok(());
+    }
+    log::error!("Failed to signal the model process group {}: {}", process_group, err);
+    Err(format!("Failed to signal the model process group {}: {}", process_group, err))
+}
+
+// Read lines from a model pipe and send them to the channel until either side closes
+async fn forward_lines<R: AsyncRead + Unpin>(pipe: R, tx: mpsc::Sender<String>) {
+    let mut lines = BufReader::new(pipe).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                if tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("Failed to read from the model pipe: {}", err);
+                break;
+            }
+        }
+    }
 }
 
 // Implementation of debug for LocalDriver
@@ -199,6 +281,7 @@ impl fmt::Debug for LocalDriver {
             .field("static_fields", &self.static_fields)
             .field("model_params", &self.model_params)
             .field("connection_params", &self.connection_params)
+            .field("process_group", &self.process_group)
             .finish()
     }
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+// src/meal/logs.rs
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use super::settings::parse_u64;
+
+
+// Default directory of the model log files, relative to the working directory of the driver
+pub const DEFAULT_LOG_DIR: &str = "logs/models";
+// Default size after which a log file is rotated
+pub const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+// Default number of rotated log files kept next to the current one
+pub const DEFAULT_LOG_MAX_FILES: u64 = 5;
+// Default number of lines kept in memory for the REPL
+pub const DEFAULT_LOG_BUFFER_LINES: u64 = 1000;
+// Capacity of the channel feeding the followers, slow followers skip lines
+const FOLLOW_CAPACITY: usize = 256;
+
+
+// Log settings parsed from the model params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogSettings {
+    // Directory of the log files, None disables the files
+    pub dir: Option<PathBuf>,
+    pub max_bytes: u64,
+    pub max_files: usize,
+    // Age after which a log file is rotated regardless of its size
+    pub max_age: Option<Duration>,
+    pub buffer_lines: usize,
+}
+
+impl LogSettings {
+    pub fn from_model_params(model_params: &HashMap<String, String>) -> Result<Self, String> {
+        let dir = match model_params.get("logDir").map(|dir| dir.as_str()) {
+            None | Some("") => Some(PathBuf::from(DEFAULT_LOG_DIR)),
+            Some("none") => None,
+            Some(dir) => Some(PathBuf::from(dir)),
+        };
+        let max_age = parse_u64(model_params, "logMaxAgeMs", 0)?;
+
+        Ok(Self {
+            dir,
+            max_bytes: parse_u64(model_params, "logMaxBytes", DEFAULT_LOG_MAX_BYTES)?.max(1),
+            max_files: parse_u64(model_params, "logMaxFiles", DEFAULT_LOG_MAX_FILES)? as usize,
+            max_age: (max_age > 0).then(|| Duration::from_millis(max_age)),
+            buffer_lines: parse_u64(model_params, "logBufferLines", DEFAULT_LOG_BUFFER_LINES)? as usize,
+        })
+    }
+}
+
+
+// Log of a single MEAL instance: the model stderr, its stdout noise and the lifecycle events of the driver
+#[derive(Debug)]
+pub struct ModelLog {
+    settings: LogSettings,
+    instance_uid: String,
+    path: Option<PathBuf>,
+    state: Mutex<LogState>,
+    follow_tx: broadcast::Sender<String>,
+}
+
+#[derive(Debug, Default)]
+struct LogState {
+    lines: VecDeque<String>,
+    file: Option<LogFile>,
+    // Set after the file failed to open so the error is only logged once
+    file_failed: bool,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl ModelLog {
+    // Create the log of an instance, the file is named after the model and the instance uid
+    pub fn new(settings: LogSettings, model_name: &str, instance_uid: &str) -> Self {
+        let sanitize = |name: &str| -> String {
+            name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect()
+        };
+        let file_name = match instance_uid {
+            "" => format!("{}.log", sanitize(model_name)),
+            uid => format!("{}-{}.log", sanitize(model_name), sanitize(uid)),
+        };
+        let path = settings.dir.as_ref().map(|dir| dir.join(file_name));
+        let (follow_tx, _) = broadcast::channel(FOLLOW_CAPACITY);
+
+        Self {
+            settings,
+            instance_uid: instance_uid.to_string(),
+            path,
+            state: Mutex::new(LogState::default()),
+            follow_tx,
+        }
+    }
+
+    // Get the uid of the instance the log belongs to
+    pub fn instance_uid(&self) -> &str {
+        &self.instance_uid
+    }
+
+    // Get the path of the current log file
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    // Append a line from a source (stdout, stderr or driver) to the buffer, the file and the followers
+    pub fn write(&self, source: &str, line: &str) {
+        let line = format!("{} [{}] {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), source, line);
+
+        let mut state = self.state.lock().unwrap();
+        if self.settings.buffer_lines > 0 {
+            if state.lines.len() >= self.settings.buffer_lines {
+                state.lines.pop_front();
+            }
+            state.lines.push_back(line.clone());
+        }
+        if let Err(err) = self.write_file(&mut state, &line) {
+            if !state.file_failed {
+                log::error!("Failed to write the model log {:#?}: {}", self.path, err);
+                state.file_failed = true;
+            }
+            state.file = None;
+        }
+        drop(state);
+
+        // Nobody following is not an error
+        let _ = self.follow_tx.send(line);
+    }
+
+    // Get the buffered lines, at most the last count ones
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.lines.iter().skip(state.lines.len().saturating_sub(count)).cloned().collect()
+    }
+
+    // Receive the lines written from now on
+    pub fn follow(&self) -> broadcast::Receiver<String> {
+        self.follow_tx.subscribe()
+    }
+
+    // Write a line to the current file, rotating it once it is too large or too old
+    fn write_file(&self, state: &mut LogState, line: &str) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let rotate = match &state.file {
+            Some(file) => file.size + line.len() as u64 + 1 > self.settings.max_bytes
+                || self.settings.max_age.map(|max_age| file.opened.elapsed() >= max_age).unwrap_or(false),
+            None => false,
+        };
+        if rotate {
+            state.file = None;
+            self.rotate(path)?;
+        }
+
+        if state.file.is_none() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| err.to_string())?;
+            let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            state.file = Some(LogFile { file, size, opened: Instant::now() });
+            state.file_failed = false;
+        }
+
+        let file = state.file.as_mut().unwrap();
+        writeln!(file.file, "{}", line).map_err(|err| err.to_string())?;
+        file.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    // Shift model.log to model.log.1, model.log.1 to model.log.2 and so on, dropping the oldest one
+    fn rotate(&self, path: &PathBuf) -> Result<(), String> {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+        if self.settings.max_files == 0 {
+            return std::fs::remove_file(path).map_err(|err| err.to_string());
+        }
+        let _ = std::fs::remove_file(rotated(self.settings.max_files));
+        for index in (1..self.settings.max_files).rev() {
+            let _ = std::fs::rename(rotated(index), rotated(index + 1));
+        }
+        std::fs::rename(path, rotated(1)).map_err(|err| err.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_log() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-logs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("logDir".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("logMaxBytes".to_string(), "200".to_string());
+        model_params.insert("logMaxFiles".to_string(), "2".to_string());
+        model_params.insert("logBufferLines".to_string(), "3".to_string());
+        let log_settings = LogSettings::from_model_params(&model_params).unwrap();
+        assert_eq!(log_settings.max_age, None);
+
+        // Only the last lines are buffered and followers get every new line
+        let model_log = ModelLog::new(log_settings, "DialoGPT/small", "1");
+        assert_eq!(model_log.path(), Some(&dir.join("DialoGPT_small-1.log")));
+        let mut follow_rx = model_log.follow();
+        for index in 0..20 {
+            model_log.write("stderr", &format!("Loading shard {}", index));
+        }
+        let tail = model_log.tail(10);
+        assert_eq!(tail.len(), 3);
+        assert!(tail[2].ends_with("[stderr] Loading shard 19"));
+        assert!(follow_rx.try_recv().unwrap().ends_with("[stderr] Loading shard 0"));
+
+        // The files are rotated by size and only the configured number of old files is kept
+        let path = model_log.path().unwrap().clone();
+        assert!(std::fs::metadata(&path).unwrap().len() <= 200);
+        assert!(dir.join("DialoGPT_small-1.log.1").exists());
+        assert!(dir.join("DialoGPT_small-1.log.2").exists());
+        assert!(!dir.join("DialoGPT_small-1.log.3").exists());
+        assert!(std::fs::read_to_string(&path).unwrap().contains("Loading shard 19"));
+
+        // The files can be disabled
+        model_params.insert("logDir".to_string(), "none".to_string());
+        assert_eq!(LogSettings::from_model_params(&model_params).unwrap().dir, None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,9 +1,18 @@
 // src/meal/mod.rs
 use std::fmt;
 use std::result::Result;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use async_trait::async_trait;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use protocol::{DecodedLine, MEALRequest, MEALResponse, ModelCapabilities, ModelError, Protocol, ProtocolKind, SessionOp};
+use logs::ModelLog;
+use settings::MEALSettings;
+use stats::ProcessStats;
+use crate::metrics;
 
 // Define MEALArgs struct
 pub struct MEALArgs {
@@ -17,30 +26,189 @@ pub trait MEALDriver: fmt::Debug + Send + Sync {
     fn new(meal_args: MEALArgs) -> Self where Self: Sized;
     
     // MEALDriver methods
-    async fn spawn_model(&mut self) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String>;
+    // Spawns the model command of the given kind (e.g. "inference" or "train") with extra arguments and returns the
+    // (stdin, stdout, stderr) channels of the process, stdin data is written as is while stdout and stderr are delivered line by line
+    async fn spawn_command(&mut self, kind: &str, args: &[String]) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String>;
+
+    // Writes a file relative to the model path, e.g. a training dataset, creating its directory,
+    // transfers to remote hosts are verified with the checksum of the written file
+    async fn put_file(&mut self, path: &str, data: &[u8]) -> Result<(), String>;
+
+    // Reads a file relative to the model path, e.g. trained weights, transfers are verified like put_file
+    async fn get_file(&mut self, path: &str) -> Result<Vec<u8>, String>;
+
+    // Lists the files below a directory relative to the model path with their checksums
+    async fn list_files(&mut self, dir: &str) -> Result<Vec<transfer::ModelFile>, String>;
+
+    // Forcefully stops whatever the last spawn_command left running, e.g. after the model ignored its exit token
+    async fn terminate(&mut self) -> Result<(), String>;
+
+    // Samples the resource usage of the model process and its children
+    async fn stats(&self) -> Result<ProcessStats, String>;
+
+    // Reports how the last spawned process ended, drivers that can not tell return None
+    fn model_exit(&self) -> Option<watch::Receiver<Option<ModelExit>>> {
+        None
+    }
 
 }
 
 pub mod local;
 pub mod ssh;
+pub mod command;
+pub mod limits;
+pub mod stats;
+pub mod logs;
+pub mod feedback;
+pub mod training;
+pub mod transfer;
+pub mod quota;
+
+// Time the MEAL waits for the driver to report how the model ended after its stdout closed
+const MODEL_EXIT_WAIT: Duration = Duration::from_secs(1);
+// Argument of the inference command selecting the weights to load
+const DEFAULT_WEIGHTS_ARG: &str = "--weights";
+
+// How a model process ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelExit {
+    // Exited with status 0
+    Succeeded,
+    // Exited with an error or was killed, with a description of the status
+    Exited(String),
+    // Killed for exceeding one of its resource limits
+    LimitExceeded(String),
+}
+pub mod protocol;
+pub mod pool;
+pub mod settings;
+
+// Lifecycle state of a MEAL instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MEALState {
+    Stopped,
+    Starting,
+    Ready,
+    Unhealthy(String),
+    Failed(String),
+    LimitExceeded(String),
+}
+
+impl fmt::Display for MEALState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MEALState::Stopped => write!(f, "stopped"),
+            MEALState::Starting => write!(f, "starting"),
+            MEALState::Ready => write!(f, "ready"),
+            MEALState::Unhealthy(reason) => write!(f, "unhealthy ({})", reason),
+            MEALState::Failed(reason) => write!(f, "failed ({})", reason),
+            MEALState::LimitExceeded(reason) => write!(f, "limit exceeded ({})", reason),
+        }
+    }
+}
+
+impl MEALState {
+    // Name the state without its reason, e.g. as a metric label
+    pub fn label(&self) -> &'static str {
+        match self {
+            MEALState::Stopped => "stopped",
+            MEALState::Starting => "starting",
+            MEALState::Ready => "ready",
+            MEALState::Unhealthy(_) => "unhealthy",
+            MEALState::Failed(_) => "failed",
+            MEALState::LimitExceeded(_) => "limit_exceeded",
+        }
+    }
+}
+
+// Requests waiting for a response from the model
+#[derive(Debug, Default)]
+struct PendingResponses {
+    // Requests matched by id (jsonl protocol)
+    by_id: HashMap<String, oneshot::Sender<MEALResponse>>,
+    // Requests matched by order (tokens protocol)
+    in_order: VecDeque<(String, oneshot::Sender<MEALResponse>)>,
+    // Health probes matched by order (tokens protocol)
+    pings: VecDeque<(u64, oneshot::Sender<MEALResponse>)>,
+    // Ids of the health probes waiting in by_id, they are not requests of a caller (jsonl protocol)
+    probes: HashSet<String>,
+    // Output chunks of the streamed requests by id (jsonl protocol)
+    chunks: HashMap<String, mpsc::UnboundedSender<String>>,
+    // Cancelled requests whose late responses are dropped (jsonl protocol)
+    cancelled: HashSet<String>,
+}
 
 // MEAL struct
 #[derive(Debug)]
 pub struct MEAL {
     driver: Box<dyn MEALDriver>,
+    name: String,
+    config: Vec<HashMap<String, String>>,
+    protocol: Protocol,
+    settings: MEALSettings,
+    state: Arc<Mutex<MEALState>>,
+    capabilities: Arc<Mutex<Option<ModelCapabilities>>>,
+    stdin_tx: Option<mpsc::Sender<String>>,
+    // Queue of the batcher, only set when batching is enabled
+    batch_tx: Option<mpsc::Sender<MEALRequest>>,
+    ready_rx: Option<watch::Receiver<bool>>,
+    exited_rx: Option<watch::Receiver<bool>>,
+    log: Arc<ModelLog>,
+    pending: Arc<Mutex<PendingResponses>>,
+    request_counter: AtomicU64,
+    // Number of times the model was spawned, the sessions of a model process are lost with it
+    generation: u64,
+    // Version of the weights the model serves, recorded with the transcript turns
+    weights_version: Option<String>,
+    last_health_check: Mutex<Instant>,
+    last_used: Mutex<Instant>,
 }
 impl MEAL {
     pub fn create(driver_type: &str, meal_args: MEALArgs) -> Result<Self, String> {
-        let driver: Box<dyn MEALDriver> = match driver_type {
-            "local" => Box::new(local::LocalDriver::new(meal_args)),
-            "ssh" => Box::new(ssh::SSHDriver::new(meal_args)),
-            _ => {
-                log::error!("Unknown MEAL driver type: {:#?}", driver_type);
-                return Err("Unknown MEAL driver type: ".to_string() + driver_type);
+        // Get the model name and parse the protocol and settings from the model params
+        let name = meal_args.meal_config.first()
+            .and_then(|static_fields| static_fields.get("name").cloned())
+            .unwrap_or_default();
+        let (protocol, settings) = match meal_args.meal_config.get(2) {
+            Some(model_params) => (Protocol::from_model_params(model_params)?, MEALSettings::from_model_params(model_params)?),
+            None => {
+                log::error!("Missing model params for the model: {:#?}", name);
+                return Err("Missing model params for the model: ".to_string() + &name);
             }
         };
 
-        Ok(Self { driver })
+        if settings.max_batch_size > 1 && protocol.kind != ProtocolKind::Jsonl {
+            log::error!("Batching of model {} requires the jsonl protocol", name);
+            return Err(format!("Batching of model {} requires the jsonl protocol", name));
+        }
+
+        let config = meal_args.meal_config.clone();
+        let uid = config[0].get("uid").cloned().unwrap_or_default();
+        let weights_version = config[2].get("weightsVersion").filter(|version| !version.is_empty()).cloned();
+        let log = Arc::new(ModelLog::new(settings.log.clone(), &name, &uid));
+
+        let driver = create_driver(driver_type, meal_args)?;
+
+        Ok(Self {
+            driver,
+            name,
+            config,
+            protocol,
+            settings,
+            state: Arc::new(Mutex::new(MEALState::Stopped)),
+            capabilities: Arc::new(Mutex::new(None)),
+            stdin_tx: None,
+            batch_tx: None,
+            ready_rx: None,
+            exited_rx: None,
+            log,
+            pending: Arc::new(Mutex::new(PendingResponses::default())),
+            request_counter: AtomicU64::new(0),
+            generation: 0,
+            weights_version,
+            last_health_check: Mutex::new(Instant::now()),
+            last_used: Mutex::new(Instant::now()),
+        })
     }
 
     // Get the driver type
@@ -48,9 +216,724 @@ impl MEAL {
         format!("{:#?}", self.driver)
     }
 
-    // Spawn the model
-    pub async fn spawn_model(&mut self) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String> {
-        self.driver.spawn_model().await
+    // Get the model name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Get the (static fields, connection params, model params) the instance was created with
+    pub fn config(&self) -> &[HashMap<String, String>] {
+        &self.config
+    }
+
+    // Get the model protocol
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    // Get the instance settings
+    pub fn settings(&self) -> &MEALSettings {
+        &self.settings
+    }
+
+    // Get the log of the model output and lifecycle events
+    pub fn log(&self) -> &Arc<ModelLog> {
+        &self.log
+    }
+
+    // Get the lifecycle state
+    pub fn state(&self) -> MEALState {
+        self.state.lock().unwrap().clone()
+    }
+
+    // Get the capabilities announced by the model when it became ready
+    pub fn capabilities(&self) -> Option<ModelCapabilities> {
+        self.capabilities.lock().unwrap().clone()
+    }
+
+    // Get the number of times the model was spawned
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Get the version of the weights the model serves
+    pub fn weights_version(&self) -> Option<&str> {
+        self.weights_version.as_deref()
+    }
+
+    // Serve other weights from the next spawn on, running instances have to be restarted to load them
+    pub fn set_weights_version(&mut self, weights_version: &str) {
+        self.weights_version = Some(weights_version.to_string());
+        self.config[2].insert("weightsVersion".to_string(), weights_version.to_string());
+    }
+
+    // Get the arguments selecting the weights of the inference command, e.g. --weights <weightsVersion>
+    fn weights_args(&self) -> Vec<String> {
+        match (&self.weights_version, self.config[2].get("weightsArg").map(|arg| arg.as_str())) {
+            (None, _) | (_, Some("none")) => Vec::new(),
+            (Some(version), None) | (Some(version), Some("")) => vec![DEFAULT_WEIGHTS_ARG.to_string(), version.clone()],
+            (Some(version), Some(arg)) => vec![arg.to_string(), version.clone()],
+        }
+    }
+
+    // Check if the model is spawned and ready
+    pub fn is_ready(&self) -> bool {
+        self.ready_rx.as_ref().map(|ready_rx| *ready_rx.borrow()).unwrap_or(false)
+    }
+
+    // Check if the model can take requests, an unhealthy model is still running but missed its last health probe
+    pub fn is_serving(&self) -> bool {
+        self.is_ready() && !matches!(self.state(), MEALState::Unhealthy(_))
+    }
+
+    // Spawn the model, wait until it is ready and stop it again if it misses the startup deadline
+    pub async fn start(&mut self) -> Result<(), String> {
+        self.spawn_model().await?;
+        if let Err(err) = self.wait_ready().await {
+            let state = self.state();
+            let _ = self.stop().await;
+            self.set_state(state);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Spawn the model and start dispatching its output
+    pub async fn spawn_model(&mut self) -> Result<(), String> {
+        self.set_state(MEALState::Starting);
+        self.generation += 1;
+        if self.generation > 1 {
+            metrics::increment("mer_instance_restarts_total", &[("model", &self.name), ("instance", self.log.instance_uid())]);
+        }
+        let spawned = Instant::now();
+        *self.last_used.lock().unwrap() = Instant::now();
+        self.log.write("driver", "Spawning the model");
+        let (stdin_tx, mut stdout_rx, mut stderr_rx) = match self.driver.spawn_command("inference", &self.weights_args()).await {
+            Ok(channels) => channels,
+            Err(err) => {
+                self.log.write("driver", &("Failed to spawn the model: ".to_string() + &err));
+                self.set_state(MEALState::Failed(err.clone()));
+                return Err(err);
+            }
+        };
+        let (ready_tx, ready_rx) = watch::channel(false);
+        let (exited_tx, exited_rx) = watch::channel(false);
+        let model_exit_rx = self.driver.model_exit();
+        *self.capabilities.lock().unwrap() = None;
+
+        // Decode the model stdout and hand the responses to the waiting requests
+        let mut decoder = self.protocol.decoder();
+        let pending = Arc::clone(&self.pending);
+        let capabilities = Arc::clone(&self.capabilities);
+        let state = Arc::clone(&self.state);
+        let model_log = Arc::clone(&self.log);
+        let (name, uid) = (self.name.clone(), self.log.instance_uid().to_string());
+        tokio::spawn(async move {
+            while let Some(line) = stdout_rx.recv().await {
+                match decoder.feed(&line) {
+                    DecodedLine::Ready(announced) => {
+                        match &announced {
+                            Some(announced) if announced.protocol_version > protocol::PROTOCOL_VERSION => log::warn!(
+                                "Model {} speaks protocol version {}, the driver only supports up to version {}",
+                                name, announced.protocol_version, protocol::PROTOCOL_VERSION
+                            ),
+                            Some(announced) => log::info!("Model {} is ready with capabilities: {:?}", name, announced),
+                            None => log::info!("Model {} is ready", name),
+                        }
+                        *capabilities.lock().unwrap() = announced;
+                        *state.lock().unwrap() = MEALState::Ready;
+                        model_log.write("driver", "The model is ready");
+                        metrics::observe("mer_instance_time_to_ready_seconds", &[("model", &name), ("instance", &uid)], spawned.elapsed().as_secs_f64());
+                        let _ = ready_tx.send(true);
+                    }
+                    DecodedLine::Response(response) => dispatch_response(&name, &pending, response),
+                    DecodedLine::Batch(responses) => {
+                        for response in responses {
+                            dispatch_response(&name, &pending, response);
+                        }
+                    }
+                    DecodedLine::Chunk(chunk) => {
+                        let pending = pending.lock().unwrap();
+                        match pending.chunks.get(&chunk.id) {
+                            Some(chunk_tx) => {
+                                let _ = chunk_tx.send(chunk.chunk);
+                            }
+                            None if pending.cancelled.contains(&chunk.id) => (),
+                            None => log::warn!("Model {} sent a chunk nobody is waiting for: {:#?}", name, chunk),
+                        }
+                    }
+                    DecodedLine::Pong => {
+                        if let Some((_, ping_tx)) = pending.lock().unwrap().pings.pop_front() {
+                            let _ = ping_tx.send(MEALResponse { id: String::new(), output: None, error: None });
+                        }
+                    }
+                    DecodedLine::Noise(line) => {
+                        log::debug!("Model {} stdout: {}", name, line);
+                        model_log.write("stdout", &line);
+                    }
+                    DecodedLine::Pending => (),
+                }
+            }
+
+            // The model exited, ask the driver how it ended
+            log::info!("Model {} stdout closed", name);
+            let _ = ready_tx.send(false);
+            let model_exit = match model_exit_rx {
+                Some(mut model_exit_rx) => tokio::time::timeout(MODEL_EXIT_WAIT, model_exit_rx.wait_for(|exit| exit.is_some())).await
+                    .ok()
+                    .and_then(|exit| exit.ok().and_then(|exit| exit.clone())),
+                None => None,
+            };
+
+            // Exiting without being asked to is a failure, the state is updated before the requests are failed
+            let mut state = state.lock().unwrap();
+            if *state != MEALState::Stopped {
+                *state = match model_exit.clone() {
+                    Some(ModelExit::LimitExceeded(reason)) => {
+                        log::error!("Model {} exceeded its resource limits: {}", name, reason);
+                        MEALState::LimitExceeded(reason)
+                    }
+                    Some(ModelExit::Exited(status)) => {
+                        log::error!("Model {} exited unexpectedly: {}", name, status);
+                        MEALState::Failed("The model process exited unexpectedly: ".to_string() + &status)
+                    }
+                    Some(ModelExit::Succeeded) | None => {
+                        log::error!("Model {} exited unexpectedly", name);
+                        MEALState::Failed("The model process exited unexpectedly".to_string())
+                    }
+                };
+            }
+            let exited = format!("The model exited, state: {}", *state);
+            drop(state);
+            model_log.write("driver", &exited);
+
+            // Drop all waiting requests so their callers get an error, limit violations get their own error code
+            if let Some(ModelExit::LimitExceeded(reason)) = &model_exit {
+                fail_pending(&pending, "limit_exceeded", reason);
+            } else {
+                let mut pending = pending.lock().unwrap();
+                pending.by_id.clear();
+                pending.in_order.clear();
+                pending.pings.clear();
+                pending.probes.clear();
+                pending.chunks.clear();
+                pending.cancelled.clear();
+            }
+            let _ = exited_tx.send(true);
+        });
+
+        // Drain the model stderr into the model log so the model never blocks on a full pipe
+        let model_log = Arc::clone(&self.log);
+        let name = self.name.clone();
+        tokio::spawn(async move {
+            while let Some(line) = stderr_rx.recv().await {
+                log::debug!("Model {} stderr: {}", name, line);
+                model_log.write("stderr", &line);
+            }
+        });
+
+        // Collect the requests into batch frames when batching is enabled
+        self.batch_tx = None;
+        if self.settings.max_batch_size > 1 {
+            let (batch_tx, batch_rx) = mpsc::channel(self.settings.max_batch_size);
+            tokio::spawn(batch_requests(
+                self.name.clone(),
+                self.protocol.clone(),
+                Arc::clone(&self.capabilities),
+                self.settings.max_batch_size,
+                self.settings.max_batch_delay,
+                batch_rx,
+                stdin_tx.clone(),
+                Arc::clone(&self.pending),
+            ));
+            self.batch_tx = Some(batch_tx);
+        }
+
+        self.stdin_tx = Some(stdin_tx);
+        self.ready_rx = Some(ready_rx);
+        self.exited_rx = Some(exited_rx);
+        Ok(())
+    }
+
+    // Wait until the model prints its ready token or the startup deadline passes
+    pub async fn wait_ready(&self) -> Result<(), String> {
+        let mut ready_rx = self.ready_rx.clone().ok_or("The model is not spawned")?;
+        let startup_timeout = self.settings.startup_timeout;
+        let ready = tokio::time::timeout(startup_timeout, ready_rx.wait_for(|ready| *ready)).await
+            .map(|ready| ready.map(|_| ()));
+        match ready {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => match self.state() {
+                // Keep the reason the stdout reader found, e.g. an exceeded limit
+                MEALState::Failed(reason) | MEALState::LimitExceeded(reason) => {
+                    Err(format!("Model {} exited before becoming ready: {}", self.name, reason))
+                }
+                _ => {
+                    let err = format!("Model {} exited before becoming ready", self.name);
+                    self.set_state(MEALState::Failed(err.clone()));
+                    Err(err)
+                }
+            },
+            Err(_) => {
+                let err = format!("Model {} did not become ready within {:?}", self.name, startup_timeout);
+                log::error!("{}", err);
+                self.set_state(MEALState::Failed(err.clone()));
+                Err(err)
+            }
+        }
+    }
+
+    // Probe the model and return the round trip time, a missing answer marks the instance unhealthy
+    pub async fn ping(&self) -> Result<Duration, String> {
+        let stdin_tx = self.stdin_tx.as_ref().ok_or("The model is not spawned")?;
+        if !self.is_ready() {
+            return Err(format!("Model {} is not ready", self.name));
+        }
+
+        // Register the probe before sending it so the answer can not be missed
+        let ping_id = self.request_counter.fetch_add(1, Ordering::Relaxed);
+        let mut id = format!("ping-{}", ping_id);
+        let (ping_tx, ping_rx) = oneshot::channel();
+        match self.protocol.kind {
+            ProtocolKind::Tokens => self.pending.lock().unwrap().pings.push_back((ping_id, ping_tx)),
+            ProtocolKind::Jsonl => {
+                // Callers choose their request ids freely, a probe never takes the id of a waiting request
+                let mut pending = self.pending.lock().unwrap();
+                while pending.by_id.contains_key(&id) {
+                    id = format!("ping-{}", self.request_counter.fetch_add(1, Ordering::Relaxed));
+                }
+                pending.probes.insert(id.clone());
+                pending.by_id.insert(id.clone(), ping_tx);
+            }
+        }
+
+        let started = Instant::now();
+        *self.last_health_check.lock().unwrap() = started;
+        let result = match stdin_tx.send(self.protocol.encode_ping(&id)).await {
+            Err(_) => Err(format!("Model {} is not running", self.name)),
+            Ok(_) => match tokio::time::timeout(self.settings.liveness_timeout, ping_rx).await {
+                Ok(Ok(_)) => Ok(started.elapsed()),
+                Ok(Err(_)) => Err(format!("Model {} exited before answering the ping", self.name)),
+                Err(_) => Err(format!("Model {} did not answer the ping within {:?}", self.name, self.settings.liveness_timeout)),
+            },
+        };
+
+        // Forget the probe if it was not answered and update the state with the outcome
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.probes.remove(&id) {
+                pending.by_id.remove(&id);
+            }
+            pending.pings.retain(|(pending_id, _)| *pending_id != ping_id);
+        }
+        let mut state = self.state.lock().unwrap();
+        match &result {
+            Ok(_) if matches!(*state, MEALState::Unhealthy(_)) => {
+                log::info!("Model {} is healthy again", self.name);
+                *state = MEALState::Ready;
+            }
+            Err(err) if *state == MEALState::Ready => {
+                log::warn!("{}", err);
+                *state = MEALState::Unhealthy(err.clone());
+            }
+            _ => (),
+        }
+        drop(state);
+        result
+    }
+
+    // Check if a periodic health probe is due, probes are skipped while requests are in flight
+    pub fn health_check_due(&self) -> bool {
+        let health_interval = match self.settings.health_interval {
+            Some(health_interval) => health_interval,
+            None => return false,
+        };
+        let state = self.state();
+        if state != MEALState::Ready && !matches!(state, MEALState::Unhealthy(_)) {
+            return false;
+        }
+        if self.in_flight() > 0 {
+            return false;
+        }
+        self.last_health_check.lock().unwrap().elapsed() >= health_interval
+    }
+
+    // Get the resource usage of the model process and its children
+    pub async fn stats(&self) -> Result<ProcessStats, String> {
+        if self.stdin_tx.is_none() {
+            return Err(format!("Model {} is not running", self.name));
+        }
+        self.driver.stats().await
+    }
+
+    // Check a request against the model capabilities
+    pub fn validate_request(&self, request: &MEALRequest) -> Result<(), String> {
+        match self.capabilities() {
+            Some(capabilities) => capabilities.validate_request(request)
+                .map_err(|err| format!("Invalid request for model {}: {}", self.name, err)),
+            None => Ok(()),
+        }
+    }
+
+    // Send a request to the model and wait for its response
+    pub async fn infer(&self, mut request: MEALRequest) -> Result<MEALResponse, String> {
+        let stdin_tx = self.stdin_tx.as_ref().ok_or("The model is not spawned")?;
+        self.validate_request(&request)?;
+
+        // Assign a request id if the caller did not provide one
+        if request.id.is_empty() {
+            request.id = self.request_counter.fetch_add(1, Ordering::Relaxed).to_string();
+        }
+        let frame = self.protocol.encode_request(&request)?;
+
+        // Register the request before sending it so the response can not be missed
+        let (response_tx, response_rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            match self.protocol.kind {
+                ProtocolKind::Tokens => pending.in_order.push_back((request.id.clone(), response_tx)),
+                ProtocolKind::Jsonl => {
+                    if pending.by_id.contains_key(&request.id) {
+                        return Err("Duplicate request id: ".to_string() + &request.id);
+                    }
+                    pending.by_id.insert(request.id.clone(), response_tx);
+                }
+            }
+        }
+
+        *self.last_used.lock().unwrap() = Instant::now();
+        let started = Instant::now();
+        let id = request.id.clone();
+        let sent = match &self.batch_tx {
+            Some(batch_tx) => batch_tx.send(request).await.is_ok(),
+            None => stdin_tx.send(frame).await.is_ok(),
+        };
+        if !sent {
+            self.forget_request(&id);
+            return Err(format!("Model {} is not running", self.name));
+        }
+
+        // A model that does not answer in time keeps generating, its late response is dropped
+        let response = match self.settings.request_timeout {
+            Some(request_timeout) => match tokio::time::timeout(request_timeout, response_rx).await {
+                Ok(response) => response,
+                Err(_) => {
+                    self.cancel_request(&id).await?;
+                    log::warn!("Model {} did not answer the request {} within {:?}", self.name, id, request_timeout);
+                    return Err(format!("Model {} did not answer the request {} within {:?}", self.name, id, request_timeout));
+                }
+            },
+            None => response_rx.await,
+        }.map_err(|_| format!("Model {} exited before responding", self.name));
+        *self.last_used.lock().unwrap() = Instant::now();
+        let labels = [("model", self.name.as_str()), ("instance", self.log.instance_uid())];
+        metrics::increment("mer_instance_requests_total", &labels);
+        metrics::observe("mer_inference_duration_seconds", &labels, started.elapsed().as_secs_f64());
+        response
+    }
+
+    // Send a request to the model and forward the output chunks it streams until it responds,
+    // the output of models that do not stream is forwarded as a single chunk
+    pub async fn infer_stream(&self, mut request: MEALRequest, chunk_tx: mpsc::UnboundedSender<String>) -> Result<MEALResponse, String> {
+        let streaming = self.protocol.kind == ProtocolKind::Jsonl && self.capabilities().map(|capabilities| capabilities.streaming).unwrap_or(false);
+        let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+        if streaming {
+            // The chunks are matched by id, so it is assigned before the request is registered
+            if request.id.is_empty() {
+                request.id = self.request_counter.fetch_add(1, Ordering::Relaxed).to_string();
+            }
+            request.stream = true;
+            self.pending.lock().unwrap().chunks.insert(request.id.clone(), stream_tx);
+        }
+
+        let id = request.id.clone();
+        let response = self.infer(request);
+        tokio::pin!(response);
+        let mut streamed = false;
+        let result = loop {
+            tokio::select! {
+                result = &mut response => break result,
+                Some(chunk) = stream_rx.recv() => {
+                    streamed = true;
+                    let _ = chunk_tx.send(chunk);
+                }
+            }
+        };
+        // The chunks are dispatched before the response, so the remaining ones are already queued
+        self.pending.lock().unwrap().chunks.remove(&id);
+        while let Ok(chunk) = stream_rx.try_recv() {
+            streamed = true;
+            let _ = chunk_tx.send(chunk);
+        }
+        if let (false, Ok(MEALResponse { output: Some(output), error: None, .. })) = (streamed, &result) {
+            let _ = chunk_tx.send(output.clone());
+        }
+        result
+    }
+
+    // Cancel a request, its caller is answered with a cancelled error right away and the late response of the model
+    // is dropped, models announcing cancel support are also told to stop generating. Returns false for unknown requests
+    pub async fn cancel_request(&self, id: &str) -> Result<bool, String> {
+        let response_tx = {
+            let mut pending = self.pending.lock().unwrap();
+            // Health probes are not requests of a caller and can not be cancelled
+            if pending.probes.contains(id) {
+                return Ok(false);
+            }
+            pending.chunks.remove(id);
+            match pending.by_id.remove(id) {
+                Some(response_tx) => {
+                    pending.cancelled.insert(id.to_string());
+                    Some(response_tx)
+                }
+                // Requests matched by order keep their place, the late response goes to a dropped receiver
+                None => {
+                    let response_tx = pending.in_order.iter_mut()
+                        .find(|(pending_id, _)| pending_id == id)
+                        .map(|(_, response_tx)| std::mem::replace(response_tx, oneshot::channel().0));
+                    if response_tx.is_some() {
+                        pending.cancelled.insert(id.to_string());
+                    }
+                    response_tx
+                }
+            }
+        };
+        let response_tx = match response_tx {
+            Some(response_tx) => response_tx,
+            None => return Ok(false),
+        };
+        let _ = response_tx.send(MEALResponse {
+            id: id.to_string(),
+            output: None,
+            error: Some(ModelError { code: "cancelled".to_string(), message: "The request was cancelled".to_string() }),
+        });
+
+        if let (Some(stdin_tx), true) = (&self.stdin_tx, self.capabilities().map(|capabilities| capabilities.cancel).unwrap_or(false)) {
+            if stdin_tx.send(self.protocol.encode_cancel(id)?).await.is_err() {
+                log::debug!("Model {} exited before the request {} was cancelled", self.name, id);
+            }
+        }
+        log::info!("Cancelled the request {} of model {}", id, self.name);
+        Ok(true)
+    }
+
+    // Create, reset or close the conversation history of a session in the model
+    pub async fn session_op(&self, session: &str, op: SessionOp) -> Result<(), String> {
+        let stdin_tx = self.stdin_tx.as_ref().ok_or("The model is not spawned")?;
+        if let Some(capabilities) = self.capabilities() {
+            if !capabilities.sessions {
+                return Err(format!("Model {} does not support sessions", self.name));
+            }
+        }
+
+        // Session operations bypass the batcher and are answered like requests
+        let id = format!("session-{}", self.request_counter.fetch_add(1, Ordering::Relaxed));
+        let frame = self.protocol.encode_session_op(&id, session, op)?;
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().unwrap().by_id.insert(id.clone(), response_tx);
+
+        *self.last_used.lock().unwrap() = Instant::now();
+        if stdin_tx.send(frame).await.is_err() {
+            self.forget_request(&id);
+            return Err(format!("Model {} is not running", self.name));
+        }
+        let response = match tokio::time::timeout(self.settings.liveness_timeout, response_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => return Err(format!("Model {} exited before answering the session operation", self.name)),
+            Err(_) => {
+                self.forget_request(&id);
+                return Err(format!("Model {} did not answer the session operation within {:?}", self.name, self.settings.liveness_timeout));
+            }
+        };
+        match response.error {
+            Some(error) => Err(format!("Model {} failed the session operation with {}: {}", self.name, error.code, error.message)),
+            None => Ok(()),
+        }
+    }
+
+    // Get the number of requests waiting for a response, cancelled requests that keep their place in the order are
+    // not waited for
+    pub fn in_flight(&self) -> usize {
+        let pending = self.pending.lock().unwrap();
+        pending.by_id.keys().filter(|id| !pending.probes.contains(*id)).count()
+            + pending.in_order.iter().filter(|(id, _)| !pending.cancelled.contains(id)).count()
+    }
+
+    // Check if the instance has been idle for longer than its idle timeout
+    pub fn idle_timeout_passed(&self) -> bool {
+        match self.settings.idle_timeout {
+            Some(idle_timeout) => self.in_flight() == 0 && self.last_used.lock().unwrap().elapsed() >= idle_timeout,
+            None => false,
+        }
+    }
+
+    // Ask the model to exit
+    pub async fn stop(&mut self) -> Result<(), String> {
+        self.set_state(MEALState::Stopped);
+        self.batch_tx = None;
+        if let Some(stdin_tx) = self.stdin_tx.take() {
+            stdin_tx.send(self.protocol.encode_exit()).await
+                .map_err(|_| format!("Model {} is not running", self.name))?;
+        }
+        self.ready_rx = None;
+        Ok(())
+    }
+
+    // Ask the model to exit, wait up to the grace period for it to do so and terminate it otherwise
+    pub async fn shutdown(&mut self, grace: Duration) -> Result<(), String> {
+        let exited_rx = self.exited_rx.take();
+        if let Err(err) = self.stop().await {
+            log::debug!("{}", err);
+        }
+
+        if let Some(mut exited_rx) = exited_rx {
+            if tokio::time::timeout(grace, exited_rx.wait_for(|exited| *exited)).await.is_err() {
+                log::warn!("Model {} did not exit within {:?}, terminating it", self.name, grace);
+            }
+        }
+        self.driver.terminate().await
+    }
+
+    // Restart a running model, e.g. to load new weights, stopped instances load them on their next start
+    pub async fn restart(&mut self, grace: Duration) -> Result<(), String> {
+        if self.stdin_tx.is_none() {
+            return Ok(());
+        }
+        self.log.write("driver", "Restarting the model");
+        self.shutdown(grace).await?;
+        self.start().await
+    }
+
+    // Fail all requests waiting for a response, releasing the instance for a shutdown
+    pub fn cancel_requests(&self, reason: &str) {
+        fail_pending(&self.pending, "cancelled", reason);
+    }
+
+    // Update the lifecycle state
+    fn set_state(&self, state: MEALState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    // Remove a request that was never sent
+    fn forget_request(&self, id: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.by_id.remove(id);
+        pending.in_order.retain(|(pending_id, _)| pending_id != id);
+    }
+}
+
+// Create the driver of the given type, e.g. for the MEAL or a training run next to it
+pub fn create_driver(driver_type: &str, meal_args: MEALArgs) -> Result<Box<dyn MEALDriver>, String> {
+    match driver_type {
+        "local" => Ok(Box::new(local::LocalDriver::new(meal_args))),
+        "ssh" => Ok(Box::new(ssh::SSHDriver::new(meal_args))),
+        _ => {
+            log::error!("Unknown MEAL driver type: {:#?}", driver_type);
+            Err("Unknown MEAL driver type: ".to_string() + driver_type)
+        }
+    }
+}
+
+// Answer all waiting requests with an error, probes are dropped so their callers see the model as gone
+fn fail_pending(pending: &Mutex<PendingResponses>, code: &str, message: &str) {
+    let mut pending = pending.lock().unwrap();
+    let pending = &mut *pending;
+    for (id, response_tx) in pending.by_id.drain().chain(pending.in_order.drain(..)) {
+        if pending.probes.contains(&id) {
+            continue;
+        }
+        let _ = response_tx.send(MEALResponse {
+            id,
+            output: None,
+            error: Some(ModelError { code: code.to_string(), message: message.to_string() }),
+        });
+    }
+    pending.pings.clear();
+    pending.probes.clear();
+    pending.chunks.clear();
+    pending.cancelled.clear();
+}
+
+// Collect queued requests until the batch is full or the first one waited for the maximum delay and write them
+// as one batch frame, models that did not announce batching support get the requests one by one
+#[allow(clippy::too_many_arguments)]
+async fn batch_requests(
+    name: String,
+    protocol: Protocol,
+    capabilities: Arc<Mutex<Option<ModelCapabilities>>>,
+    max_batch_size: usize,
+    max_batch_delay: Duration,
+    mut batch_rx: mpsc::Receiver<MEALRequest>,
+    stdin_tx: mpsc::Sender<String>,
+    pending: Arc<Mutex<PendingResponses>>,
+) {
+    while let Some(request) = batch_rx.recv().await {
+        let mut batch = vec![request];
+        let deadline = tokio::time::Instant::now() + max_batch_delay;
+        while batch.len() < max_batch_size {
+            match tokio::time::timeout_at(deadline, batch_rx.recv()).await {
+                Ok(Some(request)) => batch.push(request),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let batching = capabilities.lock().unwrap().as_ref().map(|capabilities| capabilities.batching).unwrap_or(false);
+        let frames = if batching && batch.len() > 1 {
+            log::debug!("Sending a batch of {} requests to model {}", batch.len(), name);
+            protocol.encode_batch(&batch).map(|frame| vec![frame])
+        } else {
+            batch.iter().map(|request| protocol.encode_request(request)).collect()
+        };
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(err) => {
+                log::error!("Failed to encode a batch for model {}: {}", name, err);
+                let mut pending = pending.lock().unwrap();
+                for request in &batch {
+                    if let Some(response_tx) = pending.by_id.remove(&request.id) {
+                        let _ = response_tx.send(MEALResponse {
+                            id: request.id.clone(),
+                            output: None,
+                            error: Some(ModelError { code: "batch_error".to_string(), message: err.clone() }),
+                        });
+                    }
+                }
+                continue;
+            }
+        };
+        for frame in frames {
+            // The requests are failed by the stdout reader once the model is gone
+            if stdin_tx.send(frame).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Hand a decoded response to the request waiting for it
+fn dispatch_response(name: &str, pending: &Mutex<PendingResponses>, mut response: MEALResponse) {
+    let mut pending = pending.lock().unwrap();
+    let waiting = if response.id.is_empty() {
+        // Responses without an id are matched by order
+        pending.in_order.pop_front().map(|(id, response_tx)| {
+            response.id = id;
+            response_tx
+        })
+    } else {
+        pending.probes.remove(&response.id);
+        pending.by_id.remove(&response.id)
+    };
+
+    // The callers of cancelled requests were answered already, requests matched by order only kept their place
+    if pending.cancelled.remove(&response.id) {
+        log::debug!("Dropping the response of the cancelled request {} of model {}", response.id, name);
+        return;
+    }
+    match waiting {
+        Some(response_tx) => {
+            let _ = response_tx.send(response);
+        }
+        None => log::warn!("Model {} sent a response nobody is waiting for: {:#?}", name, response),
     }
 }
 
@@ -94,12 +977,10 @@ mod tests {
         // Print the current directory
         println!("Current directory: {:#?}", current_dir);
 
-        // Generate the command to run DialoGPT-small within a Python3 virtual environment
-        let command = "conda activate transformer-venv && python3 inference.py".to_string();
-
-        // Add model_params
+        // Run DialoGPT-small within the transformer-venv conda environment
         model_params.insert("modelPath".to_string(), current_dir.to_str().unwrap().to_string());
-        model_params.insert("inferenceCommand".to_string(), command.to_string());
+        model_params.insert("inferenceArgv".to_string(), r#"["python3", "inference.py"]"#.to_string());
+        model_params.insert("condaEnv".to_string(), "transformer-venv".to_string());
         model_params.insert("readyToken".to_string(), "@!#READY#!@".to_string());
         model_params.insert("exitToken".to_string(), "@!#EXIT#!@".to_string());
         model_params.insert("startToken".to_string(), "@!#START#!@".to_string());
@@ -116,7 +997,7 @@ mod tests {
 
         // Create MEALArgs
         let meal_args = MEALArgs {
-            meal_config: meal_config,
+            meal_config,
         };
 
         // Create MEAL
@@ -125,53 +1006,375 @@ mod tests {
         println!("MEAL: {:#?}", meal);
 
         // Spawn the model
-        let (mut stdout_tx, mut stdout_rx, mut stderr_rx) = meal.spawn_model().await.unwrap();
+        meal.spawn_model().await.unwrap();
 
         // Wait for the model to be ready
         println!("Waiting for the model to be ready...");
-        let mut ready = false;
-        while !ready {
-            let stdout = stdout_rx.recv().await.unwrap();
-            let stderr = stderr_rx.recv().await.unwrap();
-            
-            if stdout.contains("@!#READY#!@") {
-                ready = true;
-            } 
-
-            if stdout.len() > 0 {
-                println!("stdout: {:#?}", stdout);
-            }
-
-            if stderr.len() > 0 {
-                println!("stderr: {:#?}", stderr);
-            }
-        }
+        meal.wait_ready().await.unwrap();
 
-
-        // Send a message to the model encapsulated in the start and stop tokens
+        // Send a message to the model, the MEAL encapsulates it in the start and stop tokens
         println!("Sending a message to the model...");
         let prompt = "Hello, how are you?";
-        let prompt = "@!#START#!@\n".to_string() + prompt + "@!#STOP#!@\n";
-        stdout_tx.send(prompt.clone()).await.unwrap();
-
-        // Wait for the model to respond
-        println!("Waiting for the model to respond...");
-        let mut response = String::new();
-        while !response.contains("@!#STOP#!@") {
-            let stdout = stdout_rx.recv().await.unwrap();
-            response = stdout;
-        }
-
-        // Remove the start and stop tokens from the response
-        response = response.replace("@!#START#!@\n", "");
-        response = response.replace("@!#STOP#!@\n", "");
+        let response = meal.infer(protocol::MEALRequest::new(prompt)).await.unwrap();
 
         // Print the prompt and response
         println!("Prompt: {:#?}", prompt);
         println!("Response: {:#?}", response);
+        assert!(response.error.is_none());
+        assert!(response.output.is_some());
 
         // Send the exit token to the model
         println!("Sending the exit token to the model...");
-        stdout_tx.send("@!#EXIT#!@".to_string()).await.unwrap();
+        meal.stop().await.unwrap();
     }
-}
\ No newline at end of file
+
+    // Test that an unhealthy model is still running but takes no requests
+    #[test]
+    fn test_meal_unhealthy() {
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("uid".to_string(), "1".to_string());
+        static_fields.insert("name".to_string(), "DialoGPT-small".to_string());
+        let meal_args = MEALArgs {
+            meal_config: vec![static_fields, HashMap::new(), HashMap::new()],
+        };
+        let mut meal = MEAL::create("local", meal_args).unwrap();
+        assert!(!meal.is_serving());
+
+        // Pretend the model announced itself as ready
+        let (_ready_tx, ready_rx) = watch::channel(true);
+        meal.ready_rx = Some(ready_rx);
+        meal.set_state(MEALState::Ready);
+        assert!(meal.is_serving());
+
+        // A missed health probe keeps the process but stops the routing to it
+        meal.set_state(MEALState::Unhealthy("Model DialoGPT-small did not answer the ping".to_string()));
+        assert!(meal.is_ready());
+        assert!(!meal.is_serving());
+    }
+
+    // Test that requests of callers are never taken for health probes, whatever their id looks like
+    #[tokio::test]
+    async fn test_probe_ids() {
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "echo".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        let meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+
+        // A caller request with a probe-like id is in flight and can be cancelled, the probe is neither
+        let (request_tx, request_rx) = oneshot::channel();
+        let (probe_tx, probe_rx) = oneshot::channel();
+        {
+            let mut pending = meal.pending.lock().unwrap();
+            pending.by_id.insert("ping-1".to_string(), request_tx);
+            pending.by_id.insert("ping-2".to_string(), probe_tx);
+            pending.probes.insert("ping-2".to_string());
+        }
+        assert_eq!(meal.in_flight(), 1);
+        assert!(!meal.cancel_request("ping-2").await.unwrap());
+
+        // On shutdown the caller gets an error and the probe sees the model as gone
+        meal.cancel_requests("The driver is shutting down");
+        assert_eq!(request_rx.await.unwrap().error.unwrap().code, "cancelled");
+        assert!(probe_rx.await.is_err());
+        assert_eq!(meal.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_protocol_tokens() {
+        // Create the tokens protocol with the default tokens
+        let protocol = protocol::Protocol::from_model_params(&HashMap::new()).unwrap();
+        assert_eq!(protocol.kind, protocol::ProtocolKind::Tokens);
+
+        // Encode a request between the start and stop tokens
+        let request = protocol::MEALRequest::new("Hello, how are you?");
+        let frame = protocol.encode_request(&request).unwrap();
+        assert_eq!(frame, "@!#START#!@\nHello, how are you?\n@!#STOP#!@\n");
+
+        // Empty and multi-line input and request params can not be expressed with tokens
+        assert!(protocol.encode_request(&protocol::MEALRequest::new("")).is_err());
+        assert!(protocol.encode_request(&protocol::MEALRequest::new("Hello\nthere")).is_err());
+        let mut request = protocol::MEALRequest::new("Hello");
+        request.params.insert("temperature".to_string(), serde_json::json!(0.7));
+        assert!(protocol.encode_request(&request).is_err());
+
+        // Decode the ready token, noise and a response frame
+        let mut decoder = protocol.decoder();
+        assert_eq!(decoder.feed("@!#READY#!@"), protocol::DecodedLine::Ready(None));
+        assert_eq!(decoder.feed("Loading weights"), protocol::DecodedLine::Noise("Loading weights".to_string()));
+        assert_eq!(decoder.feed("@!#START#!@"), protocol::DecodedLine::Pending);
+        assert_eq!(decoder.feed("I am fine."), protocol::DecodedLine::Pending);
+        assert_eq!(decoder.feed("@!#STOP#!@"), protocol::DecodedLine::Response(protocol::MEALResponse {
+            id: String::new(),
+            output: Some("I am fine.".to_string()),
+            error: None,
+        }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_protocol_tokens_order() {
+        // Echoes the inputs with the tokens protocol, answers inputs over 10 characters with an empty frame and the input
+        // "stall" after 2 seconds
+        const TOKENS_MODEL: &str = r#"
+import sys, time
+print("@!#READY#!@", flush=True)
+while True:
+    line = input()
+    if line == "@!#EXIT#!@":
+        break
+    if line != "@!#START#!@":
+        continue
+    text, stop = input(), input()
+    time.sleep(2 if text == "stall" else 0.1)
+    print("@!#START#!@")
+    print("" if len(text) > 10 or stop != "@!#STOP#!@" else "echo: " + text)
+    print("@!#STOP#!@", flush=True)
+"#;
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "tokens".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), std::env::temp_dir().to_string_lossy().to_string());
+        model_params.insert("inferenceArgv".to_string(), serde_json::json!(["python3", "-c", TOKENS_MODEL]).to_string());
+        model_params.insert("requestTimeoutMs".to_string(), "1500".to_string());
+        let mut meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+        meal.start().await.unwrap();
+
+        // The empty input is rejected before it is sent, the other answers keep their order around the invalid ones
+        let output = |result: Result<MEALResponse, String>| result.map(|response| response.output.unwrap_or_default());
+        let (first, empty, long, last) = tokio::join!(
+            meal.infer(MEALRequest::new("first")),
+            meal.infer(MEALRequest::new("")),
+            meal.infer(MEALRequest::new("far too long input")),
+            meal.infer(MEALRequest::new("last")),
+        );
+        assert_eq!(output(first), Ok("echo: first".to_string()));
+        assert!(empty.unwrap_err().starts_with("Invalid request"));
+        assert_eq!(output(long), Ok(String::new()));
+        assert_eq!(output(last), Ok("echo: last".to_string()));
+        assert_eq!(meal.in_flight(), 0);
+
+        // A request past its deadline is no longer in flight and its late frame does not reach the next caller
+        assert!(meal.infer(MEALRequest::new("stall")).await.unwrap_err().contains("did not answer"));
+        assert_eq!(meal.in_flight(), 0);
+        assert_eq!(output(meal.infer(MEALRequest::new("after")).await), Ok("echo: after".to_string()));
+
+        meal.shutdown(Duration::from_secs(1)).await.unwrap();
+    }
+
+    #[test]
+    fn test_protocol_jsonl() {
+        // Create the jsonl protocol
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        let protocol = protocol::Protocol::from_model_params(&model_params).unwrap();
+        assert_eq!(protocol.kind, protocol::ProtocolKind::Jsonl);
+
+        // Encode a multi-line request with generation params as a single line
+        let mut request = protocol::MEALRequest::new("Hello,\nhow are you?");
+        request.id = "42".to_string();
+        request.params.insert("max_length".to_string(), serde_json::json!(200));
+        let frame = protocol.encode_request(&request).unwrap();
+        assert!(frame.ends_with('\n'));
+        assert_eq!(frame.matches('\n').count(), 1);
+        let decoded: protocol::MEALRequest = serde_json::from_str(frame.trim_end()).unwrap();
+        assert_eq!(decoded, request);
+
+        // Decode responses with outputs, structured errors and plain string errors
+        let mut decoder = protocol.decoder();
+        assert_eq!(decoder.feed("@!#READY#!@"), protocol::DecodedLine::Ready(None));
+        assert_eq!(decoder.feed(r#"{"id":"42","output":"@!#STOP#!@ is fine","error":null}"#), protocol::DecodedLine::Response(protocol::MEALResponse {
+            id: "42".to_string(),
+            output: Some("@!#STOP#!@ is fine".to_string()),
+            error: None,
+        }));
+        assert_eq!(decoder.feed(r#"{"id":"43","error":{"code":"input_too_long","message":"Input exceeds 1000 characters"}}"#), protocol::DecodedLine::Response(protocol::MEALResponse {
+            id: "43".to_string(),
+            output: None,
+            error: Some(protocol::ModelError {
+                code: "input_too_long".to_string(),
+                message: "Input exceeds 1000 characters".to_string(),
+            }),
+        }));
+        assert_eq!(decoder.feed(r#"{"id":44,"error":"CUDA out of memory"}"#), protocol::DecodedLine::Response(protocol::MEALResponse {
+            id: "44".to_string(),
+            output: None,
+            error: Some(protocol::ModelError {
+                code: "model_error".to_string(),
+                message: "CUDA out of memory".to_string(),
+            }),
+        }));
+        assert_eq!(decoder.feed(r#"{"id":"45","chunk":"Hel"}"#), protocol::DecodedLine::Chunk(protocol::MEALChunk {
+            id: "45".to_string(),
+            chunk: "Hel".to_string(),
+        }));
+        assert_eq!(decoder.feed("Some warning"), protocol::DecodedLine::Noise("Some warning".to_string()));
+
+        // Cancel requests by id
+        let cancel: serde_json::Value = serde_json::from_str(&protocol.encode_cancel("42").unwrap()).unwrap();
+        assert_eq!(cancel, serde_json::json!({ "id": "42", "cancel": true }));
+
+        // Unknown protocols are rejected
+        model_params.insert("protocol".to_string(), "xml".to_string());
+        assert!(protocol::Protocol::from_model_params(&model_params).is_err());
+    }
+    #[test]
+    fn test_protocol_capabilities() {
+        let protocol = protocol::Protocol::from_model_params(&HashMap::new()).unwrap();
+        let mut decoder = protocol.decoder();
+
+        // Decode a ready token followed by a capability descriptor
+        let line = r#"@!#READY#!@ {"protocolVersion":1,"streaming":false,"maxInputLength":10,"batching":true,"parameters":["max_length"]}"#;
+        let capabilities = match decoder.feed(line) {
+            protocol::DecodedLine::Ready(Some(capabilities)) => capabilities,
+            other => panic!("Expected capabilities, got {:#?}", other),
+        };
+        assert_eq!(capabilities.protocol_version, 1);
+        assert_eq!(capabilities.max_input_length, Some(10));
+        assert!(capabilities.batching);
+        assert!(!capabilities.streaming);
+
+        // Missing fields fall back to the defaults and invalid descriptors are ignored
+        assert_eq!(decoder.feed("@!#READY#!@ {}"), protocol::DecodedLine::Ready(Some(protocol::ModelCapabilities {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            streaming: false,
+            max_input_length: None,
+            batching: false,
+            sessions: false,
+            cancel: false,
+            parameters: None,
+        })));
+        assert_eq!(decoder.feed("@!#READY#!@ not json"), protocol::DecodedLine::Ready(None));
+
+        // Validate requests against the capabilities
+        assert!(capabilities.validate_request(&protocol::MEALRequest::new("0123456789")).is_ok());
+        assert!(capabilities.validate_request(&protocol::MEALRequest::new("0123456789a")).is_err());
+        let mut request = protocol::MEALRequest::new("Hello");
+        request.params.insert("max_length".to_string(), serde_json::json!(100));
+        assert!(capabilities.validate_request(&request).is_ok());
+        request.params.insert("temperature".to_string(), serde_json::json!(0.7));
+        assert!(capabilities.validate_request(&request).is_err());
+    }
+    #[test]
+    fn test_protocol_ping() {
+        // The tokens protocol echoes the ping token
+        let protocol = protocol::Protocol::from_model_params(&HashMap::new()).unwrap();
+        assert_eq!(protocol.encode_ping("ping-1"), "@!#PING#!@\n");
+        assert_eq!(protocol.decoder().feed("@!#PING#!@"), protocol::DecodedLine::Pong);
+
+        // The jsonl protocol answers the ping id
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        let protocol = protocol::Protocol::from_model_params(&model_params).unwrap();
+        let ping: serde_json::Value = serde_json::from_str(protocol.encode_ping("ping-1").trim_end()).unwrap();
+        assert_eq!(ping, serde_json::json!({ "id": "ping-1", "ping": true }));
+        match protocol.decoder().feed(r#"{"id":"ping-1","pong":true}"#) {
+            protocol::DecodedLine::Response(response) => assert_eq!(response.id, "ping-1"),
+            other => panic!("Expected a response, got {:#?}", other),
+        }
+    }
+
+    #[test]
+    fn test_protocol_batch() {
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        let protocol = protocol::Protocol::from_model_params(&model_params).unwrap();
+
+        // The requests are sent as one frame
+        let requests = vec![
+            protocol::MEALRequest { id: "1".to_string(), input: "Hello".to_string(), params: HashMap::new(), session: None, stream: false },
+            protocol::MEALRequest { id: "2".to_string(), input: "Bye".to_string(), params: HashMap::new(), session: None, stream: false },
+        ];
+        let frame: serde_json::Value = serde_json::from_str(protocol.encode_batch(&requests).unwrap().trim_end()).unwrap();
+        assert_eq!(frame, serde_json::json!({ "batch": [{ "id": "1", "input": "Hello" }, { "id": "2", "input": "Bye" }] }));
+
+        // The responses come back as one batch line
+        match protocol.decoder().feed(r#"{"batch":[{"id":"2","output":"Goodbye"},{"id":"1","error":"Too long"}]}"#) {
+            protocol::DecodedLine::Batch(responses) => {
+                assert_eq!(responses.len(), 2);
+                assert_eq!(responses[0].output, Some("Goodbye".to_string()));
+                assert_eq!(responses[1].error.as_ref().unwrap().code, "model_error");
+            }
+            other => panic!("Expected a batch, got {:#?}", other),
+        }
+
+        // The tokens protocol can not carry batches
+        let protocol = protocol::Protocol::from_model_params(&HashMap::new()).unwrap();
+        assert!(protocol.encode_batch(&requests).is_err());
+    }
+
+    #[test]
+    fn test_protocol_sessions() {
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        let protocol = protocol::Protocol::from_model_params(&model_params).unwrap();
+
+        // The session is carried on the request and the operations get their own frame
+        let mut request = protocol::MEALRequest::new("Hello");
+        request.id = "1".to_string();
+        request.session = Some("chat-1".to_string());
+        let frame: serde_json::Value = serde_json::from_str(protocol.encode_request(&request).unwrap().trim_end()).unwrap();
+        assert_eq!(frame, serde_json::json!({ "id": "1", "input": "Hello", "session": "chat-1" }));
+        let frame: serde_json::Value = serde_json::from_str(protocol.encode_session_op("session-2", "chat-1", protocol::SessionOp::Reset).unwrap().trim_end()).unwrap();
+        assert_eq!(frame, serde_json::json!({ "id": "session-2", "session": "chat-1", "sessionOp": "reset" }));
+
+        // Models have to announce the session support
+        let capabilities: protocol::ModelCapabilities = serde_json::from_str(r#"{"sessions":true}"#).unwrap();
+        assert!(capabilities.validate_request(&request).is_ok());
+        let capabilities: protocol::ModelCapabilities = serde_json::from_str("{}").unwrap();
+        assert!(capabilities.validate_request(&request).is_err());
+
+        // The tokens protocol has no sessions
+        let protocol = protocol::Protocol::from_model_params(&HashMap::new()).unwrap();
+        assert!(protocol.encode_request(&request).is_err());
+        assert!(protocol.encode_session_op("session-3", "chat-1", protocol::SessionOp::Close).is_err());
+    }
+
+    #[test]
+    fn test_meal_settings() {
+        // Missing params fall back to the defaults
+        let settings = settings::MEALSettings::from_model_params(&HashMap::new()).unwrap();
+        assert_eq!(settings.startup_timeout, Duration::from_millis(settings::DEFAULT_STARTUP_TIMEOUT_MS));
+        assert_eq!(settings.liveness_timeout, Duration::from_millis(settings::DEFAULT_LIVENESS_TIMEOUT_MS));
+        assert_eq!(settings.request_timeout, Some(Duration::from_millis(settings::DEFAULT_REQUEST_TIMEOUT_MS)));
+        assert_eq!(settings.health_interval, Some(Duration::from_millis(settings::DEFAULT_HEALTH_INTERVAL_MS)));
+        assert_eq!(settings.lifecycle, settings::Lifecycle::Lazy);
+        assert_eq!(settings.min_warm, 0);
+        assert_eq!(settings.idle_timeout, None);
+        assert_eq!(settings.max_batch_size, 1);
+        assert!(!settings.transcript);
+
+        // Params are parsed as milliseconds and a zero interval or timeout disables the health checks or the deadline
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("startupTimeoutMs".to_string(), "60000".to_string());
+        model_params.insert("livenessTimeoutMs".to_string(), "500".to_string());
+        model_params.insert("healthIntervalMs".to_string(), "0".to_string());
+        model_params.insert("requestTimeoutMs".to_string(), "0".to_string());
+        model_params.insert("lifecycle".to_string(), "eager".to_string());
+        model_params.insert("minWarm".to_string(), "2".to_string());
+        model_params.insert("idleTimeoutMs".to_string(), "600000".to_string());
+        model_params.insert("maxBatchSize".to_string(), "8".to_string());
+        model_params.insert("maxBatchDelayMs".to_string(), "25".to_string());
+        model_params.insert("transcriptEnabled".to_string(), "true".to_string());
+        let settings = settings::MEALSettings::from_model_params(&model_params).unwrap();
+        assert_eq!(settings.startup_timeout, Duration::from_secs(60));
+        assert_eq!(settings.liveness_timeout, Duration::from_millis(500));
+        assert_eq!(settings.health_interval, None);
+        assert_eq!(settings.request_timeout, None);
+        assert_eq!(settings.lifecycle, settings::Lifecycle::Eager);
+        assert_eq!(settings.min_warm, 2);
+        assert_eq!(settings.idle_timeout, Some(Duration::from_secs(600)));
+        assert_eq!(settings.max_batch_size, 8);
+        assert_eq!(settings.max_batch_delay, Duration::from_millis(25));
+        assert!(settings.transcript);
+
+        // Unknown lifecycles are rejected
+        model_params.insert("lifecycle".to_string(), "sometimes".to_string());
+        assert!(settings::MEALSettings::from_model_params(&model_params).is_err());
+        model_params.insert("lifecycle".to_string(), "lazy".to_string());
+
+        // Invalid values are rejected
+        model_params.insert("startupTimeoutMs".to_string(), "soon".to_string());
+        assert!(settings::MEALSettings::from_model_params(&model_params).is_err());
+    }
+
+}
@@ -0,0 +1,1099 @@
+// src/meal/pool.rs
+use super::{MEAL, MEALArgs, MEALDriver, MEALState};
+use super::feedback::{FeedbackDataset, FeedbackStatus, ModelFeedback, Rating};
+use super::logs::ModelLog;
+use super::training::{TrainingJobs, TrainingSpec};
+use super::transfer::{self, ModelFile};
+use super::protocol::{MEALRequest, MEALResponse, ModelCapabilities, SessionOp};
+use super::quota::UsageLimiter;
+use super::settings::Lifecycle;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use crate::dal::{ApiKey, DAL, JobState, ModelAccess, ModelEntry, ModelWeightsVersion, TranscriptTurn};
+use crate::metrics;
+
+// Granularity of the maintenance loop, each instance is probed and evicted on its own schedule
+const MAINTENANCE_TICK: Duration = Duration::from_secs(1);
+// Interval at which the in-flight requests are counted while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Time an instance has to exit before it is restarted with new weights
+const HOT_SWAP_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+// Time an instance that missed its health probe has to exit before it is restarted
+const UNHEALTHY_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+// Time the instance of a modified or deleted model entry gets to finish its requests and exit
+const REMOVE_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A single MEAL instance shared between the front ends
+pub type MEALInstance = Arc<tokio::sync::RwLock<MEAL>>;
+
+
+// Instance a session is bound to, follow-up turns have to reach the model process holding its history
+#[derive(Debug)]
+struct SessionAffinity {
+    model_name: String,
+    instance: MEALInstance,
+    // Generation of the instance the session was created in
+    generation: u64,
+}
+
+
+// Description of an instance shared by the front ends, instances locked for a (re)start or shutdown are only
+// described as busy
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceInfo {
+    pub uid: String,
+    pub conn_type: String,
+    // user@host:port of the SSH instances
+    pub host: Option<String>,
+    pub model_path: String,
+    pub protocol: String,
+    pub weights: Option<String>,
+    pub state: String,
+    // Reason of the unhealthy, failed and limit exceeded states
+    #[serde(skip)]
+    pub reason: Option<String>,
+    pub capabilities: Option<ModelCapabilities>,
+}
+
+impl InstanceInfo {
+    fn new(meal: &MEAL) -> Self {
+        let config = meal.config();
+        let field = |map: usize, key: &str| config.get(map).and_then(|fields| fields.get(key)).cloned().unwrap_or_default();
+        let state = meal.state();
+        let reason = match &state {
+            MEALState::Unhealthy(reason) | MEALState::Failed(reason) | MEALState::LimitExceeded(reason) => Some(reason.clone()),
+            _ => None,
+        };
+        Self {
+            uid: field(0, "uid"),
+            conn_type: field(0, "connType"),
+            host: (field(0, "connType") == "ssh").then(|| format!("{}@{}:{}", field(1, "user"), field(1, "host"), field(1, "port"))),
+            model_path: field(2, "modelPath"),
+            protocol: format!("{:?}", meal.protocol().kind).to_lowercase(),
+            weights: meal.weights_version().map(str::to_string),
+            state: instance_state(Some(state)),
+            reason,
+            capabilities: meal.capabilities(),
+        }
+    }
+
+    fn busy() -> Self {
+        Self { state: instance_state(None), ..Default::default() }
+    }
+}
+
+// A model with the descriptions of its instances, as listed by the front ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelListing {
+    pub name: String,
+    pub instances: Vec<InstanceInfo>,
+}
+
+impl ModelListing {
+    pub fn ready_instances(&self) -> usize {
+        self.instances.iter().filter(|instance| instance.state == "ready").count()
+    }
+
+    // Summarize the model with the states of its instances, like GET /models of the HTTP API
+    pub fn summary(&self) -> Value {
+        let states: Vec<&str> = self.instances.iter().map(|instance| instance.state.as_str()).collect();
+        json!({ "name": self.name, "instances": self.instances.len(), "readyInstances": self.ready_instances(), "states": states })
+    }
+}
+
+// Name the state of an instance, instances locked for a (re)start or shutdown are busy
+pub fn instance_state(state: Option<MEALState>) -> String {
+    match state {
+        Some(MEALState::Stopped) => "stopped",
+        Some(MEALState::Starting) => "starting",
+        Some(MEALState::Ready) => "ready",
+        Some(MEALState::Unhealthy(_)) => "unhealthy",
+        Some(MEALState::Failed(_)) => "failed",
+        Some(MEALState::LimitExceeded(_)) => "limit exceeded",
+        None => "busy",
+    }.to_string()
+}
+
+
+// ModelPool holds the MEAL instances of every available model, grouped by model name
+#[derive(Debug, Default)]
+pub struct ModelPool {
+    models: RwLock<HashMap<String, Vec<MEALInstance>>>,
+    // Logs of the instances in the same order, readable while an instance is locked for a (re)start
+    logs: RwLock<HashMap<String, Vec<Arc<ModelLog>>>>,
+    sessions: Mutex<HashMap<String, SessionAffinity>>,
+    session_counter: AtomicU64,
+    // Receives the answered requests of the models with transcripts enabled
+    transcript_tx: Mutex<Option<mpsc::UnboundedSender<TranscriptTurn>>>,
+    // Receives the answered requests of all models with their callers
+    access_tx: Mutex<Option<mpsc::UnboundedSender<ModelAccess>>>,
+    feedback: Mutex<HashMap<String, ModelFeedback>>,
+    // Receives the weights trained from the feedback
+    weights_tx: Mutex<Option<mpsc::UnboundedSender<ModelWeightsVersion>>>,
+    request_counter: AtomicU64,
+    limiter: UsageLimiter,
+    training: Arc<TrainingJobs>,
+    shutting_down: AtomicBool,
+}
+
+impl ModelPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a MEAL instance to the instances of the same model
+    pub fn insert(&self, model_name: &str, meal: MEAL) -> MEALInstance {
+        self.feedback.lock().unwrap()
+            .entry(model_name.to_string())
+            .or_insert_with(|| ModelFeedback::new(meal.settings().feedback.clone(), model_name));
+        self.limiter.set_model_limits(model_name, meal.settings().limits.clone());
+        self.logs.write().unwrap()
+            .entry(model_name.to_string())
+            .or_default()
+            .push(Arc::clone(meal.log()));
+        let instance = Arc::new(tokio::sync::RwLock::new(meal));
+        self.models.write().unwrap()
+            .entry(model_name.to_string())
+            .or_default()
+            .push(Arc::clone(&instance));
+        instance
+    }
+
+    // Get the names of the models, sorted
+    pub fn model_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.models.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Get the instances of a model
+    pub fn instances(&self, model_name: &str) -> Option<Vec<MEALInstance>> {
+        self.models.read().unwrap().get(model_name).cloned()
+    }
+
+    // List the models with their instances without waiting for the instances that are locked, those are busy
+    pub fn list_models(&self) -> Vec<ModelListing> {
+        self.model_names().into_iter()
+            .map(|name| {
+                let instances = self.instances(&name).unwrap_or_default().iter()
+                    .map(|instance| match instance.try_read() {
+                        Ok(meal) => InstanceInfo::new(&meal),
+                        Err(_) => InstanceInfo::busy(),
+                    })
+                    .collect();
+                ModelListing { name, instances }
+            })
+            .collect()
+    }
+
+    // Describe the instances of a model, waiting for the instances that are being (re)started
+    pub async fn model_info(&self, model_name: &str) -> Option<Vec<InstanceInfo>> {
+        let mut infos = Vec::new();
+        for instance in self.instances(model_name)? {
+            infos.push(InstanceInfo::new(&*instance.read().await));
+        }
+        Some(infos)
+    }
+
+    // Get the capabilities announced by an instance of a model, instances that are being (re)started are skipped
+    pub fn capabilities(&self, model_name: &str) -> Option<ModelCapabilities> {
+        self.instances(model_name)?.iter()
+            .find_map(|instance| instance.try_read().ok().and_then(|meal| meal.capabilities()))
+    }
+
+    // Get the logs of the instances of a model
+    pub fn logs(&self, model_name: &str) -> Option<Vec<Arc<ModelLog>>> {
+        self.logs.read().unwrap().get(model_name).cloned()
+    }
+
+    // Send the transcript turns of the models with transcripts enabled to the channel
+    pub fn record_transcripts(&self, transcript_tx: mpsc::UnboundedSender<TranscriptTurn>) {
+        *self.transcript_tx.lock().unwrap() = Some(transcript_tx);
+    }
+
+    // Send the accesses of the models to the channel
+    pub fn record_access(&self, access_tx: mpsc::UnboundedSender<ModelAccess>) {
+        *self.access_tx.lock().unwrap() = Some(access_tx);
+    }
+
+    // Send the weights trained from the feedback to the channel
+    pub fn record_weights(&self, weights_tx: mpsc::UnboundedSender<ModelWeightsVersion>) {
+        *self.weights_tx.lock().unwrap() = Some(weights_tx);
+    }
+
+    // Get the rate limits and quotas of the API keys and the models
+    pub fn limiter(&self) -> &UsageLimiter {
+        &self.limiter
+    }
+
+    // Get the training jobs of the models
+    pub fn training(&self) -> &Arc<TrainingJobs> {
+        &self.training
+    }
+
+    // Get the instances of all models
+    pub fn all_instances(&self) -> Vec<MEALInstance> {
+        self.models.read().unwrap().values().flatten().cloned().collect()
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////// Routing of the requests ///////////////
+    //////////////////////////////////////////////////////
+
+    // Send a request of the caller to the least busy ready instance of a model, spawning one if none is running,
+    // requests of a session always go to the instance holding its history. The limits of the API key of the caller
+    // apply to the request and its id is recorded in the model accesses
+    pub async fn infer(&self, model_name: &str, request: MEALRequest, caller: Option<&ApiKey>) -> Result<MEALResponse, String> {
+        let result = self.route_request(model_name, request, caller, None).await;
+        self.count_request(model_name, &result);
+        result
+    }
+
+    // Send a request like infer and forward the output chunks to the channel as the model streams them
+    pub async fn infer_stream(&self, model_name: &str, request: MEALRequest, caller: Option<&ApiKey>, chunk_tx: mpsc::UnboundedSender<String>) -> Result<MEALResponse, String> {
+        let result = self.route_request(model_name, request, caller, Some(chunk_tx)).await;
+        self.count_request(model_name, &result);
+        result
+    }
+
+    // Count a routed request and its error by kind, errors reported by the model by their code. Requests to unknown
+    // models are not counted, so callers can not add labels
+    fn count_request(&self, model_name: &str, result: &Result<MEALResponse, String>) {
+        if self.instances(model_name).is_none() {
+            return;
+        }
+        metrics::increment("mer_requests_total", &[("model", model_name)]);
+        let kind = match result {
+            Ok(response) => response.error.as_ref().map(|error| error.code.as_str()),
+            Err(err) => Some(metrics::error_kind(err)),
+        };
+        if let Some(kind) = kind {
+            metrics::increment("mer_request_errors_total", &[("model", model_name), ("kind", kind)]);
+        }
+    }
+
+    // Cancel a running request of a model by its id, its caller gets a cancelled error
+    pub async fn cancel(&self, model_name: &str, id: &str) -> Result<(), String> {
+        let instances = self.instances(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+        for instance in instances {
+            if instance.read().await.cancel_request(id).await? {
+                return Ok(());
+            }
+        }
+        Err(format!("Request {} of model {} is not running", id, model_name))
+    }
+
+    // Route a request to an instance and record the answered turn
+    async fn route_request(&self, model_name: &str, mut request: MEALRequest, caller: Option<&ApiKey>, chunk_tx: Option<mpsc::UnboundedSender<String>>) -> Result<MEALResponse, String> {
+        if self.is_shutting_down() {
+            return Err("The driver is shutting down".to_string());
+        }
+        if self.instances(model_name).is_none() {
+            return Err(format!("Model {} not found", model_name));
+        }
+        // Requests without an id get one that is unique over the instances, so they can be rated
+        if request.id.is_empty() {
+            request.id = format!("{}-{}", model_name, self.request_counter.fetch_add(1, Ordering::Relaxed));
+        }
+        let instance = match &request.session {
+            Some(session) => self.session_instance(model_name, session).await?,
+            None => self.select_instance(model_name).await?,
+        };
+        // The request counts towards the limits of the model and of the key of the caller once an instance can take it,
+        // requests that fail to reach an instance are not counted
+        self.limiter.acquire(model_name, caller, request.input.len() as u64)?;
+        let meal = instance.read().await;
+        let transcript_tx = self.transcript_tx.lock().unwrap().clone().filter(|_| meal.settings().transcript);
+        let feedback = self.feedback.lock().unwrap().get(model_name).map(|feedback| feedback.enabled()).unwrap_or(false);
+        let send = |request: MEALRequest| async {
+            match chunk_tx {
+                Some(chunk_tx) => meal.infer_stream(request, chunk_tx).await,
+                None => meal.infer(request).await,
+            }
+        };
+        let access_tx = self.access_tx.lock().unwrap().clone();
+        let counts_bytes = self.limiter.counts_bytes(model_name, caller);
+        if transcript_tx.is_none() && !feedback && access_tx.is_none() && !counts_bytes {
+            return send(request).await;
+        }
+
+        // Record the access and the turn once the model answered it
+        let requested_at = chrono::Utc::now();
+        let (session_id, input) = (request.session.clone(), request.input.clone());
+        let response = send(request).await?;
+        if counts_bytes {
+            let bytes = response.output.as_ref().map(|output| output.len()).unwrap_or_default();
+            self.limiter.add_bytes(model_name, caller, bytes as u64);
+        }
+        if let (true, Some(output), None) = (feedback, &response.output, &response.error) {
+            if let Some(feedback) = self.feedback.lock().unwrap().get_mut(model_name) {
+                feedback.observe(&response.id, &input, output);
+            }
+        }
+        if let Some(access_tx) = access_tx {
+            let access = ModelAccess {
+                request_id: response.id.clone(),
+                model: model_name.to_string(),
+                caller: caller.map(|key| key.key_id.clone()),
+                start_access: requested_at,
+                stop_access: chrono::Utc::now(),
+            };
+            if access_tx.send(access).is_err() {
+                log::warn!("Dropping an access of model {}, the access writer stopped", model_name);
+            }
+        }
+        let transcript_tx = match transcript_tx {
+            Some(transcript_tx) => transcript_tx,
+            None => return Ok(response),
+        };
+        let turn = TranscriptTurn {
+            session_id,
+            request_id: response.id.clone(),
+            model: model_name.to_string(),
+            weights_version: meal.weights_version().map(|version| version.to_string()),
+            input,
+            output: response.output.clone(),
+            error: response.error.as_ref().map(|error| format!("{}: {}", error.code, error.message)),
+            requested_at,
+            responded_at: chrono::Utc::now(),
+        };
+        if transcript_tx.send(turn).is_err() {
+            log::warn!("Dropping a transcript turn of model {}, the transcript writer stopped", model_name);
+        }
+        Ok(response)
+    }
+
+    // Select a ready instance of a model or start one
+    async fn select_instance(&self, model_name: &str) -> Result<MEALInstance, String> {
+        let instances = self.instances(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+
+        // Prefer the ready instance with the fewest requests in flight, busy (re)starting and unhealthy instances
+        // are skipped
+        let ready = instances.iter()
+            .filter_map(|instance| {
+                let meal = instance.try_read().ok()?;
+                meal.is_serving().then(|| (meal.in_flight(), Arc::clone(instance)))
+            })
+            .min_by_key(|(in_flight, _)| *in_flight);
+        if let Some((_, instance)) = ready {
+            return Ok(instance);
+        }
+
+        // Start an instance, stopped ones first and failed ones only as a last resort
+        let mut candidates: Vec<(u8, MEALInstance)> = Vec::new();
+        for instance in &instances {
+            let rank = match instance.try_read().map(|meal| meal.state()) {
+                Ok(MEALState::Stopped) => 1,
+                Ok(MEALState::Failed(_)) | Ok(MEALState::LimitExceeded(_)) => 2,
+                _ => 0,
+            };
+            candidates.push((rank, Arc::clone(instance)));
+        }
+        candidates.sort_by_key(|(rank, _)| *rank);
+
+        let mut last_error = format!("No instance of model {} could be started", model_name);
+        for (_, instance) in candidates {
+            // Another request may have started the instance while we waited for the lock
+            let mut meal = instance.write().await;
+            if meal.is_serving() {
+                drop(meal);
+                return Ok(instance);
+            }
+            // Unhealthy instances are still running and are restarted by the health checks
+            if meal.is_ready() {
+                continue;
+            }
+            if self.is_shutting_down() {
+                return Err("The driver is shutting down".to_string());
+            }
+            log::info!("Starting an instance of model {} on demand", model_name);
+            match meal.start().await {
+                Ok(()) => {
+                    drop(meal);
+                    return Ok(instance);
+                }
+                Err(err) => {
+                    log::error!("Failed to start an instance of model {}: {}", model_name, err);
+                    last_error = err;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////// Conversation sessions /////////////////
+    //////////////////////////////////////////////////////
+
+    // Open a session on the least busy instance of a model and return its id
+    pub async fn create_session(&self, model_name: &str) -> Result<String, String> {
+        if self.is_shutting_down() {
+            return Err("The driver is shutting down".to_string());
+        }
+        let instance = self.select_instance(model_name).await?;
+        let session = format!(
+            "{}-{:x}{:04x}",
+            model_name, chrono::Utc::now().timestamp_micros(), self.session_counter.fetch_add(1, Ordering::Relaxed) & 0xffff
+        );
+
+        let meal = instance.read().await;
+        meal.session_op(&session, SessionOp::Create).await?;
+        let generation = meal.generation();
+        drop(meal);
+
+        log::info!("Created session {} of model {}", session, model_name);
+        self.sessions.lock().unwrap().insert(session.clone(), SessionAffinity {
+            model_name: model_name.to_string(),
+            instance,
+            generation,
+        });
+        Ok(session)
+    }
+
+    // Clear the conversation history of a session
+    pub async fn reset_session(&self, session: &str) -> Result<(), String> {
+        let model_name = self.session_model(session)?;
+        let instance = self.session_instance(&model_name, session).await?;
+        let meal = instance.read().await;
+        meal.session_op(session, SessionOp::Reset).await
+    }
+
+    // Close a session and forget its conversation history
+    pub async fn close_session(&self, session: &str) -> Result<(), String> {
+        let model_name = self.session_model(session)?;
+        let instance = self.session_instance(&model_name, session).await;
+        self.sessions.lock().unwrap().remove(session);
+        let instance = instance?;
+        let meal = instance.read().await;
+        meal.session_op(session, SessionOp::Close).await?;
+        log::info!("Closed session {} of model {}", session, model_name);
+        Ok(())
+    }
+
+    // Get the model a session belongs to
+    pub fn session_model(&self, session: &str) -> Result<String, String> {
+        self.sessions.lock().unwrap().get(session)
+            .map(|affinity| affinity.model_name.clone())
+            .ok_or_else(|| format!("Session {} not found", session))
+    }
+
+    // Get the instance holding a session, sessions whose model process exited since are forgotten
+    async fn session_instance(&self, model_name: &str, session: &str) -> Result<MEALInstance, String> {
+        let (instance, generation) = match self.sessions.lock().unwrap().get(session) {
+            Some(affinity) if affinity.model_name == model_name => (Arc::clone(&affinity.instance), affinity.generation),
+            Some(affinity) => return Err(format!("Session {} belongs to model {}", session, affinity.model_name)),
+            None => return Err(format!("Session {} not found", session)),
+        };
+
+        let meal = instance.read().await;
+        if meal.generation() != generation || !meal.is_serving() {
+            drop(meal);
+            self.sessions.lock().unwrap().remove(session);
+            log::warn!("Session {} of model {} was lost with its model process", session, model_name);
+            return Err(format!("Session {} was lost because its model instance stopped", session));
+        }
+        drop(meal);
+        Ok(instance)
+    }
+
+    // Forget the sessions whose model process exited, instances that are being (re)started are checked later
+    fn prune_sessions(&self) {
+        self.sessions.lock().unwrap().retain(|session, affinity| {
+            match affinity.instance.try_read() {
+                Ok(meal) if meal.generation() != affinity.generation || !meal.is_ready() => {
+                    log::info!("Forgetting session {} of model {}, its model process stopped", session, affinity.model_name);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////// Continuous feedback learning //////////
+    //////////////////////////////////////////////////////
+
+    // Turn the collection of rated interactions of a model on or off
+    pub fn set_feedback(&self, model_name: &str, enabled: bool) -> Result<(), String> {
+        let mut feedback = self.feedback.lock().unwrap();
+        let feedback = feedback.get_mut(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+        feedback.set_enabled(enabled);
+        log::info!("Feedback learning of model {} is {}", model_name, if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    // Get the state of the feedback learning of a model
+    pub fn feedback_status(&self, model_name: &str) -> Result<FeedbackStatus, String> {
+        self.feedback.lock().unwrap().get(model_name)
+            .map(|feedback| feedback.status())
+            .ok_or_else(|| format!("Model {} not found", model_name))
+    }
+
+    // Rate an answered request of a model, the last one if no id is given, and start a training run
+    // in the background once enough well rated interactions were collected
+    pub fn rate(self: &Arc<Self>, model_name: &str, request_id: Option<&str>, rating: u8) -> Result<Rating, String> {
+        let (rated, dataset) = {
+            let mut feedback = self.feedback.lock().unwrap();
+            let feedback = feedback.get_mut(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+            let rated = feedback.rate(request_id, rating)?;
+            (rated, feedback.take_dataset()?)
+        };
+
+        if let Some(dataset) = dataset {
+            let model_pool = Arc::clone(self);
+            let model_name = model_name.to_string();
+            tokio::spawn(async move {
+                let result = model_pool.learn(&model_name, dataset).await;
+                if let Err(err) = &result {
+                    log::error!("Feedback learning of model {} failed: {}", model_name, err);
+                }
+                if let Some(feedback) = model_pool.feedback.lock().unwrap().get_mut(&model_name) {
+                    feedback.training_finished(result);
+                }
+            });
+        }
+        Ok(rated)
+    }
+
+    // Train new weights of a model on a feedback dataset, register them and hot swap them into the instances if enabled
+    async fn learn(&self, model_name: &str, dataset: FeedbackDataset) -> Result<String, String> {
+        let old_weights = self.weights_version(model_name).await?;
+        let spec = TrainingSpec {
+            dataset: dataset.name.clone(),
+            upload: Some(dataset.data),
+            old_weights: old_weights.clone(),
+            new_weights: format!("feedback-{}", chrono::Utc::now().format("%Y%m%d%H%M%S")),
+            args: Vec::new(),
+        };
+        let new_weights = spec.new_weights.clone();
+        log::info!("Training weights {} of model {} on {} rated interactions", new_weights, model_name, dataset.examples);
+        let id = self.start_training(model_name, spec).await?;
+        let job = self.training.wait(&id).await?;
+        if job.state != JobState::Succeeded {
+            return Err(job.error.unwrap_or_else(|| format!("Training job {} {}", id, job.state)));
+        }
+
+        let hot_swap = self.feedback.lock().unwrap().get(model_name).map(|feedback| feedback.settings().hot_swap).unwrap_or(false);
+        let serving = hot_swap && !self.is_shutting_down();
+        let weights = ModelWeightsVersion {
+            model_uid: job.model_uid,
+            model: model_name.to_string(),
+            version: new_weights.clone(),
+            parent_version: old_weights,
+            dataset: Some(dataset.name),
+            created_at: chrono::Utc::now(),
+            serving,
+        };
+        match self.weights_tx.lock().unwrap().as_ref() {
+            Some(weights_tx) if weights_tx.send(weights).is_ok() => (),
+            _ => log::warn!("Weights {} of model {} are not registered, the weights writer stopped", new_weights, model_name),
+        }
+
+        // Restart the instances one by one so the others keep serving
+        if serving {
+            for instance in self.instances(model_name).unwrap_or_default() {
+                let mut meal = instance.write().await;
+                if self.is_shutting_down() {
+                    break;
+                }
+                meal.set_weights_version(&new_weights);
+                if let Err(err) = meal.restart(HOT_SWAP_EXIT_TIMEOUT).await {
+                    log::error!("Failed to restart an instance of model {} with weights {}: {}", model_name, new_weights, err);
+                }
+            }
+            log::info!("Model {} serves the weights {}", model_name, new_weights);
+        }
+        Ok(new_weights)
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////////// Training jobs /////////////////////
+    //////////////////////////////////////////////////////
+
+    // Queue a training job of a model next to its instances and return the job id
+    pub async fn start_training(&self, model_name: &str, spec: TrainingSpec) -> Result<String, String> {
+        if self.is_shutting_down() {
+            return Err("The driver is shutting down".to_string());
+        }
+        let instances = self.instances(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+        let instance = instances.first().ok_or_else(|| format!("Model {} has no instances", model_name))?;
+        let (config, model_log) = {
+            let meal = instance.read().await;
+            (meal.config().to_vec(), Arc::clone(meal.log()))
+        };
+        self.training.submit(config, model_log, spec)
+    }
+
+    // Get the weights version the instances of a model serve
+    pub async fn weights_version(&self, model_name: &str) -> Result<Option<String>, String> {
+        let instances = self.instances(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+        let instance = instances.first().ok_or_else(|| format!("Model {} has no instances", model_name))?;
+        let weights_version = instance.read().await.weights_version().map(|version| version.to_string());
+        Ok(weights_version)
+    }
+
+
+    //////////////////////////////////////////////////////
+    /////////////// Model file transfers /////////////////
+    //////////////////////////////////////////////////////
+
+    // Upload a dataset to the datasets directory of a model
+    pub async fn upload_dataset(&self, model_name: &str, dataset: &str, data: &[u8]) -> Result<(), String> {
+        transfer::check_file_name("dataset", dataset)?;
+        let mut driver = self.file_driver(model_name).await?;
+        let result = driver.put_file(&format!("{}/{}", transfer::DATASETS_DIR, dataset), data).await;
+        let _ = driver.terminate().await;
+        result
+    }
+
+    // List the files in the weights directory of a model
+    pub async fn list_weights(&self, model_name: &str) -> Result<Vec<ModelFile>, String> {
+        let mut driver = self.file_driver(model_name).await?;
+        let result = driver.list_files(transfer::WEIGHTS_DIR).await;
+        let _ = driver.terminate().await;
+        result
+    }
+
+    // Download the files of a weights version of a model, which is either a file or a directory of the weights directory
+    pub async fn download_weights(&self, model_name: &str, weights: &str) -> Result<Vec<(ModelFile, Vec<u8>)>, String> {
+        transfer::check_file_name("weights", weights)?;
+        let mut driver = self.file_driver(model_name).await?;
+        let result = async {
+            let prefix = format!("{}/", weights);
+            let files: Vec<ModelFile> = driver.list_files(transfer::WEIGHTS_DIR).await?.into_iter()
+                .filter(|file| file.path == weights || file.path.starts_with(&prefix))
+                .collect();
+            if files.is_empty() {
+                return Err(format!("Model {} has no weights {}", model_name, weights));
+            }
+
+            let mut downloads = Vec::new();
+            for file in files {
+                // The paths come from the host of the model and are written below a local directory
+                transfer::check_relative_path(&file.path)?;
+                let data = driver.get_file(&format!("{}/{}", transfer::WEIGHTS_DIR, file.path)).await?;
+                transfer::verify_checksum(&file.path, &data, &file.sha256)?;
+                downloads.push((file, data));
+            }
+            Ok(downloads)
+        }.await;
+        let _ = driver.terminate().await;
+        result
+    }
+
+    // Create a driver with the config of a model, so transfers do not wait for its instances
+    async fn file_driver(&self, model_name: &str) -> Result<Box<dyn MEALDriver>, String> {
+        let instances = self.instances(model_name).ok_or_else(|| format!("Model {} not found", model_name))?;
+        let instance = instances.first().ok_or_else(|| format!("Model {} has no instances", model_name))?;
+        let meal_config = instance.read().await.config().to_vec();
+        let driver_type = meal_config.first().and_then(|static_fields| static_fields.get("connType")).cloned().unwrap_or_default();
+        super::create_driver(&driver_type, MEALArgs { meal_config })
+    }
+
+
+    //////////////////////////////////////////////////////
+    /////////////////// Model entries ////////////////////
+    //////////////////////////////////////////////////////
+
+    // Add the instance of a model entry created at runtime, eager instances are started in the background
+    pub fn add(&self, model_name: &str, meal: MEAL) -> MEALInstance {
+        let eager = meal.settings().lifecycle == Lifecycle::Eager;
+        let instance = self.insert(model_name, meal);
+        if eager {
+            let instance = Arc::clone(&instance);
+            let model_name = model_name.to_string();
+            tokio::spawn(async move {
+                log::info!("Starting the eager instance of model {}", model_name);
+                if let Err(err) = instance.write().await.start().await {
+                    log::error!("Failed to start the eager instance of model {}: {}", model_name, err);
+                }
+            });
+        }
+        instance
+    }
+
+    // Find the instance of a model entry and the name of its model by the uid of the entry
+    pub async fn find(&self, uid: &str) -> Option<(String, MEALInstance)> {
+        let models = self.models.read().unwrap().clone();
+        for (model_name, instances) in models {
+            for instance in instances {
+                if instance.read().await.config().first().and_then(|fields| fields.get("uid")).map(|entry_uid| entry_uid == uid).unwrap_or(false) {
+                    return Some((model_name, instance));
+                }
+            }
+        }
+        None
+    }
+
+    // Remove the instance of a model entry and shut it down, requests still running after the exit timeout fail
+    // and the sessions of the instance are lost
+    pub async fn remove(&self, uid: &str, exit_timeout: Duration) -> Result<String, String> {
+        let (model_name, instance) = self.find(uid).await.ok_or_else(|| format!("Model {} not found", uid))?;
+        {
+            let mut models = self.models.write().unwrap();
+            let mut logs = self.logs.write().unwrap();
+            if let Some(instances) = models.get_mut(&model_name) {
+                if let Some(index) = instances.iter().position(|other| Arc::ptr_eq(other, &instance)) {
+                    instances.remove(index);
+                    if let Some(logs) = logs.get_mut(&model_name) {
+                        logs.remove(index);
+                    }
+                }
+                if instances.is_empty() {
+                    models.remove(&model_name);
+                    logs.remove(&model_name);
+                    self.feedback.lock().unwrap().remove(&model_name);
+                }
+            }
+        }
+        self.sessions.lock().unwrap().retain(|_, affinity| !Arc::ptr_eq(&affinity.instance, &instance));
+
+        // New requests no longer reach the instance, the running ones get the exit timeout to finish
+        let mut meal = match tokio::time::timeout(exit_timeout, instance.write()).await {
+            Ok(meal) => meal,
+            Err(_) => {
+                if let Ok(meal) = instance.try_read() {
+                    meal.cancel_requests("The model was removed");
+                }
+                instance.write().await
+            }
+        };
+        meal.shutdown(exit_timeout).await?;
+        log::info!("Removed the instance {} of model {}", uid, model_name);
+        Ok(model_name)
+    }
+
+    // Create a model entry in the DB and add its instance to the pool, returns the stored entry
+    pub async fn create_entry(&self, dal: &tokio::sync::Mutex<DAL>, entry: &ModelEntry) -> Result<Vec<HashMap<String, String>>, String> {
+        if entry.name.is_empty() {
+            return Err("Invalid request: The model name is required".to_string());
+        }
+        check_conn_type(&entry.conn_type)?;
+
+        // The instance is created from the stored entry, like on the next start of the driver
+        let mut dal = dal.lock().await;
+        let uid = dal.create_model(entry).await?;
+        let config = dal.get_model(&uid).await?;
+        let meal = match MEAL::create(&entry.conn_type, MEALArgs { meal_config: config.clone() }) {
+            Ok(meal) => meal,
+            Err(err) => {
+                if let Err(delete_err) = dal.delete_model(&uid).await {
+                    log::error!("Failed to delete the invalid model entry {}: {}", uid, delete_err);
+                }
+                return Err(format!("Invalid request: {}", err));
+            }
+        };
+        drop(dal);
+        self.add(&entry.name, meal);
+        log::info!("Created the model entry {} of model {}", uid, entry.name);
+        Ok(config)
+    }
+
+    // Modify a model entry in the DB and replace its instance, empty fields are kept and params with empty values removed
+    pub async fn modify_entry(&self, dal: &tokio::sync::Mutex<DAL>, uid: &str, changes: &ModelEntry) -> Result<Vec<HashMap<String, String>>, String> {
+        if !changes.conn_type.is_empty() {
+            check_conn_type(&changes.conn_type)?;
+        }
+
+        // The modified entry is checked before it is stored
+        let mut dal = dal.lock().await;
+        let mut config = dal.get_model(uid).await?;
+        for (field, value) in [("name", &changes.name), ("connType", &changes.conn_type)] {
+            if !value.is_empty() {
+                config[0].insert(field.to_string(), value.clone());
+            }
+        }
+        for (params, changed) in [(1, &changes.conn_params), (2, &changes.model_params)] {
+            for (name, value) in changed {
+                match value.is_empty() {
+                    true => config[params].remove(name),
+                    false => config[params].insert(name.clone(), value.clone()),
+                };
+            }
+        }
+        let conn_type = config[0].get("connType").cloned().unwrap_or_default();
+        MEAL::create(&conn_type, MEALArgs { meal_config: config }).map_err(|err| format!("Invalid request: {}", err))?;
+        dal.modify_model(uid, changes).await?;
+        let config = dal.get_model(uid).await?;
+        drop(dal);
+
+        // The old instance finishes its requests before the new one takes over
+        if let Err(err) = self.remove(uid, REMOVE_EXIT_TIMEOUT).await {
+            log::warn!("Failed to remove the instance of the modified model entry {}: {}", uid, err);
+        }
+        let meal = MEAL::create(&conn_type, MEALArgs { meal_config: config.clone() }).map_err(|err| format!("Invalid request: {}", err))?;
+        let name = meal.name().to_string();
+        self.add(&name, meal);
+        log::info!("Modified the model entry {} of model {}", uid, name);
+        Ok(config)
+    }
+
+    // Delete a model entry from the DB and shut down its instance
+    pub async fn delete_entry(&self, dal: &tokio::sync::Mutex<DAL>, uid: &str) -> Result<(), String> {
+        dal.lock().await.delete_model(uid).await?;
+        if let Err(err) = self.remove(uid, REMOVE_EXIT_TIMEOUT).await {
+            log::warn!("Failed to remove the instance of the deleted model entry {}: {}", uid, err);
+        }
+        log::info!("Deleted the model entry {}", uid);
+        Ok(())
+    }
+
+
+    //////////////////////////////////////////////////////
+    ///////////// Lifecycle of the instances /////////////
+    //////////////////////////////////////////////////////
+
+    // Start the eager instances and fill the warm pools
+    pub async fn start_eager(&self) {
+        for instance in self.all_instances() {
+            let mut meal = instance.write().await;
+            if meal.settings().lifecycle != Lifecycle::Eager || meal.is_ready() {
+                continue;
+            }
+            log::info!("Starting the eager instance of model {}", meal.name());
+            if let Err(err) = meal.start().await {
+                log::error!("Failed to start the eager instance of model {}: {}", meal.name(), err);
+            }
+        }
+        self.fill_warm_pools();
+    }
+
+    // Keep at least the largest minWarm of the instances of each model running
+    fn fill_warm_pools(&self) {
+        let models = self.models.read().unwrap().clone();
+        for (model_name, instances) in models {
+            let mut min_warm = 0;
+            let mut warm = 0;
+            let mut stopped: Vec<MEALInstance> = Vec::new();
+            for instance in &instances {
+                match instance.try_read() {
+                    Ok(meal) => {
+                        min_warm = min_warm.max(meal.settings().min_warm);
+                        match meal.state() {
+                            MEALState::Stopped => stopped.push(Arc::clone(instance)),
+                            MEALState::Failed(_) | MEALState::LimitExceeded(_) => (),
+                            _ => warm += 1,
+                        }
+                    }
+                    // Instances that are being (re)started count as warm
+                    Err(_) => warm += 1,
+                }
+            }
+
+            for instance in stopped.into_iter().take(min_warm.saturating_sub(warm)) {
+                let model_name = model_name.clone();
+                tokio::spawn(async move {
+                    let mut meal = instance.write().await;
+                    if meal.state() != MEALState::Stopped {
+                        return;
+                    }
+                    log::info!("Starting an instance of model {} to keep it warm", model_name);
+                    if let Err(err) = meal.start().await {
+                        log::error!("Failed to start a warm instance of model {}: {}", model_name, err);
+                    }
+                });
+            }
+        }
+    }
+
+    // Stop the instances that passed their idle timeout, keeping minWarm of each model running
+    async fn evict_idle(&self) {
+        let models = self.models.read().unwrap().clone();
+        for (model_name, instances) in models {
+            let mut min_warm = 0;
+            let mut running = 0;
+            for instance in &instances {
+                if let Ok(meal) = instance.try_read() {
+                    min_warm = min_warm.max(meal.settings().min_warm);
+                    if meal.is_ready() {
+                        running += 1;
+                    }
+                }
+            }
+
+            for instance in &instances {
+                if running <= min_warm {
+                    break;
+                }
+                // Instances serving requests hold the read lock and are never evicted
+                let mut meal = match instance.try_write() {
+                    Ok(meal) => meal,
+                    Err(_) => continue,
+                };
+                if !meal.is_ready() || !meal.idle_timeout_passed() {
+                    continue;
+                }
+                log::info!("Stopping an idle instance of model {}", model_name);
+                if let Err(err) = meal.stop().await {
+                    log::error!("Failed to stop an idle instance of model {}: {}", model_name, err);
+                }
+                running -= 1;
+            }
+        }
+    }
+
+    // Probe every instance whose health check is due, instances that are being (re)started are skipped and
+    // instances that do not answer within the liveness timeout are restarted
+    pub fn check_health(&self) {
+        for instance in self.all_instances() {
+            let meal = match Arc::clone(&instance).try_read_owned() {
+                Ok(meal) => meal,
+                Err(_) => continue,
+            };
+            if !meal.health_check_due() {
+                continue;
+            }
+            tokio::spawn(async move {
+                let err = match meal.ping().await {
+                    Ok(_) => return,
+                    Err(err) => err,
+                };
+                log::warn!("Health check failed: {}", err);
+                drop(meal);
+
+                // The instance may have been stopped or restarted while we waited for the lock
+                let mut meal = instance.write().await;
+                if !meal.is_ready() || !matches!(meal.state(), MEALState::Unhealthy(_)) {
+                    return;
+                }
+                log::info!("Restarting the unhealthy model {}", meal.name());
+                if let Err(err) = meal.restart(UNHEALTHY_EXIT_TIMEOUT).await {
+                    log::error!("Failed to restart the unhealthy model {}: {}", meal.name(), err);
+                }
+            });
+        }
+    }
+
+    // Sample the states and queue depths of the instances into the metrics, instances that are being (re)started or
+    // shut down are locked and reported as busy
+    pub fn record_metrics(&self) {
+        metrics::clear("mer_instance_state");
+        metrics::clear("mer_instance_queue_depth");
+        for model_name in self.model_names() {
+            let instances = self.instances(&model_name).unwrap_or_default();
+            let logs = self.logs(&model_name).unwrap_or_default();
+            for (instance, log) in instances.iter().zip(logs) {
+                let labels = [("model", model_name.as_str()), ("instance", log.instance_uid())];
+                let (state, in_flight) = match instance.try_read() {
+                    Ok(meal) => (meal.state().label(), meal.in_flight()),
+                    Err(_) => ("busy", 0),
+                };
+                for label in ["stopped", "starting", "ready", "unhealthy", "failed", "limit_exceeded", "busy"] {
+                    metrics::set("mer_instance_state", &[labels[0], labels[1], ("state", label)], if label == state { 1.0 } else { 0.0 });
+                }
+                metrics::set("mer_instance_queue_depth", &labels, in_flight as f64);
+            }
+        }
+    }
+
+    // Periodically probe, evict and warm up the instances in the background
+    pub fn spawn_maintenance(self: &Arc<Self>) {
+        let model_pool = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MAINTENANCE_TICK);
+            loop {
+                interval.tick().await;
+                if model_pool.is_shutting_down() {
+                    break;
+                }
+                model_pool.check_health();
+                model_pool.evict_idle().await;
+                model_pool.fill_warm_pools();
+                model_pool.prune_sessions();
+            }
+        });
+    }
+
+
+    //////////////////////////////////////////////////////
+    ////////////// Shutdown of the instances /////////////
+    //////////////////////////////////////////////////////
+
+    // Check if the pool stopped accepting requests
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    // Get the number of requests in flight over all instances, instances that are being (re)started count as busy
+    fn in_flight(&self) -> usize {
+        self.all_instances().iter()
+            .map(|instance| instance.try_read().map(|meal| meal.in_flight()).unwrap_or(1))
+            .sum()
+    }
+
+    // Stop accepting requests, drain the in-flight ones up to the drain timeout and shut down every instance,
+    // instances that do not exit within the exit timeout are terminated
+    pub async fn shutdown(&self, drain_timeout: Duration, exit_timeout: Duration) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        // Wait for the in-flight requests to finish
+        let deadline = Instant::now() + drain_timeout;
+        loop {
+            let in_flight = self.in_flight();
+            if in_flight == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::warn!("Cancelling {} requests still in flight after {:?}", in_flight, drain_timeout);
+                break;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        // Fail the remaining requests so they release their instances
+        for instance in self.all_instances() {
+            if let Ok(meal) = instance.try_read() {
+                meal.cancel_requests("The driver is shutting down");
+            }
+        }
+
+        // Shut the instances down in parallel
+        let mut tasks = Vec::new();
+        for instance in self.all_instances() {
+            tasks.push(tokio::spawn(async move {
+                let mut meal = match tokio::time::timeout(exit_timeout, instance.write()).await {
+                    Ok(meal) => meal,
+                    Err(_) => {
+                        log::error!("Could not shut down an instance that stayed busy for {:?}", exit_timeout);
+                        return;
+                    }
+                };
+                if let Err(err) = meal.shutdown(exit_timeout).await {
+                    log::error!("Failed to shut down an instance of model {}: {}", meal.name(), err);
+                }
+            }));
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        log::info!("All model instances are shut down");
+
+        // Cancel the training jobs, their train commands are terminated
+        self.training.shutdown(exit_timeout).await;
+
+        // Close the transcript, access, usage and weights channels so the writers can store the last records and stop
+        self.transcript_tx.lock().unwrap().take();
+        self.access_tx.lock().unwrap().take();
+        self.limiter.stop_recording();
+        self.weights_tx.lock().unwrap().take();
+    }
+}
+
+
+// The connection types the driver creates instances for
+fn check_conn_type(conn_type: &str) -> Result<(), String> {
+    match conn_type {
+        "ssh" | "local" => Ok(()),
+        _ => Err(format!("Invalid request: Unsupported connection type: {:#?}", conn_type)),
+    }
+}
@@ -0,0 +1,411 @@
+// src/meal/protocol.rs
+use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Default sentinel tokens, matching the ones used by the test model harnesses
+pub const DEFAULT_READY_TOKEN: &str = "@!#READY#!@";
+pub const DEFAULT_EXIT_TOKEN: &str = "@!#EXIT#!@";
+pub const DEFAULT_START_TOKEN: &str = "@!#START#!@";
+pub const DEFAULT_STOP_TOKEN: &str = "@!#STOP#!@";
+pub const DEFAULT_PING_TOKEN: &str = "@!#PING#!@";
+
+// Highest version of the model protocol understood by the driver
+pub const PROTOCOL_VERSION: u32 = 1;
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Wire protocol spoken between the driver and a model process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolKind {
+    // Sentinel tokens on their own lines around a single line of input/output
+    Tokens,
+    // One JSON object per line for requests and responses
+    Jsonl,
+}
+
+// Protocol configuration parsed from the model params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub kind: ProtocolKind,
+    pub ready_token: String,
+    pub exit_token: String,
+    pub start_token: String,
+    pub stop_token: String,
+    pub ping_token: String,
+}
+
+impl Protocol {
+    // Parse the protocol from the model params, missing tokens fall back to the defaults
+    pub fn from_model_params(model_params: &HashMap<String, String>) -> Result<Self, String> {
+        let kind = match model_params.get("protocol").map(|protocol| protocol.as_str()) {
+            None | Some("") | Some("tokens") => ProtocolKind::Tokens,
+            Some("jsonl") => ProtocolKind::Jsonl,
+            Some(protocol) => {
+                log::error!("Unknown model protocol: {:#?}", protocol);
+                return Err("Unknown model protocol: ".to_string() + protocol);
+            }
+        };
+
+        // Get the token or fall back to the default one
+        let token = |key: &str, default: &str| -> String {
+            match model_params.get(key) {
+                Some(token) if !token.is_empty() => token.clone(),
+                _ => default.to_string(),
+            }
+        };
+
+        Ok(Self {
+            kind,
+            ready_token: token("readyToken", DEFAULT_READY_TOKEN),
+            exit_token: token("exitToken", DEFAULT_EXIT_TOKEN),
+            start_token: token("startToken", DEFAULT_START_TOKEN),
+            stop_token: token("stopToken", DEFAULT_STOP_TOKEN),
+            ping_token: token("pingToken", DEFAULT_PING_TOKEN),
+        })
+    }
+
+    // Encode a request into the text written to the model stdin
+    pub fn encode_request(&self, request: &MEALRequest) -> Result<String, String> {
+        match self.kind {
+            ProtocolKind::Tokens => {
+                // The token protocol reads exactly one line between the start and stop tokens
+                if request.input.is_empty() {
+                    return Err("Invalid request: Empty input is not supported by the tokens protocol".to_string());
+                }
+                if request.input.contains('\n') || request.input.contains('\r') {
+                    return Err("Multi-line input is not supported by the tokens protocol, use the jsonl protocol".to_string());
+                }
+                if !request.params.is_empty() {
+                    return Err("Request parameters are not supported by the tokens protocol, use the jsonl protocol".to_string());
+                }
+                if request.session.is_some() {
+                    return Err("Sessions are not supported by the tokens protocol, use the jsonl protocol".to_string());
+                }
+                Ok(format!("{}\n{}\n{}\n", self.start_token, request.input, self.stop_token))
+            }
+            ProtocolKind::Jsonl => {
+                let line = serde_json::to_string(request).map_err(|err| err.to_string())?;
+                Ok(line + "\n")
+            }
+        }
+    }
+
+    // Encode several requests into a single batch frame, only the jsonl protocol can carry batches
+    pub fn encode_batch(&self, requests: &[MEALRequest]) -> Result<String, String> {
+        match self.kind {
+            ProtocolKind::Tokens => Err("Batching is not supported by the tokens protocol, use the jsonl protocol".to_string()),
+            ProtocolKind::Jsonl => {
+                let line = serde_json::to_string(&serde_json::json!({ "batch": requests })).map_err(|err| err.to_string())?;
+                Ok(line + "\n")
+            }
+        }
+    }
+
+    // Encode a session operation, the model answers it like a request with the same id
+    pub fn encode_session_op(&self, id: &str, session: &str, op: SessionOp) -> Result<String, String> {
+        match self.kind {
+            ProtocolKind::Tokens => Err("Sessions are not supported by the tokens protocol, use the jsonl protocol".to_string()),
+            ProtocolKind::Jsonl => Ok(format!("{}\n", serde_json::json!({ "id": id, "session": session, "sessionOp": op }))),
+        }
+    }
+
+    // Encode the cancellation of a request, the model stops generating and may still answer the request
+    pub fn encode_cancel(&self, id: &str) -> Result<String, String> {
+        match self.kind {
+            ProtocolKind::Tokens => Err("Cancelling requests is not supported by the tokens protocol, use the jsonl protocol".to_string()),
+            ProtocolKind::Jsonl => Ok(format!("{}\n", serde_json::json!({ "id": id, "cancel": true }))),
+        }
+    }
+
+    // Encode a health probe, the model echoes the ping token or answers the ping id
+    pub fn encode_ping(&self, id: &str) -> String {
+        match self.kind {
+            ProtocolKind::Tokens => format!("{}\n", self.ping_token),
+            ProtocolKind::Jsonl => format!("{}\n", serde_json::json!({ "id": id, "ping": true })),
+        }
+    }
+
+    // Encode the exit message written to the model stdin
+    pub fn encode_exit(&self) -> String {
+        format!("{}\n", self.exit_token)
+    }
+
+    // Create a decoder for the model stdout lines
+    pub fn decoder(&self) -> ResponseDecoder {
+        ResponseDecoder {
+            protocol: self.clone(),
+            frame: None,
+        }
+    }
+}
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Request sent to a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MEALRequest {
+    pub id: String,
+    pub input: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub params: HashMap<String, Value>,
+    // Conversation the request continues, the model keeps a separate history per session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    // Ask a streaming model to send the output in chunks before the response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+}
+
+impl MEALRequest {
+    // Create a request without an id, the MEAL assigns one when sending it
+    pub fn new(input: &str) -> Self {
+        Self {
+            id: String::new(),
+            input: input.to_string(),
+            params: HashMap::new(),
+            session: None,
+            stream: false,
+        }
+    }
+}
+
+// Operations on the conversation history of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionOp {
+    // Start an empty history
+    Create,
+    // Clear the history, keeping the session open
+    Reset,
+    // Forget the history
+    Close,
+}
+
+// Response received from a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MEALResponse {
+    #[serde(default, deserialize_with = "deserialize_response_id")]
+    pub id: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_model_error")]
+    pub error: Option<ModelError>,
+}
+
+// Part of the output a streaming model sends before the response, the response still carries the whole output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MEALChunk {
+    #[serde(default, deserialize_with = "deserialize_response_id")]
+    pub id: String,
+    pub chunk: String,
+}
+
+// Structured error reported by a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelError {
+    pub code: String,
+    pub message: String,
+}
+
+// Models may echo the id as a string, a number or null when the request was unparsable
+fn deserialize_response_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => String::new(),
+        Value::String(id) => id,
+        other => other.to_string(),
+    })
+}
+
+// Models may report errors either as a plain string or as a {code, message} object
+fn deserialize_model_error<'de, D>(deserializer: D) -> Result<Option<ModelError>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawModelError {
+        Message(String),
+        Structured {
+            #[serde(default)]
+            code: Option<String>,
+            #[serde(default)]
+            message: Option<String>,
+        },
+    }
+
+    Ok(match Option::<RawModelError>::deserialize(deserializer)? {
+        None => None,
+        Some(RawModelError::Message(message)) => Some(ModelError {
+            code: "model_error".to_string(),
+            message,
+        }),
+        Some(RawModelError::Structured { code, message }) => Some(ModelError {
+            code: code.unwrap_or_else(|| "model_error".to_string()),
+            message: message.unwrap_or_default(),
+        }),
+    })
+}
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Capability descriptor a model may append to its ready token as JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelCapabilities {
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub streaming: bool,
+    #[serde(default)]
+    pub max_input_length: Option<usize>,
+    #[serde(default)]
+    pub batching: bool,
+    #[serde(default)]
+    pub sessions: bool,
+    // Whether the model stops generating on {"id": ..., "cancel": true}
+    #[serde(default)]
+    pub cancel: bool,
+    // Accepted request parameters, any parameter is accepted when missing
+    #[serde(default)]
+    pub parameters: Option<Vec<String>>,
+}
+
+fn default_protocol_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+impl ModelCapabilities {
+    // Check a request against the capabilities before sending it to the model
+    pub fn validate_request(&self, request: &MEALRequest) -> Result<(), String> {
+        if let Some(max_input_length) = self.max_input_length {
+            let input_length = request.input.chars().count();
+            if input_length > max_input_length {
+                return Err(format!("Input of {} characters exceeds the maximum input length of {}", input_length, max_input_length));
+            }
+        }
+
+        if request.session.is_some() && !self.sessions {
+            return Err("The model does not support sessions".to_string());
+        }
+
+        if let Some(parameters) = &self.parameters {
+            let mut unknown: Vec<&String> = request.params.keys().filter(|name| !parameters.contains(name)).collect();
+            if !unknown.is_empty() {
+                unknown.sort();
+                let unknown: Vec<&str> = unknown.iter().map(|name| name.as_str()).collect();
+                return Err("Unsupported request parameters: ".to_string() + &unknown.join(", "));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Decoded line of the model stdout
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedLine {
+    // The model printed its ready token, optionally followed by its capabilities
+    Ready(Option<ModelCapabilities>),
+    // A complete response, the id is empty for the tokens protocol
+    Response(MEALResponse),
+    // The responses to a batch frame
+    Batch(Vec<MEALResponse>),
+    // An output chunk of a streamed request
+    Chunk(MEALChunk),
+    // The echoed ping token of the tokens protocol
+    Pong,
+    // Any output that is not part of the protocol
+    Noise(String),
+    // A line consumed as part of an unfinished response frame
+    Pending,
+}
+
+// Stateful decoder of the model stdout, fed one line at a time
+#[derive(Debug)]
+pub struct ResponseDecoder {
+    protocol: Protocol,
+    frame: Option<Vec<String>>,
+}
+
+impl ResponseDecoder {
+    pub fn feed(&mut self, line: &str) -> DecodedLine {
+        // Strip the trailing carriage return of CRLF terminated lines
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        match self.protocol.kind {
+            ProtocolKind::Tokens => {
+                // Collect lines until the stop token when inside a frame
+                if let Some(frame) = self.frame.as_mut() {
+                    if line == self.protocol.stop_token {
+                        let output = frame.join("\n");
+                        self.frame = None;
+                        return DecodedLine::Response(MEALResponse {
+                            id: String::new(),
+                            output: Some(output),
+                            error: None,
+                        });
+                    }
+                    frame.push(line.to_string());
+                    return DecodedLine::Pending;
+                }
+
+                if line == self.protocol.start_token {
+                    self.frame = Some(Vec::new());
+                    DecodedLine::Pending
+                } else if line == self.protocol.ping_token {
+                    DecodedLine::Pong
+                } else if let Some(descriptor) = line.strip_prefix(&self.protocol.ready_token) {
+                    DecodedLine::Ready(parse_capabilities(descriptor))
+                } else {
+                    DecodedLine::Noise(line.to_string())
+                }
+            }
+            ProtocolKind::Jsonl => {
+                if let Some(descriptor) = line.strip_prefix(&self.protocol.ready_token) {
+                    return DecodedLine::Ready(parse_capabilities(descriptor));
+                }
+                // Only JSON objects carrying an id or a batch of responses are treated as responses
+                match serde_json::from_str::<Value>(line) {
+                    Ok(value) if value.get("id").is_some() && value.get("chunk").is_some() => match serde_json::from_value::<MEALChunk>(value) {
+                        Ok(chunk) => DecodedLine::Chunk(chunk),
+                        Err(_) => DecodedLine::Noise(line.to_string()),
+                    },
+                    Ok(value) if value.get("id").is_some() => match serde_json::from_value::<MEALResponse>(value) {
+                        Ok(response) => DecodedLine::Response(response),
+                        Err(_) => DecodedLine::Noise(line.to_string()),
+                    },
+                    Ok(Value::Object(mut object)) if object.get("batch").map(|batch| batch.is_array()).unwrap_or(false) => {
+                        match serde_json::from_value::<Vec<MEALResponse>>(object.remove("batch").unwrap()) {
+                            Ok(responses) => DecodedLine::Batch(responses),
+                            Err(_) => DecodedLine::Noise(line.to_string()),
+                        }
+                    }
+                    _ => DecodedLine::Noise(line.to_string()),
+                }
+            }
+        }
+    }
+}
+
+// Parse the capability descriptor following the ready token
+fn parse_capabilities(descriptor: &str) -> Option<ModelCapabilities> {
+    let descriptor = descriptor.trim();
+    if descriptor.is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<ModelCapabilities>(descriptor) {
+        Ok(capabilities) => Some(capabilities),
+        Err(err) => {
+            log::warn!("Ignoring invalid model capability descriptor {:#?}: {}", descriptor, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// src/meal/quota.rs
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use tokio::sync::mpsc;
+use super::settings::parse_u64;
+use crate::dal::{ApiKey, UsageCounter, UsageLimits};
+
+
+// Parse the rate limit and quotas of a model shared by all its callers, missing or 0 params are unlimited
+pub fn limits_from_model_params(model_params: &HashMap<String, String>) -> Result<UsageLimits, String> {
+    let limit = |key: &str| parse_u64(model_params, key, 0).map(|value| (value > 0).then_some(value));
+    Ok(UsageLimits {
+        rate_per_minute: limit("rateLimitPerMinute")?,
+        burst: limit("rateLimitBurst")?,
+        daily_requests: limit("dailyRequestQuota")?,
+        monthly_requests: limit("monthlyRequestQuota")?,
+        daily_bytes: limit("dailyByteQuota")?,
+        monthly_bytes: limit("monthlyByteQuota")?,
+    })
+}
+
+// Seconds the caller has to wait according to a rate limit or quota error of the pool
+pub fn retry_after(message: &str) -> Option<u64> {
+    if !message.starts_with("Too many requests") && !message.starts_with("Quota exceeded") {
+        return None;
+    }
+    let (_, seconds) = message.rsplit_once("retry after ")?;
+    seconds.strip_suffix('s')?.parse().ok()
+}
+
+// UTC day and month the usage of a request is counted in
+pub fn periods(now: DateTime<Utc>) -> [String; 2] {
+    [now.format("%Y-%m-%d").to_string(), now.format("%Y-%m").to_string()]
+}
+
+
+// Token bucket of a rate limit, refilled continuously
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Counters of an API key or a model in the current day and month
+#[derive(Debug, Clone)]
+struct SubjectUsage {
+    day: UsageCounter,
+    month: UsageCounter,
+}
+
+impl SubjectUsage {
+    fn new(subject: &str, periods: &[String; 2]) -> Self {
+        let counter = |period: &String| UsageCounter { subject: subject.to_string(), period: period.clone(), requests: 0, bytes: 0 };
+        Self { day: counter(&periods[0]), month: counter(&periods[1]) }
+    }
+
+    // Start new counters once the day or the month is over
+    fn roll(&mut self, periods: &[String; 2]) {
+        if self.day.period != periods[0] {
+            self.day = UsageCounter { period: periods[0].clone(), requests: 0, bytes: 0, ..self.day.clone() };
+        }
+        if self.month.period != periods[1] {
+            self.month = UsageCounter { period: periods[1].clone(), requests: 0, bytes: 0, ..self.month.clone() };
+        }
+    }
+}
+
+// Subject a request is counted for with its limits
+struct Subject {
+    key: String,
+    label: String,
+    limits: UsageLimits,
+}
+
+
+// Rate limits and quotas of the API keys and the models, a request is only admitted when both allow it
+#[derive(Debug, Default)]
+pub struct UsageLimiter {
+    model_limits: Mutex<HashMap<String, UsageLimits>>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    usage: Mutex<HashMap<String, SubjectUsage>>,
+    // Receives the counters changed by a request
+    usage_tx: Mutex<Option<mpsc::UnboundedSender<UsageCounter>>>,
+}
+
+impl UsageLimiter {
+    // Set the limits shared by all callers of a model
+    pub fn set_model_limits(&self, model_name: &str, limits: UsageLimits) {
+        self.model_limits.lock().unwrap().insert(model_name.to_string(), limits);
+    }
+
+    // Continue counting from the stored counters, e.g. after a restart
+    pub fn load(&self, counters: Vec<UsageCounter>) {
+        let periods = periods(Utc::now());
+        let mut usage = self.usage.lock().unwrap();
+        for counter in counters {
+            let subject_usage = usage.entry(counter.subject.clone()).or_insert_with(|| SubjectUsage::new(&counter.subject, &periods));
+            if counter.period == periods[0] {
+                subject_usage.day = counter;
+            } else if counter.period == periods[1] {
+                subject_usage.month = counter;
+            }
+        }
+    }
+
+    // Send the changed counters to the channel
+    pub fn record(&self, usage_tx: mpsc::UnboundedSender<UsageCounter>) {
+        *self.usage_tx.lock().unwrap() = Some(usage_tx);
+    }
+
+    // Stop sending the counters, the receiver finishes once the channel is empty
+    pub fn stop_recording(&self) {
+        self.usage_tx.lock().unwrap().take();
+    }
+
+    // Get the counters of the current day and month by subject
+    pub fn usage(&self) -> Vec<(UsageCounter, UsageCounter)> {
+        let periods = periods(Utc::now());
+        let mut usage: Vec<(UsageCounter, UsageCounter)> = self.usage.lock().unwrap().values()
+            .map(|subject_usage| {
+                let mut subject_usage = subject_usage.clone();
+                subject_usage.roll(&periods);
+                (subject_usage.day, subject_usage.month)
+            })
+            .collect();
+        usage.sort_by(|a, b| a.0.subject.cmp(&b.0.subject));
+        usage
+    }
+
+    // Whether the outputs of the requests of the caller to the model count towards a byte quota
+    pub fn counts_bytes(&self, model_name: &str, caller: Option<&ApiKey>) -> bool {
+        self.subjects(model_name, caller).iter()
+            .any(|subject| subject.limits.daily_bytes.is_some() || subject.limits.monthly_bytes.is_some())
+    }
+
+    // Admit a request of the caller to the model and count it with its input bytes, or report how long to wait
+    pub fn acquire(&self, model_name: &str, caller: Option<&ApiKey>, bytes: u64) -> Result<(), String> {
+        let subjects = self.subjects(model_name, caller);
+        if subjects.is_empty() {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let instant = Instant::now();
+        let periods = periods(now);
+
+        // Check every limit first, a rejected request does not use up tokens or quota
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut usage = self.usage.lock().unwrap();
+        let mut refilled: Vec<(String, TokenBucket)> = Vec::new();
+        for subject in &subjects {
+            let subject_usage = usage.entry(subject.key.clone()).or_insert_with(|| SubjectUsage::new(&subject.key, &periods));
+            subject_usage.roll(&periods);
+            let quotas = [
+                ("daily request", subject.limits.daily_requests, subject_usage.day.requests, next_day(now)),
+                ("monthly request", subject.limits.monthly_requests, subject_usage.month.requests, next_month(now)),
+                ("daily byte", subject.limits.daily_bytes, subject_usage.day.bytes, next_day(now)),
+                ("monthly byte", subject.limits.monthly_bytes, subject_usage.month.bytes, next_month(now)),
+            ];
+            for (quota, limit, used, reset) in quotas {
+                if let Some(limit) = limit.filter(|limit| used >= *limit) {
+                    let seconds = (reset - now).num_seconds().max(1);
+                    return Err(format!("Quota exceeded: The {} quota of {} ({}) is used up, retry after {}s", quota, subject.label, limit, seconds));
+                }
+            }
+
+            if let Some(rate) = subject.limits.rate_per_minute {
+                let capacity = subject.limits.burst.unwrap_or(rate).max(1) as f64;
+                let per_second = rate as f64 / 60.0;
+                let mut bucket = buckets.get(&subject.key).copied().unwrap_or(TokenBucket { tokens: capacity, updated: instant });
+                bucket.tokens = (bucket.tokens + instant.duration_since(bucket.updated).as_secs_f64() * per_second).min(capacity);
+                bucket.updated = instant;
+                if bucket.tokens < 1.0 {
+                    let seconds = ((1.0 - bucket.tokens) / per_second).ceil().max(1.0) as u64;
+                    return Err(format!("Too many requests: The rate limit of {} ({} per minute) is exceeded, retry after {}s", subject.label, rate, seconds));
+                }
+                refilled.push((subject.key.clone(), bucket));
+            }
+        }
+
+        // Take a token of every rate limit and count the request
+        for (key, mut bucket) in refilled {
+            bucket.tokens -= 1.0;
+            buckets.insert(key, bucket);
+        }
+        for subject in &subjects {
+            if let Some(subject_usage) = usage.get_mut(&subject.key) {
+                for counter in [&mut subject_usage.day, &mut subject_usage.month] {
+                    counter.requests += 1;
+                    counter.bytes += bytes;
+                }
+                self.send(subject_usage);
+            }
+        }
+        Ok(())
+    }
+
+    // Count the output bytes of an answered request
+    pub fn add_bytes(&self, model_name: &str, caller: Option<&ApiKey>, bytes: u64) {
+        let subjects = self.subjects(model_name, caller);
+        let periods = periods(Utc::now());
+        let mut usage = self.usage.lock().unwrap();
+        for subject in subjects {
+            let subject_usage = usage.entry(subject.key.clone()).or_insert_with(|| SubjectUsage::new(&subject.key, &periods));
+            subject_usage.roll(&periods);
+            subject_usage.day.bytes += bytes;
+            subject_usage.month.bytes += bytes;
+            self.send(subject_usage);
+        }
+    }
+
+    // Subjects with limits a request of the caller to the model is counted for
+    fn subjects(&self, model_name: &str, caller: Option<&ApiKey>) -> Vec<Subject> {
+        let mut subjects = Vec::new();
+        if let Some(limits) = self.model_limits.lock().unwrap().get(model_name).filter(|limits| **limits != UsageLimits::default()) {
+            subjects.push(Subject { key: format!("model:{}", model_name), label: format!("model {}", model_name), limits: limits.clone() });
+        }
+        if let Some(key) = caller.filter(|key| key.limits != UsageLimits::default()) {
+            subjects.push(Subject { key: format!("key:{}", key.key_id), label: format!("API key {}", key.key_id), limits: key.limits.clone() });
+        }
+        subjects
+    }
+
+    fn send(&self, subject_usage: &SubjectUsage) {
+        if let Some(usage_tx) = self.usage_tx.lock().unwrap().as_ref() {
+            if usage_tx.send(subject_usage.day.clone()).is_err() || usage_tx.send(subject_usage.month.clone()).is_err() {
+                log::warn!("Dropping the usage of {}, the usage writer stopped", subject_usage.day.subject);
+            }
+        }
+    }
+}
+
+// Start of the next UTC day and month, when the quotas are reset
+fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test the rate limits and quotas of the models and the API keys
+    #[test]
+    fn test_usage_limits() {
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("rateLimitPerMinute".to_string(), "60".to_string());
+        model_params.insert("rateLimitBurst".to_string(), "2".to_string());
+        model_params.insert("dailyByteQuota".to_string(), "0".to_string());
+        let limits = limits_from_model_params(&model_params).unwrap();
+        assert_eq!((limits.rate_per_minute, limits.burst, limits.daily_bytes), (Some(60), Some(2), None));
+
+        // The bucket of the model holds the burst, further requests have to wait for a token
+        let limiter = UsageLimiter::default();
+        limiter.set_model_limits("echo", limits);
+        limiter.acquire("echo", None, 2).unwrap();
+        limiter.acquire("echo", None, 2).unwrap();
+        let err = limiter.acquire("echo", None, 2).unwrap_err();
+        assert!(err.starts_with("Too many requests"));
+        assert_eq!(retry_after(&err), Some(1));
+        assert!(limiter.acquire("other", None, 2).is_ok());
+
+        // The quotas of a key count its requests and bytes of all models, rejected requests are not counted
+        let (usage_tx, mut usage_rx) = mpsc::unbounded_channel();
+        limiter.record(usage_tx);
+        let key = ApiKey {
+            key_id: "a1".to_string(),
+            name: "team".to_string(),
+            key_hash: String::new(),
+            models: vec!["*".to_string()],
+            admin: false,
+            limits: UsageLimits { daily_requests: Some(2), monthly_bytes: Some(100), ..Default::default() },
+            created_at: Utc::now(),
+        };
+        assert!(limiter.counts_bytes("other", Some(&key)));
+        limiter.acquire("other", Some(&key), 10).unwrap();
+        limiter.add_bytes("other", Some(&key), 20);
+        limiter.acquire("other", Some(&key), 10).unwrap();
+        let err = limiter.acquire("other", Some(&key), 10).unwrap_err();
+        assert!(err.starts_with("Quota exceeded: The daily request quota of API key a1"));
+        assert!(retry_after(&err).unwrap() <= 24 * 3600);
+        let (day, month) = limiter.usage().into_iter().find(|(day, _)| day.subject == "key:a1").unwrap();
+        assert_eq!((day.requests, day.bytes, month.bytes), (2, 40, 40));
+        assert_eq!(day.period, periods(Utc::now())[0]);
+
+        // The changed counters are stored and continued after a restart
+        let mut stored = Vec::new();
+        while let Ok(counter) = usage_rx.try_recv() {
+            stored.push(counter);
+        }
+        assert_eq!(stored.len(), 6);
+        let limiter = UsageLimiter::default();
+        limiter.load(stored[4..].to_vec());
+        assert!(limiter.acquire("other", Some(&key), 10).unwrap_err().starts_with("Quota exceeded"));
+    }
+}
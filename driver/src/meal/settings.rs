@@ -0,0 +1,109 @@
+// src/meal/settings.rs
+use std::collections::HashMap;
+use std::time::Duration;
+use super::feedback::FeedbackSettings;
+use super::logs::LogSettings;
+use super::quota;
+use crate::dal::UsageLimits;
+
+
+// Default startup deadline, large models can take a while to load their weights
+pub const DEFAULT_STARTUP_TIMEOUT_MS: u64 = 300_000;
+// Default time a model has to answer a health probe
+pub const DEFAULT_LIVENESS_TIMEOUT_MS: u64 = 10_000;
+// Default time a model has to answer a request, 0 waits without a deadline
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 300_000;
+// Default interval between health probes, 0 disables the periodic probing
+pub const DEFAULT_HEALTH_INTERVAL_MS: u64 = 30_000;
+
+
+// Default number of requests sent to the model in one batch frame, 1 disables batching
+pub const DEFAULT_MAX_BATCH_SIZE: u64 = 1;
+// Default time the first request of a batch waits for more requests
+pub const DEFAULT_MAX_BATCH_DELAY_MS: u64 = 10;
+
+
+// When the model process of an instance is spawned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    // Spawned when the driver starts
+    Eager,
+    // Spawned on the first request
+    Lazy,
+}
+
+// Instance settings parsed from the model params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MEALSettings {
+    pub startup_timeout: Duration,
+    pub liveness_timeout: Duration,
+    pub request_timeout: Option<Duration>,
+    pub health_interval: Option<Duration>,
+    pub lifecycle: Lifecycle,
+    pub min_warm: usize,
+    pub idle_timeout: Option<Duration>,
+    pub max_batch_size: usize,
+    pub max_batch_delay: Duration,
+    // Whether the answered requests are stored in the transcript table
+    pub transcript: bool,
+    pub log: LogSettings,
+    pub feedback: FeedbackSettings,
+    // Rate limit and quotas shared by all callers of the model
+    pub limits: UsageLimits,
+}
+
+impl MEALSettings {
+    pub fn from_model_params(model_params: &HashMap<String, String>) -> Result<Self, String> {
+        let health_interval = parse_u64(model_params, "healthIntervalMs", DEFAULT_HEALTH_INTERVAL_MS)?;
+        let idle_timeout = parse_u64(model_params, "idleTimeoutMs", 0)?;
+        let request_timeout = parse_u64(model_params, "requestTimeoutMs", DEFAULT_REQUEST_TIMEOUT_MS)?;
+        let lifecycle = match model_params.get("lifecycle").map(|lifecycle| lifecycle.as_str()) {
+            None | Some("") | Some("lazy") => Lifecycle::Lazy,
+            Some("eager") => Lifecycle::Eager,
+            Some(lifecycle) => {
+                log::error!("Unknown model lifecycle: {:#?}", lifecycle);
+                return Err("Unknown model lifecycle: ".to_string() + lifecycle);
+            }
+        };
+
+        Ok(Self {
+            startup_timeout: Duration::from_millis(parse_u64(model_params, "startupTimeoutMs", DEFAULT_STARTUP_TIMEOUT_MS)?),
+            liveness_timeout: Duration::from_millis(parse_u64(model_params, "livenessTimeoutMs", DEFAULT_LIVENESS_TIMEOUT_MS)?),
+            request_timeout: (request_timeout > 0).then(|| Duration::from_millis(request_timeout)),
+            health_interval: (health_interval > 0).then(|| Duration::from_millis(health_interval)),
+            lifecycle,
+            min_warm: parse_u64(model_params, "minWarm", 0)? as usize,
+            idle_timeout: (idle_timeout > 0).then(|| Duration::from_millis(idle_timeout)),
+            max_batch_size: parse_u64(model_params, "maxBatchSize", DEFAULT_MAX_BATCH_SIZE)?.max(1) as usize,
+            max_batch_delay: Duration::from_millis(parse_u64(model_params, "maxBatchDelayMs", DEFAULT_MAX_BATCH_DELAY_MS)?),
+            transcript: parse_bool(model_params, "transcriptEnabled", false)?,
+            log: LogSettings::from_model_params(model_params)?,
+            feedback: FeedbackSettings::from_model_params(model_params)?,
+            limits: quota::limits_from_model_params(model_params)?,
+        })
+    }
+}
+
+// Parse an unsigned integer model param, falling back to the default when it is missing
+pub fn parse_u64(model_params: &HashMap<String, String>, key: &str, default: u64) -> Result<u64, String> {
+    match model_params.get(key) {
+        Some(value) if !value.is_empty() => value.trim().parse::<u64>().map_err(|_| {
+            log::error!("Invalid value for the model param {}: {:#?}", key, value);
+            format!("Invalid value for the model param {}: {:#?}", key, value)
+        }),
+        _ => Ok(default),
+    }
+}
+
+// Parse a boolean model param, falling back to the default when it is missing
+pub fn parse_bool(model_params: &HashMap<String, String>, key: &str, default: bool) -> Result<bool, String> {
+    match model_params.get(key).map(|value| value.trim()) {
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(value) if !value.is_empty() => {
+            log::error!("Invalid value for the model param {}: {:#?}", key, value);
+            Err(format!("Invalid value for the model param {}: {:#?}", key, value))
+        }
+        _ => Ok(default),
+    }
+}
@@ -1,17 +1,26 @@
 // src/meal/ssh.rs
-use super::{MEALDriver, MEALArgs};
+use super::{MEALDriver, MEALArgs, ModelExit};
+use super::command::ModelCommand;
+use super::stats::{self, CpuTracker, ProcConstants, ProcessStats};
+use super::transfer::{self, ModelFile};
+use std::sync::{Arc, Mutex};
 use std::fmt;
 use std::collections::HashMap;
 use async_trait::async_trait;
 
 
 // tokio libraries
-use tokio::sync::mpsc;
-use tokio::task;
-use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
 
-// SSH library
-use makiko;
+
+// Capacity of the stdin, stdout and stderr channels
+const CHANNEL_CAPACITY: usize = 64;
+// Prefix of the line the remote shell writes to stderr with the pid of the model before starting it
+const PID_MARKER: &str = "@!#PID#!@";
+// Dumps everything needed to sample a remote process tree, see ProcessStats
+const REMOTE_STATS_COMMAND: &str = "cat /proc/uptime; getconf CLK_TCK; getconf PAGESIZE; cat /proc/[0-9]*/stat 2>/dev/null; true";
+// Lists the files below the current directory as "<size> <sha256> ./<path>" lines, see transfer::parse_listing
+const REMOTE_LIST_COMMAND: &str = r#"find . -type f -exec sh -c 'for f do printf "%s %s %s\n" $(wc -c < "$f") $(sha256sum < "$f" | cut -d " " -f 1) "$f"; done' sh {} +"#;
 
 
 // Create the SSHDriver struct
@@ -19,6 +28,13 @@ pub struct SSHDriver {
     static_fields: HashMap<String, String>,
     model_params: HashMap<String, String>,
     connection_params: HashMap<String, String>,
+    // Connection and session of the last spawned model
+    client: Option<makiko::Client>,
+    session: Option<makiko::Session>,
+    // Remote pid of the model, reported by the remote shell
+    pid: Arc<Mutex<Option<u32>>>,
+    exited_rx: Option<watch::Receiver<Option<ModelExit>>>,
+    cpu_tracker: CpuTracker,
 }
 
 #[async_trait]
@@ -33,13 +49,232 @@ impl MEALDriver for SSHDriver {
             static_fields: meal_args.meal_config[0].clone(),
             connection_params: meal_args.meal_config[1].clone(),
             model_params: meal_args.meal_config[2].clone(),
+            client: None,
+            session: None,
+            pid: Arc::new(Mutex::new(None)),
+            exited_rx: None,
+            cpu_tracker: CpuTracker::default(),
         }
     }
 
     //////////////////////////////////////////////////////
     /////// Management of the SSHDriver connection ///////
     //////////////////////////////////////////////////////
-    async fn spawn_model(&mut self) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String> {
+    async fn spawn_command(&mut self, kind: &str, args: &[String]) -> Result<(mpsc::Sender<String>, mpsc::Receiver<String>, mpsc::Receiver<String>), String> {
+        // Parse the model command, it is quoted for the remote shell
+        let mut model_command = ModelCommand::from_model_params(&self.model_params, kind)?;
+        model_command.argv.extend(args.iter().cloned());
+        let model_command = model_command.to_shell_command()?;
+        // Log the model parameters
+        log::info!("Model parameters:\n    - Model command: {:#?}", model_command);
+
+        let client = self.connect().await?;
+
+        // Open a session on the server
+        let (session, mut session_rx) = client.open_session(makiko::ChannelConfig::default()).await.map_err(|err| {
+            log::error!("Failed to open a session: {}", err);
+            "Failed to open a session: ".to_string() + &err.to_string()
+        })?;
+
+        // Execute the model command in the model working directory, the shell reports its pid before exec replaces it with the model
+        let model_command = format!("echo {} $$ >&2; {}", PID_MARKER, model_command);
+        session.exec(model_command.as_bytes())
+            .map_err(|err| {
+                log::error!("Failed to execute the model command: {}", err);
+                "Failed to execute the model command: ".to_string() + &err.to_string()
+            })?
+            .wait().await
+            .map_err(|err| {
+                log::error!("The server refused to execute the model command: {}", err);
+                "The server refused to execute the model command: ".to_string() + &err.to_string()
+            })?;
+
+        // Create Tokio channels for communication
+        let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+        let (stdout_tx, stdout_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+        let (stderr_tx, stderr_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+
+        // Forward the stdin channel to the remote process, closing the channel sends EOF
+        let stdin_session = session.clone();
+        tokio::spawn(async move {
+            while let Some(data) = stdin_rx.recv().await {
+                if let Err(err) = stdin_session.send_stdin(data.into()).await {
+                    log::error!("Failed to write to the model stdin: {}", err);
+                    return;
+                }
+            }
+            let _ = stdin_session.send_eof().await;
+        });
+
+        // Forward the remote stdout and stderr line by line until the session closes
+        *self.pid.lock().unwrap() = None;
+        let remote_pid = Arc::clone(&self.pid);
+        let (exited_tx, exited_rx) = watch::channel(None);
+        self.exited_rx = Some(exited_rx);
+        tokio::spawn(async move {
+            let mut stdout = LineBuffer::default();
+            let mut stderr = LineBuffer::default();
+            let mut model_exit = None;
+            loop {
+                let event = match session_rx.recv().await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(err) => {
+                        log::error!("Failed to receive a session event: {}", err);
+                        break;
+                    }
+                };
+                match event {
+                    makiko::SessionEvent::StdoutData(data) => {
+                        for line in stdout.push(&data) {
+                            let _ = stdout_tx.send(line).await;
+                        }
+                    }
+                    makiko::SessionEvent::StderrData(data) => {
+                        for line in stderr.push(&data) {
+                            if let Some(pid) = line.strip_prefix(PID_MARKER) {
+                                *remote_pid.lock().unwrap() = pid.trim().parse().ok();
+                                continue;
+                            }
+                            let _ = stderr_tx.send(line).await;
+                        }
+                    }
+                    makiko::SessionEvent::ExitStatus(0) => {
+                        log::info!("Model exited successfully");
+                        model_exit = Some(ModelExit::Succeeded);
+                    }
+                    makiko::SessionEvent::ExitStatus(status) => {
+                        log::error!("Model exited with status: {}", status);
+                        model_exit = Some(ModelExit::Exited(format!("exit status: {}", status)));
+                    }
+                    makiko::SessionEvent::ExitSignal(signal) => {
+                        log::error!("Model exited with signal {}: {}", signal.signal_name, signal.message);
+                        model_exit = Some(ModelExit::Exited(format!("signal: {}", signal.signal_name)));
+                    }
+                    _ => (),
+                }
+            }
+
+            // Flush the unterminated last lines
+            if let Some(line) = stdout.finish() {
+                let _ = stdout_tx.send(line).await;
+            }
+            if let Some(line) = stderr.finish() {
+                let _ = stderr_tx.send(line).await;
+            }
+            *remote_pid.lock().unwrap() = None;
+            let _ = exited_tx.send(Some(model_exit.unwrap_or_else(|| ModelExit::Exited("the session closed".to_string()))));
+        });
+
+        self.client = Some(client);
+        self.session = Some(session);
+
+        Ok((stdin_tx, stdout_rx, stderr_rx))
+    }
+
+    async fn stats(&self) -> Result<ProcessStats, String> {
+        // Sampling opens an extra session, so it has to be enabled per model
+        if self.connection_params.get("collectStats").map(|collect| collect.as_str()) != Some("true") {
+            return Err("Remote stats are disabled, set the collectStats connection param to true".to_string());
+        }
+        let client = self.client.as_ref().ok_or("The model is not spawned")?;
+        let pid = self.pid.lock().unwrap().ok_or("The remote model did not report its pid")?;
+
+        let output = String::from_utf8_lossy(&exec(client, REMOTE_STATS_COMMAND, None).await?).to_string();
+        let mut lines = output.splitn(4, '\n');
+        let mut next = |name: &str| lines.next().ok_or_else(|| format!("The remote stats are missing the {}", name));
+        let uptime = stats::parse_uptime(next("uptime")?)?;
+        let clock_ticks = next("clock ticks")?.trim().parse::<u64>().map_err(|err| "Invalid remote clock ticks: ".to_string() + &err.to_string())?;
+        let page_size = next("page size")?.trim().parse::<u64>().map_err(|err| "Invalid remote page size: ".to_string() + &err.to_string())?;
+        let stat_lines = next("process stats")?;
+
+        let sample = stats::sample_tree(pid, stat_lines, uptime, ProcConstants { clock_ticks, page_size })?;
+        Ok(self.cpu_tracker.stats(sample))
+    }
+
+    async fn put_file(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        let path = transfer::model_file(&self.model_params, path)?;
+        let dir = path.parent().ok_or_else(|| format!("Invalid file path: {:#?}", path))?;
+        // The checksum of the written file is compared with the sent data
+        let command = format!("mkdir -p {} && cat > {path} && sha256sum {path}", quote(dir)?, path = quote(&path)?);
+
+        let client = self.client().await?;
+        let output = exec(&client, &command, Some(data)).await.map_err(|err| {
+            log::error!("Failed to write the remote file {:#?}: {}", path, err);
+            format!("Failed to write the remote file {:#?}: {}", path, err)
+        })?;
+        let checksum = String::from_utf8_lossy(&output).split_whitespace().next().unwrap_or_default().to_string();
+        transfer::verify_checksum(&path.to_string_lossy(), data, &checksum)
+    }
+
+    async fn get_file(&mut self, path: &str) -> Result<Vec<u8>, String> {
+        let path = transfer::model_file(&self.model_params, path)?;
+
+        let client = self.client().await?;
+        let read = |command: String| {
+            let client = client.clone();
+            let path = path.clone();
+            async move {
+                exec(&client, &command, None).await.map_err(|err| {
+                    log::error!("Failed to read the remote file {:#?}: {}", path, err);
+                    format!("Failed to read the remote file {:#?}: {}", path, err)
+                })
+            }
+        };
+        let data = read(format!("cat {}", quote(&path)?)).await?;
+        let output = read(format!("sha256sum {}", quote(&path)?)).await?;
+        let checksum = String::from_utf8_lossy(&output).split_whitespace().next().unwrap_or_default().to_string();
+        transfer::verify_checksum(&path.to_string_lossy(), &data, &checksum)?;
+        Ok(data)
+    }
+
+    async fn list_files(&mut self, dir: &str) -> Result<Vec<ModelFile>, String> {
+        let dir = transfer::model_file(&self.model_params, dir)?;
+        let command = format!("cd {} 2>/dev/null || exit 0; {}", quote(&dir)?, REMOTE_LIST_COMMAND);
+
+        let client = self.client().await?;
+        let output = exec(&client, &command, None).await.map_err(|err| {
+            log::error!("Failed to list the remote directory {:#?}: {}", dir, err);
+            format!("Failed to list the remote directory {:#?}: {}", dir, err)
+        })?;
+        transfer::parse_listing(&String::from_utf8_lossy(&output))
+    }
+
+    async fn terminate(&mut self) -> Result<(), String> {
+        // Signal the remote process and close the session, servers that ignore signals kill it on disconnect
+        if let Some(session) = self.session.take() {
+            let _ = session.signal("KILL");
+            let _ = session.close();
+        }
+        if let Some(client) = self.client.take() {
+            client.disconnect(makiko::DisconnectError::by_app()).map_err(|err| {
+                log::error!("Failed to close the SSH connection: {}", err);
+                "Failed to close the SSH connection: ".to_string() + &err.to_string()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn model_exit(&self) -> Option<watch::Receiver<Option<ModelExit>>> {
+        self.exited_rx.clone()
+    }
+}
+
+impl SSHDriver {
+    // Get the connection of the model, connecting if there is none
+    async fn client(&mut self) -> Result<makiko::Client, String> {
+        match &self.client {
+            Some(client) => Ok(client.clone()),
+            None => {
+                let client = self.connect().await?;
+                self.client = Some(client.clone());
+                Ok(client)
+            }
+        }
+    }
+
+    // Connect and authenticate to the host of the model
+    async fn connect(&self) -> Result<makiko::Client, String> {
         // Get the host
         let host = self.connection_params.get("host").ok_or_else(|| {
             log::error!("Failed to get the host");
@@ -60,160 +295,156 @@ impl MEALDriver for SSHDriver {
             log::error!("Failed to get the password");
             "Failed to get the password".to_string()
         })?;
-        // Get the model path
-        let model_path = self.model_params.get("modelPath").ok_or_else(|| {
-            log::error!("Failed to get the model path");
-            "Failed to get the model path".to_string()
-        })?;
-        // Get the model command
-        let model_command = self.model_params.get("inferenceCommand").ok_or_else(|| {
-            log::error!("Failed to get the model command");
-            "Failed to get the model command".to_string()
-        })?;
         // Log the connection parameters
         log::info!("Connection parameters:\n    - Host: {:#?}\n    - Port: {:#?}\n    - Username: {:#?}\n    - Password: {:#?}", host, port, username, password);
-        // Log the model parameters
-        log::info!("Model parameters:\n    - Model path: {:#?}\n    - Model command: {:#?}", model_path, model_command);
 
-        /*
+        // Parse the port and the optional pinned host key fingerprint
+        let port = port.parse::<u16>().map_err(|_| {
+            log::error!("Invalid port: {:#?}", port);
+            "Invalid port: ".to_string() + port
+        })?;
+        let host_key_fingerprint = self.connection_params.get("hostKeyFingerprint")
+            .filter(|fingerprint| !fingerprint.is_empty())
+            .cloned();
+
         // Open a TCP connection to the host
-        let socket = tokio::net::TcpStream::connect((host, port)).await
-            .expect("Could not open a TCP socket");
+        let socket = tokio::net::TcpStream::connect((host.as_str(), port)).await.map_err(|err| {
+            log::error!("Failed to connect to {}:{}: {}", host, port, err);
+            format!("Failed to connect to {}:{}: {}", host, port, err)
+        })?;
 
-        // Create the client config
-        let config = makiko::ClientConfig::default();
         // Open the client
-        let (client, mut client_rx, client_fut) = makiko::Client::open(socket, config).ok_or_else(|| {
-            log::error!("Failed to open the client");
-            "Failed to open the client".to_string()
+        let (client, mut client_rx, client_fut) = makiko::Client::open(socket, makiko::ClientConfig::default()).map_err(|err| {
+            log::error!("Failed to open the SSH client: {}", err);
+            "Failed to open the SSH client: ".to_string() + &err.to_string()
         })?;
 
-        
-        // Spawn a Tokio task that polls the client.
-        tokio::task::spawn(async move {
-            client_fut.await.ok_or_else(|| {
-                log::error!("Error while polling the client");
-                "Error while polling the client".to_string()
-            });
+        // Poll the client until the connection closes
+        tokio::spawn(async move {
+            if let Err(err) = client_fut.await {
+                log::error!("SSH connection closed with an error: {}", err);
+            }
         });
-        
-
-        // Spawn another Tokio task to handle the client events.
-        tokio::task::spawn(async move {
-            loop {
-                // Wait for the next event.
-                let event = client_rx.recv().await.ok_or_else(|| {
-                    log::error!("Error while receiving the next event");
-                    "Error while receiving the next event".to_string()
-                });
-
-                // Exit the loop when the client has closed.
-                let Some(event) = event else {
-                    break
-                };
 
-                match event {
-                    // Handle the server public key: for now, we just accept all keys, but this makes
-                    // us susceptible to man-in-the-middle attacks!
-                    makiko::ClientEvent::ServerPubkey(pubkey, accept) => {
-                        println!("Server pubkey type {}, fingerprint {}", pubkey.type_str(), pubkey.fingerprint());
-                        accept.accept();
-                    },
-
-                    // All other events can be safely ignored
-                    _ => {},
+        // Handle the client events, the server key is checked against the pinned fingerprint if there is one
+        let known_host = host.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(event)) = client_rx.recv().await {
+                if let makiko::ClientEvent::ServerPubkey(pubkey, accept) = event {
+                    let fingerprint = pubkey.fingerprint();
+                    match &host_key_fingerprint {
+                        Some(expected) if *expected == fingerprint => accept.accept(),
+                        // Dropping the acceptor rejects the key
+                        Some(expected) => log::error!(
+                            "Host key of {} has fingerprint {}, expected {}, rejecting it",
+                            known_host, fingerprint, expected
+                        ),
+                        None => {
+                            log::warn!("Accepting the unpinned host key of {} with fingerprint {}", known_host, fingerprint);
+                            accept.accept();
+                        }
+                    }
                 }
             }
         });
 
-
-        // Try to authenticate using a password.
-        let auth_res = client.auth_password(username.into(), password.into()).await.ok_or_else(|| {
-            log::error!("Error while authenticating");
-            "Error while authenticating".to_string()
-        });
-
-        // Deal with all possible outcomes of password authentication.
+        // Authenticate using the password
+        let auth_res = client.auth_password(username.clone(), password.clone()).await.map_err(|err| {
+            log::error!("Error while authenticating: {}", err);
+            "Error while authenticating: ".to_string() + &err.to_string()
+        })?;
         match auth_res {
-            makiko::AuthPasswordResult::Success => {
-                log::info!("Successfully authenticated");
-            },
+            makiko::AuthPasswordResult::Success => log::info!("Successfully authenticated to {}", host),
             makiko::AuthPasswordResult::ChangePassword(prompt) => {
-                log::error!("The server asked us to change our password: {}", prompt);
-                return Err("The server asked us to change our password {}".to_string() + prompt);
-            },
+                log::error!("The server asked us to change our password: {}", prompt.prompt);
+                return Err("The server asked us to change our password: ".to_string() + &prompt.prompt);
+            }
             makiko::AuthPasswordResult::Failure(failure) => {
-                log::error!("Authentication failed: {}", failure);
-                return Err("Authentication failed: {}".to_string() + failure);
-            },
+                log::error!("Authentication failed, the server accepts: {:?}", failure.methods_can_continue);
+                return Err(format!("Authentication failed, the server accepts: {:?}", failure.methods_can_continue));
+            }
         }
 
+        Ok(client)
+    }
+}
 
-        // Open a session on the server.
-        let channel_config = makiko::ChannelConfig::default();
-        let (session, mut session_rx) = client.open_session(channel_config).await.ok_or_else(|| {
-            log::error!("Failed to open a session");
-            "Failed to open a session".to_string()
-        })?;
-
-        // Handle session events asynchronously in a Tokio task.
-        let session_event_task = tokio::task::spawn(async move {
-            loop {
-                // Wait for the next event.
-                let event = session_rx.recv().await
-                    .expect("Error while receiving session event");
-
-                // Exit the loop when the session has closed.
-                let Some(event) = event else {
-                    break
-                };
-
-                match event {
-                    // Handle stdout/stderr output from the process.
-                    makiko::SessionEvent::StdoutData(data) => {
-                        println!("Process produced stdout: {:?}", data);
-                    },
-                    makiko::SessionEvent::StderrData(data) => {
-                        println!("Process produced stderr: {:?}", data);
-                    },
-
-                    // Handle exit of the process.
-                    makiko::SessionEvent::ExitStatus(status) => {
-                        println!("Process exited with status {}", status);
-                    },
-                    makiko::SessionEvent::ExitSignal(signal) => {
-                        println!("Process exited with signal {:?}: {:?}", signal.signal_name, signal.message);
-                    },
+// Quote a remote path for the shell
+fn quote(path: &std::path::Path) -> Result<String, String> {
+    shlex::try_quote(&path.to_string_lossy()).map(|quoted| quoted.to_string())
+        .map_err(|err| format!("Failed to quote {:#?}: {}", path, err))
+}
 
-                    // Ignore other events
-                    _ => {},
-                }
+// Run a command in a new session of the client, optionally with the data as its stdin,
+// and collect its stdout once it succeeded
+async fn exec(client: &makiko::Client, command: &str, input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let (session, mut session_rx) = client.open_session(makiko::ChannelConfig::default()).await
+        .map_err(|err| "Failed to open a session: ".to_string() + &err.to_string())?;
+    session.exec(command.as_bytes())
+        .map_err(|err| "Failed to execute a remote command: ".to_string() + &err.to_string())?
+        .wait().await
+        .map_err(|err| "The server refused to execute a remote command: ".to_string() + &err.to_string())?;
+    if let Some(data) = input {
+        session.send_stdin(data.to_vec().into()).await
+            .map_err(|err| "Failed to send the data to the remote command: ".to_string() + &err.to_string())?;
+    }
+    session.send_eof().await
+        .map_err(|err| "Failed to close the stdin of the remote command: ".to_string() + &err.to_string())?;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    loop {
+        match session_rx.recv().await {
+            Ok(Some(makiko::SessionEvent::StdoutData(data))) => stdout.extend_from_slice(&data),
+            Ok(Some(makiko::SessionEvent::StderrData(data))) => stderr.extend_from_slice(&data),
+            Ok(Some(makiko::SessionEvent::ExitStatus(0))) => break,
+            Ok(Some(makiko::SessionEvent::ExitStatus(status))) => {
+                return Err(format!("The remote command exited with status {}: {}", status, String::from_utf8_lossy(&stderr).trim()));
             }
-        });
-
-        // Execute a command on the session
-        session.exec("sed s/blue/green/".as_bytes())
-            .expect("Could not execute a command in the session")
-            .wait().await
-            .expect("Server returned an error when we tried to execute a command in the session");
-
-        // Send some data to the standard input of the process
-        session.send_stdin("blueberry jam\n".into()).await.unwrap();
-        session.send_stdin("blue jeans\nsky blue".into()).await.unwrap();
-        session.send_eof().await.unwrap();
-
-        // Wait for the task that handles session events
-        session_event_task.await.unwrap();
-
-        */
+            Ok(Some(_)) => (),
+            Ok(None) => return Err("The remote command did not report its exit status".to_string()),
+            Err(err) => return Err("Failed to receive a session event: ".to_string() + &err.to_string()),
+        }
+    }
+    // The output may still arrive after the exit status
+    loop {
+        match session_rx.recv().await {
+            Ok(Some(makiko::SessionEvent::StdoutData(data))) => stdout.extend_from_slice(&data),
+            Ok(Some(_)) => (),
+            Ok(None) => return Ok(stdout),
+            Err(err) => return Err("Failed to receive a session event: ".to_string() + &err.to_string()),
+        }
+    }
+}
 
-        let (stdin_tx, stdin_rx) = mpsc::channel::<String>(1);
-        let (stdout_tx, stdout_rx) = mpsc::channel::<String>(1);
-        let (stderr_tx, stderr_rx) = mpsc::channel::<String>(1);
+// Splits the data chunks of a session stream into lines
+#[derive(Default)]
+struct LineBuffer {
+    buffer: Vec<u8>,
+}
 
-        Ok((stdin_tx, stdout_rx, stderr_rx))
+impl LineBuffer {
+    // Append a chunk and return the lines it completed
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line[..newline]);
+            lines.push(line.strip_suffix('\r').unwrap_or(&line).to_string());
+        }
+        lines
     }
 
+    // Return the remaining unterminated line
+    fn finish(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.buffer).to_string();
+        self.buffer.clear();
+        Some(line)
+    }
 }
 
 // Implementation of debug for SSHDriver
@@ -224,6 +455,7 @@ impl fmt::Debug for SSHDriver {
             .field("static_fields", &self.static_fields)
             .field("model_params", &self.model_params)
             .field("connection_params", &self.connection_params)
+            .field("session_open", &self.session.is_some())
             .finish()
     }
 }
\ No newline at end of file
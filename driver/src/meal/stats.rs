@@ -0,0 +1,216 @@
+// src/meal/stats.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+// Resource usage of a model process and all of its descendants
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    // Number of processes in the tree, the model process included
+    pub processes: usize,
+    pub rss_bytes: u64,
+    // CPU usage since the previous sample (or since the start on the first one), 100% is one full core
+    pub cpu_percent: f64,
+    pub threads: u64,
+    pub uptime: Duration,
+}
+
+impl fmt::Display for ProcessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid {}, {} processes, RSS {:.1} MB, CPU {:.1}%, {} threads, up {}s",
+            self.pid, self.processes, self.rss_bytes as f64 / 1024.0 / 1024.0, self.cpu_percent, self.threads, self.uptime.as_secs()
+        )
+    }
+}
+
+// Kernel constants needed to interpret /proc/<pid>/stat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcConstants {
+    pub clock_ticks: u64,
+    pub page_size: u64,
+}
+
+impl ProcConstants {
+    // Get the constants of the local kernel
+    pub fn local() -> Self {
+        // SAFETY: sysconf has no memory safety requirements
+        let (clock_ticks, page_size) = unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+        Self {
+            clock_ticks: u64::try_from(clock_ticks).ok().filter(|ticks| *ticks > 0).unwrap_or(100),
+            page_size: u64::try_from(page_size).ok().filter(|size| *size > 0).unwrap_or(4096),
+        }
+    }
+}
+
+// Fields of a /proc/<pid>/stat line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ProcStat {
+    pid: u32,
+    ppid: u32,
+    cpu_ticks: u64,
+    threads: u64,
+    start_ticks: u64,
+    rss_pages: u64,
+}
+
+// Parse a /proc/<pid>/stat line, the command name is skipped since it may contain spaces and parentheses
+fn parse_stat(line: &str) -> Option<ProcStat> {
+    let (pid, rest) = line.split_once(" (")?;
+    let (_, rest) = rest.rsplit_once(") ")?;
+    // Field 3 (state) is the first one after the command name
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |number: usize| -> Option<u64> { fields.get(number - 3)?.parse().ok() };
+    Some(ProcStat {
+        pid: pid.trim().parse().ok()?,
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)?,
+        threads: field(20)?,
+        start_ticks: field(22)?,
+        rss_pages: field(24)?,
+    })
+}
+
+// Accumulated totals of a process tree at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreeSample {
+    pub pid: u32,
+    pub processes: usize,
+    pub rss_bytes: u64,
+    pub cpu_seconds: f64,
+    pub threads: u64,
+    pub uptime: Duration,
+}
+
+// Sum up the process with the pid and its descendants from the stat lines of all processes,
+// system_uptime is the first value of /proc/uptime
+pub fn sample_tree(pid: u32, stat_lines: &str, system_uptime: f64, constants: ProcConstants) -> Result<TreeSample, String> {
+    let stats: HashMap<u32, ProcStat> = stat_lines.lines()
+        .filter_map(parse_stat)
+        .map(|stat| (stat.pid, stat))
+        .collect();
+    let root = stats.get(&pid).ok_or_else(|| format!("Process {} is not running", pid))?;
+
+    // Walk the tree breadth first
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for stat in stats.values() {
+        children.entry(stat.ppid).or_default().push(stat.pid);
+    }
+    let mut tree = vec![pid];
+    let mut index = 0;
+    while index < tree.len() {
+        if let Some(descendants) = children.get(&tree[index]) {
+            tree.extend(descendants);
+        }
+        index += 1;
+    }
+
+    let mut sample = TreeSample {
+        pid,
+        processes: tree.len(),
+        rss_bytes: 0,
+        cpu_seconds: 0.0,
+        threads: 0,
+        uptime: Duration::from_secs_f64((system_uptime - root.start_ticks as f64 / constants.clock_ticks as f64).max(0.0)),
+    };
+    for stat in tree.iter().filter_map(|pid| stats.get(pid)) {
+        sample.rss_bytes += stat.rss_pages * constants.page_size;
+        sample.cpu_seconds += stat.cpu_ticks as f64 / constants.clock_ticks as f64;
+        sample.threads += stat.threads;
+    }
+    Ok(sample)
+}
+
+// Sample a local process tree from /proc
+pub fn sample_local_tree(pid: u32) -> Result<TreeSample, String> {
+    let uptime = std::fs::read_to_string("/proc/uptime").map_err(|err| "Failed to read /proc/uptime: ".to_string() + &err.to_string())?;
+    let uptime = parse_uptime(&uptime)?;
+
+    let mut stat_lines = String::new();
+    let entries = std::fs::read_dir("/proc").map_err(|err| "Failed to read /proc: ".to_string() + &err.to_string())?;
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        // Processes may exit while they are being read
+        if let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) {
+            stat_lines += &stat;
+        }
+    }
+    sample_tree(pid, &stat_lines, uptime, ProcConstants::local())
+}
+
+// Parse the first value of /proc/uptime
+pub fn parse_uptime(uptime: &str) -> Result<f64, String> {
+    uptime.split_whitespace().next()
+        .and_then(|uptime| uptime.parse::<f64>().ok())
+        .ok_or_else(|| format!("Invalid /proc/uptime: {:#?}", uptime))
+}
+
+
+// Turns consecutive samples of a process tree into CPU usage
+#[derive(Debug, Default)]
+pub struct CpuTracker {
+    previous: Mutex<Option<(u32, Instant, f64)>>,
+}
+
+impl CpuTracker {
+    pub fn stats(&self, sample: TreeSample) -> ProcessStats {
+        let now = Instant::now();
+        let mut previous = self.previous.lock().unwrap();
+        let cpu_percent = match *previous {
+            // Usage since the previous sample of the same process
+            Some((pid, at, cpu_seconds)) if pid == sample.pid && now > at => {
+                (sample.cpu_seconds - cpu_seconds).max(0.0) / (now - at).as_secs_f64() * 100.0
+            }
+            // Average usage since the process started
+            _ if !sample.uptime.is_zero() => sample.cpu_seconds / sample.uptime.as_secs_f64() * 100.0,
+            _ => 0.0,
+        };
+        *previous = Some((sample.pid, now, sample.cpu_seconds));
+
+        ProcessStats {
+            pid: sample.pid,
+            processes: sample.processes,
+            rss_bytes: sample.rss_bytes,
+            cpu_percent,
+            threads: sample.threads,
+            uptime: sample.uptime,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_stats() {
+        // Two processes of the model tree (one with a command name containing spaces and parentheses) and an unrelated one
+        let stat_lines = "\
+100 (python3) S 1 100 100 0 -1 4194560 0 0 0 0 300 100 0 0 20 0 4 0 1000 0 2000 0 0\n\
+101 (a (b) c) S 100 100 100 0 -1 4194560 0 0 0 0 50 50 0 0 20 0 2 0 1500 0 500 0 0\n\
+200 (bash) S 1 200 200 0 -1 4194560 0 0 0 0 999 999 0 0 20 0 1 0 10 0 100 0 0\n";
+        let constants = ProcConstants { clock_ticks: 100, page_size: 4096 };
+
+        let sample = sample_tree(100, stat_lines, 60.0, constants).unwrap();
+        assert_eq!(sample.processes, 2);
+        assert_eq!(sample.rss_bytes, 2500 * 4096);
+        assert_eq!(sample.cpu_seconds, 5.0);
+        assert_eq!(sample.threads, 6);
+        assert_eq!(sample.uptime, Duration::from_secs(50));
+
+        // The first sample reports the average usage since the start
+        let process_stats = CpuTracker::default().stats(sample);
+        assert_eq!(process_stats.cpu_percent, 10.0);
+
+        // Missing processes are reported
+        assert!(sample_tree(300, stat_lines, 60.0, constants).is_err());
+        assert_eq!(parse_uptime("12345.67 54321.00\n").unwrap(), 12345.67);
+    }
+}
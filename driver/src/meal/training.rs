@@ -0,0 +1,329 @@
+// src/meal/training.rs
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use super::{MEALArgs, MEALDriver, ModelExit};
+use super::logs::ModelLog;
+use super::transfer;
+use crate::dal::{JobState, TrainingJob};
+
+
+// Number of output lines kept per job for the REPL, the full output is in the model log
+const JOB_OUTPUT_LINES: usize = 100;
+// Number of finished jobs kept in memory, they stay in the DB
+const MAX_FINISHED_JOBS: usize = 100;
+// Interval at which the progress of a running job is stored
+const PROGRESS_RECORD_INTERVAL: Duration = Duration::from_secs(5);
+
+
+// What a training job runs, the train command gets --dataset <dataset> --newWeights <new_weights>
+// [--oldWeights <old_weights>] followed by the extra arguments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrainingSpec {
+    // File name of the dataset in the datasets folder of the model path
+    pub dataset: String,
+    // Data uploaded as the dataset before the run, None if the dataset already is on the model host
+    pub upload: Option<Vec<u8>>,
+    pub old_weights: Option<String>,
+    pub new_weights: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug)]
+struct JobEntry {
+    job: TrainingJob,
+    output: VecDeque<String>,
+    cancel_tx: watch::Sender<bool>,
+    state_rx: watch::Receiver<JobState>,
+}
+
+
+// Runs the train commands of the models, one job per model at a time and the models in parallel
+#[derive(Debug, Default)]
+pub struct TrainingJobs {
+    jobs: Mutex<HashMap<String, JobEntry>>,
+    // Jobs of a model wait for each other on its lock
+    model_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    // Receives the jobs on every state change
+    record_tx: Mutex<Option<mpsc::UnboundedSender<TrainingJob>>>,
+    counter: AtomicU64,
+}
+
+impl TrainingJobs {
+    // Send the jobs to the channel on every state change
+    pub fn record(&self, record_tx: mpsc::UnboundedSender<TrainingJob>) {
+        *self.record_tx.lock().unwrap() = Some(record_tx);
+    }
+
+    // Queue a job training a model with the driver of the given config and return its id,
+    // the output of the train command goes to the model log
+    pub fn submit(self: &Arc<Self>, meal_config: Vec<HashMap<String, String>>, model_log: Arc<ModelLog>, spec: TrainingSpec) -> Result<String, String> {
+        let model = meal_config.first().and_then(|static_fields| static_fields.get("name")).cloned().unwrap_or_default();
+        let model_uid = meal_config.first().and_then(|static_fields| static_fields.get("uid")).cloned().unwrap_or_default();
+        let driver_type = meal_config.first().and_then(|static_fields| static_fields.get("connType")).cloned().unwrap_or_default();
+        let driver = super::create_driver(&driver_type, MEALArgs { meal_config })?;
+        transfer::check_file_name("dataset", &spec.dataset)?;
+        transfer::check_file_name("weights", &spec.new_weights)?;
+
+        let id = format!(
+            "{}-train-{:x}{:04x}",
+            model, chrono::Utc::now().timestamp_micros(), self.counter.fetch_add(1, Ordering::Relaxed) & 0xffff
+        );
+        let job = TrainingJob {
+            id: id.clone(),
+            model_uid,
+            model: model.clone(),
+            dataset: spec.dataset.clone(),
+            old_weights: spec.old_weights.clone(),
+            new_weights: spec.new_weights.clone(),
+            args: spec.args.clone(),
+            state: JobState::Queued,
+            error: None,
+            progress: None,
+            created_at: chrono::Utc::now(),
+            started_at: None,
+            finished_at: None,
+        };
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        let (state_tx, state_rx) = watch::channel(JobState::Queued);
+        self.send_record(&job);
+        self.prune();
+        self.jobs.lock().unwrap().insert(id.clone(), JobEntry { job, output: VecDeque::new(), cancel_tx, state_rx });
+        log::info!("Queued the training job {} of model {}", id, model);
+
+        let model_lock = Arc::clone(self.model_locks.lock().unwrap().entry(model).or_default());
+        let jobs = Arc::clone(self);
+        let job_id = id.clone();
+        tokio::spawn(async move {
+            let (state, error) = jobs.run(&job_id, model_lock, driver, spec, &model_log, cancel_rx).await;
+            match &error {
+                Some(error) => log::error!("Training job {} {}: {}", job_id, state, error),
+                None => log::info!("Training job {} {}", job_id, state),
+            }
+            jobs.update(&job_id, |job| {
+                job.state = state;
+                job.error = error;
+                job.finished_at = Some(chrono::Utc::now());
+            });
+            let _ = state_tx.send(state);
+        });
+        Ok(id)
+    }
+
+    // Get a job
+    pub fn job(&self, id: &str) -> Option<TrainingJob> {
+        self.jobs.lock().unwrap().get(id).map(|entry| entry.job.clone())
+    }
+
+    // Get the jobs of a model or of all models, oldest first
+    pub fn jobs(&self, model: Option<&str>) -> Vec<TrainingJob> {
+        let mut jobs: Vec<TrainingJob> = self.jobs.lock().unwrap().values()
+            .filter(|entry| model.map(|model| entry.job.model == model).unwrap_or(true))
+            .map(|entry| entry.job.clone())
+            .collect();
+        jobs.sort_by_key(|job| job.created_at);
+        jobs
+    }
+
+    // Get the last lines the train command of a job printed
+    pub fn output(&self, id: &str, count: usize) -> Vec<String> {
+        match self.jobs.lock().unwrap().get(id) {
+            Some(entry) => entry.output.iter().skip(entry.output.len().saturating_sub(count)).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Cancel a queued or running job, running train commands are terminated
+    pub fn cancel(&self, id: &str) -> Result<(), String> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get(id).ok_or_else(|| format!("Training job {} not found", id))?;
+        if entry.job.state.is_finished() {
+            return Err(format!("Training job {} already {}", id, entry.job.state));
+        }
+        let _ = entry.cancel_tx.send(true);
+        Ok(())
+    }
+
+    // Wait until a job finished and return it
+    pub async fn wait(&self, id: &str) -> Result<TrainingJob, String> {
+        let mut state_rx = self.jobs.lock().unwrap().get(id)
+            .map(|entry| entry.state_rx.clone())
+            .ok_or_else(|| format!("Training job {} not found", id))?;
+        let _ = state_rx.wait_for(|state| state.is_finished()).await;
+        self.job(id).ok_or_else(|| format!("Training job {} not found", id))
+    }
+
+    // Cancel every unfinished job and wait up to the timeout for them to stop
+    pub async fn shutdown(&self, timeout: Duration) {
+        let unfinished: Vec<String> = self.jobs.lock().unwrap().values()
+            .filter(|entry| !entry.job.state.is_finished())
+            .map(|entry| entry.job.id.clone())
+            .collect();
+        for id in &unfinished {
+            log::warn!("Cancelling the training job {}, the driver is shutting down", id);
+            let _ = self.cancel(id);
+        }
+        let wait_all = async {
+            for id in &unfinished {
+                let _ = self.wait(id).await;
+            }
+        };
+        if tokio::time::timeout(timeout, wait_all).await.is_err() {
+            log::error!("The training jobs did not stop within {:?}", timeout);
+        }
+
+        // Close the record channel so the writer can store the last states and stop
+        self.record_tx.lock().unwrap().take();
+    }
+
+    // Wait for the model, upload the dataset and run the train command until it exits or the job is cancelled
+    async fn run(
+        &self,
+        id: &str,
+        model_lock: Arc<tokio::sync::Mutex<()>>,
+        mut driver: Box<dyn MEALDriver>,
+        spec: TrainingSpec,
+        model_log: &ModelLog,
+        mut cancel_rx: watch::Receiver<bool>,
+    ) -> (JobState, Option<String>) {
+        let _model_guard = tokio::select! {
+            guard = model_lock.lock_owned() => guard,
+            _ = cancel_rx.wait_for(|cancelled| *cancelled) => return (JobState::Cancelled, None),
+        };
+        self.update(id, |job| {
+            job.state = JobState::Running;
+            job.started_at = Some(chrono::Utc::now());
+        });
+
+        if let Some(data) = &spec.upload {
+            if let Err(err) = driver.put_file(&format!("{}/{}", transfer::DATASETS_DIR, spec.dataset), data).await {
+                model_log.write("driver", &format!("Training job {} failed to upload the dataset: {}", id, err));
+                return (JobState::Failed, Some(err));
+            }
+        }
+
+        let mut args = vec!["--dataset".to_string(), spec.dataset.clone(), "--newWeights".to_string(), spec.new_weights.clone()];
+        if let Some(old_weights) = &spec.old_weights {
+            args.extend(["--oldWeights".to_string(), old_weights.clone()]);
+        }
+        args.extend(spec.args.iter().cloned());
+        model_log.write("driver", &format!("Training job {} trains weights {} on {}", id, spec.new_weights, spec.dataset));
+        let (stdin_tx, mut stdout_rx, mut stderr_rx) = match driver.spawn_command("train", &args).await {
+            Ok(channels) => channels,
+            Err(err) => {
+                model_log.write("driver", &format!("Training job {} failed to start: {}", id, err));
+                return (JobState::Failed, Some(err));
+            }
+        };
+        // The train command reads nothing, closing its stdin right away
+        drop(stdin_tx);
+
+        // Forward the output until both pipes close or the job is cancelled
+        let (mut stdout_open, mut stderr_open) = (true, true);
+        let mut last_record = Instant::now();
+        while stdout_open || stderr_open {
+            let line = tokio::select! {
+                line = stdout_rx.recv(), if stdout_open => match line {
+                    Some(line) => line,
+                    None => {
+                        stdout_open = false;
+                        continue;
+                    }
+                },
+                line = stderr_rx.recv(), if stderr_open => match line {
+                    Some(line) => line,
+                    None => {
+                        stderr_open = false;
+                        continue;
+                    }
+                },
+                // The job is only ever changed to cancelled
+                _ = cancel_rx.changed() => {
+                    model_log.write("driver", &format!("Training job {} was cancelled", id));
+                    if let Err(err) = driver.terminate().await {
+                        log::error!("Failed to terminate the training job {}: {}", id, err);
+                    }
+                    return (JobState::Cancelled, None);
+                }
+            };
+            model_log.write("train", &line);
+            self.push_output(id, line, &mut last_record);
+        }
+
+        let model_exit = match driver.model_exit() {
+            Some(mut model_exit_rx) => model_exit_rx.wait_for(|exit| exit.is_some()).await.ok().and_then(|exit| exit.clone()),
+            None => None,
+        };
+        let _ = driver.terminate().await;
+        let result = match model_exit {
+            Some(ModelExit::Succeeded) => (JobState::Succeeded, None),
+            Some(ModelExit::Exited(status)) | Some(ModelExit::LimitExceeded(status)) => {
+                (JobState::Failed, Some(format!("The train command exited with {}", status)))
+            }
+            None => (JobState::Failed, Some("The train command ended without reporting its exit status".to_string())),
+        };
+        match &result {
+            (_, Some(error)) => model_log.write("driver", &format!("Training job {} failed: {}", id, error)),
+            _ => model_log.write("driver", &format!("Training job {} trained weights {}", id, spec.new_weights)),
+        }
+        result
+    }
+
+    // Keep an output line of a job as its progress, the progress is stored every few seconds
+    fn push_output(&self, id: &str, line: String, last_record: &mut Instant) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = match jobs.get_mut(id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.output.len() >= JOB_OUTPUT_LINES {
+            entry.output.pop_front();
+        }
+        entry.output.push_back(line.clone());
+        entry.job.progress = Some(line);
+        if last_record.elapsed() >= PROGRESS_RECORD_INTERVAL {
+            *last_record = Instant::now();
+            let job = entry.job.clone();
+            drop(jobs);
+            self.send_record(&job);
+        }
+    }
+
+    // Change a job and store it
+    fn update(&self, id: &str, change: impl FnOnce(&mut TrainingJob)) {
+        let job = match self.jobs.lock().unwrap().get_mut(id) {
+            Some(entry) => {
+                change(&mut entry.job);
+                entry.job.clone()
+            }
+            None => return,
+        };
+        self.send_record(&job);
+    }
+
+    fn send_record(&self, job: &TrainingJob) {
+        if let Some(record_tx) = self.record_tx.lock().unwrap().as_ref() {
+            if record_tx.send(job.clone()).is_err() {
+                log::warn!("Dropping the state of training job {}, the training job writer stopped", job.id);
+            }
+        }
+    }
+
+    // Forget the oldest finished jobs beyond the number kept in memory
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(chrono::DateTime<chrono::Utc>, String)> = jobs.values()
+            .filter(|entry| entry.job.state.is_finished())
+            .map(|entry| (entry.job.created_at, entry.job.id.clone()))
+            .collect();
+        if finished.len() < MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() + 1 - MAX_FINISHED_JOBS) {
+            jobs.remove(id);
+        }
+    }
+}
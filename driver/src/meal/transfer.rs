@@ -0,0 +1,151 @@
+// src/meal/transfer.rs
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use sha2::{Digest, Sha256};
+
+
+// Directory of the model path the training datasets are uploaded to
+pub const DATASETS_DIR: &str = "datasets";
+// Directory of the model path the train command stores the weights in
+pub const WEIGHTS_DIR: &str = "weights";
+
+
+// File below a directory of the model path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelFile {
+    // Path relative to the listed directory
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// Get the hex encoded SHA-256 checksum of the data
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Compare the checksum of the transferred data with the one computed on the other side
+pub fn verify_checksum(path: &str, data: &[u8], expected: &str) -> Result<(), String> {
+    let actual = sha256_hex(data);
+    if actual != expected.trim().to_lowercase() {
+        log::error!("Checksum mismatch of {:#?}: transferred {}, expected {}", path, actual, expected.trim());
+        return Err(format!("Checksum mismatch of {:#?}: transferred {}, expected {}", path, actual, expected.trim()));
+    }
+    Ok(())
+}
+
+// Check that a dataset or weights name is a single path component
+pub fn check_file_name(kind: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(format!("Invalid {} name: {:#?}", kind, name));
+    }
+    Ok(())
+}
+
+// Check that a path stays inside the model path
+pub fn check_relative_path(path: &str) -> Result<(), String> {
+    if path.is_empty() || !Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("The path {:#?} must be relative to the model path", path));
+    }
+    Ok(())
+}
+
+// Resolve a path relative to the modelPath model param
+pub fn model_file(model_params: &HashMap<String, String>, path: &str) -> Result<PathBuf, String> {
+    let model_path = model_params.get("modelPath").filter(|path| !path.is_empty()).ok_or_else(|| {
+        log::error!("Failed to get the model path");
+        "Failed to get the model path".to_string()
+    })?;
+    check_relative_path(path)?;
+    Ok(Path::new(model_path).join(path))
+}
+
+// Parse the "<size> <sha256> ./<path>" lines of a remote file listing
+pub fn parse_listing(output: &str) -> Result<Vec<ModelFile>, String> {
+    let mut files = Vec::new();
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let invalid = || format!("Invalid file listing line: {:#?}", line);
+        let (size, rest) = line.trim_start().split_once(' ').ok_or_else(invalid)?;
+        let (sha256, path) = rest.trim_start().split_once(' ').ok_or_else(invalid)?;
+        // The paths of find start with "./", so only the separator is trimmed
+        let path = path.trim_start();
+        files.push(ModelFile {
+            path: path.strip_prefix("./").unwrap_or(path).to_string(),
+            size: size.parse::<u64>().map_err(|_| invalid())?,
+            sha256: sha256.to_string(),
+        });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+// List the files below a local directory with their checksums, a missing directory has no files
+pub fn list_local(dir: &Path) -> Result<Vec<ModelFile>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(format!("Failed to list {:#?}: {}", current, err)),
+        };
+        for entry in entries {
+            let path = entry.map_err(|err| format!("Failed to list {:#?}: {}", current, err))?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let data = std::fs::read(&path).map_err(|err| format!("Failed to read {:#?}: {}", path, err))?;
+            files.push(ModelFile {
+                path: path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().to_string(),
+                size: data.len() as u64,
+                sha256: sha256_hex(&data),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meal::{create_driver, MEALArgs};
+
+    #[tokio::test]
+    async fn test_model_files() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        let mut driver = create_driver("local", MEALArgs { meal_config: vec![HashMap::new(), HashMap::new(), model_params] }).unwrap();
+
+        // Files are written and read relative to the model path, paths leaving it are rejected
+        assert!(driver.list_files("weights").await.unwrap().is_empty());
+        driver.put_file("datasets/train.txt", b"Hi\nHello\n\n").await.unwrap();
+        driver.put_file("weights/v1/model.bin", b"1234").await.unwrap();
+        driver.put_file("weights/v2.bin", b"").await.unwrap();
+        assert_eq!(driver.get_file("datasets/train.txt").await.unwrap(), b"Hi\nHello\n\n");
+        assert!(driver.put_file("../escape.txt", b"").await.is_err());
+        assert!(driver.get_file("/etc/hostname").await.is_err());
+        let files = driver.list_files("weights").await.unwrap();
+        assert_eq!(files, vec![
+            ModelFile { path: "v1/model.bin".to_string(), size: 4, sha256: sha256_hex(b"1234") },
+            ModelFile {
+                path: "v2.bin".to_string(),
+                size: 0,
+                sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+            },
+        ]);
+
+        // Remote listings parse to the same files and mismatching checksums are reported
+        let listing = format!("4 {}  ./v1/model.bin\n     0 {} ./v2.bin\n", files[0].sha256, files[1].sha256);
+        assert_eq!(parse_listing(&listing).unwrap(), files);
+        assert!(parse_listing("4 ./v1/model.bin").is_err());
+        assert!(verify_checksum("v1/model.bin", b"1234", &files[0].sha256.to_uppercase()).is_ok());
+        assert!(verify_checksum("v1/model.bin", b"4321", &files[0].sha256).is_err());
+        assert!(check_file_name("weights", "..").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
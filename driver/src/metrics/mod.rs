@@ -0,0 +1,303 @@
+// src/metrics/mod.rs
+// Std lib imports
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+// Registry shared by the layers of the driver and served in the Prometheus text format with hyper
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use once_cell::sync::Lazy;
+
+// Custom modules
+use crate::meal::pool::ModelPool;
+
+// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Bucket bounds in seconds, requests and queries take milliseconds to minutes and models take seconds to minutes to load
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const STARTUP_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+// Every metric the driver exports, the layers record them by name
+const DEFINITIONS: &[Definition] = &[
+    Definition { name: "mer_requests_total", kind: Kind::Counter, help: "Requests routed to a model", buckets: &[] },
+    Definition { name: "mer_request_errors_total", kind: Kind::Counter, help: "Requests to a model that failed, by error kind or model error code", buckets: &[] },
+    Definition { name: "mer_instance_requests_total", kind: Kind::Counter, help: "Requests sent to an instance", buckets: &[] },
+    Definition { name: "mer_inference_duration_seconds", kind: Kind::Histogram, help: "Time an instance took to answer a request", buckets: LATENCY_BUCKETS },
+    Definition { name: "mer_instance_queue_depth", kind: Kind::Gauge, help: "Requests waiting for a response of an instance", buckets: &[] },
+    Definition { name: "mer_instance_time_to_ready_seconds", kind: Kind::Histogram, help: "Time from spawning the model of an instance until it was ready", buckets: STARTUP_BUCKETS },
+    Definition { name: "mer_instance_restarts_total", kind: Kind::Counter, help: "Times the model of an instance was spawned again after its first start", buckets: &[] },
+    Definition { name: "mer_instance_state", kind: Kind::Gauge, help: "Lifecycle state of an instance, 1 for the current state", buckets: &[] },
+    Definition { name: "mer_dal_query_duration_seconds", kind: Kind::Histogram, help: "Time a DAL operation took", buckets: LATENCY_BUCKETS },
+    Definition { name: "mer_dal_query_errors_total", kind: Kind::Counter, help: "DAL operations that failed", buckets: &[] },
+    Definition { name: "mer_dal_connected", kind: Kind::Gauge, help: "Whether the DAL is connected to the database", buckets: &[] },
+];
+
+// The registry of the driver
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Debug)]
+struct Definition {
+    name: &'static str,
+    kind: Kind,
+    help: &'static str,
+    buckets: &'static [f64],
+}
+
+// Current value of a labelled metric
+#[derive(Debug, Clone, PartialEq)]
+enum Series {
+    Value(f64),
+    // Observations per bucket (not cumulative), their sum and count
+    Histogram { counts: Vec<u64>, sum: f64, count: u64 },
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+
+// Values of the metrics by name and labels
+#[derive(Debug, Default)]
+pub struct Registry {
+    metrics: Mutex<BTreeMap<&'static str, BTreeMap<Labels, Series>>>,
+}
+
+impl Registry {
+    // Update the series of a metric, unknown metrics are a bug of the caller and are ignored
+    fn update(&self, name: &'static str, labels: &[(&'static str, &str)], update: impl FnOnce(&Definition, &mut Series)) {
+        let definition = match DEFINITIONS.iter().find(|definition| definition.name == name) {
+            Some(definition) => definition,
+            None => {
+                log::error!("Unknown metric {}", name);
+                return;
+            }
+        };
+        let labels: Labels = labels.iter().map(|(key, value)| (*key, value.to_string())).collect();
+        let mut metrics = self.metrics.lock().unwrap();
+        let series = metrics.entry(name).or_default().entry(labels).or_insert_with(|| match definition.kind {
+            Kind::Histogram => Series::Histogram { counts: vec![0; definition.buckets.len()], sum: 0.0, count: 0 },
+            Kind::Counter | Kind::Gauge => Series::Value(0.0),
+        });
+        update(definition, series);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.update(name, labels, |_, series| {
+            if let Series::Value(current) = series {
+                *current += value;
+            }
+        });
+    }
+
+    pub fn set(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.update(name, labels, |_, series| {
+            if let Series::Value(current) = series {
+                *current = value;
+            }
+        });
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.update(name, labels, |definition, series| {
+            if let Series::Histogram { counts, sum, count } = series {
+                if let Some(bucket) = definition.buckets.iter().position(|bound| value <= *bound) {
+                    counts[bucket] += 1;
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    // Remove every series of a metric, e.g. of a gauge sampled on each scrape whose instances may be gone
+    pub fn clear(&self, name: &'static str) {
+        self.metrics.lock().unwrap().remove(name);
+    }
+
+    // Format the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut text = String::new();
+        for definition in DEFINITIONS {
+            let kind = match definition.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+                Kind::Histogram => "histogram",
+            };
+            let _ = writeln!(text, "# HELP {} {}", definition.name, definition.help);
+            let _ = writeln!(text, "# TYPE {} {}", definition.name, kind);
+            for (labels, series) in metrics.get(definition.name).into_iter().flatten() {
+                match series {
+                    Series::Value(value) => {
+                        let _ = writeln!(text, "{}{} {}", definition.name, format_labels(labels, None), value);
+                    }
+                    Series::Histogram { counts, sum, count } => {
+                        let mut cumulative = 0;
+                        for (bound, bucket_count) in definition.buckets.iter().zip(counts) {
+                            cumulative += bucket_count;
+                            let _ = writeln!(text, "{}_bucket{} {}", definition.name, format_labels(labels, Some(&bound.to_string())), cumulative);
+                        }
+                        let _ = writeln!(text, "{}_bucket{} {}", definition.name, format_labels(labels, Some("+Inf")), count);
+                        let _ = writeln!(text, "{}_sum{} {}", definition.name, format_labels(labels, None), sum);
+                        let _ = writeln!(text, "{}_count{} {}", definition.name, format_labels(labels, None), count);
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+// Format the labels of a series as {key="value",...}, histogram buckets get their le label last
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels.iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", pairs.join(",")),
+    }
+}
+
+
+//////////////////////////////////////////////////////
+//////////// Recording in the global registry ////////
+//////////////////////////////////////////////////////
+
+pub fn increment(name: &'static str, labels: &[(&'static str, &str)]) {
+    REGISTRY.add(name, labels, 1.0);
+}
+
+pub fn set(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    REGISTRY.set(name, labels, value);
+}
+
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    REGISTRY.observe(name, labels, value);
+}
+
+pub fn clear(name: &'static str) {
+    REGISTRY.clear(name);
+}
+
+// Sample the instances of the pool and format every metric of the driver
+pub fn scrape(model_pool: &ModelPool) -> String {
+    model_pool.record_metrics();
+    REGISTRY.render()
+}
+
+// Name the kind of an error of the model pool, the pool reports its errors as strings
+pub fn error_kind(message: &str) -> &'static str {
+    if message.starts_with("The driver is shutting down") {
+        "shutting_down"
+    } else if message.starts_with("Unauthorized") {
+        "unauthorized"
+    } else if message.starts_with("Forbidden") {
+        "forbidden"
+    } else if message.starts_with("Too many requests") {
+        "rate_limited"
+    } else if message.starts_with("Quota exceeded") {
+        "quota_exceeded"
+    } else if message.starts_with("Invalid request") {
+        "invalid_request"
+    } else if message.ends_with(" not found") {
+        "not_found"
+    } else {
+        "model_unavailable"
+    }
+}
+
+// Serve GET /metrics on the listener until the shutdown future completes, for the front ends without an HTTP API
+pub async fn serve(listener: std::net::TcpListener, model_pool: Arc<ModelPool>, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+    listener.set_nonblocking(true).map_err(|err| "Failed to configure the metrics listener: ".to_string() + &err.to_string())?;
+    let server = Server::from_tcp(listener).map_err(|err| {
+        log::error!("Failed to create the metrics server: {}", err);
+        "Failed to create the metrics server: ".to_string() + &err.to_string()
+    })?;
+    log::info!("Serving the metrics on {}", server.local_addr());
+
+    let make_service = make_service_fn(move |_connection| {
+        let model_pool = Arc::clone(&model_pool);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let model_pool = Arc::clone(&model_pool);
+                async move {
+                    let response = match (request.method(), request.uri().path()) {
+                        (&Method::GET, "/metrics") => response(&model_pool),
+                        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("Not found\n")).unwrap(),
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    server.serve(make_service).with_graceful_shutdown(shutdown).await.map_err(|err| {
+        log::error!("The metrics server failed: {}", err);
+        "The metrics server failed: ".to_string() + &err.to_string()
+    })
+}
+
+// Build the response of a scrape
+pub fn response(model_pool: &ModelPool) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", CONTENT_TYPE)
+        .body(Body::from(scrape(model_pool)))
+        .unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_render() {
+        let registry = Registry::default();
+        registry.add("mer_requests_total", &[("model", "echo")], 1.0);
+        registry.add("mer_requests_total", &[("model", "echo")], 1.0);
+        registry.set("mer_dal_connected", &[], 1.0);
+        registry.set("mer_instance_state", &[("model", "a\"b"), ("state", "ready")], 1.0);
+        registry.observe("mer_inference_duration_seconds", &[("model", "echo")], 0.02);
+        registry.observe("mer_inference_duration_seconds", &[("model", "echo")], 120.0);
+        // Values of the wrong kind and unknown metrics are ignored
+        registry.observe("mer_requests_total", &[("model", "echo")], 5.0);
+        registry.add("mer_unknown_total", &[], 1.0);
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE mer_requests_total counter\n"));
+        assert!(text.contains("mer_requests_total{model=\"echo\"} 2\n"));
+        assert!(text.contains("mer_dal_connected 1\n"));
+        assert!(text.contains("mer_instance_state{model=\"a\\\"b\",state=\"ready\"} 1\n"));
+        assert!(text.contains("mer_inference_duration_seconds_bucket{model=\"echo\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("mer_inference_duration_seconds_bucket{model=\"echo\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("mer_inference_duration_seconds_bucket{model=\"echo\",le=\"60\"} 1\n"));
+        assert!(text.contains("mer_inference_duration_seconds_bucket{model=\"echo\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("mer_inference_duration_seconds_count{model=\"echo\"} 2\n"));
+        assert!(!text.contains("mer_unknown_total"));
+
+        registry.clear("mer_instance_state");
+        assert!(!registry.render().contains("mer_instance_state{"));
+    }
+
+    #[test]
+    fn test_error_kind() {
+        assert_eq!(error_kind("The driver is shutting down"), "shutting_down");
+        assert_eq!(error_kind("Too many requests for model echo, retry after 3 seconds"), "rate_limited");
+        assert_eq!(error_kind("Session 7 not found"), "not_found");
+        assert_eq!(error_kind("Model echo exited before responding"), "model_unavailable");
+    }
+}
@@ -0,0 +1,269 @@
+// src/repl/batch.rs
+// Std lib imports
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Records are streamed from and to the files with tokio
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::task::JoinSet;
+
+// Custom modules
+use crate::meal::pool::ModelPool;
+use crate::meal::protocol::MEALRequest;
+use crate::meal::quota;
+
+// Number of records sent to the model at a time by default
+pub const DEFAULT_CONCURRENCY: usize = 4;
+// Answered records waiting for an earlier one before they are written, per concurrent record
+const REORDER_WINDOW: usize = 64;
+
+
+// Record of the input file, records without an id are named after their position in the file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchRecord {
+    #[serde(default)]
+    id: Option<String>,
+    input: String,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+// Outcome of a batch run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    // Records already in the output file of an interrupted run
+    pub skipped: usize,
+    pub answered: usize,
+    // Ids and errors of the records that failed
+    pub errors: Vec<(String, String)>,
+    // Set when the driver stopped before every record was answered
+    pub interrupted: bool,
+}
+
+
+// Send the records of the input file to the model, up to the concurrency at a time, and write the answers to the
+// output file in the order of the inputs. Errors of a record are written as its answer, a partial output file of an
+// interrupted run is continued after its last complete record
+pub async fn run_batch(model_pool: &Arc<ModelPool>, model: &str, input_path: &Path, output_path: &Path, concurrency: usize) -> Result<BatchReport, String> {
+    if model_pool.instances(model).is_none() {
+        return Err(format!("Model {} not found", model));
+    }
+    if concurrency == 0 {
+        return Err("The concurrency must be at least 1".to_string());
+    }
+    let input = tokio::fs::File::open(input_path).await.map_err(|err| format!("Failed to open {:#?}: {}", input_path, err))?;
+    let mut lines = BufReader::new(input).lines();
+    let done_ids = resume_output(output_path).await?;
+    let mut output = tokio::fs::OpenOptions::new().create(true).append(true).open(output_path).await
+        .map_err(|err| format!("Failed to open {:#?}: {}", output_path, err))?;
+
+    let mut report = BatchReport { skipped: done_ids.len(), ..Default::default() };
+    let mut running = JoinSet::new();
+    let mut answered: BTreeMap<usize, (String, Value)> = BTreeMap::new();
+    let (mut next_read, mut next_write) = (0, 0);
+    let mut input_done = false;
+    loop {
+        // Read records while the model has room and the earliest unanswered one is not too far behind
+        while !input_done && !report.interrupted && running.len() < concurrency && answered.len() < concurrency * REORDER_WINDOW {
+            let line = match lines.next_line().await.map_err(|err| format!("Failed to read {:#?}: {}", input_path, err))? {
+                Some(line) => line,
+                None => {
+                    input_done = true;
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let index = next_read;
+            next_read += 1;
+            let record = serde_json::from_str::<BatchRecord>(&line);
+            let id = record.as_ref().ok().and_then(|record| record.id.clone()).unwrap_or_else(|| (index + 1).to_string());
+
+            // Records of the previous run are checked against the input, so a different input file is not continued
+            if let Some(done_id) = done_ids.get(index) {
+                if *done_id != id {
+                    return Err(format!("The output file {:#?} does not belong to the input, record {} is {:#?} instead of {:#?}", output_path, index + 1, done_id, id));
+                }
+                next_write += 1;
+                continue;
+            }
+            match record {
+                Ok(record) => {
+                    running.spawn(infer_record(Arc::clone(model_pool), model.to_string(), index, id, record));
+                }
+                Err(err) => {
+                    answered.insert(index, (id.clone(), json!({ "id": id, "error": { "code": "invalid_record", "message": err.to_string() } })));
+                }
+            }
+        }
+
+        // Write the answers that are next in the order of the inputs
+        let mut written = false;
+        while let Some((id, answer)) = answered.remove(&next_write) {
+            if let Some(error) = answer.get("error") {
+                report.errors.push((id, format!("{}: {}", error["code"].as_str().unwrap_or_default(), error["message"].as_str().unwrap_or_default())));
+            }
+            output.write_all(format!("{}\n", answer).as_bytes()).await.map_err(|err| format!("Failed to write {:#?}: {}", output_path, err))?;
+            report.answered += 1;
+            next_write += 1;
+            written = true;
+        }
+        if written {
+            output.flush().await.map_err(|err| format!("Failed to write {:#?}: {}", output_path, err))?;
+        }
+
+        match running.join_next().await {
+            Some(Ok((index, id, Some(answer)))) => {
+                answered.insert(index, (id, answer));
+            }
+            // The driver is shutting down, the records after the last written one are sent again on the next run
+            Some(Ok((_, _, None))) => report.interrupted = true,
+            Some(Err(err)) => return Err(format!("A record of the batch failed: {}", err)),
+            None if input_done || report.interrupted => break,
+            None => {}
+        }
+    }
+    if next_write < next_read && !report.interrupted {
+        report.interrupted = true;
+    }
+    Ok(report)
+}
+
+// Send a record to the model until it is answered, rate limited and over quota records wait and are sent again,
+// None if the driver is shutting down
+async fn infer_record(model_pool: Arc<ModelPool>, model: String, index: usize, id: String, record: BatchRecord) -> (usize, String, Option<Value>) {
+    loop {
+        let mut request = MEALRequest::new(&record.input);
+        request.params = record.params.clone();
+        let answer = match model_pool.infer(&model, request, None).await {
+            Ok(response) => match response.error {
+                Some(error) => json!({ "id": id, "error": { "code": error.code, "message": error.message } }),
+                None => json!({ "id": id, "output": response.output }),
+            },
+            Err(err) if err.starts_with("The driver is shutting down") => return (index, id, None),
+            Err(err) => match quota::retry_after(&err) {
+                Some(seconds) => {
+                    tokio::time::sleep(Duration::from_secs(seconds)).await;
+                    continue;
+                }
+                None => json!({ "id": id, "error": { "code": "driver_error", "message": err } }),
+            },
+        };
+        return (index, id, Some(answer));
+    }
+}
+
+// Get the ids of the complete records of an existing output file, an incomplete last record is cut off
+async fn resume_output(output_path: &Path) -> Result<Vec<String>, String> {
+    let data = match tokio::fs::read(output_path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("Failed to read {:#?}: {}", output_path, err)),
+    };
+    let mut ids = Vec::new();
+    let mut complete = 0;
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        let record = match line.ends_with(b"\n") {
+            true => serde_json::from_slice::<Value>(line).ok(),
+            false => None,
+        };
+        match record.as_ref().and_then(|record| record["id"].as_str()) {
+            Some(id) => ids.push(id.to_string()),
+            None => break,
+        }
+        complete += line.len();
+    }
+    if complete < data.len() {
+        log::warn!("Cutting off the incomplete last record of {:#?}", output_path);
+        let file = tokio::fs::OpenOptions::new().write(true).open(output_path).await
+            .map_err(|err| format!("Failed to open {:#?}: {}", output_path, err))?;
+        file.set_len(complete as u64).await.map_err(|err| format!("Failed to truncate {:#?}: {}", output_path, err))?;
+    }
+    Ok(ids)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meal::{MEAL, MEALArgs};
+
+    // Echoes the inputs with the jsonl protocol, the input "fail" is answered with a model error and the input "slow"
+    // after a second, so the answers arrive out of order
+    const ECHO_MODEL: &str = r#"
+import json, sys, threading, time
+print('@!#READY#!@ {"batching": true}', flush=True)
+lock = threading.Lock()
+def answer(request):
+    if request["input"] == "slow":
+        time.sleep(1)
+    if request["input"] == "fail":
+        response = {"id": request["id"], "error": {"code": "bad_input", "message": "Failed on purpose"}}
+    else:
+        response = {"id": request["id"], "output": "echo: " + request["input"]}
+    with lock:
+        print(json.dumps(response), flush=True)
+for line in sys.stdin:
+    if line.strip() == "@!#EXIT#!@":
+        break
+    threading.Thread(target=answer, args=(json.loads(line),)).start()
+"#;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_batch() {
+        let dir = std::env::temp_dir().join(format!("mer-driver-test-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut static_fields: HashMap<String, String> = HashMap::new();
+        static_fields.insert("name".to_string(), "echo".to_string());
+        static_fields.insert("connType".to_string(), "local".to_string());
+        let mut model_params: HashMap<String, String> = HashMap::new();
+        model_params.insert("modelPath".to_string(), dir.to_string_lossy().to_string());
+        model_params.insert("protocol".to_string(), "jsonl".to_string());
+        model_params.insert("inferenceArgv".to_string(), json!(["python3", "-c", ECHO_MODEL]).to_string());
+        let meal = MEAL::create("local", MEALArgs { meal_config: vec![static_fields, HashMap::new(), model_params] }).unwrap();
+        let model_pool = Arc::new(ModelPool::new());
+        model_pool.insert("echo", meal);
+
+        // The output of an interrupted run holds the first record and half of the second one
+        let input = dir.join("input.jsonl");
+        let output = dir.join("output.jsonl");
+        std::fs::write(&input, concat!(
+            "{\"id\": \"a\", \"input\": \"first\"}\n",
+            "{\"id\": \"b\", \"input\": \"slow\"}\n",
+            "{\"input\": \"third\"}\n",
+            "\n",
+            "{\"id\": \"d\", \"input\": \"fail\"}\n",
+            "not json\n",
+            "{\"id\": \"f\", \"input\": \"last\"}\n",
+        )).unwrap();
+        std::fs::write(&output, "{\"id\":\"a\",\"output\":\"echo: first\"}\n{\"id\":\"b\",\"outp").unwrap();
+
+        let report = run_batch(&model_pool, "echo", &input, &output, 3).await.unwrap();
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.answered, 5);
+        assert!(!report.interrupted);
+        assert_eq!(report.errors.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["d", "5"]);
+
+        let records = std::fs::read_to_string(&output).unwrap().lines().map(|line| serde_json::from_str::<Value>(line).unwrap()).collect::<Vec<_>>();
+        assert_eq!(records.iter().map(|record| record["id"].as_str().unwrap()).collect::<Vec<_>>(), vec!["a", "b", "3", "d", "5", "f"]);
+        assert_eq!(records[1]["output"], "echo: slow");
+        assert_eq!(records[3]["error"]["code"], "bad_input");
+        assert_eq!(records[4]["error"]["code"], "invalid_record");
+
+        // A finished output is not sent again, and an output of another input is not continued
+        let report = run_batch(&model_pool, "echo", &input, &output, 3).await.unwrap();
+        assert_eq!((report.skipped, report.answered), (6, 0));
+        std::fs::write(&input, "{\"id\": \"x\", \"input\": \"other\"}\n").unwrap();
+        assert!(run_batch(&model_pool, "echo", &input, &output, 3).await.is_err());
+        assert!(run_batch(&model_pool, "missing", &input, &output, 3).await.unwrap_err().starts_with("Model missing not found"));
+
+        model_pool.shutdown(Duration::from_secs(1), Duration::from_secs(1)).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
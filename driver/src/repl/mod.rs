@@ -1,9 +1,27 @@
 // /src/repl/mod.rs
 // Std lib imports
 use std::io::Write;
+use std::sync::Arc;
 
 // CLI arg parsing with clap
-use clap::{Command, Arg};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+
+// Batch files of model inputs
+mod batch;
+
+// Custom modules
+use crate::auth::KeyStore;
+use crate::dal::{DAL, ModelEntry, TranscriptQuery, UsageLimits};
+use crate::jobs::{self, InferenceJobs};
+use crate::meal::feedback::Rating;
+use crate::meal::pool::ModelPool;
+use crate::meal::protocol::MEALRequest;
+use crate::meal::training::TrainingSpec;
+
+// Number of buffered log lines printed by default
+const DEFAULT_LOG_LINES: usize = 50;
+// Number of failed records listed after a batch run
+const MAX_BATCH_ERRORS: usize = 10;
 
 
 // Parse REPL command args using the clap crate with the Builder API
@@ -13,22 +31,31 @@ pub struct CliReplManager {
     stderr: std::io::Stderr,
     line: String,
     allow_model_server_runtime_changes: bool,
+    model_pool: Arc<ModelPool>,
+    dal: Arc<tokio::sync::Mutex<DAL>>,
+    keys: Arc<KeyStore>,
+    jobs: Arc<InferenceJobs>,
 }
 
 impl CliReplManager {
     // Creates a new CliReplManager
-    pub fn new(stdin: std::io::Stdin, stdout: std::io::Stdout, stderr: std::io::Stderr, allow_model_server_runtime_changes: bool) -> Result<Self, std::io::Error> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(stdin: std::io::Stdin, stdout: std::io::Stdout, stderr: std::io::Stderr, allow_model_server_runtime_changes: bool, model_pool: Arc<ModelPool>, dal: Arc<tokio::sync::Mutex<DAL>>, keys: Arc<KeyStore>, jobs: Arc<InferenceJobs>) -> Result<Self, std::io::Error> {
         Ok(Self {
             stdin,
             stdout,
             stderr,
             line: String::new(),
             allow_model_server_runtime_changes,
+            model_pool,
+            dal,
+            keys,
+            jobs,
         })
     }
 
     // Starts the REPL
-    pub fn repl(&mut self) -> Result<(), String> {
+    pub async fn repl(&mut self) -> Result<(), String> {
         loop {
             // Read a line from stdin and trim it
             self.line = self.read_line()?;
@@ -40,7 +67,7 @@ impl CliReplManager {
             }
     
             // Match the line against the commands
-            match self.respond() {
+            match self.respond().await {
                 // If the command is quit, break the loop
                 Ok(quit) => {
                     if quit {
@@ -50,8 +77,8 @@ impl CliReplManager {
 
                 // If the command is not quit, print the output
                 Err(err) => {
-                    write!(std::io::stdout(), "{err}").map_err(|e| e.to_string())?;
-                    std::io::stdout().flush().map_err(|e| e.to_string())?;
+                    write!(self.stderr, "{err}").map_err(|e| e.to_string())?;
+                    self.stderr.flush().map_err(|e| e.to_string())?;
                 }
             }
         }
@@ -59,15 +86,22 @@ impl CliReplManager {
         Ok(())
     }
 
+    // Runs a single command given on the command line instead of the REPL
+    pub async fn run_command(&mut self, args: &[String]) -> Result<(), String> {
+        self.line = shlex::try_join(args.iter().map(|arg| arg.as_str())).map_err(|err| err.to_string())?;
+        self.respond().await?;
+        Ok(())
+    }
+
     // Reads a line from stdin
     fn read_line(&mut self) -> Result<String, String> {
         // Write the prompt
         write!(self.stdout, "Model-Executor-Runtime-CLI $ ").map_err(|e| e.to_string())?;
         self.stdout.flush().map_err(|e| e.to_string())?;
     
-        // Read the input
+        // Read the input without stalling the other tasks of the runtime
         let mut buffer = String::new();
-        self.stdin.read_line(&mut buffer).map_err(|e| e.to_string())?;
+        tokio::task::block_in_place(|| self.stdin.read_line(&mut buffer)).map_err(|e| e.to_string())?;
     
         Ok(buffer)
     }
@@ -148,6 +182,46 @@ impl CliReplManager {
                     )
                     .help_template(APPLET_TEMPLATE),
             )
+            .subcommand(
+                // MER-Driver model resource usage
+                Command::new("model-stats")
+                    .alias("stats")
+                    .about("Show the CPU and memory usage of the model processes")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver model logs
+                Command::new("model-logs")
+                    .alias("logs")
+                    .about("Show the recent output of the model processes")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("lines")
+                            .help("The number of buffered lines to show per instance")
+                            .short('n')
+                            .long("lines")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .arg(
+                        Arg::new("follow")
+                            .help("Keep printing new lines until Enter is pressed")
+                            .short('f')
+                            .long("follow")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
             .subcommand(
                 // MER-Driver execute model
                 Command::new("model-execute")
@@ -166,6 +240,125 @@ impl CliReplManager {
                             .required(true)
                             .index(2),
                     )
+                    .arg(
+                        Arg::new("session")
+                            .help("The session the input continues")
+                            .short('s')
+                            .long("session"),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver execute the records of a JSONL file
+                Command::new("model-batch")
+                    .alias("batch")
+                    .about("Execute the model on the records of a JSONL file and write the answers to a JSONL file")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the MER-model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("input")
+                            .help("The JSONL file of {\"id\", \"input\", \"params\"} records")
+                            .required(true)
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::new("output")
+                            .help("The JSONL file the answers are written to, a partial file of an interrupted run is continued")
+                            .required(true)
+                            .index(3),
+                    )
+                    .arg(
+                        Arg::new("concurrency")
+                            .help("The number of records sent to the model at a time")
+                            .short('c')
+                            .long("concurrency")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver open a conversation session
+                Command::new("session-create")
+                    .alias("create-session")
+                    .about("Open a conversation session with a model")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver clear a conversation session
+                Command::new("session-reset")
+                    .alias("reset-session")
+                    .about("Clear the conversation history of a session")
+                    .arg(
+                        Arg::new("session")
+                            .help("The id of the session")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver close a conversation session
+                Command::new("session-close")
+                    .alias("close-session")
+                    .about("Close a conversation session")
+                    .arg(
+                        Arg::new("session")
+                            .help("The id of the session")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // MER-Driver export conversation transcripts
+                Command::new("transcript-export")
+                    .alias("export-transcript")
+                    .about("Export the stored conversation transcripts as JSON lines")
+                    .arg(
+                        Arg::new("model")
+                            .help("Only export the turns of the model")
+                            .short('m')
+                            .long("model"),
+                    )
+                    .arg(
+                        Arg::new("session")
+                            .help("Only export the turns of the session")
+                            .short('s')
+                            .long("session"),
+                    )
+                    .arg(
+                        Arg::new("from")
+                            .help("Only export the turns requested at or after the RFC 3339 time")
+                            .long("from"),
+                    )
+                    .arg(
+                        Arg::new("to")
+                            .help("Only export the turns requested before the RFC 3339 time")
+                            .long("to"),
+                    )
+                    .arg(
+                        Arg::new("limit")
+                            .help("The maximum number of turns to export")
+                            .short('n')
+                            .long("limit")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .arg(
+                        Arg::new("output")
+                            .help("The file to write the turns to instead of stdout")
+                            .short('o')
+                            .long("output"),
+                    )
                     .help_template(APPLET_TEMPLATE),
             )
             .subcommand(
@@ -182,12 +375,304 @@ impl CliReplManager {
                     )
                     .arg(
                         Arg::new("state")
-                            .help("Toggle continuous feedback learning for a model")
+                            .help("Turn continuous feedback learning on or off, or show its status")
+                            .required(true)
+                            .value_parser(["on", "off", "status"])
+                            .index(2),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver rate an answer of a model for feedback learning
+                Command::new("model-feedback-rate")
+                    .alias("feedback-rate")
+                    .alias("rate")
+                    .about("Rate an answer of a model, well rated answers are learned from")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("rating")
+                            .help("The rating from 1 (bad) to 5 (good)")
+                            .required(true)
+                            .value_parser(clap::value_parser!(u8).range(1..=5))
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::new("request")
+                            .help("The id of the rated request, the last answered one by default")
+                            .short('r')
+                            .long("request"),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver upload a dataset to a model
+                Command::new("dataset-upload")
+                    .alias("upload-dataset")
+                    .about("Upload a local file to the datasets directory of a model")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("file")
+                            .help("The local dataset file")
+                            .required(true)
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::new("dataset")
+                            .help("The name of the uploaded dataset, the file name by default")
+                            .short('d')
+                            .long("dataset"),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver list the weights of a model
+                Command::new("weights-list")
+                    .alias("list-weights")
+                    .about("List the files in the weights directory of a model with their checksums")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver download the weights of a model
+                Command::new("weights-download")
+                    .alias("download-weights")
+                    .about("Download a weights file or directory of a model")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("weights")
+                            .help("The weights version in the weights directory of the model")
+                            .required(true)
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::new("output")
+                            .help("The local directory to download the weights to, the working directory by default")
+                            .short('o')
+                            .long("output"),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver start a training job
+                Command::new("train-start")
+                    .alias("start-train")
+                    .about("Start a training job running the train command of a model")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the model")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("dataset")
+                            .help("The dataset in the datasets directory of the model, or the local file to upload there with --upload")
+                            .required(true)
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::new("upload")
+                            .help("Upload the local dataset file to the model before training")
+                            .short('u')
+                            .long("upload")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(
+                        Arg::new("old-weights")
+                            .help("The weights to start from, the served ones by default")
+                            .long("old-weights"),
+                    )
+                    .arg(
+                        Arg::new("new-weights")
+                            .help("The weights to produce, train-<timestamp> by default")
+                            .long("new-weights"),
+                    )
+                    .arg(
+                        Arg::new("args")
+                            .help("Extra arguments passed to the train command after --")
+                            .num_args(0..)
+                            .last(true),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver show the training jobs
+                Command::new("train-status")
+                    .alias("status-train")
+                    .about("List the training jobs or show the state and output of one")
+                    .arg(
+                        Arg::new("job")
+                            .help("The id of the training job")
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("model")
+                            .help("Only list the jobs of the model")
+                            .short('m')
+                            .long("model"),
+                    )
+                    .arg(
+                        Arg::new("lines")
+                            .help("The number of output lines of the job to show")
+                            .short('n')
+                            .long("lines")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver cancel a training job
+                Command::new("train-cancel")
+                    .alias("cancel-train")
+                    .about("Cancel a queued or running training job")
+                    .arg(
+                        Arg::new("job")
+                            .help("The id of the training job")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver show the inference jobs
+                Command::new("job-status")
+                    .alias("status-job")
+                    .about("List the inference jobs or show the state and results of one")
+                    .arg(
+                        Arg::new("job")
+                            .help("The id of the inference job")
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("results")
+                            .help("Show the results of the job")
+                            .short('r')
+                            .long("results")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver cancel an inference job
+                Command::new("job-cancel")
+                    .alias("cancel-job")
+                    .about("Cancel a queued or running inference job")
+                    .arg(
+                        Arg::new("job")
+                            .help("The id of the inference job")
+                            .required(true)
+                            .index(1),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver create an API key of the network APIs
+                Command::new("apikey-create")
+                    .alias("create-apikey")
+                    .about("Create an API key of the network APIs, the key is only shown once")
+                    .arg(
+                        Arg::new("name")
+                            .help("The name of the key owner")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::new("model")
+                            .help("A model the key may execute, every model by default")
+                            .short('m')
+                            .long("model")
+                            .action(ArgAction::Append),
+                    )
+                    .arg(
+                        Arg::new("admin")
+                            .help("Allow the key to create, modify and delete model entries")
+                            .long("admin")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(
+                        Arg::new("rate-limit")
+                            .help("Requests per minute the key may send")
+                            .long("rate-limit")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .arg(
+                        Arg::new("burst")
+                            .help("Requests the key may send at once, the rate limit by default")
+                            .long("burst")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .arg(
+                        Arg::new("daily-requests")
+                            .help("Requests the key may send per UTC day")
+                            .long("daily-requests")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .arg(
+                        Arg::new("monthly-requests")
+                            .help("Requests the key may send per UTC month")
+                            .long("monthly-requests")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .arg(
+                        Arg::new("daily-bytes")
+                            .help("Input and output bytes the key may use per UTC day")
+                            .long("daily-bytes")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .arg(
+                        Arg::new("monthly-bytes")
+                            .help("Input and output bytes the key may use per UTC month")
+                            .long("monthly-bytes")
+                            .value_parser(clap::value_parser!(u64)),
+                    )
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver list the API keys
+                Command::new("apikey-list")
+                    .alias("list-apikeys")
+                    .about("List the API keys of the network APIs")
+                    .help_template(APPLET_TEMPLATE),
+            )
+            .subcommand(
+                // Driver delete an API key
+                Command::new("apikey-delete")
+                    .alias("delete-apikey")
+                    .about("Delete an API key, requests with it are rejected right away")
+                    .arg(
+                        Arg::new("key-id")
+                            .help("The id of the API key")
                             .required(true)
                             .index(1),
                     )
                     .help_template(APPLET_TEMPLATE),
             )
+            .subcommand(
+                // Driver show the usage of the limited keys and models
+                Command::new("usage-status")
+                    .alias("status-usage")
+                    .about("Show the requests and bytes the API keys and models with limits used today and this month")
+                    .help_template(APPLET_TEMPLATE),
+            )
             .subcommand(
                 Command::new("exit")
                     .alias("quit")
@@ -202,7 +687,37 @@ impl CliReplManager {
                     // Modify model entries
                     Command::new("model-modify")
                         .alias("modify")
-                        .about("Modify an existing model entry")
+                        .about("Modify an existing model entry, params set to an empty value are removed")
+                        .arg(
+                            Arg::new("uid")
+                                .help("The uid of the model entry")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("name")
+                                .help("The new name of the model")
+                                .long("name"),
+                        )
+                        .arg(
+                            Arg::new("conn-type")
+                                .help("The new connection type of the model (local or ssh)")
+                                .long("conn-type"),
+                        )
+                        .arg(
+                            Arg::new("conn-param")
+                                .help("A connection param as KEY=VALUE")
+                                .long("conn-param")
+                                .action(ArgAction::Append)
+                                .value_parser(parse_param),
+                        )
+                        .arg(
+                            Arg::new("model-param")
+                                .help("A model param as KEY=VALUE")
+                                .long("model-param")
+                                .action(ArgAction::Append)
+                                .value_parser(parse_param),
+                        )
                         .help_template(APPLET_TEMPLATE)
                 );
                 
@@ -210,6 +725,32 @@ impl CliReplManager {
                     Command::new("model-create")
                         .alias("create")
                         .about("Create new model entry")
+                        .arg(
+                            Arg::new("name")
+                                .help("The name of the model")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("conn-type")
+                                .help("The connection type of the model (local or ssh)")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("conn-param")
+                                .help("A connection param as KEY=VALUE")
+                                .long("conn-param")
+                                .action(ArgAction::Append)
+                                .value_parser(parse_param),
+                        )
+                        .arg(
+                            Arg::new("model-param")
+                                .help("A model param as KEY=VALUE")
+                                .long("model-param")
+                                .action(ArgAction::Append)
+                                .value_parser(parse_param),
+                        )
                         .help_template(APPLET_TEMPLATE),
                 );
 
@@ -217,20 +758,26 @@ impl CliReplManager {
                     Command::new("model-delete")
                         .alias("delete")
                         .about("Delete an existing model entry")
+                        .arg(
+                            Arg::new("uid")
+                                .help("The uid of the model entry")
+                                .required(true)
+                                .index(1),
+                        )
                         .help_template(APPLET_TEMPLATE)
                 );
             }
 
-        return app;
+        app
     }
 
     // Responds to the CLI command
-    fn respond(&mut self) -> Result<bool, String> {
+    async fn respond(&mut self) -> Result<bool, String> {
         // Split the line into arguments
         let args = shlex::split(&self.line).ok_or("Error: Invalid quoting")?;
     
         // Parse the arguments
-        let matches = CliReplManager::command_parser(&self)
+        let matches = CliReplManager::command_parser(self)
             .try_get_matches_from(args)
             .map_err(|e| e.to_string())?;
 
@@ -238,52 +785,922 @@ impl CliReplManager {
         // Match the subcommand
         match matches.subcommand() {
             Some(("version", _matches)) => {
-                write!(self.stdout, "MER-Driver version: 0.1.0\n").map_err(|e| e.to_string())?;
+                writeln!(self.stdout, "MER-Driver version: 0.1.0").map_err(|e| e.to_string())?;
                 self.stdout.flush().map_err(|e| e.to_string())?;
             }
 
-            Some(("model-ping", _matches)) => {
+            Some(("model-info", _matches)) => {
                 if let Some(name) = _matches.get_one::<String>("name") {
-                    write!(self.stdout, "Checking if model {} is available...\n", name).map_err(|e| e.to_string())?;
+                    let info = self.model_info(name).await;
+                    write!(self.stdout, "{}", info).map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 } else {
-                    write!(self.stdout, "Error: Name argument is missing\n").map_err(|e| e.to_string())?;
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 }
             }
 
-            Some(("model-execute", _matches)) => {
-                if let (Some(name), Some(input)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("input")) {
-                    write!(self.stdout, "Executing model {} with input {}...\n", name, input).map_err(|e| e.to_string())?;
+            Some(("model-ping", _matches)) => {
+                if let Some(name) = _matches.get_one::<String>("name") {
+                    let report = self.model_ping(name).await;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 } else {
-                    write!(self.stdout, "Error: Name or input argument is missing\n").map_err(|e| e.to_string())?;
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 }
             }
 
-            Some(("model-toggle-feedback", _matches)) => {
+            Some(("model-stats", _matches)) => {
                 if let Some(name) = _matches.get_one::<String>("name") {
-                    write!(self.stdout, "Toggling feedback learning for model {}...\n", name).map_err(|e| e.to_string())?;
+                    let report = self.model_stats(name).await;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 } else {
-                    write!(self.stdout, "Error: Name argument is missing\n").map_err(|e| e.to_string())?;
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
                     self.stdout.flush().map_err(|e| e.to_string())?;
                 }
             }
 
-            Some(("exit", _matches)) => {
-                write!(self.stdout, "Exiting Model-Executor Runtime-CLI ...\n").map_err(|e| e.to_string())?;
-                self.stdout.flush().map_err(|e| e.to_string())?;
-                // Return true
-                return Ok(true);
+            Some(("model-logs", _matches)) => {
+                if let Some(name) = _matches.get_one::<String>("name") {
+                    let lines = _matches.get_one::<usize>("lines").copied().unwrap_or(DEFAULT_LOG_LINES);
+                    let follow = _matches.get_flag("follow");
+                    self.model_logs(name, lines, follow).await?;
+                } else {
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
             }
 
-            Some((name, _matches)) => unimplemented!("{name}\n"),
-            None => unreachable!("Error: Subcommand required\n"),
-        }
-
-        Ok(false)
-    }
+            Some(("model-execute", _matches)) => {
+                if let (Some(name), Some(input)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("input")) {
+                    let mut request = MEALRequest::new(input);
+                    request.session = _matches.get_one::<String>("session").cloned();
+                    match self.model_pool.infer(name, request, None).await {
+                        Ok(response) => match (response.output, response.error) {
+                            (_, Some(error)) => writeln!(self.stdout, "Error: Model {} failed with {}: {}", name, error.code, error.message),
+                            (Some(output), None) => writeln!(self.stdout, "{}", output),
+                            (None, None) => writeln!(self.stdout, "Error: Model {} returned no output", name),
+                        },
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or input argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
 
-}
\ No newline at end of file
+            Some(("model-batch", _matches)) => {
+                if let (Some(name), Some(input), Some(output)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("input"), _matches.get_one::<String>("output")) {
+                    let concurrency = _matches.get_one::<usize>("concurrency").copied().unwrap_or(batch::DEFAULT_CONCURRENCY);
+                    let report = self.model_batch(name, input, output, concurrency).await?;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name, input or output argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("session-create", _matches)) => {
+                if let Some(name) = _matches.get_one::<String>("name") {
+                    match self.model_pool.create_session(name).await {
+                        Ok(session) => writeln!(self.stdout, "Created session {} of model {}", session, name),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("session-reset", _matches)) => {
+                if let Some(session) = _matches.get_one::<String>("session") {
+                    match self.model_pool.reset_session(session).await {
+                        Ok(()) => writeln!(self.stdout, "Reset session {}", session),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Session argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("session-close", _matches)) => {
+                if let Some(session) = _matches.get_one::<String>("session") {
+                    match self.model_pool.close_session(session).await {
+                        Ok(()) => writeln!(self.stdout, "Closed session {}", session),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Session argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("transcript-export", _matches)) => {
+                let time = |key: &str| -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+                    match _matches.get_one::<String>(key) {
+                        Some(time) => chrono::DateTime::parse_from_rfc3339(time)
+                            .map(|time| Some(time.with_timezone(&chrono::Utc)))
+                            .map_err(|err| format!("Error: Invalid --{} time {:#?}: {}\n", key, time, err)),
+                        None => Ok(None),
+                    }
+                };
+                let query = TranscriptQuery {
+                    model: _matches.get_one::<String>("model").cloned(),
+                    session_id: _matches.get_one::<String>("session").cloned(),
+                    from: time("from")?,
+                    to: time("to")?,
+                    limit: _matches.get_one::<usize>("limit").copied(),
+                };
+                let export = self.dal.lock().await.export_transcript(&query).await.map_err(|err| format!("Error: {}\n", err))?;
+                match _matches.get_one::<String>("output") {
+                    Some(output) => {
+                        std::fs::write(output, &export).map_err(|err| format!("Error: Failed to write {:#?}: {}\n", output, err))?;
+                        writeln!(self.stdout, "Exported {} transcript turns to {}", export.lines().count(), output).map_err(|e| e.to_string())?;
+                    }
+                    None => write!(self.stdout, "{}", export).map_err(|e| e.to_string())?,
+                }
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("model-continuous-feedback", _matches)) => {
+                if let (Some(name), Some(state)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("state")) {
+                    let report = match state.as_str() {
+                        "status" => self.model_feedback(name),
+                        state => match self.model_pool.set_feedback(name, state == "on") {
+                            Ok(()) => format!("Continuous feedback learning of model {} is {}\n", name, state),
+                            Err(err) => format!("Error: {}\n", err),
+                        },
+                    };
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or state argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("model-feedback-rate", _matches)) => {
+                if let (Some(name), Some(rating)) = (_matches.get_one::<String>("name"), _matches.get_one::<u8>("rating")) {
+                    let request = _matches.get_one::<String>("request").map(|request| request.as_str());
+                    match self.model_pool.rate(name, request, *rating) {
+                        Ok(Rating::Rejected) => writeln!(self.stdout, "Rated the answer of model {}, it is not learned from", name),
+                        Ok(Rating::Collected(collected)) => {
+                            let threshold = self.model_pool.feedback_status(name).map(|status| status.threshold).unwrap_or_default();
+                            match collected >= threshold {
+                                true => writeln!(self.stdout, "Rated the answer of model {}, collected {} interactions, training new weights", name, collected),
+                                false => writeln!(self.stdout, "Rated the answer of model {}, collected {}/{} interactions", name, collected, threshold),
+                            }
+                        }
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or rating argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("dataset-upload", _matches)) => {
+                if let (Some(name), Some(file)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("file")) {
+                    let dataset = _matches.get_one::<String>("dataset").map(|dataset| dataset.as_str());
+                    let report = match self.dataset_upload(name, file, dataset).await {
+                        Ok((dataset, size)) => format!("Uploaded dataset {} ({} bytes) to model {}\n", dataset, size, name),
+                        Err(err) => format!("Error: {}\n", err),
+                    };
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or file argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("weights-list", _matches)) => {
+                if let Some(name) = _matches.get_one::<String>("name") {
+                    let report = self.weights_list(name).await;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("weights-download", _matches)) => {
+                if let (Some(name), Some(weights)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("weights")) {
+                    let output = _matches.get_one::<String>("output").map(|output| output.as_str()).unwrap_or(".");
+                    let report = match self.weights_download(name, weights, output).await {
+                        Ok(count) => format!("Downloaded {} files of the weights {} of model {} to {}\n", count, weights, name, output),
+                        Err(err) => format!("Error: {}\n", err),
+                    };
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or weights argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("train-start", _matches)) => {
+                if let (Some(name), Some(dataset)) = (_matches.get_one::<String>("name"), _matches.get_one::<String>("dataset")) {
+                    let report = match self.train_start(name, dataset, _matches).await {
+                        Ok(id) => format!("Started training job {} of model {}\n", id, name),
+                        Err(err) => format!("Error: {}\n", err),
+                    };
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name or dataset argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("train-status", _matches)) => {
+                let report = match _matches.get_one::<String>("job") {
+                    Some(id) => self.train_status(id, _matches.get_one::<usize>("lines").copied().unwrap_or(DEFAULT_LOG_LINES)),
+                    None => self.train_list(_matches.get_one::<String>("model").map(|model| model.as_str())),
+                };
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("train-cancel", _matches)) => {
+                if let Some(id) = _matches.get_one::<String>("job") {
+                    match self.model_pool.training().cancel(id) {
+                        Ok(()) => writeln!(self.stdout, "Cancelling training job {}", id),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Job argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("job-status", _matches)) => {
+                let report = match _matches.get_one::<String>("job") {
+                    Some(id) => self.job_status(id, _matches.get_flag("results")),
+                    None => self.job_list(),
+                };
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("job-cancel", _matches)) => {
+                if let Some(id) = _matches.get_one::<String>("job") {
+                    match self.jobs.cancel(id) {
+                        Ok(()) => writeln!(self.stdout, "Cancelling inference job {}", id),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Job argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("apikey-create", _matches)) => {
+                if let Some(name) = _matches.get_one::<String>("name") {
+                    let models: Vec<String> = _matches.get_many::<String>("model").map(|models| models.cloned().collect()).unwrap_or_default();
+                    let limit = |key: &str| _matches.get_one::<u64>(key).copied().filter(|limit| *limit > 0);
+                    let limits = UsageLimits {
+                        rate_per_minute: limit("rate-limit"),
+                        burst: limit("burst"),
+                        daily_requests: limit("daily-requests"),
+                        monthly_requests: limit("monthly-requests"),
+                        daily_bytes: limit("daily-bytes"),
+                        monthly_bytes: limit("monthly-bytes"),
+                    };
+                    match self.keys.create(&self.dal, name, models, _matches.get_flag("admin"), limits).await {
+                        Ok((key, api_key)) => writeln!(self.stdout, "Created API key {} of {}, store it now as it is not shown again:\n{}", api_key.key_id, api_key.name, key),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Name argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("apikey-list", _matches)) => {
+                let report = self.apikey_list();
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("apikey-delete", _matches)) => {
+                if let Some(key_id) = _matches.get_one::<String>("key-id") {
+                    match self.keys.delete(&self.dal, key_id).await {
+                        Ok(()) => writeln!(self.stdout, "Deleted API key {}", key_id),
+                        Err(err) => writeln!(self.stdout, "Error: {}", err),
+                    }.map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Key id argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("usage-status", _matches)) => {
+                let report = self.usage_status();
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("exit", _matches)) => {
+                writeln!(self.stdout, "Exiting Model-Executor Runtime-CLI ...").map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+                // Return true
+                return Ok(true);
+            }
+
+            Some(("model-list", _matches)) => {
+                let report = self.model_list();
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("model-create", _matches)) => {
+                let report = self.model_create(&model_entry(_matches)).await;
+                write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                self.stdout.flush().map_err(|e| e.to_string())?;
+            }
+
+            Some(("model-modify", _matches)) => {
+                if let Some(uid) = _matches.get_one::<String>("uid") {
+                    let report = self.model_modify(uid, &model_entry(_matches)).await;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Uid argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some(("model-delete", _matches)) => {
+                if let Some(uid) = _matches.get_one::<String>("uid") {
+                    let report = self.model_delete(uid).await;
+                    write!(self.stdout, "{}", report).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                } else {
+                    writeln!(self.stdout, "Error: Uid argument is missing").map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+
+            Some((name, _matches)) => return Err(format!("Error: Command {} not implemented\n", name)),
+            None => unreachable!("Error: Subcommand required\n"),
+        }
+
+        Ok(false)
+    }
+
+    // Runs a batch file through a model and formats the outcome, a batch that could not run is an error so the
+    // command exits with an error code
+    async fn model_batch(&self, name: &str, input: &str, output: &str, concurrency: usize) -> Result<String, String> {
+        let report = batch::run_batch(&self.model_pool, name, std::path::Path::new(input), std::path::Path::new(output), concurrency).await
+            .map_err(|err| format!("Error: {}\n", err))?;
+
+        let mut summary = format!("Batch of model {} written to {}:\n", name, output);
+        summary += &format!("    - Skipped: {} records of the previous run\n", report.skipped);
+        summary += &format!("    - Answered: {} records\n", report.answered);
+        summary += &format!("    - Errors: {} records\n", report.errors.len());
+        for (id, error) in report.errors.iter().take(MAX_BATCH_ERRORS) {
+            summary += &format!("        - {}: {}\n", id, error);
+        }
+        if report.errors.len() > MAX_BATCH_ERRORS {
+            summary += &format!("        - ... {} more in the output file\n", report.errors.len() - MAX_BATCH_ERRORS);
+        }
+        if report.interrupted {
+            summary += "    - Interrupted: run the command again to continue\n";
+        }
+        Ok(summary)
+    }
+
+    // Formats the models with the states of their instances, instances locked for a (re)start or shutdown are busy
+    fn model_list(&self) -> String {
+        let models = self.model_pool.list_models();
+        if models.is_empty() {
+            return "No models\n".to_string();
+        }
+
+        let mut report = format!("Models ({}):\n", models.len());
+        for model in models {
+            let states: Vec<&str> = model.instances.iter().map(|instance| instance.state.as_str()).collect();
+            report += &format!("    - {}: {}/{} instances ready ({})\n", model.name, model.ready_instances(), model.instances.len(), states.join(", "));
+        }
+        report
+    }
+
+    // Stores a new model entry and adds its instance to the pool
+    async fn model_create(&self, entry: &ModelEntry) -> String {
+        match self.model_pool.create_entry(&self.dal, entry).await {
+            Ok(config) => format!("Created model entry {} of model {}\n", config[0].get("uid").cloned().unwrap_or_default(), entry.name),
+            Err(err) => format!("Error: {}\n", err),
+        }
+    }
+
+    // Modifies a model entry and replaces its instance, empty fields are kept
+    async fn model_modify(&self, uid: &str, changes: &ModelEntry) -> String {
+        match self.model_pool.modify_entry(&self.dal, uid, changes).await {
+            Ok(config) => format!("Modified model entry {} of model {}\n", uid, config[0].get("name").cloned().unwrap_or_default()),
+            Err(err) => format!("Error: {}\n", err),
+        }
+    }
+
+    // Deletes a model entry and stops its instance
+    async fn model_delete(&self, uid: &str) -> String {
+        match self.model_pool.delete_entry(&self.dal, uid).await {
+            Ok(()) => format!("Deleted model entry {}\n", uid),
+            Err(err) => format!("Error: {}\n", err),
+        }
+    }
+
+    // Formats the configuration, state and capabilities of all instances of a model
+    async fn model_info(&self, name: &str) -> String {
+        let instances = match self.model_pool.model_info(name).await {
+            Some(instances) => instances,
+            None => return format!("Error: Model {} not found\n", name),
+        };
+
+        let mut info = format!("Model {} ({} instances):\n", name, instances.len());
+        for (index, instance) in instances.into_iter().enumerate() {
+            info += &format!("    - Instance {}:\n", index);
+            info += &format!("        - Connection type: {}\n", instance.conn_type);
+            if let Some(host) = &instance.host {
+                info += &format!("        - Host: {}\n", host);
+            }
+            info += &format!("        - Model path: {}\n", instance.model_path);
+            info += &format!("        - Protocol: {}\n", instance.protocol);
+            info += &format!("        - Weights: {}\n", instance.weights.as_deref().unwrap_or("default"));
+            match &instance.reason {
+                Some(reason) => info += &format!("        - State: {} ({})\n", instance.state, reason),
+                None => info += &format!("        - State: {}\n", instance.state),
+            }
+
+            // Capabilities are only known after the model announced them on startup
+            match instance.capabilities {
+                Some(capabilities) => {
+                    let parameters = match &capabilities.parameters {
+                        Some(parameters) => parameters.join(", "),
+                        None => "any".to_string(),
+                    };
+                    let max_input_length = match capabilities.max_input_length {
+                        Some(max_input_length) => max_input_length.to_string(),
+                        None => "unlimited".to_string(),
+                    };
+                    info += "        - Capabilities:\n";
+                    info += &format!("            - Protocol version: {}\n", capabilities.protocol_version);
+                    info += &format!("            - Streaming: {}\n", capabilities.streaming);
+                    info += &format!("            - Batching: {}\n", capabilities.batching);
+                    info += &format!("            - Sessions: {}\n", capabilities.sessions);
+                    info += &format!("            - Max input length: {}\n", max_input_length);
+                    info += &format!("            - Parameters: {}\n", parameters);
+                }
+                None => info += "        - Capabilities: not announced\n",
+            }
+        }
+
+        info
+    }
+
+    // Formats the state of the continuous feedback learning of a model
+    fn model_feedback(&self, name: &str) -> String {
+        let status = match self.model_pool.feedback_status(name) {
+            Ok(status) => status,
+            Err(err) => return format!("Error: {}\n", err),
+        };
+
+        let mut report = format!("Continuous feedback learning of model {}:\n", name);
+        report += &format!("    - Enabled: {}\n", status.enabled);
+        report += &format!("    - Collected: {}/{} interactions\n", status.collected, status.threshold);
+        report += &format!("    - Training: {}\n", status.training);
+        match status.last_result {
+            Some(Ok(weights)) => report += &format!("    - Last training: produced weights {}\n", weights),
+            Some(Err(err)) => report += &format!("    - Last training: failed ({})\n", err),
+            None => report += "    - Last training: none\n",
+        }
+        report
+    }
+
+    // Uploads a local file as a dataset of a model and returns the dataset name and size
+    async fn dataset_upload(&self, name: &str, file: &str, dataset: Option<&str>) -> Result<(String, usize), String> {
+        let dataset = match dataset {
+            Some(dataset) => dataset.to_string(),
+            None => std::path::Path::new(file).file_name()
+                .and_then(|file_name| file_name.to_str())
+                .ok_or_else(|| format!("Invalid dataset file {:#?}", file))?
+                .to_string(),
+        };
+        let data = std::fs::read(file).map_err(|err| format!("Failed to read {:#?}: {}", file, err))?;
+        self.model_pool.upload_dataset(name, &dataset, &data).await?;
+        Ok((dataset, data.len()))
+    }
+
+    // Formats the files in the weights directory of a model
+    async fn weights_list(&self, name: &str) -> String {
+        let files = match self.model_pool.list_weights(name).await {
+            Ok(files) => files,
+            Err(err) => return format!("Error: {}\n", err),
+        };
+        let served = self.model_pool.weights_version(name).await.ok().flatten();
+
+        let mut report = format!("Weights of model {} ({} files", name, files.len());
+        if let Some(served) = served {
+            report += &format!(", serving {}", served);
+        }
+        report += "):\n";
+        for file in files {
+            report += &format!("    - {} ({} bytes, sha256 {})\n", file.path, file.size, file.sha256);
+        }
+        report
+    }
+
+    // Downloads the files of a weights version of a model below the output directory and returns their number
+    async fn weights_download(&self, name: &str, weights: &str, output: &str) -> Result<usize, String> {
+        let downloads = self.model_pool.download_weights(name, weights).await?;
+        for (file, data) in &downloads {
+            let path = std::path::Path::new(output).join(&file.path);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| format!("Failed to create {:#?}: {}", dir, err))?;
+            }
+            std::fs::write(&path, data).map_err(|err| format!("Failed to write {:#?}: {}", path, err))?;
+        }
+        Ok(downloads.len())
+    }
+
+    // Starts a training job of a model from the train-start args
+    async fn train_start(&self, name: &str, dataset: &str, matches: &clap::ArgMatches) -> Result<String, String> {
+        // Uploaded datasets are stored under their file name
+        let (dataset, upload) = match matches.get_flag("upload") {
+            true => {
+                let file_name = std::path::Path::new(dataset).file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .ok_or_else(|| format!("Invalid dataset file {:#?}", dataset))?;
+                let data = std::fs::read(dataset).map_err(|err| format!("Failed to read {:#?}: {}", dataset, err))?;
+                (file_name.to_string(), Some(data))
+            }
+            false => (dataset.to_string(), None),
+        };
+        let old_weights = match matches.get_one::<String>("old-weights") {
+            Some(old_weights) => Some(old_weights.clone()),
+            None => self.model_pool.weights_version(name).await?,
+        };
+        let new_weights = matches.get_one::<String>("new-weights").cloned()
+            .unwrap_or_else(|| format!("train-{}", chrono::Utc::now().format("%Y%m%d%H%M%S")));
+        let args = matches.get_many::<String>("args").map(|args| args.cloned().collect()).unwrap_or_default();
+
+        self.model_pool.start_training(name, TrainingSpec { dataset, upload, old_weights, new_weights, args }).await
+    }
+
+    // Formats the training jobs, the newest first
+    fn train_list(&self, model: Option<&str>) -> String {
+        let jobs = self.model_pool.training().jobs(model);
+        if jobs.is_empty() {
+            return "No training jobs\n".to_string();
+        }
+
+        let mut report = format!("Training jobs ({}):\n", jobs.len());
+        for job in jobs {
+            report += &format!("    - {} (model {}): {}", job.id, job.model, job.state);
+            if let Some(progress) = &job.progress {
+                report += &format!(", {}", progress);
+            }
+            report += "\n";
+        }
+        report
+    }
+
+    // Formats the state and the recent output of a training job
+    fn train_status(&self, id: &str, lines: usize) -> String {
+        let job = match self.model_pool.training().job(id) {
+            Some(job) => job,
+            None => return format!("Error: Training job {} not found\n", id),
+        };
+
+        let mut report = format!("Training job {}:\n", job.id);
+        report += &format!("    - Model: {}\n", job.model);
+        report += &format!("    - State: {}\n", job.state);
+        report += &format!("    - Dataset: {}\n", job.dataset);
+        report += &format!("    - Weights: {} -> {}\n", job.old_weights.as_deref().unwrap_or("none"), job.new_weights);
+        if !job.args.is_empty() {
+            report += &format!("    - Arguments: {}\n", job.args.join(" "));
+        }
+        report += &format!("    - Created: {}\n", job.created_at.to_rfc3339());
+        if let Some(started_at) = job.started_at {
+            report += &format!("    - Started: {}\n", started_at.to_rfc3339());
+        }
+        if let Some(finished_at) = job.finished_at {
+            report += &format!("    - Finished: {}\n", finished_at.to_rfc3339());
+        }
+        if let Some(error) = &job.error {
+            report += &format!("    - Error: {}\n", error);
+        }
+        let output = self.model_pool.training().output(id, lines);
+        if !output.is_empty() {
+            report += "    - Output:\n";
+            for line in output {
+                report += &format!("        {}\n", line);
+            }
+        }
+        report
+    }
+
+    // Formats the inference jobs with their progress, the newest first
+    fn job_list(&self) -> String {
+        let jobs = self.jobs.jobs();
+        if jobs.is_empty() {
+            return "No inference jobs\n".to_string();
+        }
+
+        let mut report = format!("Inference jobs ({}):\n", jobs.len());
+        for job in jobs {
+            let failed = job.results.iter().filter(|result| result.error.is_some()).count();
+            report += &format!("    - {} (model {}): {}, {}/{} inputs answered, {} failed\n", job.id, job.model, job.state, job.results.len(), job.inputs.len(), failed);
+        }
+        report
+    }
+
+    // Formats the state of an inference job and its results in the order of the inputs
+    fn job_status(&self, id: &str, results: bool) -> String {
+        let job = match self.jobs.job(id) {
+            Some(job) => job,
+            None => return format!("Error: Inference job {} not found\n", id),
+        };
+
+        let mut report = format!("Inference job {}:\n", job.id);
+        report += &format!("    - Model: {}\n", job.model);
+        report += &format!("    - State: {}\n", job.state);
+        report += &format!("    - Caller: {}\n", job.caller.as_deref().unwrap_or("anonymous"));
+        report += &format!("    - Inputs: {} ({} answered, {} at a time)\n", job.inputs.len(), job.results.len(), job.concurrency);
+        if let Some(webhook) = &job.webhook {
+            report += &format!("    - Webhook: {} ({})\n", webhook, job.webhook_status.as_deref().unwrap_or("pending"));
+        }
+        report += &format!("    - Created: {}\n", job.created_at.to_rfc3339());
+        if let Some(started_at) = job.started_at {
+            report += &format!("    - Started: {}\n", started_at.to_rfc3339());
+        }
+        if let Some(finished_at) = job.finished_at {
+            report += &format!("    - Finished: {}\n", finished_at.to_rfc3339());
+        }
+        if let Some(error) = &job.error {
+            report += &format!("    - Error: {}\n", error);
+        }
+        if results {
+            report += "    - Results:\n";
+            for result in jobs::ordered_results(&job) {
+                match &result.error {
+                    Some(error) => report += &format!("        {}: Error: {}\n", result.id, error),
+                    None => report += &format!("        {}: {}\n", result.id, result.output.as_deref().unwrap_or_default()),
+                }
+            }
+        }
+        report
+    }
+
+    // Formats the API keys, the oldest first
+    fn apikey_list(&self) -> String {
+        let keys = self.keys.keys();
+        if keys.is_empty() {
+            return "No API keys\n".to_string();
+        }
+
+        let mut report = format!("API keys ({}):\n", keys.len());
+        for key in keys {
+            report += &format!("    - {} ({}): models {}", key.key_id, key.name, key.models.join(", "));
+            if key.admin {
+                report += ", admin";
+            }
+            let limits = [
+                ("per minute", key.limits.rate_per_minute),
+                ("burst", key.limits.burst),
+                ("requests per day", key.limits.daily_requests),
+                ("requests per month", key.limits.monthly_requests),
+                ("bytes per day", key.limits.daily_bytes),
+                ("bytes per month", key.limits.monthly_bytes),
+            ];
+            for (limit, value) in limits {
+                if let Some(value) = value {
+                    report += &format!(", {} {}", value, limit);
+                }
+            }
+            report += &format!(", created {}\n", key.created_at.to_rfc3339());
+        }
+        report
+    }
+
+    // Formats the usage of the API keys and models with limits in the current day and month
+    fn usage_status(&self) -> String {
+        let usage = self.model_pool.limiter().usage();
+        if usage.is_empty() {
+            return "No usage of API keys or models with limits\n".to_string();
+        }
+
+        let mut report = format!("Usage ({}):\n", usage.len());
+        for (day, month) in usage {
+            report += &format!("    - {}: {} requests and {} bytes on {}, {} requests and {} bytes in {}\n",
+                               day.subject, day.requests, day.bytes, day.period, month.requests, month.bytes, month.period);
+        }
+        report
+    }
+
+    // Probes all instances of a model and reports their round trip times
+    async fn model_ping(&self, name: &str) -> String {
+        let instances = match self.model_pool.instances(name) {
+            Some(instances) => instances,
+            None => return format!("Error: Model {} not found\n", name),
+        };
+
+        let mut report = format!("Pinging model {} ({} instances):\n", name, instances.len());
+        for (index, instance) in instances.iter().enumerate() {
+            let meal = instance.read().await;
+            if !meal.is_ready() {
+                report += &format!("    - Instance {}: not running ({})\n", index, meal.state());
+                continue;
+            }
+            match meal.ping().await {
+                Ok(latency) => report += &format!("    - Instance {}: alive, answered in {:.1} ms\n", index, latency.as_secs_f64() * 1000.0),
+                Err(err) => report += &format!("    - Instance {}: {}\n", index, err),
+            }
+        }
+
+        report
+    }
+
+    // Samples the resource usage of all instances of a model
+    async fn model_stats(&self, name: &str) -> String {
+        let instances = match self.model_pool.instances(name) {
+            Some(instances) => instances,
+            None => return format!("Error: Model {} not found\n", name),
+        };
+
+        let mut report = format!("Resource usage of model {} ({} instances):\n", name, instances.len());
+        for (index, instance) in instances.iter().enumerate() {
+            let meal = instance.read().await;
+            if !meal.is_ready() {
+                report += &format!("    - Instance {}: not running ({})\n", index, meal.state());
+                continue;
+            }
+            match meal.stats().await {
+                Ok(stats) => report += &format!("    - Instance {}: {}\n", index, stats),
+                Err(err) => report += &format!("    - Instance {}: {}\n", index, err),
+            }
+        }
+
+        report
+    }
+
+    // Prints the buffered output of all instances of a model and optionally follows new output
+    async fn model_logs(&mut self, name: &str, lines: usize, follow: bool) -> Result<(), String> {
+        let logs = match self.model_pool.logs(name) {
+            Some(logs) => logs,
+            None => {
+                writeln!(self.stdout, "Error: Model {} not found", name).map_err(|e| e.to_string())?;
+                return self.stdout.flush().map_err(|e| e.to_string());
+            }
+        };
+
+        // Subscribe before printing the buffer so no line falls in between
+        let (line_tx, mut line_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut followers = Vec::new();
+        for (index, log) in logs.iter().enumerate() {
+            if follow {
+                let mut follow_rx = log.follow();
+                let line_tx = line_tx.clone();
+                followers.push(tokio::spawn(async move {
+                    loop {
+                        let line = match follow_rx.recv().await {
+                            Ok(line) => line,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => format!("... {} lines skipped", skipped),
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                        };
+                        if line_tx.send((index, line)).is_err() {
+                            break;
+                        }
+                    }
+                }));
+            }
+
+            match log.path() {
+                Some(path) => writeln!(self.stdout, "==> Instance {} ({}) <==", index, path.display()),
+                None => writeln!(self.stdout, "==> Instance {} <==", index),
+            }.map_err(|e| e.to_string())?;
+            for line in log.tail(lines) {
+                writeln!(self.stdout, "{}", line).map_err(|e| e.to_string())?;
+            }
+        }
+        self.stdout.flush().map_err(|e| e.to_string())?;
+        if !follow {
+            return Ok(());
+        }
+
+        // Print new lines as they arrive until the user presses Enter
+        writeln!(self.stdout, "==> Following model {}, press Enter to stop <==", name).map_err(|e| e.to_string())?;
+        self.stdout.flush().map_err(|e| e.to_string())?;
+        let mut enter = tokio::task::spawn_blocking(|| {
+            let mut buffer = String::new();
+            std::io::stdin().read_line(&mut buffer)
+        });
+        loop {
+            tokio::select! {
+                _ = &mut enter => break,
+                Some((index, line)) = line_rx.recv() => {
+                    writeln!(self.stdout, "[{}] {}", index, line).map_err(|e| e.to_string())?;
+                    self.stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        for follower in followers {
+            follower.abort();
+        }
+
+        Ok(())
+    }
+
+}
+
+// Collects the model entry of model-create or the changes of model-modify, missing fields are left empty
+fn model_entry(matches: &ArgMatches) -> ModelEntry {
+    let params = |name: &str| matches.get_many::<(String, String)>(name).map(|params| params.cloned().collect()).unwrap_or_default();
+    ModelEntry {
+        name: matches.get_one::<String>("name").cloned().unwrap_or_default(),
+        conn_type: matches.get_one::<String>("conn-type").cloned().unwrap_or_default(),
+        conn_params: params("conn-param"),
+        model_params: params("model-param"),
+    }
+}
+
+// Parses a model entry param given as KEY=VALUE, an empty value removes the param on modify
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    match param.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Expected KEY=VALUE, got {:?}", param)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dal::DALArgs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(parse_param("maxRssMb=512").unwrap(), ("maxRssMb".to_string(), "512".to_string()));
+        assert_eq!(parse_param("inferenceArgv=[\"python3\", \"a=b.py\"]").unwrap(), ("inferenceArgv".to_string(), "[\"python3\", \"a=b.py\"]".to_string()));
+
+        // An empty value removes the param on modify, a missing key or separator is rejected
+        assert_eq!(parse_param("condaEnv=").unwrap(), ("condaEnv".to_string(), String::new()));
+        assert!(parse_param("=512").is_err());
+        assert!(parse_param("maxRssMb").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_model_admin_commands() {
+        // The DAL is not connected, so every command that gets past its checks fails on the DB
+        let dal_args = DALArgs { connection_url: "localhost:4321".to_string(), username: String::new(), password: String::new() };
+        let dal = Arc::new(tokio::sync::Mutex::new(DAL::create("surreal", dal_args).unwrap()));
+        let model_pool = Arc::new(ModelPool::new());
+        let jobs = Arc::new(InferenceJobs::new(Arc::clone(&model_pool), None, Vec::new()));
+        let crm = CliReplManager::new(std::io::stdin(), std::io::stdout(), std::io::stderr(), true, model_pool, dal, Arc::new(KeyStore::default()), jobs).unwrap();
+
+        // The params of model-create end up in the model entry
+        let matches = crm.command_parser()
+            .try_get_matches_from(["model-create", "echo", "local", "--conn-param", "host=example.com", "--model-param", "protocol=jsonl", "--model-param", "modelPath=/models/echo"])
+            .unwrap();
+        let (_, create_matches) = matches.subcommand().unwrap();
+        let entry = model_entry(create_matches);
+        assert_eq!((entry.name.as_str(), entry.conn_type.as_str()), ("echo", "local"));
+        assert_eq!(entry.conn_params, HashMap::from([("host".to_string(), "example.com".to_string())]));
+        assert_eq!(entry.model_params.len(), 2);
+        assert_eq!(entry.model_params["modelPath"], "/models/echo");
+        assert!(crm.command_parser().try_get_matches_from(["model-create", "echo", "local", "--model-param", "protocol"]).is_err());
+        assert!(crm.command_parser().try_get_matches_from(["model-create", "echo"]).is_err());
+
+        // model-modify only changes the given fields
+        let matches = crm.command_parser().try_get_matches_from(["model-modify", "1", "--model-param", "condaEnv="]).unwrap();
+        let (_, modify_matches) = matches.subcommand().unwrap();
+        let changes = model_entry(modify_matches);
+        assert!(changes.name.is_empty() && changes.conn_type.is_empty() && changes.conn_params.is_empty());
+        assert_eq!(changes.model_params["condaEnv"], "");
+        assert!(crm.command_parser().try_get_matches_from(["model-delete"]).is_err());
+
+        // Invalid entries are rejected before they reach the DB and DB errors are reported
+        let mut invalid = entry.clone();
+        invalid.conn_type = "telnet".to_string();
+        assert_eq!(crm.model_create(&invalid).await, "Error: Invalid request: Unsupported connection type: \"telnet\"\n");
+        assert_eq!(crm.model_modify("1", &invalid).await, "Error: Invalid request: Unsupported connection type: \"telnet\"\n");
+        assert!(crm.model_create(&entry).await.starts_with("Error: "));
+        assert!(crm.model_modify("1", &changes).await.starts_with("Error: "));
+        assert!(crm.model_delete("1").await.starts_with("Error: "));
+    }
+}
@@ -0,0 +1,32 @@
+BEGIN TRANSACTION;
+
+------------------------------------------------------------------------------------------------------------------------------
+-- Model commands are executed without a shell, the command strings of the test models become argv arrays
+-- and the conda activation moves to the condaEnv param
+
+------------------------------------------------------------
+-- Local test models (conda activate transformer-venv && ...)
+UPDATE ModelParams SET
+    inferenceArgv = ["python3", "inference.py"],
+    trainArgv = ["python3", "train.py"],
+    condaEnv = "transformer-venv",
+    inferenceCommand = NONE,
+    trainCommand = NONE,
+    lastUpdated = time::now()
+WHERE inferenceCommand = "conda activate transformer-venv && python3 inference.py";
+------------------------------------------------------------
+
+------------------------------------------------------------
+-- SSH test models
+UPDATE ModelParams SET
+    inferenceArgv = ["python3", "inference.py"],
+    trainArgv = ["python3", "train.py"],
+    inferenceCommand = NONE,
+    trainCommand = NONE,
+    lastUpdated = time::now()
+WHERE inferenceCommand = "python3 inference.py";
+------------------------------------------------------------
+
+------------------------------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -0,0 +1,29 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define static Transcripts table, one record per answered request of models with transcripts enabled
+DEFINE TABLE Transcripts SCHEMAFULL;
+
+-- Define uid and order and make the unique
+DEFINE FIELD uid ON TABLE Transcripts TYPE string ASSERT $value != NONE AND type::is::uuid($value);
+DEFINE INDEX order ON TABLE Transcripts COLUMNS uid UNIQUE;
+
+-- Define the conversation the turn belongs to
+DEFINE FIELD sessionId ON TABLE Transcripts TYPE option<string>;
+DEFINE FIELD requestId ON TABLE Transcripts TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD model ON TABLE Transcripts TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD weightsVersion ON TABLE Transcripts TYPE option<string>;
+
+-- Define the turn itself
+DEFINE FIELD input ON TABLE Transcripts TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD output ON TABLE Transcripts TYPE option<string>;
+DEFINE FIELD error ON TABLE Transcripts TYPE option<string>;
+DEFINE FIELD requestedAt ON TABLE Transcripts TYPE datetime ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD respondedAt ON TABLE Transcripts TYPE datetime ASSERT $value != NONE AND $value != NULL;
+
+-- Define the indexes of the session and time range queries
+DEFINE INDEX sessionTurns ON TABLE Transcripts COLUMNS sessionId, requestedAt;
+DEFINE INDEX modelTurns ON TABLE Transcripts COLUMNS model, requestedAt;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -0,0 +1,12 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define the versions of the ModelWeights records, one entry per trained set of weights of the model
+DEFINE FIELD weights.* ON TABLE ModelWeights TYPE object;
+DEFINE FIELD weights.*.version ON TABLE ModelWeights TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD weights.*.parentVersion ON TABLE ModelWeights TYPE option<string>;
+DEFINE FIELD weights.*.dataset ON TABLE ModelWeights TYPE option<string>;
+DEFINE FIELD weights.*.createdAt ON TABLE ModelWeights TYPE datetime ASSERT $value != NONE AND $value != NULL;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -0,0 +1,32 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define static TrainingJobs table, one record per run of the train command of a model keyed by the job id
+DEFINE TABLE TrainingJobs SCHEMAFULL;
+
+-- Define the job id and the model it trains
+DEFINE FIELD jobId ON TABLE TrainingJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE INDEX order ON TABLE TrainingJobs COLUMNS jobId UNIQUE;
+DEFINE FIELD modelUid ON TABLE TrainingJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD model ON TABLE TrainingJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+
+-- Define the arguments of the train command
+DEFINE FIELD dataset ON TABLE TrainingJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD oldWeights ON TABLE TrainingJobs TYPE option<string>;
+DEFINE FIELD newWeights ON TABLE TrainingJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD args ON TABLE TrainingJobs TYPE array;
+DEFINE FIELD args.* ON TABLE TrainingJobs TYPE string;
+
+-- Define the state of the job, one of queued, running, succeeded, failed and cancelled
+DEFINE FIELD state ON TABLE TrainingJobs TYPE string ASSERT $value INSIDE ["queued", "running", "succeeded", "failed", "cancelled"];
+DEFINE FIELD error ON TABLE TrainingJobs TYPE option<string>;
+DEFINE FIELD progress ON TABLE TrainingJobs TYPE option<string>;
+DEFINE FIELD createdAt ON TABLE TrainingJobs TYPE datetime ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD startedAt ON TABLE TrainingJobs TYPE option<datetime>;
+DEFINE FIELD finishedAt ON TABLE TrainingJobs TYPE option<datetime>;
+
+-- Define the index of the jobs of a model
+DEFINE INDEX modelJobs ON TABLE TrainingJobs COLUMNS model, createdAt;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -0,0 +1,28 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define static ApiKeys table, one record per API key of the network APIs, only the hash of the key is stored
+DEFINE TABLE ApiKeys SCHEMAFULL;
+
+-- Define the public id of the key and its owner
+DEFINE FIELD keyId ON TABLE ApiKeys TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE INDEX order ON TABLE ApiKeys COLUMNS keyId UNIQUE;
+DEFINE FIELD name ON TABLE ApiKeys TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD keyHash ON TABLE ApiKeys TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE INDEX keyHash ON TABLE ApiKeys COLUMNS keyHash UNIQUE;
+
+-- Define the scopes of the key, the model names it may execute ("*" for all) and whether it may change model entries
+DEFINE FIELD models ON TABLE ApiKeys TYPE array;
+DEFINE FIELD models.* ON TABLE ApiKeys TYPE string;
+DEFINE FIELD admin ON TABLE ApiKeys TYPE bool;
+DEFINE FIELD createdAt ON TABLE ApiKeys TYPE datetime ASSERT $value != NONE AND $value != NULL;
+-----------------------------------------------------------------------------------------------------------
+
+----------------------------------------------------------------------------------------------------------
+-- Record the caller of the accesses, the request ids of the driver are not uuids
+DEFINE FIELD requestUid ON TABLE ModelAccess TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD caller ON TABLE ModelAccess TYPE option<string>;
+DEFINE INDEX callerAccess ON TABLE ModelAccess COLUMNS caller, startAccess;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
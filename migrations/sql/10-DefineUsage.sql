@@ -0,0 +1,33 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define the rate limit and quotas of the API keys, unset limits are unlimited
+DEFINE FIELD limits ON TABLE ApiKeys TYPE object DEFAULT {};
+DEFINE FIELD limits.ratePerMinute ON TABLE ApiKeys TYPE option<int>;
+DEFINE FIELD limits.burst ON TABLE ApiKeys TYPE option<int>;
+DEFINE FIELD limits.dailyRequests ON TABLE ApiKeys TYPE option<int>;
+DEFINE FIELD limits.monthlyRequests ON TABLE ApiKeys TYPE option<int>;
+DEFINE FIELD limits.dailyBytes ON TABLE ApiKeys TYPE option<int>;
+DEFINE FIELD limits.monthlyBytes ON TABLE ApiKeys TYPE option<int>;
+UPDATE ApiKeys SET limits = {} WHERE limits = NONE;
+-----------------------------------------------------------------------------------------------------------
+
+----------------------------------------------------------------------------------------------------------
+-- Define static Usage table, one record per API key or model and day or month keyed by [subject, period]
+DEFINE TABLE Usage SCHEMAFULL;
+
+-- Define the subject ("key:<key id>" or "model:<model name>") and the UTC day or month of the counters
+DEFINE FIELD subject ON TABLE Usage TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD period ON TABLE Usage TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE INDEX order ON TABLE Usage COLUMNS subject, period UNIQUE;
+
+-- Define the counters of the requests and of the bytes of their inputs and outputs
+DEFINE FIELD requests ON TABLE Usage TYPE int ASSERT $value != NONE AND $value >= 0;
+DEFINE FIELD bytes ON TABLE Usage TYPE int ASSERT $value != NONE AND $value >= 0;
+DEFINE FIELD updatedAt ON TABLE Usage TYPE datetime;
+
+-- Define the index of the counters of a period
+DEFINE INDEX periodUsage ON TABLE Usage COLUMNS period;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -0,0 +1,42 @@
+BEGIN TRANSACTION;
+
+----------------------------------------------------------------------------------------------------------
+-- Define static InferenceJobs table, one record per batch of inputs run against a model keyed by the job id
+DEFINE TABLE InferenceJobs SCHEMAFULL;
+
+-- Define the job id, the model it runs and the id of the API key that submitted it
+DEFINE FIELD jobId ON TABLE InferenceJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE INDEX order ON TABLE InferenceJobs COLUMNS jobId UNIQUE;
+DEFINE FIELD model ON TABLE InferenceJobs TYPE string ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD caller ON TABLE InferenceJobs TYPE option<string>;
+
+-- Define the params sent with every input, the inputs and the results in the order the model answered them
+DEFINE FIELD params ON TABLE InferenceJobs FLEXIBLE TYPE object;
+DEFINE FIELD inputs ON TABLE InferenceJobs TYPE array;
+DEFINE FIELD inputs.* ON TABLE InferenceJobs TYPE object;
+DEFINE FIELD inputs.*.id ON TABLE InferenceJobs TYPE string;
+DEFINE FIELD inputs.*.input ON TABLE InferenceJobs TYPE string;
+DEFINE FIELD results ON TABLE InferenceJobs TYPE array;
+DEFINE FIELD results.* ON TABLE InferenceJobs TYPE object;
+DEFINE FIELD results.*.id ON TABLE InferenceJobs TYPE string;
+DEFINE FIELD results.*.output ON TABLE InferenceJobs TYPE option<string>;
+DEFINE FIELD results.*.error ON TABLE InferenceJobs TYPE option<string>;
+DEFINE FIELD concurrency ON TABLE InferenceJobs TYPE int ASSERT $value != NONE AND $value > 0;
+
+-- Define the URL the finished job is posted to and the outcome of the delivery
+DEFINE FIELD webhook ON TABLE InferenceJobs TYPE option<string>;
+DEFINE FIELD webhookStatus ON TABLE InferenceJobs TYPE option<string>;
+
+-- Define the state of the job, one of queued, running, succeeded, failed and cancelled
+DEFINE FIELD state ON TABLE InferenceJobs TYPE string ASSERT $value INSIDE ["queued", "running", "succeeded", "failed", "cancelled"];
+DEFINE FIELD error ON TABLE InferenceJobs TYPE option<string>;
+DEFINE FIELD createdAt ON TABLE InferenceJobs TYPE datetime ASSERT $value != NONE AND $value != NULL;
+DEFINE FIELD startedAt ON TABLE InferenceJobs TYPE option<datetime>;
+DEFINE FIELD finishedAt ON TABLE InferenceJobs TYPE option<datetime>;
+
+-- Define the indexes of the unfinished jobs and of the jobs of a caller
+DEFINE INDEX stateJobs ON TABLE InferenceJobs COLUMNS state, createdAt;
+DEFINE INDEX callerJobs ON TABLE InferenceJobs COLUMNS caller, createdAt;
+-----------------------------------------------------------------------------------------------------------
+
+COMMIT TRANSACTION;
@@ -1,4 +1,4 @@
-import sys, argparse
+import sys, argparse, json
 import torch
 from transformers import AutoModelForCausalLM, AutoTokenizer
 
@@ -30,6 +30,7 @@ READY_TOKEN = "@!#READY#!@"
 EXIT_TOKEN = "@!#EXIT#!@"
 START_TOKEN = "@!#START#!@"
 STOP_TOKEN = "@!#STOP#!@"
+PING_TOKEN = "@!#PING#!@"
 
 
 # Define max length of the context
@@ -37,9 +38,10 @@ MAX_LENGTH = 1000
 
 
 #############################################################################################
-# Fetch which weights to load from the command line arguments
+# Fetch which weights to load and which protocol to speak from the command line arguments
 parser = argparse.ArgumentParser(description='Run inference on a pre-trained DialoGPT model.')
 parser.add_argument('--weights', type=str, default="pretrained", help='The subfolder name to load the weights from.')
+parser.add_argument('--protocol', type=str, default="tokens", choices=["tokens", "jsonl"], help='The protocol used to communicate with the driver.')
 args = parser.parse_args()
 
 #############################################################################################
@@ -53,7 +55,7 @@ try:
     tokenizer = AutoTokenizer.from_pretrained(subfolder_name)
 
 except:
-    print("Failed to load the model from the specified subfolder {}.".format(subfolder_name), file=sys.stderr)
+    print("Failed to load the model from the specified subfolder {}.".format(subfolder_name), file=sys.stderr, flush=True)
     sys.exit(1)
 
 # Set the model to evaluation mode
@@ -61,52 +63,171 @@ model.eval()
 
 
 #############################################################################################
-# Initialize the chat history
-chat_history_ids = None
-step = 0
+# Generation parameters that can be set per request with the jsonl protocol
+GENERATION_PARAMS = {
+    "max_length": int,
+    "do_sample": bool,
+    "temperature": float,
+    "top_k": int,
+    "top_p": float,
+}
 
-# Print the running token to indicate that the model is ready
-print(READY_TOKEN)
+# Chat history of every session, requests without a session share the default one
+DEFAULT_SESSION = None
+chat_histories = {DEFAULT_SESSION: None}
 
-# Start the REPL loop
-while True:
-    # Read line from stdin
-    line = input()
 
-    # Check if the program should exit
-    if line == EXIT_TOKEN:
-        sys.exit(0)
+def generate(input_string, params, session=DEFAULT_SESSION):
+    # Encode the input string
+    new_user_input_ids = tokenizer.encode(input_string + tokenizer.eos_token, return_tensors='pt')
 
-    # Check if the program should start
-    if line == START_TOKEN:
-        input_string = input()
+    # Append the new user input tokens to the chat history of the session
+    chat_history_ids = chat_histories[session]
+    bot_input_ids = torch.cat([chat_history_ids, new_user_input_ids], dim=-1) if chat_history_ids is not None else new_user_input_ids
 
-        # Check the length of the input string
-        if len(input_string) == 0:
-            print("EMPTY", file=sys.stderr)
-            continue
-        elif len(input_string) > MAX_LENGTH:
-            print("TOO LONG".format(MAX_LENGTH), file=sys.stderr)
+    # Generate a response
+    generation_params = {"max_length": MAX_LENGTH}
+    generation_params.update(params)
+    chat_history_ids = model.generate(bot_input_ids, pad_token_id=tokenizer.eos_token_id, **generation_params)
+    chat_histories[session] = chat_history_ids
+
+    # Decode the response
+    return tokenizer.decode(chat_history_ids[:, bot_input_ids.shape[-1]:][0], skip_special_tokens=True)
+
+
+#############################################################################################
+# Sentinel tokens protocol, one line of input between the start and stop tokens
+def run_tokens_protocol():
+    while True:
+        # Read line from stdin
+        line = input()
+
+        # Check if the program should exit
+        if line == EXIT_TOKEN:
+            sys.exit(0)
+
+        # Answer the health probe by echoing the ping token
+        if line == PING_TOKEN:
+            print(PING_TOKEN, flush=True)
             continue
-        else:
+
+        # Check if the program should start
+        if line == START_TOKEN:
+            input_string = input()
             line = input()
 
-            # Check if the context stops
+            # Responses are matched by their order, so every request is answered with a frame, invalid ones
+            # with an empty output
+            output = ""
             if line != STOP_TOKEN:
-                print("Invalid stop token. Please enter a valid stop token.", file=sys.stderr)
+                print("Invalid stop token. Please enter a valid stop token.", file=sys.stderr, flush=True)
+            elif len(input_string) == 0:
+                print("EMPTY", file=sys.stderr, flush=True)
+            elif len(input_string) > MAX_LENGTH:
+                print("TOO LONG", file=sys.stderr, flush=True)
+            else:
+                output = generate(input_string, {})
+
+            # Print the response
+            print(START_TOKEN)
+            print(output)
+            print(STOP_TOKEN, flush=True)
+
+
+#############################################################################################
+# JSON-lines protocol, one {"id", "input", "params"} request and {"id", "output", "error"} response per line
+def respond(request_id, output=None, error_code=None, error_message=None):
+    error = None if error_code is None else {"code": error_code, "message": error_message}
+    print(json.dumps({"id": request_id, "output": output, "error": error}), flush=True)
+
+
+# Create, reset or close the chat history of a session
+def handle_session_op(request_id, session, op):
+    if session is None:
+        respond(request_id, error_code="invalid_request", error_message="The session operation has no session")
+    elif op in ("create", "reset"):
+        chat_histories[session] = None
+        respond(request_id)
+    elif op == "close":
+        chat_histories.pop(session, None)
+        respond(request_id)
+    else:
+        respond(request_id, error_code="invalid_request", error_message="Unknown session operation {}".format(op))
+
+
+def run_jsonl_protocol():
+    while True:
+        # Read line from stdin
+        line = input()
+
+        # Check if the program should exit
+        if line == EXIT_TOKEN:
+            sys.exit(0)
+
+        # Parse the request
+        request = None
+        try:
+            request = json.loads(line)
+            request_id = request["id"]
+            session = request.get("session", DEFAULT_SESSION)
+            if "sessionOp" in request:
+                handle_session_op(request_id, session, request["sessionOp"])
                 continue
+            input_string = request["input"]
+            params = request.get("params", {})
+        except (ValueError, KeyError, TypeError) as error:
+            # Answer the health probe, which carries no input
+            if isinstance(request, dict) and request.get("ping"):
+                print(json.dumps({"id": request.get("id"), "pong": True}), flush=True)
             else:
-                # Encode the input string
-                new_user_input_ids = tokenizer.encode(input_string + tokenizer.eos_token, return_tensors='pt')
+                respond(None, error_code="invalid_request", error_message=str(error))
+            continue
+
+        # Check the session
+        if session not in chat_histories:
+            respond(request_id, error_code="unknown_session", error_message="Unknown session {}".format(session))
+            continue
+
+        # Check the length of the input string
+        if len(input_string) == 0:
+            respond(request_id, error_code="empty_input", error_message="The input is empty")
+            continue
+        elif len(input_string) > MAX_LENGTH:
+            respond(request_id, error_code="input_too_long", error_message="The input is longer than {} characters".format(MAX_LENGTH))
+            continue
 
-                # Append the new user input tokens to the chat history
-                bot_input_ids = torch.cat([chat_history_ids, new_user_input_ids], dim=-1) if step > 0 else new_user_input_ids
+        # Check the generation parameters
+        unknown_params = [name for name in params if name not in GENERATION_PARAMS]
+        if unknown_params:
+            respond(request_id, error_code="invalid_params", error_message="Unknown parameters: {}".format(", ".join(unknown_params)))
+            continue
+        try:
+            params = {name: GENERATION_PARAMS[name](value) for name, value in params.items()}
+        except (ValueError, TypeError) as error:
+            respond(request_id, error_code="invalid_params", error_message=str(error))
+            continue
 
-                # Generate a response
-                chat_history_ids = model.generate(bot_input_ids, max_length=1000, pad_token_id=tokenizer.eos_token_id)
+        # Generate and print the response
+        try:
+            respond(request_id, output=generate(input_string, params, session))
+        except Exception as error:
+            respond(request_id, error_code="generation_failed", error_message=str(error))
 
-                # Decode and print the response
-                print(tokenizer.decode(chat_history_ids[:, bot_input_ids.shape[-1]:][0], skip_special_tokens=True))
 
-                # Increment the step
-                step = step + 1
\ No newline at end of file
+#############################################################################################
+# Print the running token followed by the capability descriptor to indicate that the model is ready
+capabilities = {
+    "protocolVersion": 1,
+    "streaming": False,
+    "maxInputLength": MAX_LENGTH,
+    "batching": False,
+    "sessions": args.protocol == "jsonl",
+    "parameters": list(GENERATION_PARAMS) if args.protocol == "jsonl" else [],
+}
+print(READY_TOKEN + " " + json.dumps(capabilities), flush=True)
+
+# Start the REPL loop
+if args.protocol == "jsonl":
+    run_jsonl_protocol()
+else:
+    run_tokens_protocol()
@@ -1,3 +1,4 @@
+import argparse
 import torch
 from transformers import GPT2LMHeadModel, GPT2Tokenizer, GPT2Config
 from transformers import TextDataset, DataCollatorForLanguageModeling
@@ -10,7 +11,7 @@ parser = argparse.ArgumentParser(description='Train a DialoGPT model.')
 # Dataset and weights
 parser.add_argument('--dataset', type=str, default="train.txt", help='The file name of the training dataset in subfolder.')
 parser.add_argument('--oldWeights', type=str, default="pretrained", help='The subfolder name to load the weights from.')
-parser.add_argument('--newWeights', type=str, default="old", help='The subfolder name to load the weights from.')
+parser.add_argument('--newWeights', type=str, default="old", help='The subfolder name to save the trained weights to.')
 # Training hyperparameters
 parser.add_argument('--block_size', type=int, default=128, help='The block size.')
 parser.add_argument('--mlm', type=bool, default=False, help='Set to True if you have masked language modeling objective.')
@@ -22,11 +23,11 @@ args = parser.parse_args()
 #############################################################################################
 
 #############################################################################################
-# Define the model configuration
-model_name = "microsoft/DialoGPT-large"
+# Define the model configuration, training continues from the old weights
+model_name = "weights/" + args.oldWeights
 model_config = GPT2Config.from_pretrained(model_name)
 
-# Load pre-trained DialoGPT model and tokenizer
+# Load the DialoGPT model and tokenizer from the old weights
 model = GPT2LMHeadModel.from_pretrained(model_name, config=model_config)
 tokenizer = GPT2Tokenizer.from_pretrained(model_name)
 
@@ -64,6 +65,6 @@ trainer = Trainer(
 # Train the model
 trainer.train()
 
-# Save the trained model
-model.save_pretrained(args.newWeights)
-tokenizer.save_pretrained(args.newWeights)
\ No newline at end of file
+# Save the trained model next to the weights it was trained from
+model.save_pretrained("weights/" + args.newWeights)
+tokenizer.save_pretrained("weights/" + args.newWeights)
\ No newline at end of file